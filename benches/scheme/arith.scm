@@ -0,0 +1,11 @@
+; Arithmetic in a loop, nested in a few `let`s, so that each reference to a
+; built-in like `+` is looked up past several frames.
+(define (loop i acc)
+  (if (= i 0)
+      acc
+      (let ((a (* i 3)))
+        (let ((b (- a i)))
+          (let ((c (+ a b)))
+            (loop (- i 1) (+ acc (- c (* 2 b)) (max c 5))))))))
+
+(vector-for-each (lambda (i) (loop 60 i)) (make-vector 300 0))
@@ -0,0 +1,10 @@
+; Procedure calls with little work in each, so the time goes to evaluating
+; list forms: deciding whether each is a special form, then applying it.
+(define (add3 a b c) (+ a (+ b c)))
+
+(define (calls n acc)
+  (if (= n 0)
+      acc
+      (calls (- n 1) (add3 (car (list n)) (car (cdr (list 1 2))) (vector-length (vector acc))))))
+
+(vector-for-each (lambda (i) (calls 100 i)) (make-vector 400 0))
@@ -0,0 +1,14 @@
+; Passes a large list around: every variable reference and procedure call
+; copies the list value, so the time goes to cloning lists.
+(define big (vector->list (make-vector 2000 1)))
+
+(define (passes lst n acc)
+  (if (= n 0)
+      acc
+      (passes lst (- n 1) (+ acc (car lst)))))
+
+(define (square x) (* x x))
+
+(passes big 300 0)
+(map square (map square (map square big)))
+(car (map (lambda (x) (car big)) big))
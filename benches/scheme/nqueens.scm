@@ -0,0 +1,25 @@
+; Counts the solutions to the n-queens problem: list building and search.
+(define (ok? row dist placed)
+  (if (null-list? placed)
+      #t
+      (and (not (= (car placed) (+ row dist)))
+           (not (= (car placed) (- row dist)))
+           (not (= (car placed) row))
+           (ok? row (+ dist 1) (cdr placed)))))
+
+(define (null-list? l) (equal? l '()))
+
+(define (try-rows row n placed)
+  (if (> row n)
+      0
+      (+ (if (ok? row 1 placed) (queens n (cons row placed)) 0)
+         (try-rows (+ row 1) n placed))))
+
+(define (length-of l) (if (null-list? l) 0 (+ 1 (length-of (cdr l)))))
+
+(define (queens n placed)
+  (if (= (length-of placed) n)
+      1
+      (try-rows 1 n placed)))
+
+(queens 6 '())
@@ -0,0 +1,14 @@
+; Builds strings through a string port and with string-append.
+(define (build port i n)
+  (if (< i n)
+      (begin
+        (write-string (number->string i) port)
+        (build port (+ i 1) n))
+      #f))
+
+(define (repeat s n)
+  (if (= n 0) "" (string-append s (repeat s (- n 1)))))
+
+(define port (open-output-string))
+(build port 0 300)
+(string-append (get-output-string port) (repeat "ab" 200))
@@ -0,0 +1,47 @@
+//! Times building strings of increasing size with a string port, to show
+//! that the cost grows linearly. Run with `cargo bench --bench string_builder`.
+
+use std::time::{Duration, Instant};
+
+use scheme_rs::env::{default_env, Value};
+use scheme_rs::eval::eval;
+use scheme_rs::lexer::tokenize;
+use scheme_rs::parser::parse;
+
+const CHUNK: &str = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+
+/// Builds a string of `chunks` copies of `CHUNK` by writing to a string port.
+fn build(chunks: usize) -> (usize, Duration) {
+    let env = default_env();
+    let define = "
+        (define (build out n)
+          (if (= n 0)
+              #t
+              (begin (write-string chunk out) (build out (- n 1)))))";
+    eval(&parse(tokenize(define).unwrap()).unwrap(), env.clone()).unwrap();
+    env.define("chunk".into(), Value::String(CHUNK.into()));
+
+    // Loop from Rust in batches so the Scheme recursion stays shallow.
+    let out = eval(&parse(tokenize("(open-output-string)").unwrap()).unwrap(), env.clone()).unwrap();
+    env.define("out".into(), out);
+    let batch = parse(tokenize("(build out 100)").unwrap()).unwrap();
+    let finish = parse(tokenize("(get-output-string out)").unwrap()).unwrap();
+    let start = Instant::now();
+    for _ in 0..chunks / 100 {
+        eval(&batch, env.clone()).unwrap();
+    }
+    let result = eval(&finish, env).unwrap();
+    let elapsed = start.elapsed();
+    match result {
+        Value::String(ref s) => (s.len(), elapsed),
+        other => panic!("unexpected result {}", other),
+    }
+}
+
+fn main() {
+    println!("{:>10}  {:>10}  {:>12}", "bytes", "time", "ns per byte");
+    for chunks in [4_000, 8_000, 16_000] {
+        let (bytes, elapsed) = build(chunks);
+        println!("{:>10}  {:>10.2?}  {:>12.1}", bytes, elapsed, elapsed.as_nanos() as f64 / bytes as f64);
+    }
+}
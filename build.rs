@@ -0,0 +1,13 @@
+//! Sets the `browser` cfg for WebAssembly without WASI, where there is no
+//! operating system and the page's JavaScript provides clocks and timers.
+//! Other WebAssembly targets, such as `wasm32-wasip1`, run like native ones
+//! apart from lacking threads and stack switching.
+
+fn main() {
+    println!("cargo::rustc-check-cfg=cfg(browser)");
+    let arch = std::env::var("CARGO_CFG_TARGET_ARCH").unwrap_or_default();
+    let os = std::env::var("CARGO_CFG_TARGET_OS").unwrap_or_default();
+    if arch == "wasm32" && os == "unknown" {
+        println!("cargo::rustc-cfg=browser");
+    }
+}
@@ -17,6 +17,9 @@ async function runWasm() {
 
             const result = ctx.eval_line(line);
             appendOutput(`scheme-rs> ${line}`);
+            for (const warning of ctx.take_warnings()) {
+                appendOutput(`⚠️ ${warning}`);
+            }
             appendOutput(result);
 
             // If user typed "exit", disable further input
@@ -0,0 +1,35 @@
+/* C interface to the scheme_rs interpreter. Link against the cdylib built
+ * by `cargo build --release` (libscheme_rs.so, .dylib, or .dll). */
+#ifndef SCHEME_RS_H
+#define SCHEME_RS_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Result codes of scheme_eval. */
+#define SCHEME_OK 0               /* *result is the printed value */
+#define SCHEME_READ_ERROR 1       /* *result is the lex or parse error */
+#define SCHEME_EVAL_ERROR 2       /* *result is the evaluation error */
+#define SCHEME_INVALID_ARGUMENT 3 /* null pointer or non-UTF-8 source; *result is not set */
+#define SCHEME_PANIC 4            /* interpreter panicked; free the context */
+
+/* An interpreter session with its own global environment. Use it only on
+ * the thread that created it. */
+typedef struct SchemeContext SchemeContext;
+
+SchemeContext *scheme_new(void);
+
+/* Evaluates the expressions in source (NUL-terminated UTF-8) and stores a
+ * new string in *result, to be freed with scheme_string_free. */
+int scheme_eval(SchemeContext *context, const char *source, char **result);
+
+void scheme_string_free(char *string);
+
+void scheme_free(SchemeContext *context);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,341 @@
+//! Actors: processes that share nothing and work together by sending each
+//! other messages. `(spawn thunk)` calls `thunk` on a new thread with a
+//! global environment of its own and returns the new process's pid;
+//! `(send pid message)` puts a copy of `message` in that process's mailbox,
+//! and `(receive)` takes the oldest message from the caller's own, waiting
+//! for one to arrive. `(self)` is the caller's pid, to send along for
+//! replies.
+//!
+//! Values never cross threads, so the interpreter's `Rc`s stay unshared.
+//! Messages are copied as `parallel::Datum`s, and so are the values of the
+//! variables the thunk refers to, with the procedures among them rebuilt
+//! from their source on the new thread. Values that cannot be copied, such
+//! as ports, stay behind: the new process sees those variables as
+//! undefined.
+
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::{LazyLock, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::ast::Expr;
+use crate::condition::error_summary;
+use crate::env::{builtin_info, default_env, Env, EvalError, Lambda, Value};
+use crate::eval::{apply, check_effect, check_wait, eval, is_pure_mode, recursion_limit, set_pure_mode, set_recursion_limit, WAIT_SLICE};
+use crate::number::Number;
+use crate::parallel::{Datum, WORKER_STACK_SIZE};
+
+/// Where to deliver messages for each process that has a mailbox.
+static MAILBOXES: LazyLock<Mutex<HashMap<u64, Sender<Datum>>>> = LazyLock::new(Default::default);
+
+static NEXT_PID: AtomicU64 = AtomicU64::new(1);
+
+thread_local! {
+    /// The current thread's mailbox, created by `spawn` for its processes
+    /// and on first use for other threads.
+    static MAILBOX: RefCell<Option<Mailbox>> = const { RefCell::new(None) };
+}
+
+struct Mailbox {
+    pid: u64,
+    messages: Receiver<Datum>,
+}
+
+impl Mailbox {
+    fn open(pid: u64) -> Mailbox {
+        let (sender, messages) = channel();
+        MAILBOXES.lock().unwrap().insert(pid, sender);
+        Mailbox { pid, messages }
+    }
+}
+
+impl Drop for Mailbox {
+    /// Messages sent after the thread ends are dropped.
+    fn drop(&mut self) {
+        MAILBOXES.lock().unwrap().remove(&self.pid);
+    }
+}
+
+fn with_mailbox<T>(f: impl FnOnce(&Mailbox) -> T) -> T {
+    MAILBOX.with(|m| {
+        let mut mailbox = m.borrow_mut();
+        f(mailbox.get_or_insert_with(|| Mailbox::open(NEXT_PID.fetch_add(1, Ordering::Relaxed))))
+    })
+}
+
+/// `(spawn thunk)` starts a process calling `thunk` on a new thread and
+/// returns its pid. The process ends when `thunk` returns; if it fails,
+/// the spawning process is sent `(failed pid message)`.
+pub fn builtin_spawn(args: Vec<Value>) -> Result<Value, EvalError> {
+    check_effect("spawn")?;
+    let thunk = match &args[0] {
+        Value::Lambda(lambda) if lambda.params.is_empty() => Closures::capture(lambda),
+        other => return Err(EvalError::TypeError(format!("spawn: expected a procedure of no arguments, got {}", other.write_string()))),
+    };
+    let pid = NEXT_PID.fetch_add(1, Ordering::Relaxed);
+    // Open the mailbox now, so that messages sent before the thread starts
+    // are kept.
+    let mailbox = Mailbox::open(pid);
+    let parent = with_mailbox(|mailbox| mailbox.pid);
+    let limit = recursion_limit();
+    let pure = is_pure_mode();
+    thread::Builder::new()
+        .name(format!("process {}", pid))
+        .stack_size(WORKER_STACK_SIZE)
+        .spawn(move || {
+            set_recursion_limit(limit);
+            set_pure_mode(pure);
+            MAILBOX.with(|m| *m.borrow_mut() = Some(mailbox));
+            if let Err(error) = apply(thunk.rebuild(&default_env()), Vec::new()) {
+                let message = error_summary(&error).1;
+                deliver(parent, Datum::List(vec![Datum::Symbol("failed".into()), Datum::Pid(pid), Datum::String(message)]));
+            }
+        })
+        .map_err(|e| EvalError::Other(format!("spawn: {}", e)))?;
+    Ok(Value::Pid(pid))
+}
+
+/// `(send pid message)` delivers a copy of `message` to the process, and
+/// returns whether it is still running to receive it.
+pub fn builtin_send(args: Vec<Value>) -> Result<Value, EvalError> {
+    let Value::Pid(pid) = args[0] else {
+        return Err(EvalError::TypeError(format!("send: expected a pid, got {}", args[0].type_name())));
+    };
+    let message = Datum::from_value(&args[1])?;
+    Ok(Value::Boolean(deliver(pid, message)))
+}
+
+/// Puts `message` in the mailbox of process `pid`, returning whether it is
+/// still running to receive it.
+fn deliver(pid: u64, message: Datum) -> bool {
+    let sender = MAILBOXES.lock().unwrap().get(&pid).cloned();
+    sender.is_some_and(|sender| sender.send(message).is_ok())
+}
+
+/// `(self)` returns the pid of the calling process.
+pub fn builtin_self(_args: Vec<Value>) -> Result<Value, EvalError> {
+    Ok(Value::Pid(with_mailbox(|mailbox| mailbox.pid)))
+}
+
+/// `(receive)` waits for a message and returns it, and
+/// `(receive seconds default)` waits at most `seconds`, a number or a
+/// variable, then gives the value of `default`. Other uses of `receive`,
+/// whose first argument is a list of formals, are SRFI 8's.
+pub(crate) fn eval_receive_message(list: &[Expr], env: Rc<Env>) -> Result<Value, EvalError> {
+    let message = match list {
+        // The mailbox's sender stays registered while the thread runs.
+        [_] => wait_for_message(None)?.expect("a mailbox outlives its thread"),
+        [_, seconds, default] => {
+            let seconds = match Number::try_from(&eval(seconds, env.clone())?).map(Number::to_f64) {
+                Ok(x) if x >= 0.0 && x.is_finite() => x,
+                _ => return Err(EvalError::TypeError("receive: expected a non-negative number of seconds".into())),
+            };
+            match wait_for_message(Some(Instant::now() + Duration::from_secs_f64(seconds)))? {
+                Some(message) => message,
+                None => return eval(default, env),
+            }
+        }
+        _ => return Err(EvalError::ArityMismatch),
+    };
+    Ok(message.into_value())
+}
+
+/// Takes the oldest message from the caller's mailbox, waiting for one
+/// until `deadline`, if given. Stops early if evaluation is interrupted.
+fn wait_for_message(deadline: Option<Instant>) -> Result<Option<Datum>, EvalError> {
+    loop {
+        check_wait()?;
+        let slice = match deadline {
+            Some(deadline) => deadline.saturating_duration_since(Instant::now()).min(WAIT_SLICE),
+            None => WAIT_SLICE,
+        };
+        match with_mailbox(|mailbox| mailbox.messages.recv_timeout(slice)) {
+            Ok(message) => return Ok(Some(message)),
+            Err(RecvTimeoutError::Timeout) if deadline.is_none_or(|deadline| Instant::now() < deadline) => {}
+            Err(RecvTimeoutError::Timeout | RecvTimeoutError::Disconnected) => return Ok(None),
+        }
+    }
+}
+
+/// A procedure copied for another thread, with the procedures it refers to.
+/// The first is the procedure itself.
+struct Closures(Vec<Closure>);
+
+struct Closure {
+    params: Vec<String>,
+    optionals: Vec<(String, Expr)>,
+    keys: Vec<(String, Expr)>,
+    body: Expr,
+    /// The variables it refers to, with their values.
+    captured: Vec<(String, Captured)>,
+}
+
+enum Captured {
+    Datum(Datum),
+    /// Another of the closures, by index.
+    Procedure(usize),
+    /// A built-in, by name.
+    Builtin(&'static str),
+}
+
+impl Closures {
+    fn capture(lambda: &Rc<Lambda>) -> Closures {
+        let mut closures = Closures(Vec::new());
+        closures.add(lambda, &mut HashMap::new());
+        closures
+    }
+
+    /// Copies `lambda` and what it refers to, unless it was copied before,
+    /// and returns its index. `seen` maps the procedures copied so far to
+    /// their indices, so that recursive procedures are copied once.
+    fn add(&mut self, lambda: &Rc<Lambda>, seen: &mut HashMap<*const Lambda, usize>) -> usize {
+        if let Some(&index) = seen.get(&Rc::as_ptr(lambda)) {
+            return index;
+        }
+        let index = self.0.len();
+        seen.insert(Rc::as_ptr(lambda), index);
+        self.0.push(Closure {
+            params: lambda.params.clone(),
+            optionals: lambda.optionals.clone(),
+            keys: lambda.keys.clone(),
+            body: lambda.body.clone(),
+            captured: Vec::new(),
+        });
+
+        let mut names = HashSet::new();
+        collect_symbols(&lambda.body, &mut names);
+        for (_, default) in lambda.optionals.iter().chain(&lambda.keys) {
+            collect_symbols(default, &mut names);
+        }
+        let parameters: HashSet<&str> = lambda.params.iter()
+            .chain(lambda.optionals.iter().chain(&lambda.keys).map(|(name, _)| name))
+            .map(String::as_str)
+            .collect();
+        let mut captured = Vec::new();
+        for name in names {
+            if parameters.contains(name) {
+                continue;
+            }
+            let value = match lambda.env.lookup(name) {
+                Some(Value::Lambda(ref other)) => Captured::Procedure(self.add(other, seen)),
+                Some(Value::Function(f)) => match builtin_info(f) {
+                    Some(info) => Captured::Builtin(info.name),
+                    None => continue,
+                },
+                Some(value) => match Datum::from_value(&value) {
+                    Ok(datum) => Captured::Datum(datum),
+                    Err(_) => continue,
+                },
+                None => continue,
+            };
+            captured.push((name.to_string(), value));
+        }
+        self.0[index].captured = captured;
+        index
+    }
+
+    /// Recreates the procedures in `global` and returns the first.
+    fn rebuild(self, global: &Rc<Env>) -> Value {
+        let envs: Vec<Rc<Env>> = self.0.iter().map(|_| Env::extend(global.clone())).collect();
+        let procedures: Vec<Value> = self.0.iter().zip(&envs).map(|(closure, env)| Value::Lambda(Rc::new(Lambda {
+            params: closure.params.clone(),
+            optionals: closure.optionals.clone(),
+            keys: closure.keys.clone(),
+            body: closure.body.clone(),
+            env: env.clone(),
+        }))).collect();
+        for (closure, env) in self.0.into_iter().zip(&envs) {
+            for (name, captured) in closure.captured {
+                let value = match captured {
+                    Captured::Datum(datum) => datum.into_value(),
+                    Captured::Procedure(index) => procedures[index].clone(),
+                    Captured::Builtin(name) => global.lookup(name).unwrap_or(Value::Unspecified),
+                };
+                env.define(name, value);
+            }
+        }
+        procedures[0].clone()
+    }
+}
+
+/// Adds every symbol in `expr` outside quoted data to `names`.
+fn collect_symbols<'a>(expr: &'a Expr, names: &mut HashSet<&'a str>) {
+    match expr {
+        Expr::Symbol(name) => {
+            names.insert(name);
+        }
+        Expr::List(items) if matches!(items.first(), Some(Expr::Symbol(s)) if s == "quote") => {}
+        Expr::List(items) => items.iter().for_each(|item| collect_symbols(item, names)),
+        Expr::HashTable(entries) => entries.iter().for_each(|(key, value)| {
+            collect_symbols(key, names);
+            collect_symbols(value, names);
+        }),
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{assert_fails, assert_prints};
+
+    #[test]
+    fn test_processes_exchange_messages() {
+        assert_prints("
+            (define factor 3)
+            (define (triple x) (* factor x))
+            (define (serve)
+              (let ((request (receive)))
+                (if (equal? (cadr request) 'stop)
+                    'done
+                    (begin
+                      (send (car request) (list 'result (triple (cadr request))))
+                      (serve)))))
+            (define server (spawn serve))
+            (send server (list (self) 7))
+            (define a (receive 5 'timeout))
+            (send server (list (self) 14))
+            (list a (receive 5 'timeout) (send server (list (self) 'stop)))",
+            "((result 21) (result 42) #t)");
+    }
+
+    #[test]
+    fn test_failures_are_sent_to_the_spawning_process() {
+        assert_prints("
+            (define worker (spawn (lambda () (error \"no more work\"))))
+            (define report (receive 5 'timeout))
+            (list (car report) (equal? (cadr report) worker) (caddr report))",
+            "(failed #t \"no more work\")");
+    }
+
+    #[test]
+    fn test_messages_are_copies() {
+        assert_prints("
+            (define v (vector 1 2))
+            (send (self) v)
+            (define copy (receive))
+            (vector-set! copy 0 'changed)
+            (list v copy (equal? (self) (self)))",
+            "(#(1 2) #(changed 2) #t)");
+    }
+
+    #[test]
+    fn test_receive_times_out_and_finished_processes_drop_messages() {
+        assert_prints("
+            (define quick (spawn (lambda () 'done)))
+            (define (wait n)
+              (if (and (> n 0) (send quick 'hello))
+                  (begin (receive 0.01 #f) (wait (- n 1)))
+                  (send quick 'hello)))
+            (list (receive 0 'empty) (wait 500))",
+            "(empty #f)");
+        assert_prints("(with-limits (seconds 0.05 default 'stopped) (receive))", "stopped");
+        assert_fails("(send (self) (lambda (x) x))", "type");
+        assert_fails("(spawn (lambda (x) x))", "type");
+        assert_fails("(receive (/ 1 100) #f)", "arity");
+        assert_fails("(send 'nobody 1)", "type");
+    }
+}
@@ -1,8 +1,100 @@
+use alloc::vec::Vec;
+use alloc::string::String;
+use core::fmt;
+
+use crate::lexer::{char_literal, escape_string, symbol_literal};
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Expr {
     Number(i64),
+    Rational(i64, i64), // `3/4`, as written
+    Float(f64),
     Boolean(bool),
     String(String),
     Symbol(String),
+    Keyword(String),
+    Char(char),
     List(Vec<Expr>),
-}
\ No newline at end of file
+    Vector(Vec<Expr>),            // `#(item ...)`
+    Bytevector(Vec<u8>),          // `#u8(byte ...)`
+    HashTable(Vec<(Expr, Expr)>), // `#hash((key . value) ...)`
+    /// Source that could not be parsed, in the results of
+    /// `parse_with_recovery`, with a description of the problem.
+    /// Evaluating it fails.
+    Error(String),
+}
+
+impl Expr {
+    /// The description of the first `Error` in the expression, if any.
+    pub fn find_error(&self) -> Option<&str> {
+        match self {
+            Expr::Error(message) => Some(message),
+            Expr::List(items) | Expr::Vector(items) => items.iter().find_map(Expr::find_error),
+            Expr::HashTable(entries) => entries.iter().find_map(|(key, value)| key.find_error().or_else(|| value.find_error())),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Expr {
+    /// Writes the expression back as Scheme source text.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Expr::Number(n) => write!(f, "{}", n),
+            Expr::Rational(n, d) => write!(f, "{}/{}", n, d),
+            // With a decimal point or an exponent, so that it reads back as
+            // a float.
+            Expr::Float(x) if x.is_nan() => write!(f, "+nan.0"),
+            Expr::Float(x) if x.is_infinite() => write!(f, "{}inf.0", if *x > 0.0 { '+' } else { '-' }),
+            Expr::Float(x) => write!(f, "{:?}", x),
+            Expr::Boolean(true) => write!(f, "#t"),
+            Expr::Boolean(false) => write!(f, "#f"),
+            Expr::String(s) => write!(f, "{}", escape_string(s)),
+            Expr::Symbol(s) => write!(f, "{}", symbol_literal(s)),
+            Expr::Keyword(k) => write!(f, "#:{}", k),
+            Expr::Char(c) => write!(f, "{}", char_literal(*c)),
+            Expr::List(items) => write_items(f, "(", items),
+            Expr::Vector(items) => write_items(f, "#(", items),
+            Expr::Bytevector(bytes) => write_items(f, "#u8(", bytes),
+            Expr::HashTable(entries) => {
+                write!(f, "#hash(")?;
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, " ")?;
+                    }
+                    write!(f, "({} . {})", key, value)?;
+                }
+                write!(f, ")")
+            }
+            Expr::Error(message) => write!(f, "#<error {}>", message),
+        }
+    }
+}
+
+fn write_items(f: &mut fmt::Formatter<'_>, open: &str, items: &[impl fmt::Display]) -> fmt::Result {
+    write!(f, "{}", open)?;
+    for (i, item) in items.iter().enumerate() {
+        if i > 0 {
+            write!(f, " ")?;
+        }
+        write!(f, "{}", item)?;
+    }
+    write!(f, ")")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_round_trips_source() {
+        let expr = Expr::List(vec![
+            Expr::Symbol("f".into()),
+            Expr::Number(1),
+            Expr::Boolean(false),
+            Expr::String("a \"b\"".into()),
+            Expr::List(vec![]),
+        ]);
+        assert_eq!(expr.to_string(), "(f 1 #f \"a \\\"b\\\"\" ())");
+    }
+}
@@ -0,0 +1,156 @@
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::env::default_env;
+use crate::eval::eval_sequence;
+use crate::json::{self, Json};
+use crate::lexer::tokenize;
+use crate::parser::parse_program;
+
+/// Timings of one benchmark file.
+#[derive(Debug, Clone)]
+pub struct BenchResult {
+    pub name: String,
+    pub mean: Duration,
+    pub median: Duration,
+}
+
+/// Runs a benchmark file `runs` times, each time in a fresh environment, and
+/// times the evaluation. The file is lexed and parsed once, outside the
+/// timings.
+pub fn run_file(path: &Path, runs: usize) -> Result<BenchResult, String> {
+    let source = std::fs::read_to_string(path)
+        .map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+    let tokens = tokenize(&source)
+        .map_err(|e| format!("Lex error in {}: {:?}", path.display(), e))?;
+    let program = parse_program(tokens)
+        .map_err(|e| format!("Parse error in {}: {:?}", path.display(), e))?;
+
+    let mut times = Vec::with_capacity(runs);
+    for _ in 0..runs.max(1) {
+        let env = default_env();
+        let start = Instant::now();
+        eval_sequence(&program, env)
+            .map_err(|e| format!("Eval error in {}: {:?}", path.display(), e))?;
+        times.push(start.elapsed());
+    }
+    times.sort();
+    let name = path.file_name().map_or_else(|| path.display().to_string(), |n| n.to_string_lossy().into_owned());
+    Ok(BenchResult {
+        name,
+        mean: times.iter().sum::<Duration>() / times.len() as u32,
+        median: times[times.len() / 2],
+    })
+}
+
+/// Runs every `.scm` file in a directory, in name order.
+pub fn run_dir(dir: &Path, runs: usize) -> Result<Vec<BenchResult>, String> {
+    let mut paths = std::fs::read_dir(dir)
+        .map_err(|e| format!("Cannot read {}: {}", dir.display(), e))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "scm"))
+        .collect::<Vec<_>>();
+    paths.sort();
+    paths.iter().map(|path| run_file(path, runs)).collect()
+}
+
+/// Writes the median of each benchmark, in milliseconds, as a JSON object
+/// that `parse_baseline` reads back.
+pub fn baseline_json(results: &[BenchResult]) -> String {
+    let entries = results.iter()
+        .map(|r| (r.name.clone(), Json::Number((millis(r.median) * 1000.0).round() / 1000.0)))
+        .collect();
+    format!("{}\n", Json::Object(entries).pretty())
+}
+
+/// Reads a baseline written by `baseline_json`: a flat JSON object mapping
+/// benchmark names to median milliseconds.
+pub fn parse_baseline(text: &str) -> Result<BTreeMap<String, f64>, String> {
+    let Json::Object(entries) = json::parse(text)? else {
+        return Err("Baseline must be a JSON object".into());
+    };
+    entries.into_iter()
+        .map(|(name, value)| match value {
+            Json::Number(ms) => Ok((name, ms)),
+            other => Err(format!("Bad baseline time for {}: {}", name, other)),
+        })
+        .collect()
+}
+
+/// Formats results as a table, with the change in median time against a
+/// baseline for the benchmarks it contains.
+pub fn report(results: &[BenchResult], baseline: Option<&BTreeMap<String, f64>>) -> String {
+    let mut out = String::new();
+    let width = results.iter().map(|r| r.name.len()).max().unwrap_or(0).max("benchmark".len());
+    let _ = write!(out, "{:width$}  {:>12}  {:>12}", "benchmark", "mean (ms)", "median (ms)");
+    if baseline.is_some() {
+        let _ = write!(out, "  {:>12}", "vs baseline");
+    }
+    out.push('\n');
+    for r in results {
+        let _ = write!(out, "{:width$}  {:>12.3}  {:>12.3}", r.name, millis(r.mean), millis(r.median));
+        if let Some(baseline) = baseline {
+            match baseline.get(&r.name) {
+                Some(&before) if before > 0.0 => {
+                    let _ = write!(out, "  {:>+11.1}%", (millis(r.median) / before - 1.0) * 100.0);
+                }
+                _ => {
+                    let _ = write!(out, "  {:>12}", "new");
+                }
+            }
+        }
+        out.push('\n');
+    }
+    out
+}
+
+fn millis(duration: Duration) -> f64 {
+    duration.as_secs_f64() * 1000.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn result(name: &str, ms: u64) -> BenchResult {
+        BenchResult { name: name.into(), mean: Duration::from_millis(ms), median: Duration::from_millis(ms) }
+    }
+
+    #[test]
+    fn test_baseline_round_trip() {
+        let results = vec![result("fib.scm", 12), result("tak.scm", 3)];
+        let baseline = parse_baseline(&baseline_json(&results)).unwrap();
+        assert_eq!(baseline.get("fib.scm"), Some(&12.0));
+        assert_eq!(baseline.get("tak.scm"), Some(&3.0));
+        assert!(parse_baseline("[1, 2]").is_err());
+        assert!(parse_baseline("{\"fib.scm\": \"fast\"}").is_err());
+    }
+
+    #[test]
+    fn test_baseline_names_with_json_punctuation() {
+        let name = "a,b: \"c\"\\d\u{e9}\n.scm";
+        let baseline = parse_baseline(&baseline_json(&[result(name, 7)])).unwrap();
+        assert_eq!(baseline, BTreeMap::from([(name.to_string(), 7.0)]));
+    }
+
+    #[test]
+    fn test_report_compares_with_baseline() {
+        let baseline = BTreeMap::from([("fib.scm".to_string(), 10.0)]);
+        let table = report(&[result("fib.scm", 12), result("new.scm", 1)], Some(&baseline));
+        let lines: Vec<_> = table.lines().collect();
+        assert!(lines[1].ends_with("+20.0%"), "{}", lines[1]);
+        assert!(lines[2].ends_with("new"), "{}", lines[2]);
+    }
+
+    #[test]
+    fn test_bundled_benchmarks_run() {
+        // Debug builds need more stack than a test thread has by default.
+        let results = std::thread::Builder::new().stack_size(64 * 1024 * 1024).spawn(|| {
+            let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("benches/scheme");
+            run_dir(&dir, 1).map(|results| results.len())
+        }).unwrap().join().unwrap();
+        assert_eq!(results, Ok(7));
+    }
+}
@@ -1,51 +1,54 @@
-use crate::env::{Value, EvalError};
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::VecDeque;
+use std::rc::Rc;
+
+use indexmap::IndexMap;
+
+use crate::ast::Expr;
+use crate::env::{builtin_info, Arity, BuiltinFn, Value, EvalError, Memoized, Promise};
+use crate::eval::{apply, filled_vec};
+use crate::number::{format_fixed, format_scientific, Number};
+use crate::port::Port;
+use crate::record::RecordProcedure;
+use crate::style;
 
 /// Adds all numeric arguments. Returns the sum.
 pub fn builtin_add(args: Vec<Value>) -> Result<Value, EvalError> {
-    let sum = args.into_iter().map(|v| match v {
-        Value::Number(n) => Ok(n),
-        _ => Err(EvalError::TypeError("Expected number".into())),
-    }).collect::<Result<Vec<_>, _>>()?.into_iter().sum();
+    let sum = extract_numbers(args)?
+        .into_iter()
+        .try_fold(Number::Integer(0), Number::checked_add)?;
 
-    Ok(Value::Number(sum))
+    Ok(sum.into())
 }
 
 /// Subtracts all subsequent numbers from the first. Requires at least one argument.
 pub fn builtin_sub(args: Vec<Value>) -> Result<Value, EvalError> {
-    let mut nums = args
-        .into_iter()
-        .map(|v| match v {
-            Value::Number(n) => Ok(n),
-            _ => Err(EvalError::TypeError("Expected number".into())),
-        })
-        .collect::<Result<Vec<_>, _>>()?;
+    let mut nums = extract_numbers(args)?;
 
     if nums.is_empty() {
         return Err(EvalError::Other("Expected at least one argument".into()));
     }
 
     let first = nums.remove(0);
-    let result = nums.into_iter().fold(first, |acc, x| acc - x);
+    let result = nums.into_iter().try_fold(first, Number::checked_sub)?;
 
-    Ok(Value::Number(result))
+    Ok(result.into())
 }
 
 /// Multiplies all numeric arguments. Returns the product.
 pub fn builtin_mul(args: Vec<Value>) -> Result<Value, EvalError> {
-    let product = args.into_iter().map(|v| match v {
-        Value::Number(n) => Ok(n),
-        _ => Err(EvalError::TypeError("Expected number".into())),
-    }).collect::<Result<Vec<_>, _>>()?.into_iter().product();
+    let product = extract_numbers(args)?
+        .into_iter()
+        .try_fold(Number::Integer(1), Number::checked_mul)?;
 
-    Ok(Value::Number(product))
+    Ok(product.into())
 }
 
-/// Divides the first number by each subsequent number. Returns an error on division by zero or if no arguments are provided.
+/// Divides the first number by each subsequent number. Exact division produces a rational.
+/// Returns an error on exact division by zero or if no arguments are provided.
 pub fn builtin_div(args: Vec<Value>) -> Result<Value, EvalError> {
-    let mut nums = args.into_iter().map(|v| match v {
-        Value::Number(n) => Ok(n),
-        _ => Err(EvalError::TypeError("Expected number".into())),
-    }).collect::<Result<Vec<_>, _>>()?;
+    let mut nums = extract_numbers(args)?;
 
     if nums.is_empty() {
         return Err(EvalError::Other("Expected at least one argument".into()));
@@ -53,44 +56,78 @@ pub fn builtin_div(args: Vec<Value>) -> Result<Value, EvalError> {
 
     let first = nums.remove(0);
     let result = nums.into_iter().try_fold(first, |acc, x| {
-        if x == 0 {
+        if x.is_exact() && x.is_zero() {
             Err(EvalError::Other("Division by zero".into()))
         } else {
-            Ok(acc / x)
+            acc.checked_div(x)
         }
     })?;
 
-    Ok(Value::Number(result))
+    Ok(result.into())
 }
 
-/// Returns true if all arguments are equal.
+/// Returns true if all arguments are numerically equal, regardless of exactness.
 pub fn builtin_eq(args: Vec<Value>) -> Result<Value, EvalError> {
-    if args.len() < 2 {
-        return Ok(Value::Boolean(true)); // Trivially equal
-    }
-
-    let first = &args[0];
-    Ok(Value::Boolean(args.iter().all(|x| x == first)))
+    compare_chain(args, |o| o == Ordering::Equal)
 }
 
 /// Returns true if arguments are in strictly increasing order.
 pub fn builtin_lt(args: Vec<Value>) -> Result<Value, EvalError> {
-    let nums = extract_numbers(args)?;
-    Ok(Value::Boolean(nums.windows(2).all(|w| w[0] < w[1])))
+    compare_chain(args, |o| o == Ordering::Less)
 }
 
 /// Returns true if arguments are in strictly decreasing order.
 pub fn builtin_gt(args: Vec<Value>) -> Result<Value, EvalError> {
+    compare_chain(args, |o| o == Ordering::Greater)
+}
+
+/// Returns true if arguments are in non-decreasing order.
+pub fn builtin_le(args: Vec<Value>) -> Result<Value, EvalError> {
+    compare_chain(args, |o| o != Ordering::Greater)
+}
+
+/// Returns true if arguments are in non-increasing order.
+pub fn builtin_ge(args: Vec<Value>) -> Result<Value, EvalError> {
+    compare_chain(args, |o| o != Ordering::Less)
+}
+
+/// Checks that every adjacent pair of numbers satisfies the ordering test. Used internally.
+fn compare_chain(args: Vec<Value>, test: fn(Ordering) -> bool) -> Result<Value, EvalError> {
+    let nums = extract_numbers(args)?;
+    Ok(Value::Boolean(nums.windows(2).all(|w| w[0].compare(w[1]).is_some_and(test))))
+}
+
+/// Returns the smallest argument. The result is inexact if any argument is inexact.
+pub fn builtin_min(args: Vec<Value>) -> Result<Value, EvalError> {
+    select_extreme(args, Ordering::Less)
+}
+
+/// Returns the largest argument. The result is inexact if any argument is inexact.
+pub fn builtin_max(args: Vec<Value>) -> Result<Value, EvalError> {
+    select_extreme(args, Ordering::Greater)
+}
+
+/// Shared implementation of `min` and `max`. Used internally.
+fn select_extreme(args: Vec<Value>, wanted: Ordering) -> Result<Value, EvalError> {
     let nums = extract_numbers(args)?;
-    Ok(Value::Boolean(nums.windows(2).all(|w| w[0] > w[1])))
+    let mut iter = nums.iter().copied();
+    let first = iter.next().ok_or_else(|| EvalError::Other("Expected at least one argument".into()))?;
+    let best = iter.fold(first, |best, x| if x.compare(best) == Some(wanted) { x } else { best });
+    // NaN is neither smaller nor larger than anything, so it wins outright.
+    if let Some(nan) = nums.iter().find(|n| n.to_f64().is_nan()) {
+        return Ok((*nan).into());
+    }
+
+    if nums.iter().all(|n| n.is_exact()) {
+        Ok(best.into())
+    } else {
+        Ok(best.to_inexact().into())
+    }
 }
 
 /// Extracts and validates numeric arguments. Used internally.
-fn extract_numbers(args: Vec<Value>) -> Result<Vec<i64>, EvalError> {
-    args.into_iter().map(|v| match v {
-        Value::Number(n) => Ok(n),
-        _ => Err(EvalError::TypeError("Expected number".into())),
-    }).collect()
+fn extract_numbers(args: Vec<Value>) -> Result<Vec<Number>, EvalError> {
+    args.iter().map(Number::try_from).collect()
 }
 
 /// Returns false if any argument is false, otherwise true. All arguments must be booleans.
@@ -130,7 +167,7 @@ pub fn builtin_not(args: Vec<Value>) -> Result<Value, EvalError> {
 
 /// Constructs a list from the given arguments.
 pub fn builtin_list(args: Vec<Value>) -> Result<Value, EvalError> {
-    Ok(Value::List(args))
+    Ok(Value::List(args.into()))
 }
 
 /// Returns the first element of a non-empty list.
@@ -145,277 +182,2826 @@ pub fn builtin_car(args: Vec<Value>) -> Result<Value, EvalError> {
 pub fn builtin_cdr(args: Vec<Value>) -> Result<Value, EvalError> {
     match &args[..] {
         [Value::List(list)] if !list.is_empty() => {
-            Ok(Value::List(list[1..].to_vec()))
+            Ok(Value::List(list[1..].into()))
         }
         _ => Err(EvalError::TypeError("Expected non-empty list".into())),
     }
 }
 
+/// Applies the `car`s and `cdr`s that a name such as `cadr` spells out,
+/// rightmost first: `(cadr x)` is `(car (cdr x))`.
+fn compose_car_cdr(args: Vec<Value>, name: &str) -> Result<Value, EvalError> {
+    let [mut value] = <[Value; 1]>::try_from(args).map_err(|_| EvalError::ArityMismatch)?;
+    for op in name[1..name.len() - 1].bytes().rev() {
+        value = match (&value, op) {
+            (Value::List(list), b'a') if !list.is_empty() => list[0].clone(),
+            (Value::List(list), _) if !list.is_empty() => Value::List(list[1..].into()),
+            _ => return Err(EvalError::TypeError(format!("{}: list is too short", name))),
+        };
+    }
+    Ok(value)
+}
+
+/// Defines the compositions of `car` and `cdr`, each a built-in taking one
+/// list, and `CAR_CDR_COMPOSITIONS` to register them by.
+macro_rules! car_cdr_compositions {
+    ($($function:ident => $name:literal),* $(,)?) => {
+        $(
+            #[doc = concat!("`(", $name, " list)`, a composition of `car` and `cdr`.")]
+            pub fn $function(args: Vec<Value>) -> Result<Value, EvalError> {
+                compose_car_cdr(args, $name)
+            }
+        )*
+
+        /// The compositions of `car` and `cdr` up to four deep, by name.
+        pub const CAR_CDR_COMPOSITIONS: &[(&str, BuiltinFn)] = &[$(($name, $function)),*];
+    };
+}
+
+car_cdr_compositions! {
+    builtin_caar => "caar",
+    builtin_cadr => "cadr",
+    builtin_cdar => "cdar",
+    builtin_cddr => "cddr",
+    builtin_caaar => "caaar",
+    builtin_caadr => "caadr",
+    builtin_cadar => "cadar",
+    builtin_caddr => "caddr",
+    builtin_cdaar => "cdaar",
+    builtin_cdadr => "cdadr",
+    builtin_cddar => "cddar",
+    builtin_cdddr => "cdddr",
+    builtin_caaaar => "caaaar",
+    builtin_caaadr => "caaadr",
+    builtin_caadar => "caadar",
+    builtin_caaddr => "caaddr",
+    builtin_cadaar => "cadaar",
+    builtin_cadadr => "cadadr",
+    builtin_caddar => "caddar",
+    builtin_cadddr => "cadddr",
+    builtin_cdaaar => "cdaaar",
+    builtin_cdaadr => "cdaadr",
+    builtin_cdadar => "cdadar",
+    builtin_cdaddr => "cdaddr",
+    builtin_cddaar => "cddaar",
+    builtin_cddadr => "cddadr",
+    builtin_cdddar => "cdddar",
+    builtin_cddddr => "cddddr",
+}
+
 /// Prepends a value to a list and returns the new list.
 pub fn builtin_cons(args: Vec<Value>) -> Result<Value, EvalError> {
     match &args[..] {
         [item, Value::List(rest)] => {
             let mut new_list = vec![item.clone()];
             new_list.extend_from_slice(rest);
-            Ok(Value::List(new_list))
+            Ok(Value::List(new_list.into()))
         }
         _ => Err(EvalError::TypeError("Expected value and list".into())),
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::env::Value;
-
-    #[test]
-    fn test_builtin_add_normal_case() {
-        let args = vec![Value::Number(1), Value::Number(2), Value::Number(3)];
-        let result = builtin_add(args).unwrap();
-        assert_eq!(result, Value::Number(6));
+/// Returns true if the argument is a proper list. Also bound to SRFI 1's
+/// `proper-list?`. Lists are immutable sequences: the reader has no dotted
+/// pairs, `cons` requires a list as its second argument, and a list cannot
+/// be changed to contain itself, so none is dotted or circular, every list is
+/// proper, and the check takes constant time.
+pub fn builtin_is_list(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [value] => Ok(Value::Boolean(matches!(value, Value::List(_)))),
+        _ => Err(EvalError::ArityMismatch),
     }
+}
 
-    #[test]
-    fn test_builtin_add_empty_args() {
-        let args = vec![];
-        let result = builtin_add(args).unwrap();
-        assert_eq!(result, Value::Number(0)); // Sum of no numbers is 0
+/// Returns a list of the elements of the argument lists, in order.
+pub fn builtin_append(args: Vec<Value>) -> Result<Value, EvalError> {
+    let mut items = Vec::new();
+    for arg in &args {
+        match arg {
+            Value::List(list) => items.extend_from_slice(list),
+            other => return Err(EvalError::TypeError(format!("append: expected a list, got {}", other.type_name()))),
+        }
     }
+    Ok(Value::List(items.into()))
+}
 
-    #[test]
-    fn test_builtin_add_type_error() {
-        let args = vec![Value::Number(1), Value::String("bad".into())];
-        let result = builtin_add(args);
-        assert!(matches!(result, Err(EvalError::TypeError(_))));
+/// Returns the elements of a list in reverse order.
+pub fn builtin_reverse(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [Value::List(items)] => Ok(Value::List(items.iter().rev().cloned().collect())),
+        [other] => Err(EvalError::TypeError(format!("reverse: expected a list, got {}", other.type_name()))),
+        _ => Err(EvalError::ArityMismatch),
     }
+}
 
-    #[test]
-    fn test_builtin_sub_normal_case() {
-        let args = vec![Value::Number(10), Value::Number(3), Value::Number(2)];
-        let result = builtin_sub(args).unwrap();
-        assert_eq!(result, Value::Number(5)); // 10 - 3 - 2
+/// Returns the number of elements of a list.
+pub fn builtin_length(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [Value::List(items)] => Ok(Value::Number(items.len() as i64)),
+        [other] => Err(EvalError::TypeError(format!("length: expected a list, got {}", other.type_name()))),
+        _ => Err(EvalError::ArityMismatch),
     }
+}
 
-    #[test]
-    fn test_builtin_sub_single_arg() {
-        let args = vec![Value::Number(5)];
-        let result = builtin_sub(args).unwrap();
-        assert_eq!(result, Value::Number(5)); // single arg, nothing to subtract
+/// Compares two values in the default ordering, returning -1, 0, or 1.
+/// Numbers compare numerically, and strings and symbols lexicographically;
+/// values of different kinds (or NaN) cannot be compared.
+pub fn builtin_compare(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [a, b] => Ok(Value::Number(compare_values(a, b)? as i64)),
+        _ => Err(EvalError::ArityMismatch),
     }
+}
 
-    #[test]
-    fn test_builtin_sub_type_error() {
-        let args = vec![Value::Number(1), Value::Boolean(true)];
-        let result = builtin_sub(args);
-        assert!(matches!(result, Err(EvalError::TypeError(_))));
-    }
+/// The default total ordering used by `compare` and `sort`.
+pub fn compare_values(a: &Value, b: &Value) -> Result<Ordering, EvalError> {
+    let ordering = match (a, b) {
+        (Value::String(x), Value::String(y)) | (Value::Symbol(x), Value::Symbol(y)) => Some(x.cmp(y)),
+        (Value::Char(x), Value::Char(y)) => Some(x.cmp(y)),
+        _ => match (Number::try_from(a), Number::try_from(b)) {
+            (Ok(x), Ok(y)) => x.compare(y),
+            _ => None,
+        },
+    };
+    ordering.ok_or_else(|| {
+        EvalError::TypeError(format!("compare: cannot compare {} {} with {} {}", a.type_name(), a, b.type_name(), b))
+    })
+}
 
-    #[test]
-    fn test_builtin_sub_empty_args_should_error() {
-        let args = vec![];
-        let result = builtin_sub(args);
-        assert!(result.is_err()); // panics or returns an error due to remove(0)
+/// Returns a sorted copy of a list or vector. Without a `less?` procedure,
+/// elements are ordered by `compare`. The sort is stable.
+pub fn builtin_sort(args: Vec<Value>) -> Result<Value, EvalError> {
+    let mut args = args.into_iter();
+    let (sequence, less) = match (args.next(), args.next(), args.next()) {
+        (Some(sequence), less, None) => (sequence, less),
+        _ => return Err(EvalError::ArityMismatch),
+    };
+    let mut less_than = |a: &Value, b: &Value| match &less {
+        Some(less) => Ok(apply(less.clone(), vec![a.clone(), b.clone()])? != Value::Boolean(false)),
+        None => Ok(compare_values(a, b)? == Ordering::Less),
+    };
+    match &sequence {
+        Value::List(items) => Ok(Value::List(merge_sort(items.to_vec(), &mut less_than)?.into())),
+        Value::Vector(items) => {
+            let sorted = merge_sort(items.borrow().clone(), &mut less_than)?;
+            Ok(Value::Vector(Rc::new(RefCell::new(sorted))))
+        }
+        _ => Err(EvalError::TypeError("Expected list or vector to sort".into())),
     }
+}
 
-        #[test]
-    fn test_builtin_mul_normal_case() {
-        let args = vec![Value::Number(2), Value::Number(3), Value::Number(4)];
-        let result = builtin_mul(args).unwrap();
-        assert_eq!(result, Value::Number(24));
+/// A stable merge sort with a fallible comparison. Unlike `slice::sort_by`,
+/// it stops at the first error and tolerates inconsistent user comparators.
+fn merge_sort(
+    items: Vec<Value>,
+    less: &mut impl FnMut(&Value, &Value) -> Result<bool, EvalError>,
+) -> Result<Vec<Value>, EvalError> {
+    if items.len() <= 1 {
+        return Ok(items);
     }
+    let mut left = items;
+    let right = left.split_off(left.len() / 2);
+    let (left, right) = (merge_sort(left, less)?, merge_sort(right, less)?);
 
-    #[test]
-    fn test_builtin_mul_empty_args() {
-        let args = vec![];
-        let result = builtin_mul(args).unwrap();
-        assert_eq!(result, Value::Number(1)); // product of no numbers is 1
+    let mut merged = Vec::with_capacity(left.len() + right.len());
+    let (mut left, mut right) = (left.into_iter().peekable(), right.into_iter().peekable());
+    while let (Some(a), Some(b)) = (left.peek(), right.peek()) {
+        // Taking from the right only when strictly less keeps the sort stable.
+        if less(b, a)? {
+            merged.extend(right.next());
+        } else {
+            merged.extend(left.next());
+        }
     }
+    merged.extend(left);
+    merged.extend(right);
+    Ok(merged)
+}
 
-    #[test]
-    fn test_builtin_mul_type_error() {
-        let args = vec![Value::Number(2), Value::Boolean(true)];
-        let result = builtin_mul(args);
-        assert!(matches!(result, Err(EvalError::TypeError(_))));
-    }
+/// Applies a procedure to corresponding elements of one or more lists and
+/// returns a list of the results. All lists must have the same length.
+pub fn builtin_map(args: Vec<Value>) -> Result<Value, EvalError> {
+    let (func, rows) = transpose_list_args(args, "map")?;
+    let results = rows
+        .into_iter()
+        .map(|row| apply(func.clone(), row))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Value::List(results.into()))
+}
 
-    #[test]
-    fn test_builtin_div_normal_case() {
-        let args = vec![Value::Number(20), Value::Number(2), Value::Number(2)];
-        let result = builtin_div(args).unwrap();
-        assert_eq!(result, Value::Number(5)); // 20 / 2 / 2
+/// Applies a procedure to corresponding elements of one or more lists, in order,
+/// for its side effects. All lists must have the same length.
+pub fn builtin_for_each(args: Vec<Value>) -> Result<Value, EvalError> {
+    let (func, rows) = transpose_list_args(args, "for-each")?;
+    for row in rows {
+        apply(func.clone(), row)?;
     }
+    Ok(Value::Unspecified)
+}
 
-    #[test]
-    fn test_builtin_div_divide_by_zero() {
-        let args = vec![Value::Number(10), Value::Number(0)];
-        let result = builtin_div(args);
-        assert!(matches!(result, Err(EvalError::Other(_))));
-    }
+/// Splits `(proc list1 list2 ...)` arguments into the procedure and one
+/// argument row per element position. Used internally.
+fn transpose_list_args(args: Vec<Value>, name: &str) -> Result<(Value, Vec<Vec<Value>>), EvalError> {
+    let mut args = args.into_iter();
+    let func = args.next().ok_or(EvalError::ArityMismatch)?;
+    let lists = args
+        .map(|arg| match &arg {
+            Value::List(items) => Ok(items.clone()),
+            _ => Err(EvalError::TypeError("Expected list".into())),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
 
-    #[test]
-    fn test_builtin_div_empty_args_should_error() {
-        let args = vec![];
-        let result = builtin_div(args);
-        assert!(matches!(result, Err(EvalError::Other(_))));
+    let len = lists.first().ok_or(EvalError::ArityMismatch)?.len();
+    if lists.iter().any(|list| list.len() != len) {
+        return Err(EvalError::Other(format!("{}: lists must have the same length", name)));
     }
 
-    #[test]
-    fn test_builtin_eq_true() {
-        let args = vec![Value::Number(5), Value::Number(5), Value::Number(5)];
-        let result = builtin_eq(args).unwrap();
-        assert_eq!(result, Value::Boolean(true));
+    let mut rows = vec![Vec::with_capacity(lists.len()); len];
+    for list in lists {
+        for (row, item) in rows.iter_mut().zip(list.iter().cloned()) {
+            row.push(item);
+        }
     }
+    Ok((func, rows))
+}
 
-    #[test]
-    fn test_builtin_eq_false() {
-        let args = vec![Value::Number(5), Value::Number(6)];
-        let result = builtin_eq(args).unwrap();
-        assert_eq!(result, Value::Boolean(false));
+/// Returns true if the two arguments are structurally equal.
+/// Numbers are only equal to numbers of the same exactness.
+pub fn builtin_equal(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [a, b] => Ok(Value::Boolean(a == b)),
+        _ => Err(EvalError::ArityMismatch),
     }
+}
 
-    #[test]
-    fn test_builtin_lt_true() {
-        let args = vec![Value::Number(1), Value::Number(2), Value::Number(3)];
-        let result = builtin_lt(args).unwrap();
-        assert_eq!(result, Value::Boolean(true));
-    }
+/// Constructs a mutable vector from the given arguments.
+pub fn builtin_vector(args: Vec<Value>) -> Result<Value, EvalError> {
+    Ok(Value::Vector(Rc::new(RefCell::new(args))))
+}
 
-    #[test]
-    fn test_builtin_lt_false() {
-        let args = vec![Value::Number(1), Value::Number(3), Value::Number(2)];
-        let result = builtin_lt(args).unwrap();
-        assert_eq!(result, Value::Boolean(false));
-    }
+/// Creates a vector of the given length, filled with the optional second argument (default `#f`).
+pub fn builtin_make_vector(args: Vec<Value>) -> Result<Value, EvalError> {
+    let (len, fill) = match &args[..] {
+        [Value::Number(n)] => (*n, Value::Boolean(false)),
+        [Value::Number(n), fill] => (*n, fill.clone()),
+        [_] | [_, _] => return Err(EvalError::TypeError("Expected vector length".into())),
+        _ => return Err(EvalError::ArityMismatch),
+    };
+    let len = usize::try_from(len).map_err(|_| EvalError::TypeError("Expected non-negative length".into()))?;
+    Ok(Value::Vector(Rc::new(RefCell::new(filled_vec(fill, len)?))))
+}
 
-    #[test]
-    fn test_builtin_gt_true() {
-        let args = vec![Value::Number(5), Value::Number(3), Value::Number(1)];
-        let result = builtin_gt(args).unwrap();
-        assert_eq!(result, Value::Boolean(true));
+/// Returns true if the argument is a vector.
+pub fn builtin_is_vector(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [value] => Ok(Value::Boolean(matches!(value, Value::Vector(_)))),
+        _ => Err(EvalError::ArityMismatch),
     }
+}
 
-    #[test]
-    fn test_builtin_gt_false() {
-        let args = vec![Value::Number(5), Value::Number(6)];
-        let result = builtin_gt(args).unwrap();
-        assert_eq!(result, Value::Boolean(false));
+/// Returns the number of elements in a vector.
+pub fn builtin_vector_length(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [Value::Vector(items)] => Ok(Value::Number(items.borrow().len() as i64)),
+        [_] => Err(EvalError::TypeError("Expected vector".into())),
+        _ => Err(EvalError::ArityMismatch),
     }
+}
 
-    #[test]
-    fn test_builtin_and_true() {
-        let args = vec![Value::Boolean(true), Value::Boolean(true)];
-        let result = builtin_and(args).unwrap();
-        assert_eq!(result, Value::Boolean(true));
+/// Returns the element of a vector at the given index.
+pub fn builtin_vector_ref(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [Value::Vector(items), Value::Number(i)] => {
+            let items = items.borrow();
+            let i = vector_index(*i, items.len())?;
+            Ok(items[i].clone())
+        }
+        [_, _] => Err(EvalError::TypeError("Expected vector and index".into())),
+        _ => Err(EvalError::ArityMismatch),
     }
+}
 
-    #[test]
-    fn test_builtin_and_false() {
-        let args = vec![Value::Boolean(true), Value::Boolean(false)];
-        let result = builtin_and(args).unwrap();
-        assert_eq!(result, Value::Boolean(false));
+/// Replaces the element of a vector at the given index.
+pub fn builtin_vector_set(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [Value::Vector(items), Value::Number(i), value] => {
+            let mut items = items.borrow_mut();
+            let i = vector_index(*i, items.len())?;
+            items[i] = value.clone();
+            Ok(Value::Unspecified)
+        }
+        [_, _, _] => Err(EvalError::TypeError("Expected vector, index, and value".into())),
+        _ => Err(EvalError::ArityMismatch),
     }
+}
 
-    #[test]
-    fn test_builtin_and_type_error() {
-        let args = vec![Value::Boolean(true), Value::Number(1)];
-        let result = builtin_and(args);
-        assert!(matches!(result, Err(EvalError::TypeError(_))));
-    }
+/// Checks that an index is within a vector of the given length. Used internally.
+fn vector_index(i: i64, len: usize) -> Result<usize, EvalError> {
+    usize::try_from(i)
+        .ok()
+        .filter(|&i| i < len)
+        .ok_or_else(|| EvalError::Other(format!("Index {} out of range for vector of length {}", i, len)))
+}
 
-    #[test]
-    fn test_builtin_or_true() {
-        let args = vec![Value::Boolean(false), Value::Boolean(true)];
-        let result = builtin_or(args).unwrap();
-        assert_eq!(result, Value::Boolean(true));
+/// Returns a list of the elements of a vector.
+pub fn builtin_vector_to_list(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [Value::Vector(items)] => Ok(Value::List(items.borrow().as_slice().into())),
+        [_] => Err(EvalError::TypeError("Expected vector".into())),
+        _ => Err(EvalError::ArityMismatch),
     }
+}
 
-    #[test]
-    fn test_builtin_or_false() {
-        let args = vec![Value::Boolean(false), Value::Boolean(false)];
-        let result = builtin_or(args).unwrap();
-        assert_eq!(result, Value::Boolean(false));
+/// Returns a new vector containing the elements of a list.
+pub fn builtin_list_to_vector(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [Value::List(items)] => Ok(Value::Vector(Rc::new(RefCell::new(items.to_vec())))),
+        [_] => Err(EvalError::TypeError("Expected list".into())),
+        _ => Err(EvalError::ArityMismatch),
     }
+}
 
-    #[test]
-    fn test_builtin_or_type_error() {
-        let args = vec![Value::Boolean(false), Value::Number(42)];
-        let result = builtin_or(args);
-        assert!(matches!(result, Err(EvalError::TypeError(_))));
-    }
+/// Sorts a vector in place. Without a `less?` procedure, elements are
+/// ordered by `compare`. The sort is stable.
+pub fn builtin_vector_sort(args: Vec<Value>) -> Result<Value, EvalError> {
+    let (items, less) = match &args[..] {
+        [Value::Vector(items)] => (items, None),
+        [Value::Vector(items), less] => (items, Some(less)),
+        [_] | [_, _] => return Err(EvalError::TypeError("Expected vector to sort".into())),
+        _ => return Err(EvalError::ArityMismatch),
+    };
+    let mut less_than = |a: &Value, b: &Value| match less {
+        Some(less) => Ok(apply(less.clone(), vec![a.clone(), b.clone()])? != Value::Boolean(false)),
+        None => Ok(compare_values(a, b)? == Ordering::Less),
+    };
+    // Sorting a copy keeps the vector intact if the comparison fails or
+    // looks at the vector itself.
+    let sorted = merge_sort(items.borrow().clone(), &mut less_than)?;
+    *items.borrow_mut() = sorted;
+    Ok(Value::Unspecified)
+}
 
-    #[test]
-    fn test_builtin_not_true() {
-        let args = vec![Value::Boolean(true)];
-        let result = builtin_not(args).unwrap();
-        assert_eq!(result, Value::Boolean(false));
+/// `(vector-binary-search vec value [cmp])` returns the index of an element
+/// of a sorted vector that matches `value`, or `#f`. `cmp` is called with an
+/// element and `value` and returns a negative number, zero, or a positive
+/// number as the element is less than, equal to, or greater than `value`;
+/// without it, elements are ordered by `compare`.
+pub fn builtin_vector_binary_search(args: Vec<Value>) -> Result<Value, EvalError> {
+    let (items, value, cmp) = match &args[..] {
+        [Value::Vector(items), value] => (items.borrow().clone(), value, None),
+        [Value::Vector(items), value, cmp] => (items.borrow().clone(), value, Some(cmp)),
+        [_, _] | [_, _, _] => return Err(EvalError::TypeError("Expected vector to search".into())),
+        _ => return Err(EvalError::ArityMismatch),
+    };
+    let (mut low, mut high) = (0, items.len());
+    while low < high {
+        let middle = low + (high - low) / 2;
+        let ordering = match cmp {
+            Some(cmp) => Number::try_from(&apply(cmp.clone(), vec![items[middle].clone(), value.clone()])?)?
+                .compare(Number::Integer(0))
+                .ok_or_else(|| EvalError::TypeError("vector-binary-search: comparison returned NaN".into()))?,
+            None => compare_values(&items[middle], value)?,
+        };
+        match ordering {
+            Ordering::Less => low = middle + 1,
+            Ordering::Greater => high = middle,
+            Ordering::Equal => return Ok(Value::Number(middle as i64)),
+        }
     }
+    Ok(Value::Boolean(false))
+}
 
-    #[test]
-    fn test_builtin_not_false() {
-        let args = vec![Value::Boolean(false)];
-        let result = builtin_not(args).unwrap();
-        assert_eq!(result, Value::Boolean(true));
+/// Returns a new vector holding the elements of a vector from index
+/// `start` up to but not including `end`.
+pub fn builtin_subvector(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [Value::Vector(items), Value::Number(start), Value::Number(end)] => {
+            let items = items.borrow();
+            let range = vector_range(*start, *end, items.len())?;
+            Ok(Value::Vector(Rc::new(RefCell::new(items[range].to_vec()))))
+        }
+        [_, _, _] => Err(EvalError::TypeError("Expected vector, start, and end".into())),
+        _ => Err(EvalError::ArityMismatch),
     }
+}
 
-    #[test]
-    fn test_builtin_not_wrong_arity() {
-        let args = vec![];
-        let result = builtin_not(args);
-        assert!(matches!(result, Err(EvalError::ArityMismatch)));
-    }
+/// `(vector-copy! to at from [start [end]])` copies the elements of `from`
+/// between `start` and `end` into `to`, starting at index `at`. The two
+/// vectors may be the same, with overlapping ranges.
+pub fn builtin_vector_copy(args: Vec<Value>) -> Result<Value, EvalError> {
+    let (to, at, from, start, end) = match &args[..] {
+        [Value::Vector(to), Value::Number(at), Value::Vector(from), rest @ ..] if rest.len() <= 2 => {
+            let len = from.borrow().len() as i64;
+            match rest {
+                [] => (to, *at, from, 0, len),
+                [Value::Number(start)] => (to, *at, from, *start, len),
+                [Value::Number(start), Value::Number(end)] => (to, *at, from, *start, *end),
+                _ => return Err(EvalError::TypeError("Expected start and end indices".into())),
+            }
+        }
+        [_, _, _, ..] if args.len() <= 5 => return Err(EvalError::TypeError("Expected vector, index, and vector".into())),
+        _ => return Err(EvalError::ArityMismatch),
+    };
+    let source = from.borrow()[vector_range(start, end, from.borrow().len())?].to_vec();
+    let mut target = to.borrow_mut();
+    let at = usize::try_from(at).ok().filter(|&at| at + source.len() <= target.len())
+        .ok_or_else(|| EvalError::Other(format!("vector-copy!: {} elements do not fit at index {} of a vector of length {}", source.len(), at, target.len())))?;
+    target[at..at + source.len()].clone_from_slice(&source);
+    Ok(Value::Unspecified)
+}
 
-    #[test]
-    fn test_builtin_list_basic() {
-        let args = vec![Value::Number(1), Value::Boolean(true)];
-        let result = builtin_list(args.clone()).unwrap();
-        assert_eq!(result, Value::List(args));
+/// Checks that `start..end` is a range within a vector of the given length. Used internally.
+fn vector_range(start: i64, end: i64, len: usize) -> Result<std::ops::Range<usize>, EvalError> {
+    match (usize::try_from(start), usize::try_from(end)) {
+        (Ok(start), Ok(end)) if start <= end && end <= len => Ok(start..end),
+        _ => Err(EvalError::Other(format!("Range {} to {} out of range for vector of length {}", start, end, len))),
     }
+}
 
-    #[test]
-    fn test_builtin_car_success() {
-        let args = vec![Value::List(vec![Value::Number(42), Value::Boolean(false)])];
-        let result = builtin_car(args).unwrap();
-        assert_eq!(result, Value::Number(42));
+/// Applies a procedure to corresponding elements of one or more vectors and
+/// returns a vector of the results. Stops at the end of the shortest vector.
+pub fn builtin_vector_map(args: Vec<Value>) -> Result<Value, EvalError> {
+    let (func, rows) = transpose_vector_args(args)?;
+    let results = rows
+        .into_iter()
+        .map(|row| apply(func.clone(), row))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Value::Vector(Rc::new(RefCell::new(results))))
+}
+
+/// Applies a procedure to corresponding elements of one or more vectors, in
+/// order, for its side effects. Stops at the end of the shortest vector.
+pub fn builtin_vector_for_each(args: Vec<Value>) -> Result<Value, EvalError> {
+    let (func, rows) = transpose_vector_args(args)?;
+    for row in rows {
+        apply(func.clone(), row)?;
+    }
+    Ok(Value::Unspecified)
+}
+
+/// Splits `(proc vector1 vector2 ...)` arguments into the procedure and one
+/// argument row per index of the shortest vector. The vectors are read up
+/// front, so the procedure may modify them. Used internally.
+fn transpose_vector_args(args: Vec<Value>) -> Result<(Value, Vec<Vec<Value>>), EvalError> {
+    let mut args = args.into_iter();
+    let func = args.next().ok_or(EvalError::ArityMismatch)?;
+    let vectors = args
+        .map(|arg| match &arg {
+            Value::Vector(items) => Ok(items.borrow().clone()),
+            _ => Err(EvalError::TypeError("Expected vector".into())),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let len = vectors.iter().map(Vec::len).min().ok_or(EvalError::ArityMismatch)?;
+    let mut rows = vec![Vec::with_capacity(vectors.len()); len];
+    for vector in vectors {
+        for (row, item) in rows.iter_mut().zip(vector) {
+            row.push(item);
+        }
+    }
+    Ok((func, rows))
+}
+
+/// Returns a new queue holding the arguments, front first. Queues add and
+/// remove elements at either end in constant time.
+pub fn builtin_make_queue(args: Vec<Value>) -> Result<Value, EvalError> {
+    Ok(Value::Queue(Rc::new(RefCell::new(args.into()))))
+}
+
+/// Returns true if the argument is a queue.
+pub fn builtin_is_queue(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [value] => Ok(Value::Boolean(matches!(value, Value::Queue(_)))),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Returns true if a queue has no elements.
+pub fn builtin_is_queue_empty(args: Vec<Value>) -> Result<Value, EvalError> {
+    Ok(Value::Boolean(extract_queue(&args, 1)?.borrow().is_empty()))
+}
+
+/// Returns the number of elements in a queue.
+pub fn builtin_queue_length(args: Vec<Value>) -> Result<Value, EvalError> {
+    Ok(Value::Number(extract_queue(&args, 1)?.borrow().len() as i64))
+}
+
+/// Adds an element at the back of a queue.
+pub fn builtin_enqueue(args: Vec<Value>) -> Result<Value, EvalError> {
+    extract_queue(&args, 2)?.borrow_mut().push_back(args[1].clone());
+    Ok(Value::Unspecified)
+}
+
+/// Adds an element at the front of a queue.
+pub fn builtin_queue_push_front(args: Vec<Value>) -> Result<Value, EvalError> {
+    extract_queue(&args, 2)?.borrow_mut().push_front(args[1].clone());
+    Ok(Value::Unspecified)
+}
+
+/// Removes and returns the element at the front of a queue.
+pub fn builtin_dequeue(args: Vec<Value>) -> Result<Value, EvalError> {
+    let popped = extract_queue(&args, 1)?.borrow_mut().pop_front();
+    popped.ok_or_else(|| EvalError::Other("dequeue!: queue is empty".into()))
+}
+
+/// Removes and returns the element at the back of a queue.
+pub fn builtin_queue_pop_back(args: Vec<Value>) -> Result<Value, EvalError> {
+    let popped = extract_queue(&args, 1)?.borrow_mut().pop_back();
+    popped.ok_or_else(|| EvalError::Other("queue-pop-back!: queue is empty".into()))
+}
+
+/// Returns the element at the front of a queue without removing it.
+pub fn builtin_queue_front(args: Vec<Value>) -> Result<Value, EvalError> {
+    let front = extract_queue(&args, 1)?.borrow().front().cloned();
+    front.ok_or_else(|| EvalError::Other("queue-front: queue is empty".into()))
+}
+
+/// Returns a list of the elements of a queue, front first.
+pub fn builtin_queue_to_list(args: Vec<Value>) -> Result<Value, EvalError> {
+    Ok(Value::List(extract_queue(&args, 1)?.borrow().iter().cloned().collect()))
+}
+
+/// Checks the argument count and that the first argument is a queue. Used internally.
+fn extract_queue(args: &[Value], arity: usize) -> Result<&Rc<RefCell<VecDeque<Value>>>, EvalError> {
+    match args {
+        _ if args.len() != arity => Err(EvalError::ArityMismatch),
+        [Value::Queue(items), ..] => Ok(items),
+        _ => Err(EvalError::TypeError("Expected queue".into())),
+    }
+}
+
+/// Describes how a procedure is implemented, for the server's `inspect`
+/// request. This is a tree-walking interpreter, so a lambda runs its body expression directly
+/// and that expression is what is shown. Returns `None` for non-procedures.
+pub fn describe_procedure(value: &Value) -> Option<String> {
+    let text = match value {
+        Value::Function(_) => "built-in procedure (native code)".to_string(),
+        Value::Lambda(lambda) => {
+            let mut params = lambda.params.clone();
+            let with_default = |(name, default): &(String, Expr)| format!("({} {})", name, default);
+            if !lambda.optionals.is_empty() {
+                params.push("#:optional".into());
+                params.extend(lambda.optionals.iter().map(with_default));
+            }
+            if !lambda.keys.is_empty() {
+                params.push("#:key".into());
+                params.extend(lambda.keys.iter().map(with_default));
+            }
+            let body = match &lambda.body {
+                Expr::List(forms) if forms.first() == Some(&Expr::Symbol("begin".into())) => &forms[1..],
+                single => std::slice::from_ref(single),
+            };
+            let mut text = format!("lambda ({})\n  body:", params.join(" "));
+            for form in body {
+                text.push_str(&format!("\n    {}", form));
+            }
+            text
+        }
+        Value::Memoized(memo) => {
+            let inner = describe_procedure(&memo.func)?;
+            format!("memoized procedure with {} cached results, wrapping:\n  {}", memo.len(), inner.replace('\n', "\n  "))
+        }
+        Value::Combinator(combinator) => format!("procedure made by {}", combinator.name()),
+        Value::RecordProcedure(procedure) => match procedure.as_ref() {
+            RecordProcedure::Constructor(rtd, fields) => {
+                let names: Vec<_> = fields.iter().map(|&i| rtd.fields[i].as_str()).collect();
+                format!("record constructor for {} taking ({})", rtd.name, names.join(" "))
+            }
+            RecordProcedure::Predicate(rtd) => format!("record predicate for {}", rtd.name),
+            RecordProcedure::Accessor(rtd, i) => format!("record accessor for field {} of {}", rtd.fields[*i], rtd.name),
+            RecordProcedure::Modifier(rtd, i) => format!("record modifier for field {} of {}", rtd.fields[*i], rtd.name),
+        },
+        _ => return None,
+    };
+    Some(text)
+}
+
+/// Returns how many arguments a procedure accepts, or `None` for anything
+/// else. Keyword arguments count two each, name and value.
+pub fn procedure_arity(value: &Value) -> Option<Arity> {
+    match value {
+        Value::Function(f) => builtin_info(*f).map(|info| info.arity),
+        Value::Lambda(lambda) => {
+            let min = lambda.params.len();
+            Some(Arity::between(min, min + lambda.optionals.len() + 2 * lambda.keys.len()))
+        }
+        Value::Memoized(memo) => procedure_arity(&memo.func),
+        Value::Combinator(combinator) => combinator.arity(),
+        Value::RecordProcedure(procedure) => Some(match procedure.as_ref() {
+            RecordProcedure::Constructor(_, fields) => Arity::exact(fields.len()),
+            RecordProcedure::Predicate(_) | RecordProcedure::Accessor(..) => Arity::exact(1),
+            RecordProcedure::Modifier(..) => Arity::exact(2),
+        }),
+        _ => None,
+    }
+}
+
+/// `(procedure-arity procedure)` returns `(min max)`, the fewest and most
+/// arguments the procedure accepts, with `max` `#f` if there is no limit.
+pub fn builtin_procedure_arity(args: Vec<Value>) -> Result<Value, EvalError> {
+    let [procedure] = <[Value; 1]>::try_from(args).map_err(|_| EvalError::ArityMismatch)?;
+    let arity = procedure_arity(&procedure)
+        .ok_or_else(|| EvalError::TypeError(format!("Expected procedure, got {}", procedure.type_name())))?;
+    let max = arity.max.map_or(Value::Boolean(false), |max| Value::Number(max as i64));
+    Ok(Value::List([Value::Number(arity.min as i64), max].into()))
+}
+
+/// Returns true if the argument is an instance of any record type.
+pub fn builtin_is_record(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [value] => Ok(Value::Boolean(matches!(value, Value::Record(_)))),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Sets the procedure used to print records of a type. It is called with
+/// the record and should return a string; `#f` restores the default
+/// `#<name field: value ...>` form.
+pub fn builtin_set_record_type_printer(args: Vec<Value>) -> Result<Value, EvalError> {
+    match <[Value; 2]>::try_from(args) {
+        Ok([Value::RecordType(ref rtd), Value::Boolean(false)]) => {
+            *rtd.printer.borrow_mut() = None;
+            Ok(Value::Unspecified)
+        }
+        Ok([Value::RecordType(ref rtd), printer]) => {
+            *rtd.printer.borrow_mut() = Some(printer);
+            Ok(Value::Unspecified)
+        }
+        Ok(_) => Err(EvalError::TypeError("Expected record type and procedure".into())),
+        Err(_) => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Returns a new, empty hash table. Keys are compared with `equal?`.
+pub fn builtin_make_hash_table(args: Vec<Value>) -> Result<Value, EvalError> {
+    if !args.is_empty() {
+        return Err(EvalError::ArityMismatch);
+    }
+    Ok(Value::HashTable(Rc::new(RefCell::new(IndexMap::new()))))
+}
+
+/// Returns true if the argument is a hash table.
+pub fn builtin_is_hash_table(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [value] => Ok(Value::Boolean(matches!(value, Value::HashTable(_)))),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Associates a key with a value in a hash table.
+pub fn builtin_hash_table_set(args: Vec<Value>) -> Result<Value, EvalError> {
+    match <[Value; 3]>::try_from(args) {
+        Ok([Value::HashTable(ref table), key, value]) => {
+            table.borrow_mut().insert(key, value);
+            Ok(Value::Unspecified)
+        }
+        Ok(_) => Err(EvalError::TypeError("Expected hash table, key, and value".into())),
+        Err(_) => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Returns the value for a key. If the key is missing, calls the optional
+/// failure thunk, or errors if none is given.
+pub fn builtin_hash_table_ref(args: Vec<Value>) -> Result<Value, EvalError> {
+    let mut args = args.into_iter();
+    match (args.next(), args.next(), args.next(), args.next()) {
+        (Some(Value::HashTable(ref table)), Some(key), failure, None) => {
+            let found = table.borrow().get(&key).cloned();
+            match (found, failure) {
+                (Some(value), _) => Ok(value),
+                (None, Some(failure)) => apply(failure, vec![]),
+                (None, None) => Err(EvalError::Other(format!("hash-table-ref: no value for key {}", key))),
+            }
+        }
+        (Some(_), Some(_), _, None) => Err(EvalError::TypeError("Expected hash table".into())),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Returns the value for a key, or the given default if the key is missing.
+pub fn builtin_hash_table_ref_default(args: Vec<Value>) -> Result<Value, EvalError> {
+    match <[Value; 3]>::try_from(args) {
+        Ok([Value::HashTable(ref table), key, default]) => Ok(table.borrow().get(&key).cloned().unwrap_or(default)),
+        Ok(_) => Err(EvalError::TypeError("Expected hash table, key, and default".into())),
+        Err(_) => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Returns true if a hash table has a value for the key.
+pub fn builtin_hash_table_contains(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [Value::HashTable(table), key] => Ok(Value::Boolean(table.borrow().contains_key(key))),
+        [_, _] => Err(EvalError::TypeError("Expected hash table and key".into())),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Removes a key from a hash table, if present.
+pub fn builtin_hash_table_delete(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [Value::HashTable(table), key] => {
+            table.borrow_mut().shift_remove(key);
+            Ok(Value::Unspecified)
+        }
+        [_, _] => Err(EvalError::TypeError("Expected hash table and key".into())),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Replaces the value for a key with the result of calling `proc` on it. If
+/// the key is missing, the optional thunk supplies the value to update,
+/// and it is an error if none is given.
+pub fn builtin_hash_table_update(args: Vec<Value>) -> Result<Value, EvalError> {
+    let mut args = args.into_iter();
+    match (args.next(), args.next(), args.next(), args.next(), args.next()) {
+        (Some(Value::HashTable(ref table)), Some(key), Some(proc), failure, None) => {
+            let found = table.borrow().get(&key).cloned();
+            let current = match (found, failure) {
+                (Some(value), _) => value,
+                (None, Some(failure)) => apply(failure, vec![])?,
+                (None, None) => return Err(EvalError::Other(format!("hash-table-update!: no value for key {}", key))),
+            };
+            let updated = apply(proc, vec![current])?;
+            table.borrow_mut().insert(key, updated);
+            Ok(Value::Unspecified)
+        }
+        (Some(_), Some(_), Some(_), _, None) => Err(EvalError::TypeError("Expected hash table".into())),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Like `hash-table-update!`, but a missing key is updated from the given default value.
+pub fn builtin_hash_table_update_default(args: Vec<Value>) -> Result<Value, EvalError> {
+    match <[Value; 4]>::try_from(args) {
+        Ok([Value::HashTable(ref table), key, proc, default]) => {
+            let current = table.borrow().get(&key).cloned().unwrap_or(default);
+            let updated = apply(proc, vec![current])?;
+            table.borrow_mut().insert(key, updated);
+            Ok(Value::Unspecified)
+        }
+        Ok(_) => Err(EvalError::TypeError("Expected hash table, key, procedure, and default".into())),
+        Err(_) => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Returns the number of entries in a hash table.
+pub fn builtin_hash_table_count(args: Vec<Value>) -> Result<Value, EvalError> {
+    Ok(Value::Number(extract_hash_table(&args)?.borrow().len() as i64))
+}
+
+/// Returns a new hash table with the same entries. The keys and values
+/// themselves are shared, not copied.
+pub fn builtin_hash_table_copy(args: Vec<Value>) -> Result<Value, EvalError> {
+    let copy = extract_hash_table(&args)?.borrow().clone();
+    Ok(Value::HashTable(Rc::new(RefCell::new(copy))))
+}
+
+/// Returns a list of the keys of a hash table.
+pub fn builtin_hash_table_keys(args: Vec<Value>) -> Result<Value, EvalError> {
+    Ok(Value::List(extract_hash_table(&args)?.borrow().keys().cloned().collect()))
+}
+
+/// Returns a list of the values of a hash table.
+pub fn builtin_hash_table_values(args: Vec<Value>) -> Result<Value, EvalError> {
+    Ok(Value::List(extract_hash_table(&args)?.borrow().values().cloned().collect()))
+}
+
+/// Calls `(proc key value)` for each entry of a hash table. The entries are
+/// read up front, so `proc` may modify the table.
+pub fn builtin_hash_table_for_each(args: Vec<Value>) -> Result<Value, EvalError> {
+    match <[Value; 2]>::try_from(args) {
+        Ok([Value::HashTable(ref table), proc]) => {
+            let entries: Vec<_> = table.borrow().iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+            for (key, value) in entries {
+                apply(proc.clone(), vec![key, value])?;
+            }
+            Ok(Value::Unspecified)
+        }
+        Ok(_) => Err(EvalError::TypeError("Expected hash table and procedure".into())),
+        Err(_) => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Returns the entries of a hash table as an association list. Without
+/// dotted pairs, each entry is a two-element list `(key value)`.
+pub fn builtin_hash_table_to_alist(args: Vec<Value>) -> Result<Value, EvalError> {
+    let entries = extract_hash_table(&args)?
+        .borrow()
+        .iter()
+        .map(|(k, v)| Value::List([k.clone(), v.clone()].into()))
+        .collect();
+    Ok(Value::List(entries))
+}
+
+/// Builds a hash table from an association list of `(key value)` entries.
+/// When a key appears more than once, the first entry wins, as with `assoc`.
+pub fn builtin_alist_to_hash_table(args: Vec<Value>) -> Result<Value, EvalError> {
+    let entries = match &args[..] {
+        [Value::List(entries)] => entries,
+        [_] => return Err(EvalError::TypeError("Expected association list".into())),
+        _ => return Err(EvalError::ArityMismatch),
+    };
+    let mut table = IndexMap::new();
+    for entry in entries.iter() {
+        match entry {
+            Value::List(pair) if pair.len() == 2 => {
+                table.entry(pair[0].clone()).or_insert_with(|| pair[1].clone());
+            }
+            _ => return Err(EvalError::TypeError("Expected (key value) entries in association list".into())),
+        }
+    }
+    Ok(Value::HashTable(Rc::new(RefCell::new(table))))
+}
+
+fn extract_hash_table(args: &[Value]) -> Result<&Rc<RefCell<IndexMap<Value, Value>>>, EvalError> {
+    match args {
+        [Value::HashTable(table)] => Ok(table),
+        [_] => Err(EvalError::TypeError("Expected hash table".into())),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Converts any value to a string using its `display` representation.
+pub fn builtin_to_string(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [Value::String(s)] => Ok(Value::String(s.clone())),
+        [value] => Ok(Value::String(value.display_string().into())),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Concatenates all string arguments.
+pub fn builtin_string_append(args: Vec<Value>) -> Result<Value, EvalError> {
+    let strings = args.iter().map(|arg| match arg {
+        Value::String(s) => Ok(s),
+        _ => Err(EvalError::TypeError("Expected string".into())),
+    }).collect::<Result<Vec<_>, _>>()?;
+    // Sizing the result up front copies each argument exactly once.
+    let mut result = String::with_capacity(strings.iter().map(|s| s.len()).sum());
+    for s in strings {
+        result.push_str(s);
+    }
+    Ok(Value::String(result.into()))
+}
+
+/// Strings are sequences of Unicode scalar values: lengths and indices
+/// count characters, not bytes, so a combining accent is a character of its
+/// own. Extracts a string argument. Used internally.
+fn extract_string<'a>(value: &'a Value, name: &str) -> Result<&'a str, EvalError> {
+    match value {
+        Value::String(s) => Ok(s),
+        other => Err(EvalError::TypeError(format!("{} expects a string, got {}", name, other.type_name()))),
+    }
+}
+
+/// Returns true if the argument is a string.
+pub fn builtin_is_string(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [value] => Ok(Value::Boolean(matches!(value, Value::String(_)))),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Returns the number of characters in a string.
+pub fn builtin_string_length(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [s] => Ok(Value::Number(extract_string(s, "string-length")?.chars().count() as i64)),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Returns the character of a string at the given index.
+pub fn builtin_string_ref(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [s, Value::Number(i)] => {
+            let s = extract_string(s, "string-ref")?;
+            usize::try_from(*i).ok()
+                .and_then(|i| s.chars().nth(i))
+                .map(Value::Char)
+                .ok_or_else(|| EvalError::Other(format!("Index {} out of range for string of length {}", i, s.chars().count())))
+        }
+        [_, _] => Err(EvalError::TypeError("Expected string and index".into())),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// `(substring s start [end])` returns the characters of a string from
+/// index `start` up to but not including `end`, or to the end.
+pub fn builtin_substring(args: Vec<Value>) -> Result<Value, EvalError> {
+    let (s, start, end) = match &args[..] {
+        [s, Value::Number(start)] => (extract_string(s, "substring")?, *start, None),
+        [s, Value::Number(start), Value::Number(end)] => (extract_string(s, "substring")?, *start, Some(*end)),
+        [_, _] | [_, _, _] => return Err(EvalError::TypeError("Expected string and indices".into())),
+        _ => return Err(EvalError::ArityMismatch),
+    };
+    let len = s.chars().count() as i64;
+    let end = end.unwrap_or(len);
+    if start < 0 || start > end || end > len {
+        return Err(EvalError::Other(format!("Range {} to {} out of range for string of length {}", start, end, len)));
+    }
+    Ok(Value::String(s.chars().skip(start as usize).take((end - start) as usize).collect::<String>().into()))
+}
+
+/// Returns a string in upper case, by the full Unicode mapping, so that
+/// `"straße"` becomes `"STRASSE"`.
+pub fn builtin_string_upcase(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [s] => Ok(Value::String(extract_string(s, "string-upcase")?.to_uppercase().into())),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Returns a string in lower case, by the full Unicode mapping.
+pub fn builtin_string_downcase(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [s] => Ok(Value::String(extract_string(s, "string-downcase")?.to_lowercase().into())),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Returns a list of the characters of a string.
+pub fn builtin_string_to_list(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [s] => Ok(Value::List(extract_string(s, "string->list")?.chars().map(Value::Char).collect())),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Returns a string of the characters in a list.
+pub fn builtin_list_to_string(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [Value::List(items)] => items.iter().map(|item| match item {
+            Value::Char(c) => Ok(*c),
+            _ => Err(EvalError::TypeError("list->string expects a list of characters".into())),
+        }).collect::<Result<String, _>>().map(|s| Value::String(s.into())),
+        [_] => Err(EvalError::TypeError("Expected list".into())),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Returns true if the argument is a keyword (`#:name`).
+pub fn builtin_is_keyword(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [value] => Ok(Value::Boolean(matches!(value, Value::Keyword(_)))),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Returns the name of a keyword, without the `#:`.
+pub fn builtin_keyword_to_string(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [Value::Keyword(name)] => Ok(Value::String(name.clone())),
+        [other] => Err(EvalError::TypeError(format!("Expected keyword, got {}", other.type_name()))),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Returns the keyword with the given name: `(string->keyword "x")` is `#:x`.
+pub fn builtin_string_to_keyword(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [Value::String(name)] => Ok(Value::Keyword(name.clone())),
+        [other] => Err(EvalError::TypeError(format!("Expected string, got {}", other.type_name()))),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Writes a value's `display` form to a port, or to standard output.
+pub fn builtin_display(args: Vec<Value>) -> Result<Value, EvalError> {
+    write_to_port(args, 1, |values| values[0].display_string())
+}
+
+/// Writes a value's `write` form to a port, or to standard output.
+pub fn builtin_write(args: Vec<Value>) -> Result<Value, EvalError> {
+    write_to_port(args, 1, |values| values[0].write_string())
+}
+
+/// Writes a string's characters to a port, or to standard output.
+pub fn builtin_write_string(args: Vec<Value>) -> Result<Value, EvalError> {
+    match args.first() {
+        Some(Value::String(_)) | None => write_to_port(args, 1, |values| values[0].display_string()),
+        Some(_) => Err(EvalError::TypeError("Expected string".into())),
+    }
+}
+
+/// Writes a newline to a port, or to standard output.
+pub fn builtin_newline(args: Vec<Value>) -> Result<Value, EvalError> {
+    write_to_port(args, 0, |_| "\n".to_string())
+}
+
+/// Writes the text made from the first `count` arguments to the port given
+/// as an optional extra argument. Used internally.
+fn write_to_port(args: Vec<Value>, count: usize, text: impl Fn(&[Value]) -> String) -> Result<Value, EvalError> {
+    if args.len() < count || args.len() > count + 1 {
+        return Err(EvalError::ArityMismatch);
+    }
+    let port = match args.get(count) {
+        Some(Value::Port(port)) => port.clone(),
+        Some(_) => return Err(EvalError::TypeError("Expected output port".into())),
+        None => Rc::new(Port::Stdout),
+    };
+    port.write_str(&text(&args[..count]))?;
+    Ok(Value::Unspecified)
+}
+
+/// Returns the standard output port.
+pub fn builtin_current_output_port(args: Vec<Value>) -> Result<Value, EvalError> {
+    if !args.is_empty() {
+        return Err(EvalError::ArityMismatch);
+    }
+    Ok(Value::Port(Rc::new(Port::Stdout)))
+}
+
+/// Returns a new string port that accumulates everything written to it.
+pub fn builtin_open_output_string(args: Vec<Value>) -> Result<Value, EvalError> {
+    if !args.is_empty() {
+        return Err(EvalError::ArityMismatch);
+    }
+    Ok(Value::Port(Rc::new(Port::output_string())))
+}
+
+/// Returns the text written so far to a string port.
+pub fn builtin_get_output_string(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [Value::Port(port)] => port.contents()
+            .map(|text| Value::String(text.into()))
+            .ok_or_else(|| EvalError::TypeError("Expected string port".into())),
+        [_] => Err(EvalError::TypeError("Expected string port".into())),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Returns the standard input port.
+pub fn builtin_current_input_port(args: Vec<Value>) -> Result<Value, EvalError> {
+    if !args.is_empty() {
+        return Err(EvalError::ArityMismatch);
+    }
+    Ok(Value::Port(Rc::new(Port::Stdin)))
+}
+
+/// Returns a port that reads the characters of a string.
+pub fn builtin_open_input_string(args: Vec<Value>) -> Result<Value, EvalError> {
+    match <[Value; 1]>::try_from(args) {
+        Ok([Value::String(ref text)]) => Ok(Value::Port(Rc::new(Port::input_string(text.to_string())))),
+        Ok([other]) => Err(EvalError::TypeError(format!("Expected string, got {}", other.type_name()))),
+        Err(_) => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Returns a port that reads the characters of a text file, read in full
+/// when opened.
+#[cfg(feature = "io")]
+pub fn builtin_open_input_file(args: Vec<Value>) -> Result<Value, EvalError> {
+    let path = match &args[..] {
+        [Value::String(path)] => path,
+        [_] => return Err(EvalError::TypeError("Expected file name".into())),
+        _ => return Err(EvalError::ArityMismatch),
+    };
+    crate::eval::check_effect("open-input-file")?;
+    let text = crate::resolver::read_text_file(path)?;
+    Ok(Value::Port(Rc::new(Port::input_string(text))))
+}
+
+/// Returns the port given as the only argument, or standard input.
+fn input_port(args: &[Value]) -> Result<Rc<Port>, EvalError> {
+    match args {
+        [] => Ok(Rc::new(Port::Stdin)),
+        [Value::Port(port)] => Ok(port.clone()),
+        [_] => Err(EvalError::TypeError("Expected input port".into())),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Reads the next character from a port (standard input by default), or
+/// returns the end-of-file object.
+pub fn builtin_read_char(args: Vec<Value>) -> Result<Value, EvalError> {
+    Ok(input_port(&args)?.read_char()?.map_or(Value::Eof, Value::Char))
+}
+
+/// Like `read-char`, but leaves the character to be read again.
+pub fn builtin_peek_char(args: Vec<Value>) -> Result<Value, EvalError> {
+    Ok(input_port(&args)?.peek_char()?.map_or(Value::Eof, Value::Char))
+}
+
+/// Reads a line from a port (standard input by default), without its line
+/// ending, or returns the end-of-file object.
+pub fn builtin_read_line(args: Vec<Value>) -> Result<Value, EvalError> {
+    Ok(input_port(&args)?.read_line()?.map_or(Value::Eof, |line| Value::String(line.into())))
+}
+
+/// Returns true if a character can be read from a port without waiting,
+/// so interactive programs can poll the console.
+pub fn builtin_is_char_ready(args: Vec<Value>) -> Result<Value, EvalError> {
+    Ok(Value::Boolean(input_port(&args)?.char_ready()?))
+}
+
+/// Returns the end-of-file object.
+pub fn builtin_eof_object(args: Vec<Value>) -> Result<Value, EvalError> {
+    if !args.is_empty() {
+        return Err(EvalError::ArityMismatch);
+    }
+    Ok(Value::Eof)
+}
+
+/// Returns true if the argument is the end-of-file object.
+pub fn builtin_is_eof_object(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [value] => Ok(Value::Boolean(matches!(value, Value::Eof))),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Returns true if the argument is a character.
+pub fn builtin_is_char(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [value] => Ok(Value::Boolean(matches!(value, Value::Char(_)))),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Reads a style argument: a symbol such as `'red`, or a list such as
+/// `'(bold red)`.
+fn style_names(value: &Value) -> Result<Vec<&str>, EvalError> {
+    let symbols = match value {
+        Value::List(items) => items.iter().collect(),
+        other => vec![other],
+    };
+    symbols.into_iter().map(|symbol| match symbol {
+        Value::Symbol(name) => Ok(&**name),
+        other => Err(EvalError::TypeError(format!("Expected style name, got {}", other))),
+    }).collect()
+}
+
+fn unknown_style(name: &str) -> EvalError {
+    EvalError::TypeError(format!("Unknown style: {}", name))
+}
+
+/// `(colorize text style)` returns `text` wrapped in ANSI escapes for the
+/// style, or unchanged when styled output is off (see `color-enabled?`).
+pub fn builtin_colorize(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [Value::String(text), style] => style::paint(text, &style_names(style)?)
+            .map(|text| Value::String(text.into()))
+            .map_err(unknown_style),
+        [_, _] => Err(EvalError::TypeError("Expected string to colorize".into())),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// `(with-style style thunk)` calls `thunk` with the style turned on for
+/// everything it writes to standard output, and turns it off afterwards,
+/// even if `thunk` fails.
+pub fn builtin_with_style(args: Vec<Value>) -> Result<Value, EvalError> {
+    let [style, thunk] = <[Value; 2]>::try_from(args).map_err(|_| EvalError::ArityMismatch)?;
+    let escape = style::escape_for(&style_names(&style)?).map_err(unknown_style)?;
+    if !style::colors_enabled() {
+        return apply(thunk, vec![]);
+    }
+    Port::Stdout.write_str(&escape)?;
+    let result = apply(thunk, vec![]);
+    Port::Stdout.write_str(style::RESET)?;
+    result
+}
+
+/// Returns true if `colorize` and `with-style` produce styled output.
+pub fn builtin_is_color_enabled(args: Vec<Value>) -> Result<Value, EvalError> {
+    if !args.is_empty() {
+        return Err(EvalError::ArityMismatch);
+    }
+    Ok(Value::Boolean(style::colors_enabled()))
+}
+
+/// `(set-color-enabled! on?)` forces styled output on or off, overriding the
+/// check for a terminal.
+pub fn builtin_set_color_enabled(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [value] => {
+            style::set_colors_enabled(Some(*value != Value::Boolean(false)));
+            Ok(Value::Unspecified)
+        }
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Formats arguments according to a template string.
+///
+/// Called as `(format template args...)` or `(format #f template args...)` it
+/// returns the formatted string; `(format #t template args...)` writes it to
+/// standard output instead, and `(format port template args...)` to a port. Supported directives are `~a` (display form),
+/// `~s` (write form), `~d` (number), `~f` (fixed-point number), `~e` (number in
+/// scientific notation), `~%` (newline), and `~~` (a tilde). `~f` and `~e`
+/// take a number of digits after the decimal point, as in `~2f`.
+pub fn builtin_format(args: Vec<Value>) -> Result<Value, EvalError> {
+    let mut args = args.into_iter();
+    let (port, template) = match args.next() {
+        Some(Value::String(ref template)) => (None, template.clone()),
+        Some(destination @ (Value::Boolean(_) | Value::Port(_))) => {
+            let port = match &destination {
+                Value::Boolean(false) => None,
+                Value::Port(port) => Some(port.clone()),
+                _ => Some(Rc::new(Port::Stdout)),
+            };
+            match args.next() {
+                Some(Value::String(ref template)) => (port, template.clone()),
+                _ => return Err(EvalError::TypeError("Expected format string".into())),
+            }
+        }
+        Some(_) => return Err(EvalError::TypeError("Expected format string".into())),
+        None => return Err(EvalError::ArityMismatch),
+    };
+
+    let text = format_template(&template, args)?;
+    match port {
+        Some(port) => {
+            port.write_str(&text)?;
+            Ok(Value::Unspecified)
+        }
+        None => Ok(Value::String(text.into())),
+    }
+}
+
+/// Expands the directives of a `format` template. Used internally.
+fn format_template(template: &str, mut args: impl Iterator<Item = Value>) -> Result<String, EvalError> {
+    let mut out = String::new();
+    let mut chars = template.chars().peekable();
+    let mut next_arg = |directive: char| {
+        args.next().ok_or_else(|| EvalError::Other(format!("format: missing argument for ~{}", directive)))
+    };
+
+    while let Some(c) = chars.next() {
+        if c != '~' {
+            out.push(c);
+            continue;
+        }
+        let mut digits = String::new();
+        while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+            digits.push(digit);
+        }
+        let precision = match digits.parse::<usize>() {
+            Ok(precision) => Some(precision),
+            Err(_) if digits.is_empty() => None,
+            Err(_) => return Err(EvalError::Other(format!("format: too many digits in ~{}", digits))),
+        };
+        match chars.next() {
+            Some(d @ ('f' | 'F')) => out.push_str(&format_fixed(Number::try_from(&next_arg(d)?)?.to_f64(), precision)),
+            Some(d @ ('e' | 'E')) => out.push_str(&format_scientific(Number::try_from(&next_arg(d)?)?.to_f64(), precision)),
+            Some(other) if precision.is_some() => {
+                return Err(EvalError::Other(format!("format: ~{} takes no number of digits", other)));
+            }
+            Some(d @ ('a' | 'A')) => out.push_str(&next_arg(d)?.display_string()),
+            Some(d @ ('s' | 'S')) => out.push_str(&next_arg(d)?.write_string()),
+            Some(d @ ('d' | 'D')) => {
+                let number = Number::try_from(&next_arg(d)?)?;
+                out.push_str(&number.to_string());
+            }
+            Some('%') => out.push('\n'),
+            Some('~') => out.push('~'),
+            Some(other) => return Err(EvalError::Other(format!("format: unknown directive ~{}", other))),
+            None => return Err(EvalError::Other("format: template ends with ~".into())),
+        }
+    }
+
+    if args.next().is_some() {
+        return Err(EvalError::Other("format: too many arguments".into()));
+    }
+    Ok(out)
+}
+
+/// Converts a number to a string, optionally in the given radix (2 to 36).
+/// Inexact numbers can only be written in radix 10.
+///
+/// `(number->string x 'fixed [digits])` writes it in fixed-point notation
+/// with `digits` digits after the decimal point, and
+/// `(number->string x 'scientific [digits])` in scientific notation with
+/// `digits` digits after the mantissa's; without `digits`, as many as it
+/// takes to read back as the same float.
+pub fn builtin_number_to_string(args: Vec<Value>) -> Result<Value, EvalError> {
+    if args.is_empty() || args.len() > 3 {
+        return Err(EvalError::ArityMismatch);
+    }
+
+    let number = Number::try_from(&args[0])?;
+    if let Some(Value::Symbol(notation)) = args.get(1) {
+        let digits = args.get(2).map(extract_precision).transpose()?;
+        let text = match notation.as_ref() {
+            "fixed" => format_fixed(number.to_f64(), digits),
+            "scientific" => format_scientific(number.to_f64(), digits),
+            _ => return Err(EvalError::TypeError(format!("number->string: unknown notation {}, expected fixed or scientific", notation))),
+        };
+        return Ok(Value::String(text.into()));
+    }
+    if args.len() > 2 {
+        return Err(EvalError::ArityMismatch);
+    }
+    let radix = extract_radix(args.get(1))?;
+    Ok(Value::String(number.to_string_radix(radix)?.into()))
+}
+
+/// Reads a number of digits to write after the decimal point. Used
+/// internally.
+fn extract_precision(arg: &Value) -> Result<usize, EvalError> {
+    match arg {
+        Value::Number(n) if *n >= 0 => Ok(*n as usize),
+        _ => Err(EvalError::TypeError(format!("Expected a non-negative number of digits, got {}", arg))),
+    }
+}
+
+/// Parses a string as a number, optionally in the given radix (2 to 36).
+/// Accepts `#x`, `#o`, `#b`, `#d`, `#e`, and `#i` prefixes, fractions, decimals, and exponents.
+/// Returns `#f` if the string is not a valid number.
+pub fn builtin_string_to_number(args: Vec<Value>) -> Result<Value, EvalError> {
+    if args.is_empty() || args.len() > 2 {
+        return Err(EvalError::ArityMismatch);
+    }
+
+    let radix = extract_radix(args.get(1))?;
+    match &args[0] {
+        Value::String(s) => Ok(Number::parse(s, radix).map_or(Value::Boolean(false), Value::from)),
+        _ => Err(EvalError::TypeError("Expected string".into())),
+    }
+}
+
+/// Reads an optional radix argument, defaulting to 10. Used internally.
+fn extract_radix(arg: Option<&Value>) -> Result<u32, EvalError> {
+    match arg {
+        None => Ok(10),
+        Some(Value::Number(r)) if (2..=36).contains(r) => Ok(*r as u32),
+        Some(_) => Err(EvalError::TypeError("Expected radix between 2 and 36".into())),
+    }
+}
+
+/// Returns true if the argument is an exact number.
+pub fn builtin_is_exact(args: Vec<Value>) -> Result<Value, EvalError> {
+    let number = extract_single_number(args)?;
+    Ok(Value::Boolean(number.is_exact()))
+}
+
+/// Returns true if the argument is an inexact number.
+pub fn builtin_is_inexact(args: Vec<Value>) -> Result<Value, EvalError> {
+    let number = extract_single_number(args)?;
+    Ok(Value::Boolean(!number.is_exact()))
+}
+
+/// Returns true if the argument is NaN. Exact numbers never are.
+pub fn builtin_is_nan(args: Vec<Value>) -> Result<Value, EvalError> {
+    let number = extract_single_number(args)?;
+    Ok(Value::Boolean(number.to_f64().is_nan()))
+}
+
+/// Returns true if the argument is `+inf.0` or `-inf.0`.
+pub fn builtin_is_infinite(args: Vec<Value>) -> Result<Value, EvalError> {
+    let number = extract_single_number(args)?;
+    Ok(Value::Boolean(number.to_f64().is_infinite()))
+}
+
+/// Returns true if the argument is neither infinite nor NaN.
+pub fn builtin_is_finite(args: Vec<Value>) -> Result<Value, EvalError> {
+    let number = extract_single_number(args)?;
+    Ok(Value::Boolean(number.to_f64().is_finite()))
+}
+
+/// Converts a number to an exact number. Errors if the value has no exact representation.
+pub fn builtin_exact(args: Vec<Value>) -> Result<Value, EvalError> {
+    let number = extract_single_number(args)?;
+    Ok(number.to_exact()?.into())
+}
+
+/// Converts a number to an inexact number.
+pub fn builtin_inexact(args: Vec<Value>) -> Result<Value, EvalError> {
+    let number = extract_single_number(args)?;
+    Ok(number.to_inexact().into())
+}
+
+/// Returns the largest integer not greater than the argument.
+pub fn builtin_floor(args: Vec<Value>) -> Result<Value, EvalError> {
+    Ok(extract_single_number(args)?.floor().into())
+}
+
+/// Returns the smallest integer not less than the argument.
+pub fn builtin_ceiling(args: Vec<Value>) -> Result<Value, EvalError> {
+    Ok(extract_single_number(args)?.ceiling().into())
+}
+
+/// Returns the closest integer to the argument, rounding to even on ties.
+pub fn builtin_round(args: Vec<Value>) -> Result<Value, EvalError> {
+    Ok(extract_single_number(args)?.round().into())
+}
+
+/// Returns the integer closest to the argument whose magnitude is not larger.
+pub fn builtin_truncate(args: Vec<Value>) -> Result<Value, EvalError> {
+    Ok(extract_single_number(args)?.truncate().into())
+}
+
+/// Extracts the only argument as a number. Used internally.
+fn extract_single_number(args: Vec<Value>) -> Result<Number, EvalError> {
+    match &args[..] {
+        [value] => Number::try_from(value),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Floor division. Returns the quotient rounded toward negative infinity and
+/// the remainder as two values.
+pub fn builtin_floor_div(args: Vec<Value>) -> Result<Value, EvalError> {
+    divide_with_remainder(args, Number::floor)
+}
+
+/// Truncating division. Returns the quotient rounded toward zero and
+/// the remainder as two values.
+pub fn builtin_truncate_div(args: Vec<Value>) -> Result<Value, EvalError> {
+    divide_with_remainder(args, Number::truncate)
+}
+
+/// Shared implementation of `floor/` and `truncate/`. Rationals are rejected;
+/// the result is inexact if either argument is. Used internally.
+fn divide_with_remainder(args: Vec<Value>, rounding: fn(Number) -> Number) -> Result<Value, EvalError> {
+    let (n, d) = match &args[..] {
+        [a, b] => (Number::try_from(a)?, Number::try_from(b)?),
+        _ => return Err(EvalError::ArityMismatch),
+    };
+    if matches!(n, Number::Rational(..)) || matches!(d, Number::Rational(..)) {
+        return Err(EvalError::TypeError("Expected integer".into()));
+    }
+    if d.is_zero() {
+        return Err(EvalError::Other("Division by zero".into()));
+    }
+
+    let q = rounding(n.checked_div(d)?);
+    let r = n.checked_sub(q.checked_mul(d)?)?;
+    Ok(Value::Values([q.into(), r.into()].into()))
+}
+
+/// Returns the largest integer whose square is at most the argument, and
+/// the difference between the argument and that square, as two values.
+pub fn builtin_exact_integer_sqrt(args: Vec<Value>) -> Result<Value, EvalError> {
+    let n = match &args[..] {
+        [Value::Number(n)] if *n >= 0 => *n,
+        [_] => return Err(EvalError::TypeError("exact-integer-sqrt expects a non-negative exact integer".into())),
+        _ => return Err(EvalError::ArityMismatch),
+    };
+    let s = n.isqrt();
+    Ok(Value::Values([Value::Number(s), Value::Number(n - s * s)].into()))
+}
+
+/// Extracts the arguments of a bitwise operation as exact integers. Used internally.
+fn extract_integers(args: &[Value], name: &str) -> Result<Vec<i64>, EvalError> {
+    args.iter().map(|arg| match arg {
+        Value::Number(n) => Ok(*n),
+        _ => Err(EvalError::TypeError(format!("{} expects exact integers", name))),
+    }).collect()
+}
+
+/// Returns the bitwise and of the arguments, in two's complement; -1 with none.
+pub fn builtin_bitwise_and(args: Vec<Value>) -> Result<Value, EvalError> {
+    Ok(Value::Number(extract_integers(&args, "bitwise-and")?.into_iter().fold(-1, |a, b| a & b)))
+}
+
+/// Returns the bitwise inclusive or of the arguments; 0 with none.
+pub fn builtin_bitwise_ior(args: Vec<Value>) -> Result<Value, EvalError> {
+    Ok(Value::Number(extract_integers(&args, "bitwise-ior")?.into_iter().fold(0, |a, b| a | b)))
+}
+
+/// Returns the bitwise exclusive or of the arguments; 0 with none.
+pub fn builtin_bitwise_xor(args: Vec<Value>) -> Result<Value, EvalError> {
+    Ok(Value::Number(extract_integers(&args, "bitwise-xor")?.into_iter().fold(0, |a, b| a ^ b)))
+}
+
+/// Returns the bitwise complement of the argument, which is `(- -1 n)`.
+pub fn builtin_bitwise_not(args: Vec<Value>) -> Result<Value, EvalError> {
+    match extract_integers(&args, "bitwise-not")?[..] {
+        [n] => Ok(Value::Number(!n)),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// `(arithmetic-shift n count)` shifts `n` left by `count` bits, or right
+/// when `count` is negative, rounding toward negative infinity. Shifting
+/// bits out of the integer range is an error.
+pub fn builtin_arithmetic_shift(args: Vec<Value>) -> Result<Value, EvalError> {
+    let (n, count) = match extract_integers(&args, "arithmetic-shift")?[..] {
+        [n, count] => (n, count),
+        _ => return Err(EvalError::ArityMismatch),
+    };
+    if count < 0 {
+        return Ok(Value::Number(n >> count.unsigned_abs().min(63)));
+    }
+    u32::try_from(count).ok()
+        .and_then(|count| n.checked_shl(count))
+        .filter(|shifted| count < 64 && shifted >> count == n)
+        .map(Value::Number)
+        .ok_or_else(|| EvalError::Other("arithmetic-shift: integer overflow".into()))
+}
+
+/// Returns the number of one bits in a non-negative argument, or of zero
+/// bits in a negative one, in two's complement.
+pub fn builtin_bit_count(args: Vec<Value>) -> Result<Value, EvalError> {
+    match extract_integers(&args, "bit-count")?[..] {
+        [n] if n < 0 => Ok(Value::Number(i64::from(n.count_zeros()))),
+        [n] => Ok(Value::Number(i64::from(n.count_ones()))),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Wraps a procedure so that results are cached by arguments, compared with `equal?`.
+pub fn builtin_memoize(args: Vec<Value>) -> Result<Value, EvalError> {
+    match <[Value; 1]>::try_from(args) {
+        Ok([func @ (Value::Function(_) | Value::Lambda(_) | Value::Memoized(_))]) => {
+            let memoized = Rc::new(Memoized::new(func));
+            crate::weak::track_memoized(&memoized);
+            Ok(Value::Memoized(memoized))
+        }
+        Ok(_) => Err(EvalError::TypeError("Expected procedure".into())),
+        Err(_) => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Returns true if the argument was made by `memoize`.
+pub fn builtin_is_memoized(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [value] => Ok(Value::Boolean(matches!(value, Value::Memoized(_)))),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Returns the cache of a memoized procedure as a list of `(args result)` entries.
+pub fn builtin_memo_cache(args: Vec<Value>) -> Result<Value, EvalError> {
+    let entries = extract_memoized(&args)?
+        .entries()
+        .into_iter()
+        .map(|(args, result)| Value::List([Value::List(args.into()), result].into()))
+        .collect();
+    Ok(Value::List(entries))
+}
+
+/// Returns the number of results cached by a memoized procedure.
+pub fn builtin_memo_cache_size(args: Vec<Value>) -> Result<Value, EvalError> {
+    Ok(Value::Number(extract_memoized(&args)?.len() as i64))
+}
+
+/// Empties the cache of a memoized procedure.
+pub fn builtin_memo_clear(args: Vec<Value>) -> Result<Value, EvalError> {
+    extract_memoized(&args)?.clear();
+    Ok(Value::Unspecified)
+}
+
+fn extract_memoized(args: &[Value]) -> Result<&Memoized, EvalError> {
+    match args {
+        [Value::Memoized(m)] => Ok(m),
+        [_] => Err(EvalError::TypeError("Expected memoized procedure".into())),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Forces a promise, returning its (cached) value. Other values are returned as is.
+pub fn builtin_force(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [Value::Promise(promise)] => promise.force(),
+        [value] => Ok(value.clone()),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Returns true if the argument is the empty stream.
+pub fn builtin_is_stream_null(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [value] => Ok(Value::Boolean(matches!(value, Value::List(items) if items.is_empty()))),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Returns true if the argument is a non-empty stream, as built by `cons-stream`.
+pub fn builtin_is_stream_pair(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [value] => Ok(Value::Boolean(matches!(value, Value::List(items) if matches!(items[..], [_, Value::Promise(_)])))),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Returns the first element of a stream.
+pub fn builtin_stream_car(args: Vec<Value>) -> Result<Value, EvalError> {
+    match <[Value; 1]>::try_from(args) {
+        Ok([stream]) => match stream_parts(stream)? {
+            Some((head, _)) => Ok(head),
+            None => Err(EvalError::TypeError("stream-car of empty stream".into())),
+        },
+        Err(_) => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Returns the rest of a stream, forcing it if necessary.
+pub fn builtin_stream_cdr(args: Vec<Value>) -> Result<Value, EvalError> {
+    match <[Value; 1]>::try_from(args) {
+        Ok([stream]) => match stream_parts(stream)? {
+            Some((_, tail)) => tail.force(),
+            None => Err(EvalError::TypeError("stream-cdr of empty stream".into())),
+        },
+        Err(_) => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Lazily applies a procedure to each element of a stream.
+pub fn builtin_stream_map(args: Vec<Value>) -> Result<Value, EvalError> {
+    match <[Value; 2]>::try_from(args) {
+        Ok([func, stream]) => stream_map(func, stream),
+        Err(_) => Err(EvalError::ArityMismatch),
+    }
+}
+
+fn stream_map(func: Value, stream: Value) -> Result<Value, EvalError> {
+    match stream_parts(stream)? {
+        None => Ok(Value::List(Rc::new([]))),
+        Some((head, tail)) => {
+            let head = apply(func.clone(), vec![head])?;
+            Ok(stream_cons(head, move || stream_map(func.clone(), tail.force()?)))
+        }
+    }
+}
+
+/// Lazily keeps the elements of a stream that satisfy a predicate. Finding
+/// each element forces the stream up to it.
+pub fn builtin_stream_filter(args: Vec<Value>) -> Result<Value, EvalError> {
+    match <[Value; 2]>::try_from(args) {
+        Ok([pred, stream]) => stream_filter(pred, stream),
+        Err(_) => Err(EvalError::ArityMismatch),
+    }
+}
+
+fn stream_filter(pred: Value, mut stream: Value) -> Result<Value, EvalError> {
+    while let Some((head, tail)) = stream_parts(stream)? {
+        if apply(pred.clone(), vec![head.clone()])? != Value::Boolean(false) {
+            return Ok(stream_cons(head, move || stream_filter(pred.clone(), tail.force()?)));
+        }
+        stream = tail.force()?;
+    }
+    Ok(Value::List(Rc::new([])))
+}
+
+/// Returns a stream of the first `n` elements of a stream (or all of them, if fewer).
+pub fn builtin_stream_take(args: Vec<Value>) -> Result<Value, EvalError> {
+    match <[Value; 2]>::try_from(args) {
+        Ok([Value::Number(n), stream]) if n >= 0 => stream_take(n, stream),
+        Ok(_) => Err(EvalError::TypeError("Expected non-negative count and stream".into())),
+        Err(_) => Err(EvalError::ArityMismatch),
+    }
+}
+
+fn stream_take(n: i64, stream: Value) -> Result<Value, EvalError> {
+    if n == 0 {
+        return Ok(Value::List(Rc::new([])));
+    }
+    match stream_parts(stream)? {
+        None => Ok(Value::List(Rc::new([]))),
+        Some((head, tail)) => Ok(stream_cons(head, move || stream_take(n - 1, tail.force()?))),
+    }
+}
+
+/// Returns a list of the elements of a finite stream, or of its first `n`
+/// elements if a count is given.
+pub fn builtin_stream_to_list(args: Vec<Value>) -> Result<Value, EvalError> {
+    let mut args = args.into_iter();
+    let (mut stream, limit) = match (args.next(), args.next(), args.next()) {
+        (Some(stream), None, None) => (stream, None),
+        (Some(stream), Some(Value::Number(n)), None) if n >= 0 => (stream, Some(n as usize)),
+        (Some(_), Some(_), None) => return Err(EvalError::TypeError("Expected non-negative count".into())),
+        _ => return Err(EvalError::ArityMismatch),
+    };
+    let mut items = Vec::new();
+    while limit.is_none_or(|n| items.len() < n) {
+        match stream_parts(stream)? {
+            Some((head, tail)) => {
+                items.push(head);
+                stream = tail.force()?;
+            }
+            None => break,
+        }
+    }
+    Ok(Value::List(items.into()))
+}
+
+/// Builds a stream pair whose tail is computed on demand. Used internally.
+fn stream_cons(head: Value, tail: impl Fn() -> Result<Value, EvalError> + 'static) -> Value {
+    Value::List([head, Value::Promise(Rc::new(Promise::new(tail)))].into())
+}
+
+/// Splits a stream into its first element and the promise of the rest, or
+/// returns `None` for the empty stream. Used internally.
+fn stream_parts(stream: Value) -> Result<Option<(Value, Rc<Promise>)>, EvalError> {
+    match &stream {
+        Value::List(items) if items.is_empty() => Ok(None),
+        Value::List(items) => match &items[..] {
+            [head, Value::Promise(tail)] => Ok(Some((head.clone(), tail.clone()))),
+            _ => Err(EvalError::TypeError("Expected stream".into())),
+        },
+        _ => Err(EvalError::TypeError("Expected stream".into())),
+    }
+}
+
+/// Returns its arguments as multiple values. A single argument is returned as is.
+pub fn builtin_values(mut args: Vec<Value>) -> Result<Value, EvalError> {
+    if args.len() == 1 {
+        Ok(args.remove(0))
+    } else {
+        Ok(Value::Values(args.into()))
+    }
+}
+
+/// Calls the producer with no arguments and passes the values it returns to the consumer.
+pub fn builtin_call_with_values(args: Vec<Value>) -> Result<Value, EvalError> {
+    let mut args = args.into_iter();
+    match (args.next(), args.next(), args.next()) {
+        (Some(producer), Some(consumer), None) => {
+            let produced = match apply(producer, vec![])? {
+                Value::Values(ref values) => values.to_vec(),
+                value => vec![value],
+            };
+            apply(consumer, produced)
+        }
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::Value;
+    use crate::testing::{assert_fails, assert_prints};
+
+    #[test]
+    fn test_builtin_add_normal_case() {
+        let args = vec![Value::Number(1), Value::Number(2), Value::Number(3)];
+        let result = builtin_add(args).unwrap();
+        assert_eq!(result, Value::Number(6));
+    }
+
+    #[test]
+    fn test_builtin_add_empty_args() {
+        let args = vec![];
+        let result = builtin_add(args).unwrap();
+        assert_eq!(result, Value::Number(0)); // Sum of no numbers is 0
+    }
+
+    #[test]
+    fn test_builtin_add_type_error() {
+        let args = vec![Value::Number(1), Value::String("bad".into())];
+        let result = builtin_add(args);
+        assert!(matches!(result, Err(EvalError::TypeError(_))));
+    }
+
+    #[test]
+    fn test_builtin_sub_normal_case() {
+        let args = vec![Value::Number(10), Value::Number(3), Value::Number(2)];
+        let result = builtin_sub(args).unwrap();
+        assert_eq!(result, Value::Number(5)); // 10 - 3 - 2
+    }
+
+    #[test]
+    fn test_builtin_sub_single_arg() {
+        let args = vec![Value::Number(5)];
+        let result = builtin_sub(args).unwrap();
+        assert_eq!(result, Value::Number(5)); // single arg, nothing to subtract
+    }
+
+    #[test]
+    fn test_builtin_sub_type_error() {
+        let args = vec![Value::Number(1), Value::Boolean(true)];
+        let result = builtin_sub(args);
+        assert!(matches!(result, Err(EvalError::TypeError(_))));
+    }
+
+    #[test]
+    fn test_builtin_sub_empty_args_should_error() {
+        let args = vec![];
+        let result = builtin_sub(args);
+        assert!(result.is_err()); // panics or returns an error due to remove(0)
+    }
+
+        #[test]
+    fn test_builtin_mul_normal_case() {
+        let args = vec![Value::Number(2), Value::Number(3), Value::Number(4)];
+        let result = builtin_mul(args).unwrap();
+        assert_eq!(result, Value::Number(24));
+    }
+
+    #[test]
+    fn test_builtin_mul_empty_args() {
+        let args = vec![];
+        let result = builtin_mul(args).unwrap();
+        assert_eq!(result, Value::Number(1)); // product of no numbers is 1
+    }
+
+    #[test]
+    fn test_builtin_mul_type_error() {
+        let args = vec![Value::Number(2), Value::Boolean(true)];
+        let result = builtin_mul(args);
+        assert!(matches!(result, Err(EvalError::TypeError(_))));
+    }
+
+    #[test]
+    fn test_builtin_div_normal_case() {
+        let args = vec![Value::Number(20), Value::Number(2), Value::Number(2)];
+        let result = builtin_div(args).unwrap();
+        assert_eq!(result, Value::Number(5)); // 20 / 2 / 2
+    }
+
+    #[test]
+    fn test_builtin_div_divide_by_zero() {
+        let args = vec![Value::Number(10), Value::Number(0)];
+        let result = builtin_div(args);
+        assert!(matches!(result, Err(EvalError::Other(_))));
+    }
+
+    #[test]
+    fn test_builtin_div_empty_args_should_error() {
+        let args = vec![];
+        let result = builtin_div(args);
+        assert!(matches!(result, Err(EvalError::Other(_))));
+    }
+
+    #[test]
+    fn test_builtin_eq_true() {
+        let args = vec![Value::Number(5), Value::Number(5), Value::Number(5)];
+        let result = builtin_eq(args).unwrap();
+        assert_eq!(result, Value::Boolean(true));
+    }
+
+    #[test]
+    fn test_builtin_eq_false() {
+        let args = vec![Value::Number(5), Value::Number(6)];
+        let result = builtin_eq(args).unwrap();
+        assert_eq!(result, Value::Boolean(false));
+    }
+
+    #[test]
+    fn test_builtin_lt_true() {
+        let args = vec![Value::Number(1), Value::Number(2), Value::Number(3)];
+        let result = builtin_lt(args).unwrap();
+        assert_eq!(result, Value::Boolean(true));
+    }
+
+    #[test]
+    fn test_builtin_lt_false() {
+        let args = vec![Value::Number(1), Value::Number(3), Value::Number(2)];
+        let result = builtin_lt(args).unwrap();
+        assert_eq!(result, Value::Boolean(false));
+    }
+
+    #[test]
+    fn test_builtin_gt_true() {
+        let args = vec![Value::Number(5), Value::Number(3), Value::Number(1)];
+        let result = builtin_gt(args).unwrap();
+        assert_eq!(result, Value::Boolean(true));
+    }
+
+    #[test]
+    fn test_builtin_gt_false() {
+        let args = vec![Value::Number(5), Value::Number(6)];
+        let result = builtin_gt(args).unwrap();
+        assert_eq!(result, Value::Boolean(false));
+    }
+
+    #[test]
+    fn test_builtin_and_true() {
+        let args = vec![Value::Boolean(true), Value::Boolean(true)];
+        let result = builtin_and(args).unwrap();
+        assert_eq!(result, Value::Boolean(true));
+    }
+
+    #[test]
+    fn test_builtin_and_false() {
+        let args = vec![Value::Boolean(true), Value::Boolean(false)];
+        let result = builtin_and(args).unwrap();
+        assert_eq!(result, Value::Boolean(false));
+    }
+
+    #[test]
+    fn test_builtin_and_type_error() {
+        let args = vec![Value::Boolean(true), Value::Number(1)];
+        let result = builtin_and(args);
+        assert!(matches!(result, Err(EvalError::TypeError(_))));
+    }
+
+    #[test]
+    fn test_builtin_or_true() {
+        let args = vec![Value::Boolean(false), Value::Boolean(true)];
+        let result = builtin_or(args).unwrap();
+        assert_eq!(result, Value::Boolean(true));
+    }
+
+    #[test]
+    fn test_builtin_or_false() {
+        let args = vec![Value::Boolean(false), Value::Boolean(false)];
+        let result = builtin_or(args).unwrap();
+        assert_eq!(result, Value::Boolean(false));
+    }
+
+    #[test]
+    fn test_builtin_or_type_error() {
+        let args = vec![Value::Boolean(false), Value::Number(42)];
+        let result = builtin_or(args);
+        assert!(matches!(result, Err(EvalError::TypeError(_))));
+    }
+
+    #[test]
+    fn test_builtin_not_true() {
+        let args = vec![Value::Boolean(true)];
+        let result = builtin_not(args).unwrap();
+        assert_eq!(result, Value::Boolean(false));
+    }
+
+    #[test]
+    fn test_builtin_not_false() {
+        let args = vec![Value::Boolean(false)];
+        let result = builtin_not(args).unwrap();
+        assert_eq!(result, Value::Boolean(true));
+    }
+
+    #[test]
+    fn test_builtin_not_wrong_arity() {
+        let args = vec![];
+        let result = builtin_not(args);
+        assert!(matches!(result, Err(EvalError::ArityMismatch)));
+    }
+
+    #[test]
+    fn test_builtin_list_basic() {
+        let args = vec![Value::Number(1), Value::Boolean(true)];
+        let result = builtin_list(args.clone()).unwrap();
+        assert_eq!(result, Value::List(args.into()));
+    }
+
+    #[test]
+    fn test_builtin_car_success() {
+        let args = vec![Value::List(Rc::new([Value::Number(42), Value::Boolean(false)]))];
+        let result = builtin_car(args).unwrap();
+        assert_eq!(result, Value::Number(42));
+    }
+
+    #[test]
+    fn test_builtin_car_empty_list_error() {
+        let args = vec![Value::List(Rc::new([]))];
+        let result = builtin_car(args);
+        assert!(matches!(result, Err(EvalError::TypeError(_))));
+    }
+
+    #[test]
+    fn test_builtin_cdr_success() {
+        let args = vec![Value::List(Rc::new([Value::Number(42), Value::Boolean(false)]))];
+        let result = builtin_cdr(args).unwrap();
+        assert_eq!(result, Value::List(Rc::new([Value::Boolean(false)])));
+    }
+
+    #[test]
+    fn test_builtin_cdr_empty_list_error() {
+        let args = vec![Value::List(Rc::new([]))];
+        let result = builtin_cdr(args);
+        assert!(matches!(result, Err(EvalError::TypeError(_))));
+    }
+
+    #[test]
+    fn test_car_cdr_compositions() {
+        let datum = crate::parser::parse(crate::lexer::tokenize("((1 2) 3 (4 5) 6)").unwrap()).unwrap();
+        let nested = || vec![crate::eval::quote_expr(&datum)];
+        assert_eq!(builtin_caar(nested()).unwrap(), Value::Number(1));
+        assert_eq!(builtin_cadr(nested()).unwrap(), Value::Number(3));
+        assert_eq!(builtin_cddr(nested()).unwrap().to_string(), "((4 5) 6)");
+        assert_eq!(builtin_caddr(nested()).unwrap().to_string(), "(4 5)");
+        assert_eq!(builtin_cadddr(nested()).unwrap(), Value::Number(6));
+        assert_eq!(builtin_cdaddr(nested()).unwrap().to_string(), "(5)");
+        assert!(matches!(builtin_cddddr(vec![Value::List(Rc::new([Value::Number(1)]))]), Err(EvalError::TypeError(m)) if m == "cddddr: list is too short"));
+        assert_eq!(CAR_CDR_COMPOSITIONS.len(), 28);
+    }
+
+    #[test]
+    fn test_list_predicates_and_length() {
+        let list = Value::List(Rc::new([Value::Number(1), Value::List(Rc::new([]))]));
+        assert_eq!(builtin_is_list(vec![list.clone()]).unwrap(), Value::Boolean(true));
+        assert_eq!(builtin_is_list(vec![Value::List(Rc::new([]))]).unwrap(), Value::Boolean(true));
+        assert_eq!(builtin_is_list(vec![Value::Vector(Rc::new(RefCell::new(vec![])))]).unwrap(), Value::Boolean(false));
+        assert_eq!(builtin_length(vec![list]).unwrap(), Value::Number(2));
+        assert!(matches!(builtin_length(vec![Value::Number(1)]), Err(EvalError::TypeError(m)) if m == "length: expected a list, got number"));
+        assert_prints("(list (proper-list? (list 1)) (proper-list? (cons 1 '())) (proper-list? (vector 1)))", "(#t #t #f)");
+    }
+
+    #[test]
+    fn test_append_and_reverse() {
+        assert_prints("(define xs (list 1 2 3)) (list (reverse xs) (append xs (list) (list 4)) (append) xs)",
+            "((3 2 1) (1 2 3 4) () (1 2 3))");
+        assert_fails("(reverse 1)", "type");
+    }
+
+    #[test]
+    fn test_builtin_cons_success() {
+        let args = vec![
+            Value::Number(1),
+            Value::List(Rc::new([Value::Number(2), Value::Number(3)])),
+        ];
+        let result = builtin_cons(args).unwrap();
+        assert_eq!(
+            result,
+            Value::List(Rc::new([Value::Number(1), Value::Number(2), Value::Number(3)]))
+        );
+    }
+
+    #[test]
+    fn test_builtin_cons_type_error() {
+        let args = vec![Value::Number(1), Value::Number(2)];
+        let result = builtin_cons(args);
+        assert!(matches!(result, Err(EvalError::TypeError(_))));
+    }
+
+    #[test]
+    fn test_builtin_number_to_string_decimal() {
+        let args = vec![Value::Number(-42)];
+        let result = builtin_number_to_string(args).unwrap();
+        assert_eq!(result, Value::String("-42".into()));
+    }
+
+    #[test]
+    fn test_builtin_number_to_string_radix() {
+        let args = vec![Value::Number(255), Value::Number(16)];
+        let result = builtin_number_to_string(args).unwrap();
+        assert_eq!(result, Value::String("ff".into()));
+    }
+
+    #[test]
+    fn test_builtin_number_to_string_float() {
+        let args = vec![Value::Float(1.5)];
+        let result = builtin_number_to_string(args).unwrap();
+        assert_eq!(result, Value::String("1.5".into()));
+    }
+
+    #[test]
+    fn test_builtin_number_to_string_notations() {
+        let text = |args: Vec<Value>| builtin_number_to_string(args).unwrap().display_string();
+        let notation = |name: &str| Value::Symbol(name.into());
+        assert_eq!(text(vec![Value::Float(1.23456), notation("fixed"), Value::Number(2)]), "1.23");
+        assert_eq!(text(vec![Value::Float(1e21), notation("fixed")]), "1000000000000000000000.0");
+        assert_eq!(text(vec![Value::Number(1234), notation("scientific"), Value::Number(1)]), "1.2e3");
+        assert_eq!(text(vec![Value::Float(0.00025), notation("scientific")]), "2.5e-4");
+        let result = builtin_number_to_string(vec![Value::Float(1.0), notation("octal")]);
+        assert!(matches!(result, Err(EvalError::TypeError(_))));
+        let result = builtin_number_to_string(vec![Value::Float(1.0), notation("fixed"), Value::Number(-1)]);
+        assert!(matches!(result, Err(EvalError::TypeError(_))));
+    }
+
+    #[test]
+    fn test_builtin_number_to_string_bad_radix() {
+        let args = vec![Value::Number(10), Value::Number(1)];
+        let result = builtin_number_to_string(args);
+        assert!(matches!(result, Err(EvalError::TypeError(_))));
+    }
+
+    #[test]
+    fn test_builtin_string_to_number_integer() {
+        let args = vec![Value::String("-17".into())];
+        let result = builtin_string_to_number(args).unwrap();
+        assert_eq!(result, Value::Number(-17));
+    }
+
+    #[test]
+    fn test_builtin_string_to_number_exponent() {
+        let args = vec![Value::String("1e-3".into())];
+        let result = builtin_string_to_number(args).unwrap();
+        assert_eq!(result, Value::Float(0.001));
+    }
+
+    #[test]
+    fn test_builtin_string_to_number_radix_prefix() {
+        let args = vec![Value::String("#xff".into())];
+        let result = builtin_string_to_number(args).unwrap();
+        assert_eq!(result, Value::Number(255));
+    }
+
+    #[test]
+    fn test_builtin_string_to_number_radix_argument() {
+        let args = vec![Value::String("101".into()), Value::Number(2)];
+        let result = builtin_string_to_number(args).unwrap();
+        assert_eq!(result, Value::Number(5));
+    }
+
+    #[test]
+    fn test_builtin_string_to_number_unparsable() {
+        for text in ["abc", "", "-", "1.2.3", "1e", "inf", "#x", "#q1"] {
+            let args = vec![Value::String(text.into())];
+            let result = builtin_string_to_number(args).unwrap();
+            assert_eq!(result, Value::Boolean(false), "{:?} should not parse", text);
+        }
+    }
+
+    #[test]
+    fn test_builtin_string_to_number_type_error() {
+        let args = vec![Value::Number(1)];
+        let result = builtin_string_to_number(args);
+        assert!(matches!(result, Err(EvalError::TypeError(_))));
+    }
+
+    #[test]
+    fn test_builtin_string_to_number_exactness_prefix() {
+        let exact = builtin_string_to_number(vec![Value::String("#e2.0".into())]).unwrap();
+        let inexact = builtin_string_to_number(vec![Value::String("#i#x10".into())]).unwrap();
+        assert_eq!(exact, Value::Number(2));
+        assert_eq!(inexact, Value::Float(16.0));
+    }
+
+    #[test]
+    fn test_builtin_is_exact() {
+        assert_eq!(builtin_is_exact(vec![Value::Number(1)]).unwrap(), Value::Boolean(true));
+        assert_eq!(builtin_is_exact(vec![Value::Float(1.0)]).unwrap(), Value::Boolean(false));
+    }
+
+    #[test]
+    fn test_special_float_values() {
+        assert_prints("
+            (define inf (/ 1.0 0.0))
+            (define nan (- inf inf))
+            (list inf (- 0 inf) nan (nan? nan) (nan? 1) (infinite? -inf.0) (infinite? (/ 1 2))
+                  (finite? 1.5) (finite? +nan.0) (= nan nan) (< nan 1) (> nan 1)
+                  (< 1 +inf.0) (max 1 nan) (min -inf.0 2) (equal? nan nan))",
+            "(+inf.0 -inf.0 +nan.0 #t #f #t #f #t #f #f #f #f #t +nan.0 -inf.0 #t)");
+        assert_prints("(list (string->number \"-inf.0\") (number->string +nan.0))", "(-inf.0 \"+nan.0\")");
+        assert_fails("(exact +inf.0)", "error");
+        assert_fails("(nan? 'a)", "type");
+    }
+
+    #[test]
+    fn test_builtin_is_inexact_type_error() {
+        let result = builtin_is_inexact(vec![Value::String("1".into())]);
+        assert!(matches!(result, Err(EvalError::TypeError(_))));
+    }
+
+    #[test]
+    fn test_builtin_exact_integral_float() {
+        let result = builtin_exact(vec![Value::Float(-3.0)]).unwrap();
+        assert_eq!(result, Value::Number(-3));
+    }
+
+    #[test]
+    fn test_builtin_exact_rejects_infinity() {
+        let result = builtin_exact(vec![Value::Float(f64::INFINITY)]);
+        assert!(matches!(result, Err(EvalError::Other(_))));
+    }
+
+    #[test]
+    fn test_builtin_inexact() {
+        let result = builtin_inexact(vec![Value::Number(7)]).unwrap();
+        assert_eq!(result, Value::Float(7.0));
+    }
+
+    #[test]
+    fn test_builtin_rounding() {
+        let x = || vec![Value::Float(-2.5)];
+        assert_eq!(builtin_floor(x()).unwrap(), Value::Float(-3.0));
+        assert_eq!(builtin_ceiling(x()).unwrap(), Value::Float(-2.0));
+        assert_eq!(builtin_round(x()).unwrap(), Value::Float(-2.0));
+        assert_eq!(builtin_truncate(x()).unwrap(), Value::Float(-2.0));
+        assert_eq!(builtin_round(vec![Value::Number(5)]).unwrap(), Value::Number(5));
+    }
+
+    #[test]
+    fn test_builtin_floor_div() {
+        let args = vec![Value::Number(-7), Value::Number(2)];
+        let result = builtin_floor_div(args).unwrap();
+        assert_eq!(result, Value::Values(Rc::new([Value::Number(-4), Value::Number(1)])));
     }
 
     #[test]
-    fn test_builtin_car_empty_list_error() {
-        let args = vec![Value::List(vec![])];
-        let result = builtin_car(args);
+    fn test_builtin_truncate_div() {
+        let args = vec![Value::Number(-7), Value::Number(2)];
+        let result = builtin_truncate_div(args).unwrap();
+        assert_eq!(result, Value::Values(Rc::new([Value::Number(-3), Value::Number(-1)])));
+    }
+
+    #[test]
+    fn test_builtin_floor_div_inexact() {
+        let args = vec![Value::Float(7.5), Value::Number(-2)];
+        let result = builtin_floor_div(args).unwrap();
+        assert_eq!(result, Value::Values(Rc::new([Value::Float(-4.0), Value::Float(-0.5)])));
+    }
+
+    #[test]
+    fn test_builtin_exact_integer_sqrt() {
+        let result = builtin_exact_integer_sqrt(vec![Value::Number(17)]).unwrap();
+        assert_eq!(result, Value::Values(Rc::new([Value::Number(4), Value::Number(1)])));
+        let result = builtin_exact_integer_sqrt(vec![Value::Number(i64::MAX)]).unwrap();
+        assert_eq!(result, Value::Values(Rc::new([Value::Number(3037000499), Value::Number(5928526806)])));
+        assert!(matches!(builtin_exact_integer_sqrt(vec![Value::Number(-4)]), Err(EvalError::TypeError(_))));
+        assert!(matches!(builtin_exact_integer_sqrt(vec![Value::Float(4.0)]), Err(EvalError::TypeError(_))));
+    }
+
+    #[test]
+    fn test_builtin_bitwise_operators() {
+        let n = |values: &[i64]| values.iter().map(|&v| Value::Number(v)).collect::<Vec<_>>();
+        assert_eq!(builtin_bitwise_and(n(&[12, 10])).unwrap(), Value::Number(8));
+        assert_eq!(builtin_bitwise_and(n(&[])).unwrap(), Value::Number(-1));
+        assert_eq!(builtin_bitwise_ior(n(&[12, 10, 1])).unwrap(), Value::Number(15));
+        assert_eq!(builtin_bitwise_xor(n(&[12, 10])).unwrap(), Value::Number(6));
+        assert_eq!(builtin_bitwise_not(n(&[5])).unwrap(), Value::Number(-6));
+        assert_eq!(builtin_bit_count(n(&[13])).unwrap(), Value::Number(3));
+        assert_eq!(builtin_bit_count(n(&[-1])).unwrap(), Value::Number(0));
+        assert!(matches!(builtin_bitwise_and(vec![Value::Float(1.0)]), Err(EvalError::TypeError(_))));
+    }
+
+    #[test]
+    fn test_builtin_arithmetic_shift() {
+        let n = |values: &[i64]| values.iter().map(|&v| Value::Number(v)).collect::<Vec<_>>();
+        assert_eq!(builtin_arithmetic_shift(n(&[3, 4])).unwrap(), Value::Number(48));
+        assert_eq!(builtin_arithmetic_shift(n(&[-7, -1])).unwrap(), Value::Number(-4));
+        assert_eq!(builtin_arithmetic_shift(n(&[5, -100])).unwrap(), Value::Number(0));
+        assert_eq!(builtin_arithmetic_shift(n(&[-5, -100])).unwrap(), Value::Number(-1));
+        assert_eq!(builtin_arithmetic_shift(n(&[-1, 63])).unwrap(), Value::Number(i64::MIN));
+        assert!(matches!(builtin_arithmetic_shift(n(&[1, 63])), Err(EvalError::Other(_))));
+        assert!(matches!(builtin_arithmetic_shift(n(&[1, 64])), Err(EvalError::Other(_))));
+    }
+
+    #[test]
+    fn test_builtin_floor_div_by_zero() {
+        let args = vec![Value::Number(1), Value::Number(0)];
+        let result = builtin_floor_div(args);
+        assert!(matches!(result, Err(EvalError::Other(_))));
+    }
+
+    #[test]
+    fn test_builtin_values_single() {
+        let result = builtin_values(vec![Value::Number(1)]).unwrap();
+        assert_eq!(result, Value::Number(1));
+    }
+
+    #[test]
+    fn test_builtin_values_multiple() {
+        let result = builtin_values(vec![Value::Number(1), Value::Number(2)]).unwrap();
+        assert_eq!(result, Value::Values(Rc::new([Value::Number(1), Value::Number(2)])));
+    }
+
+    #[test]
+    fn test_builtin_add_mixed_exactness() {
+        let args = vec![Value::Number(1), Value::Float(0.5)];
+        let result = builtin_add(args).unwrap();
+        assert_eq!(result, Value::Float(1.5));
+    }
+
+    #[test]
+    fn test_builtin_add_rationals() {
+        let args = vec![Value::Rational(1, 3), Value::Rational(2, 3)];
+        let result = builtin_add(args).unwrap();
+        assert_eq!(result, Value::Number(1));
+    }
+
+    #[test]
+    fn test_builtin_div_produces_rational() {
+        let args = vec![Value::Number(3), Value::Number(6)];
+        let result = builtin_div(args).unwrap();
+        assert_eq!(result, Value::Rational(1, 2));
+    }
+
+    #[test]
+    fn test_builtin_div_inexact_by_zero() {
+        let args = vec![Value::Number(1), Value::Float(0.0)];
+        let result = builtin_div(args).unwrap();
+        assert_eq!(result, Value::Float(f64::INFINITY));
+    }
+
+    #[test]
+    fn test_builtin_eq_across_exactness() {
+        let args = vec![Value::Number(1), Value::Float(1.0), Value::Rational(2, 2)];
+        let result = builtin_eq(args).unwrap();
+        assert_eq!(result, Value::Boolean(true));
+    }
+
+    #[test]
+    fn test_builtin_eq_type_error() {
+        let args = vec![Value::String("a".into()), Value::String("a".into())];
+        let result = builtin_eq(args);
         assert!(matches!(result, Err(EvalError::TypeError(_))));
     }
 
     #[test]
-    fn test_builtin_cdr_success() {
-        let args = vec![Value::List(vec![Value::Number(42), Value::Boolean(false)])];
-        let result = builtin_cdr(args).unwrap();
-        assert_eq!(result, Value::List(vec![Value::Boolean(false)]));
+    fn test_builtin_le_and_ge() {
+        let args = || vec![Value::Number(1), Value::Float(1.0), Value::Rational(3, 2)];
+        assert_eq!(builtin_le(args()).unwrap(), Value::Boolean(true));
+        assert_eq!(builtin_ge(args()).unwrap(), Value::Boolean(false));
     }
 
     #[test]
-    fn test_builtin_cdr_empty_list_error() {
-        let args = vec![Value::List(vec![])];
-        let result = builtin_cdr(args);
+    fn test_builtin_min_exact() {
+        let args = vec![Value::Number(3), Value::Rational(1, 2), Value::Number(2)];
+        let result = builtin_min(args).unwrap();
+        assert_eq!(result, Value::Rational(1, 2));
+    }
+
+    #[test]
+    fn test_builtin_max_inexact_contagion() {
+        let args = vec![Value::Number(3), Value::Float(1.5)];
+        let result = builtin_max(args).unwrap();
+        assert_eq!(result, Value::Float(3.0));
+    }
+
+    #[test]
+    fn test_builtin_min_empty_args_should_error() {
+        let result = builtin_min(vec![]);
+        assert!(matches!(result, Err(EvalError::Other(_))));
+    }
+
+    #[test]
+    fn test_builtin_floor_div_rejects_rational() {
+        let args = vec![Value::Rational(1, 2), Value::Number(2)];
+        let result = builtin_floor_div(args);
         assert!(matches!(result, Err(EvalError::TypeError(_))));
     }
 
     #[test]
-    fn test_builtin_cons_success() {
+    fn test_builtin_equal_nested_lists() {
+        let list = || Value::List(Rc::new([Value::Number(1), Value::List(Rc::new([Value::String("a".into())]))]));
+        let result = builtin_equal(vec![list(), list()]).unwrap();
+        assert_eq!(result, Value::Boolean(true));
+    }
+
+    #[test]
+    fn test_builtin_equal_distinguishes_exactness() {
+        let result = builtin_equal(vec![Value::Number(1), Value::Float(1.0)]).unwrap();
+        assert_eq!(result, Value::Boolean(false));
+    }
+
+    #[test]
+    fn test_builtin_equal_wrong_arity() {
+        let result = builtin_equal(vec![Value::Number(1)]);
+        assert!(matches!(result, Err(EvalError::ArityMismatch)));
+    }
+
+    #[test]
+    fn test_builtin_make_vector_with_fill() {
+        let result = builtin_make_vector(vec![Value::Number(2), Value::Number(7)]).unwrap();
+        assert_eq!(result, builtin_vector(vec![Value::Number(7), Value::Number(7)]).unwrap());
+    }
+
+    #[test]
+    fn test_builtin_make_vector_negative_length() {
+        let result = builtin_make_vector(vec![Value::Number(-1)]);
+        assert!(matches!(result, Err(EvalError::TypeError(_))));
+    }
+
+    #[test]
+    fn test_builtin_vector_ref() {
+        let vector = builtin_vector(vec![Value::Number(1), Value::Number(2)]).unwrap();
+        let result = builtin_vector_ref(vec![vector, Value::Number(1)]).unwrap();
+        assert_eq!(result, Value::Number(2));
+    }
+
+    #[test]
+    fn test_builtin_vector_ref_out_of_range() {
+        let vector = builtin_vector(vec![Value::Number(1)]).unwrap();
+        let result = builtin_vector_ref(vec![vector, Value::Number(1)]);
+        assert!(matches!(result, Err(EvalError::Other(_))));
+    }
+
+    #[test]
+    fn test_builtin_vector_set_is_visible_through_aliases() {
+        let vector = builtin_vector(vec![Value::Number(1)]).unwrap();
+        builtin_vector_set(vec![vector.clone(), Value::Number(0), Value::Number(9)]).unwrap();
+        let result = builtin_vector_to_list(vec![vector]).unwrap();
+        assert_eq!(result, Value::List(Rc::new([Value::Number(9)])));
+    }
+
+    #[test]
+    fn test_builtin_list_to_vector_type_error() {
+        let result = builtin_list_to_vector(vec![Value::Number(1)]);
+        assert!(matches!(result, Err(EvalError::TypeError(_))));
+    }
+
+    #[test]
+    fn test_builtin_equal_circular_vectors() {
+        let make = || {
+            let vector = builtin_vector(vec![Value::Number(1)]).unwrap();
+            builtin_vector_set(vec![vector.clone(), Value::Number(0), vector.clone()]).unwrap();
+            vector
+        };
+        let result = builtin_equal(vec![make(), make()]).unwrap();
+        assert_eq!(result, Value::Boolean(true));
+    }
+
+    #[test]
+    fn test_builtin_map_builtin_function() {
         let args = vec![
-            Value::Number(1),
-            Value::List(vec![Value::Number(2), Value::Number(3)]),
+            Value::Function(builtin_add),
+            Value::List(Rc::new([Value::Number(1), Value::Number(2)])),
+            Value::List(Rc::new([Value::Number(10), Value::Number(20)])),
         ];
-        let result = builtin_cons(args).unwrap();
-        assert_eq!(
-            result,
-            Value::List(vec![Value::Number(1), Value::Number(2), Value::Number(3)])
-        );
+        let result = builtin_map(args).unwrap();
+        assert_eq!(result, Value::List(Rc::new([Value::Number(11), Value::Number(22)])));
     }
 
     #[test]
-    fn test_builtin_cons_type_error() {
-        let args = vec![Value::Number(1), Value::Number(2)];
-        let result = builtin_cons(args);
+    fn test_builtin_map_requires_a_list() {
+        let result = builtin_map(vec![Value::Function(builtin_add)]);
+        assert!(matches!(result, Err(EvalError::ArityMismatch)));
+    }
+
+    #[test]
+    fn test_builtin_for_each_type_error() {
+        let args = vec![Value::Function(builtin_add), Value::Number(1)];
+        let result = builtin_for_each(args);
+        assert!(matches!(result, Err(EvalError::TypeError(_))));
+    }
+
+    #[test]
+    fn test_builtin_to_string_uses_display_form() {
+        let args = vec![Value::List(Rc::new([Value::String("a".into()), Value::Rational(1, 2)]))];
+        let result = builtin_to_string(args).unwrap();
+        assert_eq!(result, Value::String("(a 1/2)".into()));
+    }
+
+    #[test]
+    fn test_builtin_to_string_procedures() {
+        let result = builtin_to_string(vec![Value::Function(builtin_add)]).unwrap();
+        assert_eq!(result, Value::String("<builtin-function>".into()));
+    }
+
+    #[test]
+    fn test_builtin_string_append() {
+        let args = vec![Value::String("foo".into()), Value::String("".into()), Value::String("bar".into())];
+        let result = builtin_string_append(args).unwrap();
+        assert_eq!(result, Value::String("foobar".into()));
+    }
+
+    #[test]
+    fn test_builtin_string_append_type_error() {
+        let args = vec![Value::String("foo".into()), Value::Number(1)];
+        let result = builtin_string_append(args);
+        assert!(matches!(result, Err(EvalError::TypeError(_))));
+    }
+
+    #[test]
+    fn test_builtin_string_length_counts_characters() {
+        let s = |text: &str| Value::String(text.into());
+        // An emoji is one scalar value; "é" written with a combining accent is two.
+        assert_eq!(builtin_string_length(vec![s("a😀b")]).unwrap(), Value::Number(3));
+        assert_eq!(builtin_string_length(vec![s("e\u{301}")]).unwrap(), Value::Number(2));
+        assert_eq!(builtin_string_ref(vec![s("a😀b"), Value::Number(2)]).unwrap(), Value::Char('b'));
+        assert_eq!(builtin_string_ref(vec![s("e\u{301}"), Value::Number(1)]).unwrap(), Value::Char('\u{301}'));
+        assert!(matches!(builtin_string_ref(vec![s("😀"), Value::Number(1)]), Err(EvalError::Other(_))));
+        assert_eq!(builtin_substring(vec![s("naïve 😀!"), Value::Number(2), Value::Number(7)]).unwrap(), s("ïve 😀"));
+        assert_eq!(builtin_substring(vec![s("naïve"), Value::Number(3)]).unwrap(), s("ve"));
+        assert!(matches!(builtin_substring(vec![s("abc"), Value::Number(2), Value::Number(1)]), Err(EvalError::Other(_))));
+    }
+
+    #[test]
+    fn test_builtin_string_case_is_unicode_aware() {
+        let s = |text: &str| Value::String(text.into());
+        assert_eq!(builtin_string_upcase(vec![s("straße ñ")]).unwrap(), s("STRASSE Ñ"));
+        assert_eq!(builtin_string_downcase(vec![s("ÀΣ")]).unwrap(), s("àς"));
+        let chars = builtin_string_to_list(vec![s("ü😀")]).unwrap();
+        assert_eq!(chars, Value::List(Rc::new([Value::Char('ü'), Value::Char('😀')])));
+        assert_eq!(builtin_list_to_string(vec![chars]).unwrap(), s("ü😀"));
+        assert!(matches!(builtin_string_upcase(vec![Value::Number(1)]), Err(EvalError::TypeError(_))));
+    }
+
+    #[test]
+    fn test_builtin_format_directives() {
+        let args = vec![
+            Value::String("~a and ~s: ~d~%~~".into()),
+            Value::String("shown".into()),
+            Value::String("written".into()),
+            Value::Rational(3, 4),
+        ];
+        let result = builtin_format(args).unwrap();
+        assert_eq!(result, Value::String("shown and \"written\": 3/4\n~".into()));
+    }
+
+    #[test]
+    fn test_builtin_format_float_directives() {
+        let args = vec![
+            Value::String("~2f ~f ~e ~3E ~0f".into()),
+            Value::Float(1.23456),
+            Value::Number(2),
+            Value::Float(6.022e23),
+            Value::Rational(1, 3),
+            Value::Float(2.75),
+        ];
+        let result = builtin_format(args).unwrap();
+        assert_eq!(result, Value::String("1.23 2.0 6.022e23 3.333e-1 3".into()));
+        let args = vec![Value::String("~2a".into()), Value::Number(1)];
+        assert!(matches!(builtin_format(args), Err(EvalError::Other(_))));
+    }
+
+    #[test]
+    fn test_builtin_format_false_destination_returns_string() {
+        let args = vec![Value::Boolean(false), Value::String("~a!".into()), Value::Number(1)];
+        let result = builtin_format(args).unwrap();
+        assert_eq!(result, Value::String("1!".into()));
+    }
+
+    #[test]
+    fn test_builtin_format_missing_argument() {
+        let args = vec![Value::String("~a ~a".into()), Value::Number(1)];
+        let result = builtin_format(args);
+        assert!(matches!(result, Err(EvalError::Other(_))));
+    }
+
+    #[test]
+    fn test_builtin_format_too_many_arguments() {
+        let args = vec![Value::String("~a".into()), Value::Number(1), Value::Number(2)];
+        let result = builtin_format(args);
+        assert!(matches!(result, Err(EvalError::Other(_))));
+    }
+
+    #[test]
+    fn test_builtin_format_unknown_directive() {
+        let args = vec![Value::String("~q".into())];
+        let result = builtin_format(args);
+        assert!(matches!(result, Err(EvalError::Other(_))));
+    }
+
+    #[test]
+    fn test_builtin_format_d_requires_number() {
+        let args = vec![Value::String("~d".into()), Value::String("1".into())];
+        let result = builtin_format(args);
+        assert!(matches!(result, Err(EvalError::TypeError(_))));
+    }
+
+    fn countdown(n: i64) -> Value {
+        if n == 0 {
+            return Value::List(Rc::new([]));
+        }
+        stream_cons(Value::Number(n), move || Ok(countdown(n - 1)))
+    }
+
+    #[test]
+    fn test_builtin_stream_to_list() {
+        let result = builtin_stream_to_list(vec![countdown(3)]).unwrap();
+        assert_eq!(result, Value::List(Rc::new([Value::Number(3), Value::Number(2), Value::Number(1)])));
+        let result = builtin_stream_to_list(vec![countdown(3), Value::Number(1)]).unwrap();
+        assert_eq!(result, Value::List(Rc::new([Value::Number(3)])));
+    }
+
+    #[test]
+    fn test_builtin_stream_take_more_than_available() {
+        let taken = builtin_stream_take(vec![Value::Number(10), countdown(2)]).unwrap();
+        let result = builtin_stream_to_list(vec![taken]).unwrap();
+        assert_eq!(result, Value::List(Rc::new([Value::Number(2), Value::Number(1)])));
+    }
+
+    #[test]
+    fn test_builtin_force_non_promise() {
+        assert_eq!(builtin_force(vec![Value::Number(7)]).unwrap(), Value::Number(7));
+    }
+
+    #[test]
+    fn test_promise_retries_after_error() {
+        let attempts = Rc::new(RefCell::new(0));
+        let counter = attempts.clone();
+        let promise = Promise::new(move || {
+            *counter.borrow_mut() += 1;
+            match *counter.borrow() {
+                1 => Err(EvalError::Other("first attempt fails".into())),
+                n => Ok(Value::Number(n)),
+            }
+        });
+        assert!(promise.force().is_err());
+        assert_eq!(promise.force().unwrap(), Value::Number(2));
+        assert_eq!(promise.force().unwrap(), Value::Number(2));
+        assert_eq!(*attempts.borrow(), 2);
+    }
+
+    #[test]
+    fn test_builtin_memoize_caches_by_equal_arguments() {
+        let memo = builtin_memoize(vec![Value::Function(builtin_list)]).unwrap();
+        let key = || vec![Value::List(Rc::new([Value::Number(1)])), Value::String("a".into())];
+        let first = apply(memo.clone(), key()).unwrap();
+        let second = apply(memo.clone(), key()).unwrap();
+        assert_eq!(first, second);
+        assert_eq!(builtin_memo_cache_size(vec![memo.clone()]).unwrap(), Value::Number(1));
+        builtin_memo_clear(vec![memo.clone()]).unwrap();
+        assert_eq!(builtin_memo_cache(vec![memo]).unwrap(), Value::List(Rc::new([])));
+    }
+
+    #[test]
+    fn test_builtin_memoize_rejects_non_procedures() {
+        let result = builtin_memoize(vec![Value::Number(1)]);
+        assert!(matches!(result, Err(EvalError::TypeError(_))));
+    }
+
+    #[test]
+    fn test_builtin_hash_table_set_and_ref() {
+        let table = builtin_make_hash_table(vec![]).unwrap();
+        let key = || Value::List(Rc::new([Value::Number(1), Value::String("a".into())]));
+        builtin_hash_table_set(vec![table.clone(), key(), Value::Number(10)]).unwrap();
+        assert_eq!(builtin_hash_table_ref(vec![table.clone(), key()]).unwrap(), Value::Number(10));
+        assert_eq!(builtin_hash_table_contains(vec![table.clone(), key()]).unwrap(), Value::Boolean(true));
+        builtin_hash_table_delete(vec![table.clone(), key()]).unwrap();
+        assert!(matches!(builtin_hash_table_ref(vec![table.clone(), key()]), Err(EvalError::Other(_))));
+        let default = builtin_hash_table_ref_default(vec![table, key(), Value::Boolean(false)]).unwrap();
+        assert_eq!(default, Value::Boolean(false));
+    }
+
+    #[test]
+    fn test_builtin_hash_table_ref_calls_failure_thunk() {
+        let table = builtin_make_hash_table(vec![]).unwrap();
+        let result = builtin_hash_table_ref(vec![table, Value::Number(1), Value::Function(builtin_list)]).unwrap();
+        assert_eq!(result, Value::List(Rc::new([])));
+    }
+
+    #[test]
+    fn test_builtin_alist_round_trip() {
+        let alist = Value::List(Rc::new([
+            Value::List(Rc::new([Value::Symbol("a".into()), Value::Number(1)])),
+            Value::List(Rc::new([Value::Symbol("a".into()), Value::Number(2)])),
+        ]));
+        let table = builtin_alist_to_hash_table(vec![alist]).unwrap();
+        assert_eq!(builtin_hash_table_count(vec![table.clone()]).unwrap(), Value::Number(1));
+        let expected = Value::List(Rc::new([Value::List(Rc::new([Value::Symbol("a".into()), Value::Number(1)]))]));
+        assert_eq!(builtin_hash_table_to_alist(vec![table]).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_builtin_hash_table_copy_is_independent() {
+        let table = builtin_make_hash_table(vec![]).unwrap();
+        let copy = builtin_hash_table_copy(vec![table.clone()]).unwrap();
+        builtin_hash_table_set(vec![copy.clone(), Value::Number(1), Value::Number(2)]).unwrap();
+        assert_eq!(builtin_hash_table_count(vec![table]).unwrap(), Value::Number(0));
+        assert_eq!(builtin_hash_table_count(vec![copy]).unwrap(), Value::Number(1));
+    }
+
+    #[test]
+    fn test_builtin_hash_table_update_missing_key() {
+        let table = builtin_make_hash_table(vec![]).unwrap();
+        let result = builtin_hash_table_update(vec![table, Value::Number(1), Value::Function(builtin_list)]);
+        assert!(matches!(result, Err(EvalError::Other(_))));
+    }
+
+    #[test]
+    fn test_builtin_compare() {
+        let compare = |a: Value, b: Value| builtin_compare(vec![a, b]).unwrap();
+        assert_eq!(compare(Value::Number(1), Value::Float(1.5)), Value::Number(-1));
+        assert_eq!(compare(Value::Rational(1, 2), Value::Float(0.5)), Value::Number(0));
+        assert_eq!(compare(Value::String("b".into()), Value::String("a".into())), Value::Number(1));
+        assert_eq!(compare(Value::Symbol("a".into()), Value::Symbol("b".into())), Value::Number(-1));
+    }
+
+    #[test]
+    fn test_builtin_compare_mixed_kinds() {
+        let result = builtin_compare(vec![Value::Number(1), Value::String("1".into())]);
+        assert!(matches!(result, Err(EvalError::TypeError(msg)) if msg == "compare: cannot compare number 1 with string \"1\""));
+        let result = builtin_compare(vec![Value::Float(f64::NAN), Value::Number(1)]);
         assert!(matches!(result, Err(EvalError::TypeError(_))));
     }
+
+    #[test]
+    fn test_builtin_sort_default_order() {
+        let list = Value::List(Rc::new([Value::Number(3), Value::Float(1.5), Value::Rational(1, 2), Value::Number(2)]));
+        let result = builtin_sort(vec![list]).unwrap();
+        assert_eq!(result, Value::List(Rc::new([Value::Rational(1, 2), Value::Float(1.5), Value::Number(2), Value::Number(3)])));
+    }
+
+    #[test]
+    fn test_builtin_sort_vector_returns_copy() {
+        let vector = Value::Vector(Rc::new(RefCell::new(vec![Value::String("b".into()), Value::String("a".into())])));
+        let result = builtin_sort(vec![vector.clone()]).unwrap();
+        assert_eq!(result.to_string(), r#"#("a" "b")"#);
+        assert_eq!(vector.to_string(), r#"#("b" "a")"#);
+    }
+
+    #[test]
+    fn test_builtin_sort_mixed_kinds() {
+        let list = Value::List(Rc::new([Value::Number(1), Value::Symbol("a".into())]));
+        assert!(matches!(builtin_sort(vec![list]), Err(EvalError::TypeError(_))));
+    }
+
+    #[test]
+    fn test_builtin_vector_sort_in_place() {
+        let vector = builtin_vector(vec![Value::Number(3), Value::Number(1), Value::Number(2)]).unwrap();
+        assert_eq!(builtin_vector_sort(vec![vector.clone()]).unwrap(), Value::Unspecified);
+        assert_eq!(vector.to_string(), "#(1 2 3)");
+        builtin_vector_sort(vec![vector.clone(), Value::Function(builtin_gt)]).unwrap();
+        assert_eq!(vector.to_string(), "#(3 2 1)");
+    }
+
+    #[test]
+    fn test_builtin_vector_binary_search() {
+        let vector = builtin_vector((0..10).map(|i| Value::Number(i * 2)).collect()).unwrap();
+        assert_eq!(builtin_vector_binary_search(vec![vector.clone(), Value::Number(14)]).unwrap(), Value::Number(7));
+        assert_eq!(builtin_vector_binary_search(vec![vector.clone(), Value::Number(0)]).unwrap(), Value::Number(0));
+        assert_eq!(builtin_vector_binary_search(vec![vector.clone(), Value::Number(5)]).unwrap(), Value::Boolean(false));
+        let result = builtin_vector_binary_search(vec![vector, Value::Number(6), Value::Function(builtin_sub)]).unwrap();
+        assert_eq!(result, Value::Number(3));
+    }
+
+    #[test]
+    fn test_builtin_subvector_and_vector_copy() {
+        let vector = builtin_vector((1..=5).map(Value::Number).collect()).unwrap();
+        let n = Value::Number;
+        assert_eq!(builtin_subvector(vec![vector.clone(), n(1), n(3)]).unwrap().to_string(), "#(2 3)");
+        assert!(matches!(builtin_subvector(vec![vector.clone(), n(3), n(1)]), Err(EvalError::Other(_))));
+        // Overlapping copies within one vector read the source first.
+        builtin_vector_copy(vec![vector.clone(), n(1), vector.clone(), n(0), n(3)]).unwrap();
+        assert_eq!(vector.to_string(), "#(1 1 2 3 5)");
+        let target = builtin_make_vector(vec![n(3), n(0)]).unwrap();
+        builtin_vector_copy(vec![target.clone(), n(1), vector.clone(), n(3)]).unwrap();
+        assert_eq!(target.to_string(), "#(0 3 5)");
+        assert!(matches!(builtin_vector_copy(vec![target, n(2), vector]), Err(EvalError::Other(_))));
+    }
+
+    #[test]
+    fn test_builtin_vector_map_over_several_vectors() {
+        let a = builtin_vector(vec![Value::Number(1), Value::Number(2), Value::Number(3)]).unwrap();
+        let b = builtin_vector(vec![Value::Number(10), Value::Number(20)]).unwrap();
+        let result = builtin_vector_map(vec![Value::Function(builtin_add), a.clone(), b]).unwrap();
+        assert_eq!(result.to_string(), "#(11 22)");
+        assert_eq!(builtin_vector_for_each(vec![Value::Function(builtin_add), a]).unwrap(), Value::Unspecified);
+        assert!(matches!(builtin_vector_map(vec![Value::Function(builtin_add), Value::Number(1)]), Err(EvalError::TypeError(_))));
+    }
+
+    #[test]
+    fn test_builtin_queue_fifo_order() {
+        let queue = builtin_make_queue(vec![Value::Number(1)]).unwrap();
+        builtin_enqueue(vec![queue.clone(), Value::Number(2)]).unwrap();
+        builtin_queue_push_front(vec![queue.clone(), Value::Number(0)]).unwrap();
+        assert_eq!(builtin_queue_length(vec![queue.clone()]).unwrap(), Value::Number(3));
+        assert_eq!(builtin_dequeue(vec![queue.clone()]).unwrap(), Value::Number(0));
+        assert_eq!(builtin_queue_pop_back(vec![queue.clone()]).unwrap(), Value::Number(2));
+        assert_eq!(builtin_queue_front(vec![queue.clone()]).unwrap(), Value::Number(1));
+        assert_eq!(queue.to_string(), "#<queue 1>");
+    }
+
+    #[test]
+    fn test_builtin_dequeue_empty() {
+        let queue = builtin_make_queue(vec![]).unwrap();
+        assert_eq!(builtin_is_queue_empty(vec![queue.clone()]).unwrap(), Value::Boolean(true));
+        assert!(matches!(builtin_dequeue(vec![queue.clone()]), Err(EvalError::Other(_))));
+        assert_eq!(queue.to_string(), "#<queue>");
+    }
+
+    #[test]
+    fn test_builtin_queue_type_errors() {
+        assert!(matches!(builtin_enqueue(vec![Value::List(Rc::new([])), Value::Number(1)]), Err(EvalError::TypeError(_))));
+        assert!(matches!(builtin_enqueue(vec![builtin_make_queue(vec![]).unwrap()]), Err(EvalError::ArityMismatch)));
+    }
+
+    #[test]
+    fn test_builtin_string_port() {
+        let port = builtin_open_output_string(vec![]).unwrap();
+        builtin_write_string(vec![Value::String("a \"b\"".into()), port.clone()]).unwrap();
+        builtin_write(vec![Value::String("c".into()), port.clone()]).unwrap();
+        builtin_display(vec![Value::List(Rc::new([Value::Number(1)])), port.clone()]).unwrap();
+        builtin_newline(vec![port.clone()]).unwrap();
+        let result = builtin_get_output_string(vec![port]).unwrap();
+        assert_eq!(result, Value::String("a \"b\"\"c\"(1)\n".into()));
+    }
+
+    #[test]
+    fn test_builtin_format_to_port() {
+        let port = builtin_open_output_string(vec![]).unwrap();
+        let args = vec![port.clone(), Value::String("~a-~s".into()), Value::Number(1), Value::String("x".into())];
+        assert_eq!(builtin_format(args).unwrap(), Value::Unspecified);
+        assert_eq!(builtin_get_output_string(vec![port]).unwrap(), Value::String("1-\"x\"".into()));
+    }
+
+    #[test]
+    fn test_builtin_output_port_errors() {
+        assert!(matches!(builtin_write_string(vec![Value::Number(1)]), Err(EvalError::TypeError(_))));
+        assert!(matches!(builtin_display(vec![Value::Number(1), Value::Number(2)]), Err(EvalError::TypeError(_))));
+        let stdout = builtin_current_output_port(vec![]).unwrap();
+        assert!(matches!(builtin_get_output_string(vec![stdout]), Err(EvalError::TypeError(_))));
+    }
+
+    #[test]
+    fn test_builtin_keywords() {
+        let keyword = Value::Keyword("color".into());
+        assert_eq!(builtin_is_keyword(vec![keyword.clone()]).unwrap(), Value::Boolean(true));
+        assert_eq!(builtin_is_keyword(vec![Value::Symbol("color".into())]).unwrap(), Value::Boolean(false));
+        assert_eq!(builtin_keyword_to_string(vec![keyword.clone()]).unwrap(), Value::String("color".into()));
+        assert_eq!(builtin_string_to_keyword(vec![Value::String("color".into())]).unwrap(), keyword);
+        assert!(matches!(builtin_keyword_to_string(vec![Value::Symbol("color".into())]), Err(EvalError::TypeError(_))));
+    }
+
+    #[test]
+    fn test_builtin_input_string_port() {
+        let port = builtin_open_input_string(vec![Value::String("hé\r\nlast".into())]).unwrap();
+        assert_eq!(builtin_is_char_ready(vec![port.clone()]).unwrap(), Value::Boolean(true));
+        assert_eq!(builtin_peek_char(vec![port.clone()]).unwrap(), Value::Char('h'));
+        assert_eq!(builtin_read_char(vec![port.clone()]).unwrap(), Value::Char('h'));
+        assert_eq!(builtin_read_char(vec![port.clone()]).unwrap(), Value::Char('é'));
+        assert_eq!(builtin_read_line(vec![port.clone()]).unwrap(), Value::String("".into()));
+        assert_eq!(builtin_read_line(vec![port.clone()]).unwrap(), Value::String("last".into()));
+        assert_eq!(builtin_read_line(vec![port.clone()]).unwrap(), Value::Eof);
+        assert_eq!(builtin_read_char(vec![port.clone()]).unwrap(), Value::Eof);
+        assert_eq!(builtin_is_eof_object(vec![builtin_eof_object(vec![]).unwrap()]).unwrap(), Value::Boolean(true));
+    }
+
+    #[test]
+    fn test_builtin_ports_reject_wrong_direction() {
+        let input = builtin_open_input_string(vec![Value::String("x".into())]).unwrap();
+        let output = builtin_open_output_string(vec![]).unwrap();
+        assert!(matches!(builtin_display(vec![Value::Number(1), input]), Err(EvalError::TypeError(_))));
+        assert!(matches!(builtin_read_char(vec![output]), Err(EvalError::TypeError(_))));
+    }
+
+    #[test]
+    fn test_builtin_colorize() {
+        let text = Value::String("ok".into());
+        let styles = Value::List(Rc::new([Value::Symbol("bold".into()), Value::Symbol("green".into())]));
+        builtin_set_color_enabled(vec![Value::Boolean(true)]).unwrap();
+        assert_eq!(builtin_colorize(vec![text.clone(), styles.clone()]).unwrap(), Value::String("\x1b[1;32mok\x1b[0m".into()));
+        builtin_set_color_enabled(vec![Value::Boolean(false)]).unwrap();
+        assert_eq!(builtin_colorize(vec![text.clone(), styles]).unwrap(), text);
+        assert!(matches!(builtin_colorize(vec![text, Value::Symbol("plaid".into())]), Err(EvalError::TypeError(_))));
+        let result = builtin_with_style(vec![Value::Symbol("red".into()), Value::Function(builtin_eof_object)]);
+        assert_eq!(result.unwrap(), Value::Eof);
+    }
 }
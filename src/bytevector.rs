@@ -0,0 +1,279 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::env::{EvalError, Value};
+use crate::eval::filled_vec;
+use crate::port::Port;
+
+fn bytevector(bytes: Vec<u8>) -> Value {
+    Value::Bytevector(Rc::new(RefCell::new(bytes)))
+}
+
+fn byte_arg(value: &Value) -> Result<u8, EvalError> {
+    match value {
+        Value::Number(n) => u8::try_from(*n).map_err(|_| EvalError::TypeError(format!("Byte {} out of range 0 to 255", n))),
+        _ => Err(EvalError::TypeError("Expected byte".into())),
+    }
+}
+
+/// Reads an index of a byte in a bytevector of the given length.
+fn index_arg(value: &Value, len: usize) -> Result<usize, EvalError> {
+    bound_arg(value, len).and_then(|i| if i < len { Ok(i) } else { Err(out_of_range(i, len)) })
+}
+
+/// Reads the start or end of a range of bytes, which may be the length.
+fn bound_arg(value: &Value, len: usize) -> Result<usize, EvalError> {
+    match value {
+        Value::Number(i) => usize::try_from(*i).ok().filter(|&i| i <= len)
+            .ok_or_else(|| out_of_range(*i, len)),
+        _ => Err(EvalError::TypeError("Expected index".into())),
+    }
+}
+
+fn out_of_range(i: impl std::fmt::Display, len: usize) -> EvalError {
+    EvalError::Other(format!("Index {} out of range for bytevector of length {}", i, len))
+}
+
+fn binary_port(value: &Value) -> Result<Rc<Port>, EvalError> {
+    match value {
+        Value::Port(port) if port.is_binary() => Ok(port.clone()),
+        _ => Err(EvalError::TypeError("Expected binary port".into())),
+    }
+}
+
+/// Returns a new bytevector holding the arguments, which are bytes.
+pub fn builtin_bytevector(args: Vec<Value>) -> Result<Value, EvalError> {
+    Ok(bytevector(args.iter().map(byte_arg).collect::<Result<_, _>>()?))
+}
+
+/// `(make-bytevector k [byte])` returns a bytevector of `k` bytes, each
+/// `byte` or 0.
+pub fn builtin_make_bytevector(args: Vec<Value>) -> Result<Value, EvalError> {
+    let (len, fill) = match &args[..] {
+        [Value::Number(n)] => (*n, 0),
+        [Value::Number(n), fill] => (*n, byte_arg(fill)?),
+        [_] | [_, _] => return Err(EvalError::TypeError("Expected bytevector length".into())),
+        _ => return Err(EvalError::ArityMismatch),
+    };
+    let len = usize::try_from(len).map_err(|_| EvalError::TypeError("Expected non-negative length".into()))?;
+    Ok(bytevector(filled_vec(fill, len)?))
+}
+
+/// Returns true if the argument is a bytevector.
+pub fn builtin_is_bytevector(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [value] => Ok(Value::Boolean(matches!(value, Value::Bytevector(_)))),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Returns the number of bytes in a bytevector.
+pub fn builtin_bytevector_length(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [Value::Bytevector(bytes)] => Ok(Value::Number(bytes.borrow().len() as i64)),
+        [_] => Err(EvalError::TypeError("Expected bytevector".into())),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Returns the byte of a bytevector at the given index.
+pub fn builtin_bytevector_u8_ref(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [Value::Bytevector(bytes), index] => {
+            let bytes = bytes.borrow();
+            Ok(Value::Number(i64::from(bytes[index_arg(index, bytes.len())?])))
+        }
+        [_, _] => Err(EvalError::TypeError("Expected bytevector and index".into())),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Replaces the byte of a bytevector at the given index.
+pub fn builtin_bytevector_u8_set(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [Value::Bytevector(bytes), index, byte] => {
+            let byte = byte_arg(byte)?;
+            let mut bytes = bytes.borrow_mut();
+            let i = index_arg(index, bytes.len())?;
+            bytes[i] = byte;
+            Ok(Value::Unspecified)
+        }
+        [_, _, _] => Err(EvalError::TypeError("Expected bytevector, index, and byte".into())),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Decodes a bytevector as UTF-8 text.
+pub fn builtin_utf8_to_string(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [Value::Bytevector(bytes)] => String::from_utf8(bytes.borrow().clone())
+            .map(|text| Value::String(text.into()))
+            .map_err(|e| EvalError::Other(format!("utf8->string: {}", e))),
+        [_] => Err(EvalError::TypeError("Expected bytevector".into())),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Encodes a string as UTF-8 bytes.
+pub fn builtin_string_to_utf8(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [Value::String(s)] => Ok(bytevector(s.as_bytes().to_vec())),
+        [_] => Err(EvalError::TypeError("Expected string".into())),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Returns a binary input port that reads the bytes of a bytevector.
+pub fn builtin_open_input_bytevector(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [Value::Bytevector(bytes)] => Ok(Value::Port(Rc::new(Port::input_bytes(bytes.borrow().clone())))),
+        [_] => Err(EvalError::TypeError("Expected bytevector".into())),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Returns a binary output port that collects bytes in memory; see
+/// `get-output-bytevector`.
+pub fn builtin_open_output_bytevector(args: Vec<Value>) -> Result<Value, EvalError> {
+    if !args.is_empty() {
+        return Err(EvalError::ArityMismatch);
+    }
+    Ok(Value::Port(Rc::new(Port::OutputBytes(RefCell::new(Vec::new())))))
+}
+
+/// Returns the bytes written so far to a bytevector output port.
+pub fn builtin_get_output_bytevector(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [Value::Port(port)] => port.byte_contents()
+            .map(bytevector)
+            .ok_or_else(|| EvalError::TypeError("Expected bytevector port".into())),
+        [_] => Err(EvalError::TypeError("Expected bytevector port".into())),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Returns a binary input port that reads the bytes of a file, which is
+/// read in full when opened.
+#[cfg(feature = "io")]
+pub fn builtin_open_binary_input_file(args: Vec<Value>) -> Result<Value, EvalError> {
+    let path = match &args[..] {
+        [Value::String(path)] => path,
+        [_] => return Err(EvalError::TypeError("Expected file name".into())),
+        _ => return Err(EvalError::ArityMismatch),
+    };
+    crate::eval::check_effect("open-binary-input-file")?;
+    let bytes = crate::resolver::read_file(path)?;
+    Ok(Value::Port(Rc::new(Port::input_bytes(bytes))))
+}
+
+/// Reads the next byte from a binary port, or returns the end-of-file
+/// object.
+pub fn builtin_read_u8(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [port] => Ok(binary_port(port)?.read_bytes(1)?.first().map_or(Value::Eof, |&b| Value::Number(i64::from(b)))),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Like `read-u8`, but leaves the byte to be read again.
+pub fn builtin_peek_u8(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [port] => Ok(binary_port(port)?.peek_u8()?.map_or(Value::Eof, |b| Value::Number(i64::from(b)))),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// `(read-bytevector k port)` reads up to `k` bytes from a binary port into
+/// a new bytevector, fewer at the end of input, or returns the end-of-file
+/// object if there are none left.
+pub fn builtin_read_bytevector(args: Vec<Value>) -> Result<Value, EvalError> {
+    let (count, port) = match &args[..] {
+        [Value::Number(k), port] if *k >= 0 => (*k as usize, binary_port(port)?),
+        [_, _] => return Err(EvalError::TypeError("Expected byte count and binary port".into())),
+        _ => return Err(EvalError::ArityMismatch),
+    };
+    match port.read_bytes(count)? {
+        bytes if bytes.is_empty() && count > 0 => Ok(Value::Eof),
+        bytes => Ok(bytevector(bytes)),
+    }
+}
+
+/// `(write-u8 byte port)` writes a byte to a binary port.
+pub fn builtin_write_u8(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [byte, port] => {
+            binary_port(port)?.write_bytes(&[byte_arg(byte)?])?;
+            Ok(Value::Unspecified)
+        }
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// `(write-bytevector bytes port [start [end]])` writes the bytes of a
+/// bytevector between `start` and `end` to a binary port.
+pub fn builtin_write_bytevector(args: Vec<Value>) -> Result<Value, EvalError> {
+    let (bytes, port, range) = match &args[..] {
+        [Value::Bytevector(bytes), port, range @ ..] if range.len() <= 2 => (bytes.borrow().clone(), binary_port(port)?, range),
+        [_, _, ..] if args.len() <= 4 => return Err(EvalError::TypeError("Expected bytevector and binary port".into())),
+        _ => return Err(EvalError::ArityMismatch),
+    };
+    let start = range.first().map_or(Ok(0), |start| bound_arg(start, bytes.len()))?;
+    let end = range.get(1).map_or(Ok(bytes.len()), |end| bound_arg(end, bytes.len()))?;
+    if start > end {
+        return Err(EvalError::Other(format!("Range {} to {} out of range for bytevector of length {}", start, end, bytes.len())));
+    }
+    port.write_bytes(&bytes[start..end])?;
+    Ok(Value::Unspecified)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::default_env;
+    use crate::testing::eval_source;
+
+    #[test]
+    fn test_bytevector_basics() {
+        let source = "
+            (define b (make-bytevector 3 7))
+            (bytevector-u8-set! b 1 255)
+            (list b (bytevector-length b) (bytevector-u8-ref b 1) (bytevector? b) (bytevector? (vector))
+                  (equal? (bytevector 1 2) (bytevector 1 2)) (utf8->string (string->utf8 \"héllo\")))";
+        assert_eq!(eval_source(source, &default_env()).unwrap().to_string(), "(#u8(7 255 7) 3 255 #t #f #t \"héllo\")");
+        assert!(matches!(eval_source("(bytevector 256)", &default_env()), Err(EvalError::TypeError(_))));
+        assert!(matches!(eval_source("(bytevector-u8-ref (bytevector 1) 1)", &default_env()), Err(EvalError::Other(_))));
+    }
+
+    #[test]
+    fn test_binary_ports_parse_a_record() {
+        // A length-prefixed record: a big-endian 16-bit length, then text.
+        let source = "
+            (define out (open-output-bytevector))
+            (write-u8 0 out)
+            (write-u8 5 out)
+            (write-bytevector (string->utf8 \"xhello!\") out 1 6)
+            (define in (open-input-bytevector (get-output-bytevector out)))
+            (define len (+ (* 256 (read-u8 in)) (read-u8 in)))
+            (define peeked (peek-u8 in))
+            (define text (utf8->string (read-bytevector len in)))
+            (list len peeked text (eof-object? (read-u8 in)) (eof-object? (read-bytevector 4 in)))";
+        assert_eq!(eval_source(source, &default_env()).unwrap().to_string(), "(5 104 \"hello\" #t #t)");
+    }
+
+    #[test]
+    fn test_textual_and_binary_ports_do_not_mix() {
+        assert!(matches!(eval_source("(read-char (open-input-bytevector (bytevector 65)))", &default_env()), Err(EvalError::TypeError(_))));
+        assert!(matches!(eval_source("(display 1 (open-output-bytevector))", &default_env()), Err(EvalError::TypeError(_))));
+        assert!(matches!(eval_source("(read-u8 (open-input-string \"A\"))", &default_env()), Err(EvalError::TypeError(_))));
+    }
+
+    #[test]
+    #[cfg(feature = "io")]
+    fn test_open_binary_input_file() {
+        let path = std::env::temp_dir().join(format!("scheme-rs-binary-{}.bin", std::process::id()));
+        std::fs::write(&path, [0xCA, 0xFE]).unwrap();
+        let result = eval_source(&format!("(define in (open-binary-input-file {:?})) (read-bytevector 10 in)", path.display().to_string()), &default_env());
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(result.unwrap().to_string(), "#u8(202 254)");
+    }
+}
@@ -0,0 +1,147 @@
+//! Channels: queues that fibers pass values through, waiting for each
+//! other as needed. `(channel-get ch)` waits while `ch` is empty and
+//! `(channel-put! ch value)` while it is full, letting other fibers run in
+//! the meantime (see `fiber::wait_until`), so no OS threads are involved
+//! and they work the same in the browser. `(select ch ...)` waits for the
+//! first of several channels to have a value.
+//!
+//! Channels belong to one interpreter; actors on other threads talk
+//! through their mailboxes instead (see `actor`).
+
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::env::{EvalError, Value};
+use crate::fiber::wait_until;
+use crate::number::Number;
+
+/// A channel from `make-channel`.
+pub struct Channel {
+    items: RefCell<VecDeque<Value>>,
+    /// How many values it holds before `channel-put!` waits, or `None` for
+    /// no limit.
+    capacity: Option<usize>,
+}
+
+impl fmt::Debug for Channel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Channel").field("items", &self.items.borrow().len()).field("capacity", &self.capacity).finish()
+    }
+}
+
+impl Channel {
+    fn is_full(&self) -> bool {
+        self.capacity.is_some_and(|capacity| self.items.borrow().len() >= capacity)
+    }
+
+    fn take(&self) -> Option<Value> {
+        self.items.borrow_mut().pop_front()
+    }
+}
+
+fn channel_arg<'a>(value: &'a Value, name: &str) -> Result<&'a Rc<Channel>, EvalError> {
+    match value {
+        Value::Channel(channel) => Ok(channel),
+        other => Err(EvalError::TypeError(format!("{}: expected a channel, got {}", name, other.type_name()))),
+    }
+}
+
+/// `(make-channel [capacity])` returns a new channel holding at most
+/// `capacity` values, or any number without one.
+pub fn builtin_make_channel(args: Vec<Value>) -> Result<Value, EvalError> {
+    let capacity = match args.first() {
+        None => None,
+        Some(value) => match Number::try_from(value).ok().and_then(Number::as_integer) {
+            Some(n) if n > 0 => Some(n as usize),
+            _ => return Err(EvalError::TypeError(format!("make-channel: capacity must be a positive integer, got {}", value))),
+        },
+    };
+    Ok(Value::Channel(Rc::new(Channel { items: RefCell::new(VecDeque::new()), capacity })))
+}
+
+/// Returns true if the argument is a channel.
+pub fn builtin_is_channel(args: Vec<Value>) -> Result<Value, EvalError> {
+    Ok(Value::Boolean(matches!(args[0], Value::Channel(_))))
+}
+
+/// `(channel-put! ch value)` adds `value` to the channel, first waiting for
+/// room if it is full.
+pub fn builtin_channel_put(args: Vec<Value>) -> Result<Value, EvalError> {
+    let channel = channel_arg(&args[0], "channel-put!")?;
+    wait_until("channel-put!", || !channel.is_full())?;
+    channel.items.borrow_mut().push_back(args[1].clone());
+    Ok(Value::Unspecified)
+}
+
+/// `(channel-get ch)` removes and returns the oldest value in the channel,
+/// first waiting for one if it is empty.
+pub fn builtin_channel_get(args: Vec<Value>) -> Result<Value, EvalError> {
+    let channel = channel_arg(&args[0], "channel-get")?;
+    wait_until("channel-get", || !channel.items.borrow().is_empty())?;
+    Ok(channel.take().expect("the channel has a value"))
+}
+
+/// `(select ch ...)` waits until one of the channels has a value, then
+/// removes it and returns two values: the channel and the value. If several
+/// have one, the first given wins.
+pub fn builtin_select(args: Vec<Value>) -> Result<Value, EvalError> {
+    let channels = args.iter().map(|arg| channel_arg(arg, "select")).collect::<Result<Vec<_>, _>>()?;
+    let ready = || channels.iter().position(|channel| !channel.items.borrow().is_empty());
+    wait_until("select", || ready().is_some())?;
+    let index = ready().expect("a channel has a value");
+    let value = channels[index].take().expect("the channel has a value");
+    Ok(Value::Values([args[index].clone(), value].into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{assert_fails, assert_prints};
+
+    #[test]
+    fn test_producer_and_consumer() {
+        assert_prints("
+            (define ch (make-channel 2))
+            (define log (make-queue))
+            (define (produce i)
+              (if (< i 5)
+                  (begin (channel-put! ch i) (enqueue! log (list 'put i)) (produce (+ i 1)))
+                  (channel-put! ch 'end)))
+            (define (consume total)
+              (let ((x (channel-get ch)))
+                (if (equal? x 'end)
+                    total
+                    (begin (enqueue! log (list 'got x)) (consume (+ total x))))))
+            (define producer (spawn-fiber (lambda () (produce 0))))
+            (list (consume 0) (channel? ch) (queue->list log))",
+            // The consumer outside the fibers lets the producer run when the
+            // channel is empty, and the producer waits when it is full.
+            "(10 #t ((put 0) (put 1) (got 0) (got 1) (put 2) (put 3) (got 2) (got 3) (put 4) (got 4)))");
+    }
+
+    #[test]
+    fn test_select_takes_from_the_first_ready_channel() {
+        assert_prints("
+            (define a (make-channel))
+            (define b (make-channel))
+            (spawn-fiber (lambda () (channel-put! b 'from-b) (yield) (channel-put! a 'from-a)))
+            (define (next)
+              (call-with-values (lambda () (select a b))
+                (lambda (ch value) (list (if (equal? ch a) 'a 'b) value))))
+            (define first (next))
+            (list first (next))",
+            "((b from-b) (a from-a))");
+    }
+
+    #[test]
+    fn test_waiting_with_no_fiber_to_help_fails() {
+        assert_fails("(channel-get (make-channel))", "error");
+        assert_fails("
+            (define ch (make-channel))
+            (define f (spawn-fiber (lambda () (channel-get ch))))
+            (join f)", "error");
+        assert_fails("(make-channel 0)", "type");
+        assert_fails("(channel-put! 'nowhere 1)", "type");
+    }
+}
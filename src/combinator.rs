@@ -0,0 +1,182 @@
+//! Combinators: small procedures for building procedures out of others,
+//! handy with `map`, `for-each`, and the like. `(compose f g)` calls `g` and
+//! then `f` on the result, `(const v)` ignores its arguments and returns
+//! `v`, `(flip f)` calls `f` with its first two arguments swapped,
+//! `(curry f a ...)` fixes the first arguments of `f`, and
+//! `(curried f [n])` collects `f`'s `n` arguments over as many calls as it
+//! takes.
+//!
+//! Lambdas take a fixed number of arguments, so the procedures these
+//! return are `Value::Combinator`s, which pass on however many they get.
+
+use std::rc::Rc;
+
+use crate::builtins::procedure_arity;
+use crate::env::{Arity, EvalError, Value};
+use crate::eval::apply;
+
+/// A procedure made by one of the combinators.
+#[derive(Debug)]
+pub enum Combinator {
+    /// `(const value)`.
+    Const(Value),
+    /// `(compose f ...)`, with the procedures in the order given, so the
+    /// last is called first.
+    Compose(Vec<Value>),
+    /// `(flip f)`.
+    Flip(Value),
+    /// `(curry f arg ...)` and `(curried f n)`: `f` with the arguments
+    /// collected so far, and for `curried`, how many to collect before
+    /// calling it.
+    Curry { func: Value, args: Vec<Value>, arity: Option<usize> },
+}
+
+impl Combinator {
+    /// The name of the combinator that made it.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Combinator::Const(_) => "const",
+            Combinator::Compose(_) => "compose",
+            Combinator::Flip(_) => "flip",
+            Combinator::Curry { arity: None, .. } => "curry",
+            Combinator::Curry { arity: Some(_), .. } => "curried",
+        }
+    }
+
+    pub fn call(&self, mut args: Vec<Value>) -> Result<Value, EvalError> {
+        match self {
+            Combinator::Const(value) => Ok(value.clone()),
+            Combinator::Compose(procedures) => {
+                let mut result = Value::Values(args.into());
+                for procedure in procedures.iter().rev() {
+                    // Multiple values from one procedure are the arguments of
+                    // the next.
+                    let args = match result {
+                        Value::Values(ref values) => values.to_vec(),
+                        single => vec![single],
+                    };
+                    result = apply(procedure.clone(), args)?;
+                }
+                Ok(result)
+            }
+            Combinator::Flip(func) => {
+                if args.len() < 2 {
+                    return Err(EvalError::WrongArgumentCount(format!("flip: expected at least 2, got {}", args.len())));
+                }
+                args.swap(0, 1);
+                apply(func.clone(), args)
+            }
+            Combinator::Curry { func, args: fixed, arity } => {
+                let all: Vec<Value> = fixed.iter().cloned().chain(args).collect();
+                match arity {
+                    Some(n) if all.len() < *n => {
+                        Ok(Value::Combinator(Rc::new(Combinator::Curry { func: func.clone(), args: all, arity: *arity })))
+                    }
+                    _ => apply(func.clone(), all),
+                }
+            }
+        }
+    }
+
+    /// How many arguments it accepts, where that is known.
+    pub fn arity(&self) -> Option<Arity> {
+        match self {
+            Combinator::Const(_) => Some(Arity::at_least(0)),
+            Combinator::Compose(procedures) => procedure_arity(procedures.last()?),
+            Combinator::Flip(func) => procedure_arity(func).map(|arity| Arity { min: arity.min.max(2), ..arity }),
+            Combinator::Curry { func, args, arity: None } => procedure_arity(func).map(|arity| Arity {
+                min: arity.min.saturating_sub(args.len()),
+                max: arity.max.map(|max| max.saturating_sub(args.len())),
+            }),
+            Combinator::Curry { args, arity: Some(n), .. } => Some(Arity::between(0, n - args.len())),
+        }
+    }
+}
+
+fn procedure_arg(value: &Value, name: &str) -> Result<(), EvalError> {
+    match procedure_arity(value) {
+        Some(_) => Ok(()),
+        None => Err(EvalError::TypeError(format!("{}: expected a procedure, got {}", name, value.type_name()))),
+    }
+}
+
+/// `(identity x)` returns `x`.
+pub fn builtin_identity(args: Vec<Value>) -> Result<Value, EvalError> {
+    Ok(args[0].clone())
+}
+
+/// `(const v)` returns a procedure that takes any arguments and returns `v`.
+pub fn builtin_const(args: Vec<Value>) -> Result<Value, EvalError> {
+    Ok(Value::Combinator(Rc::new(Combinator::Const(args[0].clone()))))
+}
+
+/// `(compose f ... g)` returns a procedure that calls `g` with its
+/// arguments, then each procedure before it, from right to left, with the
+/// previous result.
+pub fn builtin_compose(args: Vec<Value>) -> Result<Value, EvalError> {
+    args.iter().try_for_each(|arg| procedure_arg(arg, "compose"))?;
+    Ok(Value::Combinator(Rc::new(Combinator::Compose(args))))
+}
+
+/// `(flip f)` returns a procedure that calls `f` with its first two
+/// arguments swapped.
+pub fn builtin_flip(args: Vec<Value>) -> Result<Value, EvalError> {
+    procedure_arg(&args[0], "flip")?;
+    Ok(Value::Combinator(Rc::new(Combinator::Flip(args[0].clone()))))
+}
+
+/// `(curry f arg ...)` returns a procedure that calls `f` with the given
+/// arguments followed by its own.
+pub fn builtin_curry(mut args: Vec<Value>) -> Result<Value, EvalError> {
+    procedure_arg(&args[0], "curry")?;
+    let func = args.remove(0);
+    Ok(Value::Combinator(Rc::new(Combinator::Curry { func, args, arity: None })))
+}
+
+/// `(curried f [n])` returns a procedure that collects arguments, over as
+/// many calls as it takes, until it has `n`, then calls `f` with them. `n`
+/// defaults to the number `f` takes, if that is fixed.
+pub fn builtin_curried(args: Vec<Value>) -> Result<Value, EvalError> {
+    procedure_arg(&args[0], "curried")?;
+    let arity = match args.get(1) {
+        Some(Value::Number(n)) if *n >= 0 => *n as usize,
+        Some(other) => return Err(EvalError::TypeError(format!("curried: expected a number of arguments, got {}", other))),
+        None => match procedure_arity(&args[0]) {
+            Some(Arity { min, max: Some(max) }) if min == max => min,
+            _ => return Err(EvalError::TypeError("curried: the procedure takes a varying number of arguments; give how many to collect".into())),
+        },
+    };
+    Ok(Value::Combinator(Rc::new(Combinator::Curry { func: args[0].clone(), args: Vec::new(), arity: Some(arity) })))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{assert_fails, assert_prints};
+
+    #[test]
+    fn test_combinators_with_map() {
+        assert_prints("
+            (define (square x) (* x x))
+            (list (map identity '(1 2))
+                  (map (const 0) '(1 2))
+                  (map (compose square car) '((1 a) (2 b) (3 c)))
+                  ((compose list (lambda () (values 1 2))))
+                  (map (flip -) '(1 2) '(10 20))
+                  (map (curry < 2) '(1 3))
+                  (map (curry + 1 2) '(10 20))
+                  (procedure-arity (curry vector-ref (vector 1 2))))",
+            "((1 2) (0 0) (1 4 9) (1 2) (9 18) (#f #t) (13 23) (1 1))");
+    }
+
+    #[test]
+    fn test_curried_collects_arguments_over_several_calls() {
+        assert_prints("
+            (define add3 (curried (lambda (a b c) (+ a b c))))
+            (define add-ten (add3 4 6))
+            (list (add-ten 1) (((add3 1) 2) 3) (((curried + 2) 5) 6) (add3 1 2 3))",
+            "(11 6 11 6)");
+        assert_fails("(curried +)", "type");
+        assert_fails("(compose car 1)", "type");
+        assert_fails("((flip list) 1)", "arity");
+    }
+}
@@ -0,0 +1,297 @@
+use std::path::{Path, PathBuf};
+
+use crate::ast::Expr;
+use crate::lexer::tokenize;
+use crate::parser::parse_program;
+
+/// File extension of compiled programs, written by `scheme_rs compile`.
+pub const EXTENSION: &str = "scmc";
+
+const MAGIC: &[u8; 4] = b"SCMC";
+
+/// Bumped whenever the encoding changes. Artifacts written by a different
+/// format or interpreter version are stale and are not trusted.
+const FORMAT_VERSION: u32 = 3;
+
+const TAG_NUMBER: u8 = 0;
+const TAG_BOOLEAN: u8 = 1;
+const TAG_STRING: u8 = 2;
+const TAG_SYMBOL: u8 = 3;
+const TAG_KEYWORD: u8 = 4;
+const TAG_LIST: u8 = 5;
+const TAG_HASH_TABLE: u8 = 6;
+const TAG_CHAR: u8 = 7;
+const TAG_ERROR: u8 = 8;
+const TAG_FLOAT: u8 = 9;
+const TAG_RATIONAL: u8 = 10;
+const TAG_VECTOR: u8 = 11;
+const TAG_BYTEVECTOR: u8 = 12;
+
+#[derive(Debug, PartialEq)]
+pub enum CompiledError {
+    NotCompiled, // missing the magic number
+    Stale,       // written by another format or interpreter version
+    Corrupt,
+}
+
+/// Serializes a parsed program, so loading it later skips lexing and
+/// parsing. The header records the format and interpreter versions.
+pub fn encode(program: &[Expr]) -> Vec<u8> {
+    let mut out = Vec::new();
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+    write_str(&mut out, env!("CARGO_PKG_VERSION"));
+    write_len(&mut out, program.len());
+    for expr in program {
+        write_expr(&mut out, expr);
+    }
+    out
+}
+
+/// Reads back a program written by `encode`.
+pub fn decode(bytes: &[u8]) -> Result<Vec<Expr>, CompiledError> {
+    let mut input = Input { bytes, pos: 0 };
+    if input.take(MAGIC.len()).ok() != Some(&MAGIC[..]) {
+        return Err(CompiledError::NotCompiled);
+    }
+    let format = input.u32()?;
+    if format != FORMAT_VERSION || input.string()? != env!("CARGO_PKG_VERSION") {
+        return Err(CompiledError::Stale);
+    }
+    let count = input.u32()?;
+    let program = (0..count).map(|_| input.expr()).collect::<Result<Vec<_>, _>>()?;
+    if input.pos != bytes.len() {
+        return Err(CompiledError::Corrupt);
+    }
+    Ok(program)
+}
+
+/// Returns the source file a compiled program was made from:
+/// `lib.scmc` comes from `lib.scm`.
+pub fn source_path(compiled: &Path) -> PathBuf {
+    compiled.with_extension("scm")
+}
+
+/// Parses a source file and writes the compiled program to `output`.
+pub fn compile_file(input: &Path, output: &Path) -> Result<Vec<Expr>, String> {
+    let program = parse_source_file(input)?;
+    std::fs::write(output, encode(&program))
+        .map_err(|e| format!("Cannot write {}: {}", output.display(), e))?;
+    Ok(program)
+}
+
+/// Loads a compiled program. If the artifact is stale, or older than its
+/// source file next to it, the source is compiled again and the artifact
+/// rewritten (best effort: a read-only artifact is just left as is).
+pub fn load_compiled(path: &Path) -> Result<Vec<Expr>, String> {
+    let source = source_path(path);
+    let modified = |p: &Path| std::fs::metadata(p).and_then(|m| m.modified()).ok();
+    let outdated = match (modified(&source), modified(path)) {
+        (Some(source_time), Some(compiled_time)) => source_time > compiled_time,
+        _ => false,
+    };
+    if !outdated {
+        let bytes = std::fs::read(path)
+            .map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+        match decode(&bytes) {
+            Ok(program) => return Ok(program),
+            Err(CompiledError::Stale) if source.exists() => {}
+            Err(e) => return Err(format!("Cannot load {}: {:?}", path.display(), e)),
+        }
+    }
+    let program = parse_source_file(&source)?;
+    let _ = std::fs::write(path, encode(&program));
+    Ok(program)
+}
+
+fn parse_source_file(path: &Path) -> Result<Vec<Expr>, String> {
+    let source = std::fs::read_to_string(path)
+        .map_err(|e| format!("Cannot read {}: {}", path.display(), e))?;
+    let tokens = tokenize(&source)
+        .map_err(|e| format!("Lex error in {}: {:?}", path.display(), e))?;
+    parse_program(tokens).map_err(|e| format!("Parse error in {}: {:?}", path.display(), e))
+}
+
+fn write_len(out: &mut Vec<u8>, len: usize) {
+    out.extend_from_slice(&(len as u32).to_le_bytes());
+}
+
+fn write_str(out: &mut Vec<u8>, s: &str) {
+    write_len(out, s.len());
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn write_expr(out: &mut Vec<u8>, expr: &Expr) {
+    match expr {
+        Expr::Number(n) => {
+            out.push(TAG_NUMBER);
+            out.extend_from_slice(&n.to_le_bytes());
+        }
+        Expr::Rational(n, d) => {
+            out.push(TAG_RATIONAL);
+            out.extend_from_slice(&n.to_le_bytes());
+            out.extend_from_slice(&d.to_le_bytes());
+        }
+        Expr::Float(x) => {
+            out.push(TAG_FLOAT);
+            out.extend_from_slice(&x.to_le_bytes());
+        }
+        Expr::Boolean(b) => out.extend_from_slice(&[TAG_BOOLEAN, *b as u8]),
+        Expr::String(s) => {
+            out.push(TAG_STRING);
+            write_str(out, s);
+        }
+        Expr::Symbol(s) => {
+            out.push(TAG_SYMBOL);
+            write_str(out, s);
+        }
+        Expr::Keyword(k) => {
+            out.push(TAG_KEYWORD);
+            write_str(out, k);
+        }
+        Expr::Char(c) => {
+            out.push(TAG_CHAR);
+            out.extend_from_slice(&(*c as u32).to_le_bytes());
+        }
+        Expr::List(items) => write_items(out, TAG_LIST, items),
+        Expr::Vector(items) => write_items(out, TAG_VECTOR, items),
+        Expr::Bytevector(bytes) => {
+            out.push(TAG_BYTEVECTOR);
+            write_len(out, bytes.len());
+            out.extend_from_slice(bytes);
+        }
+        Expr::HashTable(entries) => {
+            out.push(TAG_HASH_TABLE);
+            write_len(out, entries.len());
+            for (key, value) in entries {
+                write_expr(out, key);
+                write_expr(out, value);
+            }
+        }
+        Expr::Error(message) => {
+            out.push(TAG_ERROR);
+            write_str(out, message);
+        }
+    }
+}
+
+fn write_items(out: &mut Vec<u8>, tag: u8, items: &[Expr]) {
+    out.push(tag);
+    write_len(out, items.len());
+    for item in items {
+        write_expr(out, item);
+    }
+}
+
+struct Input<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Input<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], CompiledError> {
+        let end = self.pos.checked_add(n).filter(|&end| end <= self.bytes.len())
+            .ok_or(CompiledError::Corrupt)?;
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn u32(&mut self) -> Result<u32, CompiledError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn string(&mut self) -> Result<String, CompiledError> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| CompiledError::Corrupt)
+    }
+
+    fn expr(&mut self) -> Result<Expr, CompiledError> {
+        match self.take(1)?[0] {
+            TAG_NUMBER => Ok(Expr::Number(i64::from_le_bytes(self.take(8)?.try_into().unwrap()))),
+            TAG_RATIONAL => {
+                let n = i64::from_le_bytes(self.take(8)?.try_into().unwrap());
+                match i64::from_le_bytes(self.take(8)?.try_into().unwrap()) {
+                    0 => Err(CompiledError::Corrupt),
+                    d => Ok(Expr::Rational(n, d)),
+                }
+            }
+            TAG_FLOAT => Ok(Expr::Float(f64::from_le_bytes(self.take(8)?.try_into().unwrap()))),
+            TAG_BOOLEAN => match self.take(1)?[0] {
+                0 => Ok(Expr::Boolean(false)),
+                1 => Ok(Expr::Boolean(true)),
+                _ => Err(CompiledError::Corrupt),
+            },
+            TAG_STRING => Ok(Expr::String(self.string()?)),
+            TAG_SYMBOL => Ok(Expr::Symbol(self.string()?)),
+            TAG_KEYWORD => Ok(Expr::Keyword(self.string()?)),
+            TAG_CHAR => char::from_u32(self.u32()?).map(Expr::Char).ok_or(CompiledError::Corrupt),
+            TAG_ERROR => Ok(Expr::Error(self.string()?)),
+            TAG_LIST => {
+                let len = self.u32()?;
+                Ok(Expr::List((0..len).map(|_| self.expr()).collect::<Result<_, _>>()?))
+            }
+            TAG_VECTOR => {
+                let len = self.u32()?;
+                Ok(Expr::Vector((0..len).map(|_| self.expr()).collect::<Result<_, _>>()?))
+            }
+            TAG_BYTEVECTOR => {
+                let len = self.u32()? as usize;
+                Ok(Expr::Bytevector(self.take(len)?.to_vec()))
+            }
+            TAG_HASH_TABLE => {
+                let len = self.u32()?;
+                let entries = (0..len)
+                    .map(|_| Ok((self.expr()?, self.expr()?)))
+                    .collect::<Result<_, _>>()?;
+                Ok(Expr::HashTable(entries))
+            }
+            _ => Err(CompiledError::Corrupt),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_all(source: &str) -> Vec<Expr> {
+        parse_program(tokenize(source).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let program = parse_all("(define (f x) (* x 2.5)) \"str\" #t #\\space #:key 'sym #hash((a . 1)) #(1 -3/4) #u8(0 255) (f 21)");
+        assert_eq!(decode(&encode(&program)).unwrap(), program);
+    }
+
+    #[test]
+    fn test_rejects_other_files() {
+        assert_eq!(decode(b"(define x 1)"), Err(CompiledError::NotCompiled));
+        let mut bytes = encode(&parse_all("(list 1 2 3)"));
+        bytes.truncate(bytes.len() - 3);
+        assert_eq!(decode(&bytes), Err(CompiledError::Corrupt));
+    }
+
+    #[test]
+    fn test_detects_stale_versions() {
+        let mut bytes = encode(&parse_all("1"));
+        bytes[4..8].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        assert_eq!(decode(&bytes), Err(CompiledError::Stale));
+    }
+
+    #[test]
+    fn test_stale_artifact_is_recompiled_from_source() {
+        let dir = std::env::temp_dir();
+        let source = dir.join(format!("scheme-rs-{}-stale.scm", std::process::id()));
+        let compiled = source.with_extension(EXTENSION);
+        std::fs::write(&source, "(define answer 42)").unwrap();
+        std::fs::write(&compiled, b"SCMC\xff\xff\xff\xff").unwrap();
+
+        let program = load_compiled(&compiled).unwrap();
+        assert_eq!(program, parse_all("(define answer 42)"));
+        assert_eq!(decode(&std::fs::read(&compiled).unwrap()).unwrap(), program);
+        std::fs::remove_file(&source).unwrap();
+        std::fs::remove_file(&compiled).unwrap();
+    }
+}
@@ -0,0 +1,243 @@
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+
+use crate::ast::Expr;
+use crate::env::{Env, EvalError, Value};
+use crate::eval::{eval, eval_sequence};
+
+/// An error object: what `guard` handlers receive for errors raised by
+/// `error` or by the interpreter itself.
+#[derive(Debug)]
+pub struct Condition {
+    /// What went wrong: `read`, `file`, `arity`, `type`, `undefined`,
+    /// `assertion`, `syntax`, `effect`, `limit`, or `error` for everything else,
+    /// including calls to `error`.
+    pub kind: &'static str,
+    pub message: String,
+    pub irritants: Vec<Value>,
+}
+
+thread_local! {
+    /// The object passed to the `raise` or `error` call being unwound, until
+    /// a `guard` takes it. `EvalError::Raised` only carries its printed form.
+    static RAISED: RefCell<Option<Value>> = const { RefCell::new(None) };
+    /// How many `guard` bodies are being evaluated.
+    static GUARDS: Cell<usize> = const { Cell::new(0) };
+}
+
+/// Returns whether an error raised now would be handled by a `guard`, so
+/// that error handlers (see `eval::set_error_handler`) leave it alone.
+pub fn in_guard() -> bool {
+    GUARDS.with(|g| g.get()) > 0
+}
+
+pub(crate) fn raise(object: Value) -> EvalError {
+    let text = match &object {
+        Value::Condition(condition) => describe(condition),
+        other => other.to_string(),
+    };
+    RAISED.with(|r| *r.borrow_mut() = Some(object));
+    EvalError::Raised(text)
+}
+
+/// Raises a new condition, as the interpreter's own special forms do for
+/// errors `guard` should be able to handle.
+pub(crate) fn raise_condition(kind: &'static str, message: String, irritants: Vec<Value>) -> EvalError {
+    raise(Value::Condition(Rc::new(Condition { kind, message, irritants })))
+}
+
+fn describe(condition: &Condition) -> String {
+    let mut text = condition.message.clone();
+    for irritant in &condition.irritants {
+        text.push(' ');
+        text.push_str(&irritant.to_string());
+    }
+    text
+}
+
+/// Returns the object a `guard` receives for an error: the raised object for
+/// `raise`, or a condition describing any other error. Running out of fuel,
+/// going past the recursion limit, and interruptions cannot be handled, so
+/// these give `None`.
+pub fn error_object(error: &EvalError) -> Option<Value> {
+    let condition = |kind, message: String, irritants| Some(Value::Condition(Rc::new(Condition { kind, message, irritants })));
+    match error {
+        EvalError::Raised(text) => RAISED.with(|r| r.borrow_mut().take())
+            .or_else(|| condition("error", text.clone(), vec![])),
+        EvalError::UndefinedSymbol(name) => condition("undefined", "Undefined symbol".into(), vec![Value::Symbol(name.as_str().into())]),
+        EvalError::TypeError(message) => condition("type", message.clone(), vec![]),
+        EvalError::ArityMismatch => condition("arity", "Wrong number of arguments".into(), vec![]),
+        EvalError::WrongArgumentCount(message) => condition("arity", message.clone(), vec![]),
+        EvalError::NotCallable => condition("type", "Not a procedure".into(), vec![]),
+        EvalError::EffectInPureMode(operation) => condition("effect", "Effect not allowed in pure mode".into(), vec![Value::String(operation.as_str().into())]),
+        EvalError::AssertionFailed(text) => condition("assertion", "Assertion failed".into(), vec![Value::String(text.as_str().into())]),
+        EvalError::DuplicateBinding(name) => condition("syntax", "Duplicate binding".into(), vec![Value::Symbol(name.as_str().into())]),
+        EvalError::Read(message) => condition("read", message.clone(), vec![]),
+        EvalError::File(message) => condition("file", message.clone(), vec![]),
+        EvalError::Other(message) => condition("error", message.clone(), vec![]),
+        EvalError::RecursionLimit | EvalError::OutOfFuel | EvalError::OutOfMemory | EvalError::Interrupted => None,
+    }
+}
+
+/// The kind and message of an error as a `guard` would see them, for
+/// reporting errors outside Scheme. The errors no `guard` can handle have
+/// kinds of their own: `recursion`, `fuel`, `memory`, and `interrupted`.
+pub fn error_summary(error: &EvalError) -> (&'static str, String) {
+    match error {
+        EvalError::Raised(text) => ("error", text.clone()),
+        EvalError::RecursionLimit => ("recursion", "Recursion limit exceeded".into()),
+        EvalError::OutOfFuel => ("fuel", "Out of fuel".into()),
+        EvalError::OutOfMemory => ("memory", "Out of memory".into()),
+        EvalError::Interrupted => ("interrupted", "Interrupted".into()),
+        other => match error_object(other) {
+            Some(Value::Condition(ref condition)) => (condition.kind, describe(condition)),
+            _ => ("error", format!("{:?}", other)),
+        },
+    }
+}
+
+/// `(guard (var clause ...) body ...)` evaluates the body, and if it fails,
+/// binds `var` to the error object and tries each clause like `cond` would:
+/// `(test expr ...)`, or `(else expr ...)` last. If no clause applies, the
+/// error continues to the enclosing handler.
+pub fn eval_guard(list: &[Expr], env: Rc<Env>) -> Result<Value, EvalError> {
+    let (var, clauses, body) = match list {
+        [_, Expr::List(spec), body @ ..] if !body.is_empty() => match &spec[..] {
+            [Expr::Symbol(var), clauses @ ..] => (var, clauses, body),
+            _ => return Err(EvalError::TypeError("Expected (variable clause ...) in guard".into())),
+        },
+        _ => return Err(EvalError::ArityMismatch),
+    };
+    GUARDS.with(|g| g.set(g.get() + 1));
+    let result = eval_sequence(body, env.clone());
+    GUARDS.with(|g| g.set(g.get() - 1));
+    let error = match result {
+        Ok(value) => return Ok(value),
+        Err(error) => error,
+    };
+    let Some(object) = error_object(&error) else { return Err(error) };
+    let handler_env = Env::extend(env);
+    handler_env.define(var.clone(), object.clone());
+    for clause in clauses {
+        let Expr::List(clause) = clause else {
+            return Err(EvalError::TypeError("Expected (test expr ...) clause in guard".into()));
+        };
+        let Some((test, exprs)) = clause.split_first() else {
+            return Err(EvalError::TypeError("Empty clause in guard".into()));
+        };
+        let result = match test {
+            Expr::Symbol(s) if s == "else" => Value::Unspecified,
+            test => eval(test, handler_env.clone())?,
+        };
+        if result == Value::Boolean(false) {
+            continue;
+        }
+        return match exprs {
+            [] => Ok(result),
+            exprs => eval_sequence(exprs, handler_env.clone()),
+        };
+    }
+    Err(raise(object))
+}
+
+/// `(raise obj)` signals an error whose error object, as seen by `guard`,
+/// is `obj` itself.
+pub fn builtin_raise(args: Vec<Value>) -> Result<Value, EvalError> {
+    match <[Value; 1]>::try_from(args) {
+        Ok([object]) => Err(raise(object)),
+        Err(_) => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// `(error message irritant ...)` signals an error with a condition of kind
+/// `error` carrying the message and the other arguments.
+pub fn builtin_error(args: Vec<Value>) -> Result<Value, EvalError> {
+    let mut args = args.into_iter();
+    let message = match args.next() {
+        Some(Value::String(ref message)) => message.to_string(),
+        Some(other) => other.display_string(),
+        None => return Err(EvalError::ArityMismatch),
+    };
+    let condition = Condition { kind: "error", message, irritants: args.collect() };
+    Err(raise(Value::Condition(Rc::new(condition))))
+}
+
+/// Returns true if the argument is an error object.
+pub fn builtin_is_error_object(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [value] => Ok(Value::Boolean(matches!(value, Value::Condition(_)))),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+fn condition_arg(args: &[Value], name: &str) -> Result<Rc<Condition>, EvalError> {
+    match args {
+        [Value::Condition(condition)] => Ok(condition.clone()),
+        [_] => Err(EvalError::TypeError(format!("{} expects an error object", name))),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Returns the message of an error object.
+pub fn builtin_condition_message(args: Vec<Value>) -> Result<Value, EvalError> {
+    Ok(Value::String(condition_arg(&args, "condition-message")?.message.as_str().into()))
+}
+
+/// Returns the irritants of an error object, as a list.
+pub fn builtin_condition_irritants(args: Vec<Value>) -> Result<Value, EvalError> {
+    Ok(Value::List(condition_arg(&args, "condition-irritants")?.irritants.as_slice().into()))
+}
+
+/// Returns the kind of an error object as a symbol, such as `type`.
+pub fn builtin_condition_kind(args: Vec<Value>) -> Result<Value, EvalError> {
+    Ok(Value::Symbol(condition_arg(&args, "condition-kind")?.kind.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::default_env;
+    use crate::testing::eval_source;
+
+    #[test]
+    fn test_guard_branches_on_condition_kind() {
+        let source = "
+            (define (try thunk)
+              (guard (e ((not (error-object? e)) (list 'raised e))
+                        ((equal? (condition-kind e) 'type) (list 'type (condition-message e)))
+                        ((equal? (condition-kind e) 'arity) 'arity)
+                        (else (list (condition-kind e) (condition-message e) (condition-irritants e))))
+                (thunk)))
+            (list (try (lambda () (car 1)))
+                  (try (lambda () ((lambda (x) x) 1 2)))
+                  (try (lambda () (error \"Bad value:\" 42 'x)))
+                  (try (lambda () undefined-name))
+                  (try (lambda () (raise 'oops)))
+                  (try (lambda () 'fine)))";
+        assert_eq!(eval_source(source, &default_env()).unwrap().to_string(),
+            "((type \"Expected non-empty list\") arity (error \"Bad value:\" (42 x)) (undefined \"Undefined symbol\" (undefined-name)) (raised oops) fine)");
+    }
+
+    #[test]
+    fn test_file_errors_have_their_own_kind() {
+        let result = eval_source("(guard (e (#t (condition-kind e))) (load \"/nonexistent/file.scm\"))", &default_env());
+        assert_eq!(result.unwrap(), Value::Symbol("file".into()));
+    }
+
+    #[test]
+    fn test_unhandled_errors_propagate() {
+        let result = eval_source("(guard (e ((equal? e 'other) 'other)) (raise 'oops))", &default_env());
+        assert!(matches!(result, Err(EvalError::Raised(text)) if text == "oops"));
+        let result = eval_source("(guard (e ((equal? e 'inner) e)) (guard (e ((equal? e 'other) 'other)) (raise 'inner)))", &default_env());
+        assert_eq!(result.unwrap(), Value::Symbol("inner".into()));
+        let result = eval_source("(error \"Out of range:\" 5)", &default_env());
+        assert!(matches!(result, Err(EvalError::Raised(text)) if text == "Out of range: 5"));
+    }
+
+    #[test]
+    fn test_resource_limits_cannot_be_handled() {
+        crate::eval::set_recursion_limit(100);
+        let result = eval_source("(define (f) (+ 1 (f))) (guard (e (#t 'caught)) (f))", &default_env());
+        assert!(matches!(result, Err(EvalError::RecursionLimit)));
+    }
+}
@@ -0,0 +1,100 @@
+use wasm_bindgen::prelude::*;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::diagnostics::check_program;
+use crate::env::{default_env, Env};
+use crate::eval::{eval, set_recursion_limit};
+use crate::highlight::{classify_tokens, TokenClass};
+use crate::json::Json;
+use crate::lexer::tokenize;
+use crate::parser::parse;
+use crate::printer::{print_value, PrintOptions};
+
+/// Persistent REPL context
+#[wasm_bindgen]
+pub struct EvalContext {
+    env: Rc<Env>,
+    warnings: RefCell<Vec<String>>,
+}
+
+#[wasm_bindgen]
+impl EvalContext {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> EvalContext {
+        EvalContext {
+            env: default_env(),
+            warnings: RefCell::new(Vec::new()),
+        }
+    }
+
+    /// Sets the maximum evaluation depth; deeper programs fail with an
+    /// error instead of overflowing the stack and killing the page.
+    pub fn set_recursion_limit(&self, limit: usize) {
+        set_recursion_limit(limit);
+    }
+
+    /// Returns the warnings about the last line evaluated, such as unused
+    /// variables, and clears them. They are kept apart from the result so
+    /// that the page can show them differently.
+    pub fn take_warnings(&self) -> Vec<String> {
+        self.warnings.take()
+    }
+
+    /// Classifies the tokens of `input` for highlighting, as a JSON array
+    /// of `{"start", "end", "class"}` objects, with `"depth"` for
+    /// parentheses. Offsets count UTF-16 code units, like JavaScript strings.
+    pub fn classify_tokens(&self, input: &str) -> String {
+        let mut units = 0;
+        let mut last = 0;
+        let mut offset = |byte: usize| {
+            units += input[last..byte].encode_utf16().count();
+            last = byte;
+            Json::Number(units as f64)
+        };
+        let tokens = classify_tokens(input).into_iter().map(|(span, class)| {
+            let mut fields = vec![
+                ("start".to_string(), offset(span.start)),
+                ("end".to_string(), offset(span.end)),
+                ("class".to_string(), class.name().into()),
+            ];
+            if let TokenClass::Paren { depth } = class {
+                fields.push(("depth".to_string(), Json::Number(depth as f64)));
+            }
+            Json::Object(fields)
+        });
+        Json::Array(tokens.collect()).to_string()
+    }
+
+    pub fn eval_line(&self, input: &str) -> String {
+        let trimmed = input.trim();
+
+        if trimmed == "exit" || trimmed == "quit" {
+            return "👋 Goodbye and thanks for all the fish!".to_string();
+        }
+
+        let tokens = match tokenize(trimmed) {
+            Ok(t) => t,
+            Err(e) => return format!("Lex error: {:?}", e),
+        };
+
+        let ast = match parse(tokens) {
+            Ok(a) => a,
+            Err(e) => return format!("Parse error: {:?}", e),
+        };
+
+        *self.warnings.borrow_mut() = check_program(std::slice::from_ref(&ast), &self.env, None)
+            .iter().map(|d| d.to_string()).collect();
+
+        match eval(&ast, self.env.clone()) {
+            Ok(val) => print_value(&val, &PrintOptions::from_env(&self.env)),
+            Err(e) => format!("Eval error: {:?}", e),
+        }
+    }
+}
+
+impl Default for EvalContext {
+    fn default() -> Self {
+        Self::new()
+    }
+}
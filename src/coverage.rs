@@ -0,0 +1,221 @@
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::rc::Rc;
+
+use crate::ast::Expr;
+use crate::env::{Env, EvalError, Lambda, Value};
+use crate::eval::{eval, EvalHooks};
+use crate::lexer::Token;
+
+/// Records which expressions of a program are evaluated, as evaluation
+/// hooks, and reports the results per source line.
+///
+/// Expressions are recognized by address, so a program must stay alive and
+/// unmodified between `register` and the end of its evaluation. Closures
+/// created while recording are kept alive too, since their bodies are
+/// copies of the source that must not be freed and their addresses reused.
+#[derive(Default)]
+pub struct Coverage {
+    lines: RefCell<HashMap<*const Expr, usize>>,
+    hits: RefCell<HashMap<*const Expr, usize>>,
+    closures: RefCell<Vec<Rc<Lambda>>>,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers the expressions of a program, as parsed from `tokens`, with
+    /// the lines they start on. Quoted data is not code, so it is skipped.
+    pub fn register(&self, program: &[Expr], tokens: &[(Token, usize)]) {
+        let mut tokens = tokens.iter();
+        let mut lines = self.lines.borrow_mut();
+        for expr in program {
+            assign_lines(expr, &mut tokens, &mut lines, true);
+        }
+    }
+
+    /// Returns, for every line on which a registered expression starts, the
+    /// most times any expression starting there was evaluated.
+    pub fn line_hits(&self) -> BTreeMap<usize, usize> {
+        let hits = self.hits.borrow();
+        let mut result = BTreeMap::new();
+        for (expr, &line) in self.lines.borrow().iter() {
+            let count = hits.get(expr).copied().unwrap_or(0);
+            let entry = result.entry(line).or_insert(0);
+            *entry = count.max(*entry);
+        }
+        result
+    }
+
+    /// Returns the source with each line prefixed by its hit count, `#####`
+    /// for code that never ran, or nothing for lines without code.
+    pub fn annotate(&self, source: &str) -> String {
+        let hits = self.line_hits();
+        let mut out = String::new();
+        for (i, text) in source.lines().enumerate() {
+            let count = match hits.get(&(i + 1)) {
+                Some(0) => "#####".to_string(),
+                Some(n) => n.to_string(),
+                None => String::new(),
+            };
+            out.push_str(&format!("{:>6} | {}\n", count, text));
+        }
+        out
+    }
+
+    /// Returns the line coverage in LCOV tracefile format.
+    pub fn lcov(&self, source_path: &str) -> String {
+        let hits = self.line_hits();
+        let mut out = format!("SF:{}\n", source_path);
+        for (line, count) in &hits {
+            out.push_str(&format!("DA:{},{}\n", line, count));
+        }
+        out.push_str(&format!("LH:{}\n", hits.values().filter(|&&n| n > 0).count()));
+        out.push_str(&format!("LF:{}\n", hits.len()));
+        out.push_str("end_of_record\n");
+        out
+    }
+}
+
+impl EvalHooks for Coverage {
+    fn on_eval_enter(&self, expr: &Expr, _env: &Rc<Env>) {
+        let ptr = expr as *const Expr;
+        if self.lines.borrow().contains_key(&ptr) {
+            *self.hits.borrow_mut().entry(ptr).or_insert(0) += 1;
+        }
+    }
+
+    fn on_lambda(&self, source: &[Expr], lambda: &Rc<Lambda>) {
+        // A body of several expressions is wrapped in `begin`; see `make_lambda`.
+        let copies = match (source, &lambda.body) {
+            ([single], body) => vec![(single, body)],
+            (_, Expr::List(items)) => source.iter().zip(&items[1..]).collect(),
+            _ => return,
+        };
+        let mut lines = self.lines.borrow_mut();
+        for (original, copy) in copies {
+            copy_lines(original, copy, &mut lines);
+        }
+        self.closures.borrow_mut().push(lambda.clone());
+    }
+}
+
+/// Walks an expression in the order the parser built it, consuming its
+/// tokens and recording the line of each expression's first token.
+pub(crate) fn assign_lines<'a>(
+    expr: &Expr,
+    tokens: &mut impl Iterator<Item = &'a (Token, usize)>,
+    lines: &mut HashMap<*const Expr, usize>,
+    is_code: bool,
+) {
+    let Some((token, line)) = tokens.next() else { return };
+    if is_code {
+        lines.insert(expr, *line);
+    }
+    match (token, expr) {
+        (Token::Quote, Expr::List(items)) if items.len() == 2 => assign_lines(&items[1], tokens, lines, false),
+        (Token::LParen, Expr::List(items)) => {
+            let is_quote = matches!(items.first(), Some(Expr::Symbol(s)) if s == "quote");
+            for item in items {
+                assign_lines(item, tokens, lines, is_code && !is_quote);
+            }
+            tokens.next(); // the closing parenthesis
+        }
+        (Token::HashTableOpen | Token::VectorOpen | Token::BytevectorOpen, _) => {
+            let mut depth = 1;
+            while depth > 0 {
+                match tokens.next() {
+                    Some((Token::LParen | Token::HashTableOpen | Token::VectorOpen | Token::BytevectorOpen, _)) => depth += 1,
+                    Some((Token::RParen, _)) => depth -= 1,
+                    Some(_) => {}
+                    None => break,
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Gives each expression in `copy` the line of its counterpart in `original`.
+pub(crate) fn copy_lines(original: &Expr, copy: &Expr, lines: &mut HashMap<*const Expr, usize>) {
+    let mut pending = vec![(original, copy)];
+    while let Some((original, copy)) = pending.pop() {
+        if let Some(&line) = lines.get(&(original as *const Expr)) {
+            lines.insert(copy, line);
+        }
+        if let (Expr::List(xs), Expr::List(ys)) = (original, copy) {
+            pending.extend(xs.iter().zip(ys));
+        }
+    }
+}
+
+/// Evaluates a program with coverage recording, stopping at the first error.
+/// Returns the coverage together with the result of the last form.
+pub fn eval_with_coverage(
+    program: &[Expr],
+    tokens: &[(Token, usize)],
+    env: Rc<Env>,
+) -> (Rc<Coverage>, Result<Value, EvalError>) {
+    let coverage = Rc::new(Coverage::new());
+    coverage.register(program, tokens);
+    crate::eval::set_hooks(Some(coverage.clone()));
+    let mut result = Ok(Value::Unspecified);
+    for expr in program {
+        result = eval(expr, env.clone());
+        if result.is_err() {
+            break;
+        }
+    }
+    crate::eval::set_hooks(None);
+    (coverage, result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::default_env;
+    use crate::lexer::tokenize_with_lines;
+    use crate::parser::parse_program;
+
+    fn run(source: &str) -> Rc<Coverage> {
+        let tokens = tokenize_with_lines(source).unwrap();
+        let program = parse_program(tokens.iter().map(|(t, _)| t.clone()).collect()).unwrap();
+        let (coverage, result) = eval_with_coverage(&program, &tokens, default_env());
+        result.unwrap();
+        coverage
+    }
+
+    const SOURCE: &str = "\
+(define (sign n)
+  (if (< n 0)
+      'negative
+      'positive))
+(sign 5)
+(sign 7)
+'(not
+  code)";
+
+    #[test]
+    fn test_line_hits() {
+        let hits = run(SOURCE).line_hits();
+        let expected: BTreeMap<usize, usize> = [(1, 1), (2, 2), (3, 0), (4, 2), (5, 1), (6, 1), (7, 1)].into();
+        assert_eq!(hits, expected);
+    }
+
+    #[test]
+    fn test_annotate() {
+        let report = run(SOURCE).annotate(SOURCE);
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(lines[1], "     2 |   (if (< n 0)");
+        assert_eq!(lines[2], " ##### |       'negative");
+        assert_eq!(lines[7], "       |   code)");
+    }
+
+    #[test]
+    fn test_lcov() {
+        let lcov = run("(define x 1)\n(if #t x\n  (car x))").lcov("test.scm");
+        assert_eq!(lcov, "SF:test.scm\nDA:1,1\nDA:2,1\nDA:3,0\nLH:2\nLF:3\nend_of_record\n");
+    }
+}
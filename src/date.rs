@@ -0,0 +1,537 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::env::{EvalError, Value};
+use crate::eval::check_effect;
+use crate::record::{Record, RecordType};
+
+#[cfg(browser)]
+use wasm_bindgen::prelude::*;
+
+const MONTHS: [&str; 12] = [
+    "January", "February", "March", "April", "May", "June",
+    "July", "August", "September", "October", "November", "December",
+];
+
+const WEEK_DAYS: [&str; 7] = ["Sunday", "Monday", "Tuesday", "Wednesday", "Thursday", "Friday", "Saturday"];
+
+/// The format `date->string` uses when given none: ISO 8601 with the zone.
+const DEFAULT_FORMAT: &str = "~4";
+
+thread_local! {
+    /// The record type of dates, so that they print and compare like other
+    /// records. Created once per thread because records are not `Send`.
+    static DATE_TYPE: Rc<RecordType> = Rc::new(RecordType {
+        name: "<date>".into(),
+        fields: ["nanosecond", "second", "minute", "hour", "day", "month", "year", "zone-offset"]
+            .map(String::from).to_vec(),
+        printer: RefCell::new(Some(Value::Function(print_date))),
+    });
+}
+
+#[cfg(browser)]
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_name = Date)]
+    type JsDate;
+
+    #[wasm_bindgen(constructor, js_class = "Date")]
+    fn new(ms: f64) -> JsDate;
+
+    #[wasm_bindgen(method, js_class = "Date", js_name = getTimezoneOffset)]
+    fn timezone_offset(this: &JsDate) -> f64;
+
+    #[wasm_bindgen(static_method_of = JsDate, js_class = "Date")]
+    fn now() -> f64;
+}
+
+/// A calendar date and time of day in a fixed offset from UTC, as in
+/// SRFI 19. Scheme code sees it as a `<date>` record.
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Date {
+    nanosecond: i64,
+    second: i64,
+    minute: i64,
+    hour: i64,
+    day: i64,
+    month: i64,
+    year: i64,
+    /// Seconds east of UTC.
+    zone_offset: i64,
+}
+
+impl Date {
+    /// The date `seconds` after the Unix epoch, seen from `zone_offset`.
+    fn from_unix(seconds: i64, nanosecond: i64, zone_offset: i64) -> Date {
+        let local = seconds + zone_offset;
+        let (year, month, day) = civil_from_days(local.div_euclid(86400));
+        let time = local.rem_euclid(86400);
+        Date { nanosecond, second: time % 60, minute: time / 60 % 60, hour: time / 3600, day, month, year, zone_offset }
+    }
+
+    fn validate(self) -> Result<Date, EvalError> {
+        let valid = (1..=12).contains(&self.month)
+            && (1..=days_in_month(self.year, self.month)).contains(&self.day)
+            && (0..24).contains(&self.hour)
+            && (0..60).contains(&self.minute)
+            && (0..=60).contains(&self.second)
+            && (0..1_000_000_000).contains(&self.nanosecond)
+            && (-18 * 3600..=18 * 3600).contains(&self.zone_offset);
+        if valid { Ok(self) } else { Err(EvalError::TypeError("Invalid date".into())) }
+    }
+
+    fn days(self) -> i64 {
+        days_from_civil(self.year, self.month, self.day)
+    }
+
+    /// Day of the week, from 0 for Sunday.
+    fn week_day(self) -> i64 {
+        (self.days() + 4).rem_euclid(7)
+    }
+
+    /// Day of the year, from 1 for January 1st.
+    fn year_day(self) -> i64 {
+        self.days() - days_from_civil(self.year, 1, 1) + 1
+    }
+
+    fn to_value(self) -> Value {
+        let fields = [self.nanosecond, self.second, self.minute, self.hour, self.day, self.month, self.year, self.zone_offset]
+            .map(Value::Number).to_vec();
+        Value::Record(Rc::new(Record { rtd: DATE_TYPE.with(Rc::clone), fields: RefCell::new(fields) }))
+    }
+
+    fn from_value(value: &Value, name: &str) -> Result<Date, EvalError> {
+        let fields = match value {
+            Value::Record(record) if DATE_TYPE.with(|t| Rc::ptr_eq(&record.rtd, t)) => record.fields.borrow().clone(),
+            _ => return Err(EvalError::TypeError(format!("{} expects a date", name))),
+        };
+        let field = |i: usize| match fields[i] {
+            Value::Number(n) => n,
+            _ => 0,
+        };
+        Ok(Date {
+            nanosecond: field(0), second: field(1), minute: field(2), hour: field(3),
+            day: field(4), month: field(5), year: field(6), zone_offset: field(7),
+        })
+    }
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date.
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146097 + day_of_era - 719468
+}
+
+/// The proleptic Gregorian date that is `days` after 1970-01-01.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    (year_of_era + era * 400 + i64::from(month <= 2), month, day)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// The current time as seconds and nanoseconds since the Unix epoch.
+#[cfg(not(browser))]
+fn now() -> (i64, i64) {
+    let since_epoch = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    (since_epoch.as_secs() as i64, i64::from(since_epoch.subsec_nanos()))
+}
+
+#[cfg(browser)]
+fn now() -> (i64, i64) {
+    let ms = JsDate::now();
+    let seconds = (ms / 1000.0).floor();
+    (seconds as i64, ((ms - seconds * 1000.0) * 1_000_000.0) as i64)
+}
+
+/// The local time zone's offset from UTC, in seconds, at the given time.
+#[cfg(unix)]
+fn local_offset(seconds: i64) -> i64 {
+    let time = seconds as libc::time_t;
+    // SAFETY: `tm` is plain data that `localtime_r` fills in.
+    let mut tm: libc::tm = unsafe { std::mem::zeroed() };
+    if unsafe { libc::localtime_r(&time, &mut tm) }.is_null() {
+        return 0;
+    }
+    tm.tm_gmtoff as i64
+}
+
+#[cfg(browser)]
+fn local_offset(seconds: i64) -> i64 {
+    -(JsDate::new(seconds as f64 * 1000.0).timezone_offset() as i64) * 60
+}
+
+#[cfg(not(any(unix, browser)))]
+fn local_offset(_seconds: i64) -> i64 {
+    0
+}
+
+/// Writes a date following a format, in which `~` starts a directive:
+///
+/// - `~Y` year, `~y` last two digits of the year
+/// - `~m` month, `~b` and `~B` its abbreviated and full name
+/// - `~d` day of the month, `~e` the same padded with a space
+/// - `~a` and `~A` abbreviated and full name of the day of the week
+/// - `~j` day of the year
+/// - `~H` hour, `~M` minute, `~S` second, `~N` nanosecond
+/// - `~z` zone offset as `+hhmm`
+/// - `~T` for `~H:~M:~S`, `~D` for `~m/~d/~y`, `~1` for `~Y-~m-~d`,
+///   `~3` for `~H:~M:~S~z`, `~4` for `~Y-~m-~dT~H:~M:~S~z`, and `~5` for
+///   `~Y-~m-~dT~H:~M:~S`
+/// - `~~` a tilde
+fn format_date(date: Date, format: &str) -> Result<String, EvalError> {
+    let mut out = String::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '~' {
+            out.push(c);
+            continue;
+        }
+        let directive = chars.next().ok_or_else(|| EvalError::TypeError("date->string: format ends with ~".into()))?;
+        if let Some(expansion) = composite(directive) {
+            out.push_str(&format_date(date, expansion)?);
+            continue;
+        }
+        let text = match directive {
+            '~' => "~".into(),
+            'Y' => date.year.to_string(),
+            'y' => format!("{:02}", date.year.rem_euclid(100)),
+            'm' => format!("{:02}", date.month),
+            'b' => MONTHS[date.month as usize - 1][..3].into(),
+            'B' => MONTHS[date.month as usize - 1].into(),
+            'd' => format!("{:02}", date.day),
+            'e' => format!("{:2}", date.day),
+            'a' => WEEK_DAYS[date.week_day() as usize][..3].into(),
+            'A' => WEEK_DAYS[date.week_day() as usize].into(),
+            'j' => format!("{:03}", date.year_day()),
+            'H' => format!("{:02}", date.hour),
+            'M' => format!("{:02}", date.minute),
+            'S' => format!("{:02}", date.second),
+            'N' => format!("{:09}", date.nanosecond),
+            'z' => {
+                let sign = if date.zone_offset < 0 { '-' } else { '+' };
+                let minutes = date.zone_offset.abs() / 60;
+                format!("{}{:02}{:02}", sign, minutes / 60, minutes % 60)
+            }
+            other => return Err(EvalError::TypeError(format!("date->string: unknown directive ~{}", other))),
+        };
+        out.push_str(&text);
+    }
+    Ok(out)
+}
+
+/// Directives that stand for a sequence of others.
+fn composite(directive: char) -> Option<&'static str> {
+    match directive {
+        'T' => Some("~H:~M:~S"),
+        'D' => Some("~m/~d/~y"),
+        '1' => Some("~Y-~m-~d"),
+        '3' => Some("~H:~M:~S~z"),
+        '4' => Some("~Y-~m-~dT~H:~M:~S~z"),
+        '5' => Some("~Y-~m-~dT~H:~M:~S"),
+        _ => None,
+    }
+}
+
+/// Reads a date written in a format, with the directives of `format_date`
+/// except `~j`. Fields the format leaves out default to the start of their
+/// range, and the zone to UTC; `~z` also accepts `Z` and `+hh:mm`.
+fn parse_date(text: &str, format: &str) -> Result<Date, EvalError> {
+    let fail = || EvalError::Other(format!("string->date: {:?} does not match {:?}", text, format));
+    let mut date = Date { nanosecond: 0, second: 0, minute: 0, hour: 0, day: 1, month: 1, year: 1970, zone_offset: 0 };
+    let mut rest = text;
+    let expanded = expand_composites(format);
+    let mut chars = expanded.chars();
+    while let Some(c) = chars.next() {
+        let directive = match c {
+            '~' => chars.next().ok_or_else(fail)?,
+            literal => {
+                rest = rest.strip_prefix(literal).ok_or_else(fail)?;
+                continue;
+            }
+        };
+        match directive {
+            '~' => rest = rest.strip_prefix('~').ok_or_else(fail)?,
+            'Y' => {
+                let negative = rest.starts_with('-');
+                let (year, after) = take_digits(&rest[usize::from(negative)..], 1, 9).ok_or_else(fail)?;
+                date.year = if negative { -year } else { year };
+                rest = after;
+            }
+            'y' => (date.year, rest) = take_digits(rest, 2, 2).map(|(y, r)| (2000 + y, r)).ok_or_else(fail)?,
+            'm' => (date.month, rest) = take_digits(rest, 1, 2).ok_or_else(fail)?,
+            'd' => (date.day, rest) = take_digits(rest, 1, 2).ok_or_else(fail)?,
+            'e' => (date.day, rest) = take_digits(rest.trim_start_matches(' '), 1, 2).ok_or_else(fail)?,
+            'H' => (date.hour, rest) = take_digits(rest, 1, 2).ok_or_else(fail)?,
+            'M' => (date.minute, rest) = take_digits(rest, 1, 2).ok_or_else(fail)?,
+            'S' => (date.second, rest) = take_digits(rest, 1, 2).ok_or_else(fail)?,
+            'N' => {
+                let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+                let (fraction, after) = take_digits(rest, 1, digits.min(9)).ok_or_else(fail)?;
+                date.nanosecond = fraction * 10i64.pow(9 - (rest.len() - after.len()) as u32);
+                rest = after;
+            }
+            'b' | 'B' => {
+                let index = MONTHS.iter().position(|month| {
+                    let name = if directive == 'b' { &month[..3] } else { month };
+                    rest.get(..name.len()).is_some_and(|prefix| prefix.eq_ignore_ascii_case(name))
+                }).ok_or_else(fail)?;
+                date.month = index as i64 + 1;
+                rest = &rest[if directive == 'b' { 3 } else { MONTHS[index].len() }..];
+            }
+            'a' | 'A' => {
+                let name = WEEK_DAYS.iter()
+                    .map(|day| if directive == 'a' { &day[..3] } else { day })
+                    .find(|name| rest.get(..name.len()).is_some_and(|prefix| prefix.eq_ignore_ascii_case(name)))
+                    .ok_or_else(fail)?;
+                rest = &rest[name.len()..];
+            }
+            'z' => (date.zone_offset, rest) = take_zone(rest).ok_or_else(fail)?,
+            other => return Err(EvalError::TypeError(format!("string->date: unknown directive ~{}", other))),
+        }
+    }
+    if !rest.is_empty() {
+        return Err(fail());
+    }
+    date.validate()
+}
+
+fn expand_composites(format: &str) -> String {
+    let mut out = String::new();
+    let mut chars = format.chars();
+    while let Some(c) = chars.next() {
+        if c != '~' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some(directive) => match composite(directive) {
+                Some(expansion) => out.push_str(&expand_composites(expansion)),
+                None => out.extend(['~', directive]),
+            },
+            None => out.push('~'),
+        }
+    }
+    out
+}
+
+/// Reads between `min` and `max` ASCII digits from the start of `text`.
+fn take_digits(text: &str, min: usize, max: usize) -> Option<(i64, &str)> {
+    let len = text.bytes().take(max).take_while(u8::is_ascii_digit).count();
+    if len < min {
+        return None;
+    }
+    Some((text[..len].parse().ok()?, &text[len..]))
+}
+
+fn take_zone(text: &str) -> Option<(i64, &str)> {
+    if let Some(rest) = text.strip_prefix('Z') {
+        return Some((0, rest));
+    }
+    let sign = match text.chars().next()? {
+        '+' => 1,
+        '-' => -1,
+        _ => return None,
+    };
+    let (hours, rest) = take_digits(&text[1..], 2, 2)?;
+    let rest = rest.strip_prefix(':').unwrap_or(rest);
+    let (minutes, rest) = take_digits(rest, 2, 2)?;
+    Some((sign * (hours * 3600 + minutes * 60), rest))
+}
+
+fn integer_arg(value: &Value, name: &str) -> Result<i64, EvalError> {
+    match value {
+        Value::Number(n) => Ok(*n),
+        _ => Err(EvalError::TypeError(format!("{} expects integers", name))),
+    }
+}
+
+fn string_arg<'a>(value: &'a Value, name: &str) -> Result<&'a str, EvalError> {
+    match value {
+        Value::String(s) => Ok(s),
+        _ => Err(EvalError::TypeError(format!("{} expects a format string", name))),
+    }
+}
+
+fn print_date(args: Vec<Value>) -> Result<Value, EvalError> {
+    let [date] = <[Value; 1]>::try_from(args).map_err(|_| EvalError::ArityMismatch)?;
+    let date = Date::from_value(&date, "date->string")?;
+    Ok(Value::String(format!("#<date {}>", format_date(date, DEFAULT_FORMAT)?).into()))
+}
+
+/// `(current-date [zone-offset])` returns the current date in the local time
+/// zone, or at an offset from UTC in seconds.
+pub fn builtin_current_date(args: Vec<Value>) -> Result<Value, EvalError> {
+    check_effect("current-date")?;
+    let (seconds, nanosecond) = now();
+    let zone_offset = match &args[..] {
+        [] => local_offset(seconds),
+        [offset] => integer_arg(offset, "current-date")?,
+        _ => return Err(EvalError::ArityMismatch),
+    };
+    Ok(Date::from_unix(seconds, nanosecond, zone_offset).validate()?.to_value())
+}
+
+/// `(make-date nanosecond second minute hour day month year zone-offset)`
+/// returns a date, failing if the fields do not describe one.
+pub fn builtin_make_date(args: Vec<Value>) -> Result<Value, EvalError> {
+    let fields = <[Value; 8]>::try_from(args).map_err(|_| EvalError::ArityMismatch)?;
+    let [nanosecond, second, minute, hour, day, month, year, zone_offset] = fields.each_ref().map(|f| integer_arg(f, "make-date"));
+    let date = Date { nanosecond: nanosecond?, second: second?, minute: minute?, hour: hour?, day: day?, month: month?, year: year?, zone_offset: zone_offset? };
+    Ok(date.validate()?.to_value())
+}
+
+/// Returns true if the argument is a date.
+pub fn builtin_is_date(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [value] => Ok(Value::Boolean(Date::from_value(value, "date?").is_ok())),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+fn date_field(args: &[Value], name: &str, field: fn(Date) -> i64) -> Result<Value, EvalError> {
+    match args {
+        [date] => Ok(Value::Number(field(Date::from_value(date, name)?))),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Returns the nanosecond of a date.
+pub fn builtin_date_nanosecond(args: Vec<Value>) -> Result<Value, EvalError> {
+    date_field(&args, "date-nanosecond", |d| d.nanosecond)
+}
+
+/// Returns the second of a date.
+pub fn builtin_date_second(args: Vec<Value>) -> Result<Value, EvalError> {
+    date_field(&args, "date-second", |d| d.second)
+}
+
+/// Returns the minute of a date.
+pub fn builtin_date_minute(args: Vec<Value>) -> Result<Value, EvalError> {
+    date_field(&args, "date-minute", |d| d.minute)
+}
+
+/// Returns the hour of a date.
+pub fn builtin_date_hour(args: Vec<Value>) -> Result<Value, EvalError> {
+    date_field(&args, "date-hour", |d| d.hour)
+}
+
+/// Returns the day of the month of a date.
+pub fn builtin_date_day(args: Vec<Value>) -> Result<Value, EvalError> {
+    date_field(&args, "date-day", |d| d.day)
+}
+
+/// Returns the month of a date, from 1 for January.
+pub fn builtin_date_month(args: Vec<Value>) -> Result<Value, EvalError> {
+    date_field(&args, "date-month", |d| d.month)
+}
+
+/// Returns the year of a date.
+pub fn builtin_date_year(args: Vec<Value>) -> Result<Value, EvalError> {
+    date_field(&args, "date-year", |d| d.year)
+}
+
+/// Returns the offset of a date's time zone from UTC, in seconds.
+pub fn builtin_date_zone_offset(args: Vec<Value>) -> Result<Value, EvalError> {
+    date_field(&args, "date-zone-offset", |d| d.zone_offset)
+}
+
+/// Returns the day of the week of a date, from 0 for Sunday.
+pub fn builtin_date_week_day(args: Vec<Value>) -> Result<Value, EvalError> {
+    date_field(&args, "date-week-day", Date::week_day)
+}
+
+/// Returns the day of the year of a date, from 1 for January 1st.
+pub fn builtin_date_year_day(args: Vec<Value>) -> Result<Value, EvalError> {
+    date_field(&args, "date-year-day", Date::year_day)
+}
+
+/// `(date->string date [format])` writes a date following a format of `~`
+/// directives, such as `"~Y-~m-~d ~H:~M"`; without one, as ISO 8601.
+pub fn builtin_date_to_string(args: Vec<Value>) -> Result<Value, EvalError> {
+    let (date, format) = match &args[..] {
+        [date] => (date, DEFAULT_FORMAT),
+        [date, format] => (date, string_arg(format, "date->string")?),
+        _ => return Err(EvalError::ArityMismatch),
+    };
+    Ok(Value::String(format_date(Date::from_value(date, "date->string")?, format)?.into()))
+}
+
+/// `(string->date text format)` reads a date written in a format of the
+/// directives `date->string` takes.
+pub fn builtin_string_to_date(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [Value::String(text), format] => Ok(parse_date(text, string_arg(format, "string->date")?)?.to_value()),
+        [_, _] => Err(EvalError::TypeError("string->date expects a string".into())),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::default_env;
+    use crate::testing::eval_source;
+
+    #[test]
+    fn test_civil_days_round_trip() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(civil_from_days(days_from_civil(2000, 2, 29)), (2000, 2, 29));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        let date = Date::from_unix(1_700_000_000, 0, 3600);
+        assert_eq!((date.year, date.month, date.day, date.hour, date.minute), (2023, 11, 14, 23, 13));
+    }
+
+    #[test]
+    fn test_date_accessors_and_formatting() {
+        let source = "
+            (define d (make-date 5000000 7 30 9 3 3 2024 (- 0 18000)))
+            (list (date-year d) (date-month d) (date-day d) (date-week-day d) (date-year-day d)
+                  (date->string d)
+                  (date->string d \"~A, ~B ~e ~y ~H:~M:~S.~N ~~\")
+                  (date? d) (date? 5))";
+        assert_eq!(eval_source(source, &default_env()).unwrap().to_string(),
+            "(2024 3 3 0 63 \"2024-03-03T09:30:07-0500\" \"Sunday, March  3 24 09:30:07.005000000 ~\" #t #f)");
+        assert_eq!(eval_source("(make-date 0 0 0 0 1 1 2024 0)", &default_env()).unwrap().to_string(), "#<date 2024-01-01T00:00:00+0000>");
+    }
+
+    #[test]
+    fn test_string_to_date_reads_what_date_to_string_writes() {
+        let source = "
+            (define d (string->date \"2024-02-29T23:59:60+05:30\" \"~4\"))
+            (list (date-month d) (date-second d) (date-zone-offset d)
+                  (date->string (string->date \"mar 9 2021, 10:05:01.25Z\" \"~b ~d ~Y, ~T.~N~z\") \"~5 ~N\"))";
+        assert_eq!(eval_source(source, &default_env()).unwrap().to_string(), "(2 60 19800 \"2021-03-09T10:05:01 250000000\")");
+        assert!(matches!(eval_source("(string->date \"2023-02-29\" \"~1\")", &default_env()), Err(EvalError::TypeError(_))));
+        assert!(matches!(eval_source("(string->date \"2023-01-01x\" \"~1\")", &default_env()), Err(EvalError::Other(_))));
+    }
+
+    #[test]
+    fn test_current_date_at_offset() {
+        let date = builtin_current_date(vec![Value::Number(3600)]).unwrap();
+        let date = Date::from_value(&date, "test").unwrap();
+        assert_eq!(date.zone_offset, 3600);
+        assert!(date.year >= 2024);
+        assert!(Date::from_value(&builtin_current_date(vec![]).unwrap(), "test").is_ok());
+    }
+}
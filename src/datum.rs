@@ -0,0 +1,132 @@
+//! Reading values back from the text `Value::write_string` gives them, so
+//! that data produced by Scheme code can be saved and restored.
+//!
+//! Values are read with the program reader, as quoted data would be, so
+//! the two accept the same syntax.
+
+use std::collections::HashSet;
+use std::rc::Rc;
+
+use crate::env::Value;
+use crate::eval::quote_expr;
+use crate::lexer::tokenize;
+use crate::parser::parse_datum;
+
+impl Value {
+    /// Reads one value from its written form, so that
+    /// `Value::parse(&v.write_string())` is `Ok(v)` for every value `v`
+    /// for which `is_readable` holds. Surrounding whitespace is allowed;
+    /// anything else after the value is an error.
+    pub fn parse(text: &str) -> Result<Value, String> {
+        let mut tokens = tokenize(text).map_err(|e| format!("{:?}", e))?.into_iter().peekable();
+        let datum = parse_datum(&mut tokens).map_err(|e| format!("{:?}", e))?;
+        if let Some(token) = tokens.next() {
+            return Err(format!("Unexpected {:?} after the value", token));
+        }
+        Ok(quote_expr(&datum))
+    }
+
+    /// Whether the value's written form reads back as an equal value with
+    /// `Value::parse`: true of numbers (infinities and NaN included),
+    /// booleans, characters, strings, keywords, symbols other than `.`, and
+    /// lists, vectors, bytevectors, and hash tables of such values that do
+    /// not contain themselves. Procedures, ports, records, and the like
+    /// print as `#<...>` and cannot be read.
+    pub fn is_readable(&self) -> bool {
+        let mut open = HashSet::new();
+        readable(self, &mut open)
+    }
+}
+
+fn readable(value: &Value, open: &mut HashSet<*const ()>) -> bool {
+    let mut within = |ptr: *const (), items: Vec<Value>| {
+        if !open.insert(ptr) {
+            return false;
+        }
+        let all = items.iter().all(|item| readable(item, open));
+        open.remove(&ptr);
+        all
+    };
+    match value {
+        Value::Number(_) | Value::Rational(..) | Value::Boolean(_) | Value::Char(_) | Value::String(_)
+        | Value::Keyword(_) | Value::Bytevector(_) | Value::Float(_) => true,
+        // Read alone, `.` is the dot of `(a . rest)`.
+        Value::Symbol(name) => **name != *".",
+        Value::List(items) => items.iter().all(|item| readable(item, open)),
+        Value::Vector(items) => within(Rc::as_ptr(items).cast(), items.borrow().clone()),
+        Value::HashTable(table) => {
+            let items = table.borrow().iter().flat_map(|(k, v)| [k.clone(), v.clone()]).collect();
+            within(Rc::as_ptr(table).cast(), items)
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use crate::number::Number;
+    use proptest::prelude::*;
+
+    #[test]
+    fn test_parse() {
+        let value = Value::parse(" (1 -2 3/4 -0.5 #t \"a \\\"b\\\"\\n\" #\\space #\\( sym #:key #(1 #u8(0 255)) #hash((a . (1)))) ").unwrap();
+        assert_eq!(value.write_string(), "(1 -2 3/4 -0.5 #t \"a \\\"b\\\"\\n\" #\\space #\\( sym #:key #(1 #u8(0 255)) #hash((a . (1))))");
+        assert_eq!(Value::parse("6/4").unwrap(), Value::Rational(3, 2));
+        assert_eq!(Value::parse("(1/3 1 /3)").unwrap().write_string(), "(1/3 1 /3)");
+        assert_eq!(Value::parse("|a \\| b|").unwrap(), Value::Symbol("a | b".into()));
+        for bad in ["(1 2", ")", "1 2", "(1 . 2)", "#(1 . 2)", ".", "#hash(1)", "#u8(256)", "1/0", "#<eof>", ""] {
+            assert!(Value::parse(bad).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn test_is_readable() {
+        let vector = Value::Vector(Rc::new(RefCell::new(vec![Value::Number(1)])));
+        assert!(Value::List([vector.clone(), vector.clone()].into()).is_readable());
+        if let Value::Vector(items) = &vector {
+            items.borrow_mut().push(vector.clone());
+        }
+        assert!(!vector.is_readable());
+        assert!(Value::Float(f64::NAN).is_readable());
+        assert!(!Value::Symbol(".".into()).is_readable());
+        for name in ["a b", "12", "", "#x", "|", "a;b"] {
+            let symbol = Value::Symbol(name.into());
+            assert_eq!(Value::parse(&symbol.write_string()), Ok(symbol.clone()), "{}", symbol.write_string());
+        }
+        assert_eq!(Value::Symbol("a b".into()).write_string(), "|a b|");
+        assert!(!Value::Eof.is_readable());
+    }
+
+    fn value() -> impl Strategy<Value = Value> {
+        let atom = prop_oneof![
+            any::<i64>().prop_map(Value::Number),
+            (any::<i64>(), 1..i64::MAX).prop_map(|(n, d)| Number::rational(n, d).unwrap().into()),
+            any::<f64>().prop_map(Value::Float),
+            any::<bool>().prop_map(Value::Boolean),
+            any::<String>().prop_map(|s| Value::String(s.as_str().into())),
+            any::<char>().prop_map(Value::Char),
+            "[a-z][a-z0-9-]{0,8}".prop_map(|k| Value::Keyword(k.as_str().into())),
+            "[a-z!$%&*/<=>?^_~+-][a-z0-9!$%&*/<=>?^_~+.-]{0,8}".prop_map(|s| Value::Symbol(s.as_str().into())),
+            any::<String>().prop_map(|s| Value::Symbol(s.as_str().into())),
+            prop::collection::vec(any::<u8>(), 0..4).prop_map(|b| Value::Bytevector(Rc::new(RefCell::new(b)))),
+        ];
+        atom.prop_filter("readable", Value::is_readable).prop_recursive(4, 64, 6, |inner| {
+            prop_oneof![
+                prop::collection::vec(inner.clone(), 0..6).prop_map(|items| Value::List(items.into())),
+                prop::collection::vec(inner.clone(), 0..6).prop_map(|items| Value::Vector(Rc::new(RefCell::new(items)))),
+                prop::collection::vec((inner.clone(), inner), 0..4)
+                    .prop_map(|entries| Value::HashTable(Rc::new(RefCell::new(entries.into_iter().collect())))),
+            ]
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn test_written_values_parse_back_unchanged(v in value()) {
+            let written = v.write_string();
+            prop_assert_eq!(Value::parse(&written), Ok(v), "written as {:?}", written);
+        }
+    }
+}
@@ -0,0 +1,421 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+
+use crate::ast::Expr;
+use crate::coverage::assign_lines;
+use crate::env::{Env, Value};
+use crate::json::Json;
+use crate::lexer::Token;
+use crate::parser::SyntaxError;
+
+/// How serious a diagnostic is. Errors are syntax errors found without
+/// running the program (see `syntax_diagnostics`); errors while it runs
+/// stop evaluation and are reported as `EvalError`s.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Note,
+    Warning,
+    Error,
+}
+
+impl Severity {
+    pub fn name(self) -> &'static str {
+        match self {
+            Severity::Note => "note",
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+        }
+    }
+}
+
+/// A problem found in a program that does not stop it from running.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    /// The line (counting from 1) of the expression concerned, if known.
+    pub line: Option<usize>,
+}
+
+impl Diagnostic {
+    /// The diagnostic as a JSON object with its `severity`, `message`, and
+    /// `line` (or `null`).
+    pub fn to_json(&self) -> Json {
+        Json::Object(vec![
+            ("severity".into(), self.severity.name().into()),
+            ("message".into(), self.message.clone().into()),
+            ("line".into(), self.line.map_or(Json::Null, |line| Json::Number(line as f64))),
+        ])
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{} (line {}): {}", self.severity.name(), line, self.message),
+            None => write!(f, "{}: {}", self.severity.name(), self.message),
+        }
+    }
+}
+
+/// Turns the syntax errors `parse_with_recovery` found in `source` into
+/// diagnostics with line numbers.
+pub fn syntax_diagnostics(source: &str, errors: &[SyntaxError]) -> Vec<Diagnostic> {
+    errors.iter().map(|e| Diagnostic {
+        severity: Severity::Error,
+        message: format!("{:?}", e.error),
+        line: Some(source[..e.span.start].matches('\n').count() + 1),
+    }).collect()
+}
+
+/// Looks for likely mistakes in a program before it runs:
+/// top-level definitions that replace a built-in procedure of `env`,
+/// variables bound by `lambda`, `define`, or `let` forms but never used
+/// (names starting with `_` are exempt), and loops that recurse outside
+/// tail position (see `is_tail_recursive`).
+///
+/// `tokens` are the tokens the program was parsed from, as returned by
+/// `tokenize_with_lines`; when given, diagnostics carry line numbers.
+pub fn check_program(program: &[Expr], env: &Env, tokens: Option<&[(Token, usize)]>) -> Vec<Diagnostic> {
+    let mut lines = HashMap::new();
+    if let Some(tokens) = tokens {
+        let mut tokens = tokens.iter();
+        for expr in program {
+            assign_lines(expr, &mut tokens, &mut lines, true);
+        }
+    }
+    let mut checker = Checker { lines: &lines, diagnostics: Vec::new() };
+    for expr in program {
+        if let Some(name) = defined_name(expr)
+            && matches!(env.get(name), Some(Value::Function(_)))
+        {
+            checker.warn(expr, format!("definition of {} replaces the built-in procedure", name));
+        }
+        checker.check(expr);
+    }
+    checker.diagnostics
+}
+
+/// Audits the procedures defined in `env` and the environments around it,
+/// the built-ins and any library loaded there, for recursion that would
+/// overflow the stack on long inputs such as a million-element list. Every
+/// procedure defined in Scheme that calls itself is reported: the evaluator
+/// does not optimize tail calls, so even calls in tail position (see
+/// `is_tail_recursive`) use stack and run into the recursion limit.
+/// Built-in procedures loop natively, so they always pass.
+pub fn audit_library(env: &Env) -> Vec<Diagnostic> {
+    env.visible_names().into_iter().filter_map(|name| {
+        let Some(Value::Lambda(ref lambda)) = env.get(&name) else { return None };
+        let message = match is_tail_recursive(&name, std::slice::from_ref(&lambda.body))? {
+            false => format!("{} recurses outside tail position, so long inputs can overflow the stack", name),
+            true => format!("{} calls itself, and tail calls are not optimized, so long inputs can overflow the stack", name),
+        };
+        Some(Diagnostic { severity: Severity::Warning, message, line: None })
+    }).collect()
+}
+
+struct Checker<'a> {
+    lines: &'a HashMap<*const Expr, usize>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl Checker<'_> {
+    fn warn(&mut self, expr: &Expr, message: String) {
+        let line = self.lines.get(&(expr as *const Expr)).copied();
+        self.diagnostics.push(Diagnostic { severity: Severity::Warning, message, line });
+    }
+
+    fn check(&mut self, expr: &Expr) {
+        let Expr::List(items) = expr else { return };
+        let head = match items.first() {
+            Some(Expr::Symbol(s)) => s.as_str(),
+            _ => "",
+        };
+        match (head, &items[..]) {
+            ("quote", _) => return,
+            ("lambda", [_, params, body @ ..]) => self.check_params(expr, params, body),
+            ("define", [_, Expr::List(signature), body @ ..]) if !signature.is_empty() => {
+                let params = Expr::List(signature[1..].to_vec());
+                // Only procedures that loop by calling themselves in tail
+                // position; other recursion, as in `fib`, is intended.
+                if let Expr::Symbol(name) = &signature[0]
+                    && !param_names(&params).0.contains(&name.as_str())
+                    && body_has_tail_call(name, body)
+                {
+                    self.check_loop(expr, name, body);
+                }
+                self.check_params(expr, &params, body);
+            }
+            ("let" | "let*" | "letrec", [_, Expr::Symbol(_), bindings, body @ ..])
+            | ("let" | "let*" | "letrec", [_, bindings, body @ ..]) => {
+                let (names, inits) = binding_names(bindings);
+                // Later initializers of `let*` and `letrec` can use earlier names.
+                let uses: Vec<&Expr> = match head {
+                    "let" => body.iter().collect(),
+                    _ => inits.into_iter().chain(body).collect(),
+                };
+                self.check_unused(expr, "variable", &names, &uses);
+            }
+            _ => {}
+        }
+        for item in items {
+            self.check(item);
+        }
+    }
+
+    /// A procedure that calls itself in tail position is written as a loop,
+    /// so its other calls to itself are likely mistakes.
+    fn check_loop(&mut self, form: &Expr, name: &str, body: &[Expr]) {
+        if is_tail_recursive(name, body) == Some(false) {
+            self.warn(form, format!("non-tail recursion in loop {}: each iteration uses more stack", name));
+        }
+    }
+
+    /// Parameters may be used in the body or in later default expressions.
+    fn check_params(&mut self, form: &Expr, params: &Expr, body: &[Expr]) {
+        let (names, defaults) = param_names(params);
+        let uses: Vec<&Expr> = defaults.into_iter().chain(body).collect();
+        self.check_unused(form, "parameter", &names, &uses);
+    }
+
+    fn check_unused(&mut self, form: &Expr, kind: &str, names: &[&str], uses: &[&Expr]) {
+        let mut used = HashSet::new();
+        for expr in uses {
+            collect_symbols(expr, &mut used);
+        }
+        for name in names {
+            if !name.starts_with('_') && !used.contains(*name) {
+                self.warn(form, format!("unused {} {}", kind, name));
+            }
+        }
+    }
+}
+
+/// Reports whether the calls to `name` in a procedure body are all in tail
+/// position, that is, whether their result is the result of the procedure.
+/// `None` means the body does not call `name` at all (calls in nested
+/// `lambda`s are not counted, as they belong to other procedures).
+///
+/// Note that tail calls are not yet optimized, so tail-recursive loops also
+/// use stack for each iteration; this analysis finds the ones that could not
+/// be run in constant space even then.
+pub fn is_tail_recursive(name: &str, body: &[Expr]) -> Option<bool> {
+    let mut calls = Vec::new();
+    visit_body(name, body, &mut calls);
+    (!calls.is_empty()).then(|| calls.iter().all(|&tail| tail))
+}
+
+fn body_has_tail_call(name: &str, body: &[Expr]) -> bool {
+    let mut calls = Vec::new();
+    visit_body(name, body, &mut calls);
+    calls.contains(&true)
+}
+
+/// Records, for each call to `name`, whether it is in tail position. The
+/// last form of a body is in tail position if the body is.
+fn visit_body(name: &str, body: &[Expr], calls: &mut Vec<bool>) {
+    for (i, expr) in body.iter().enumerate() {
+        visit_calls(name, expr, i + 1 == body.len(), calls);
+    }
+}
+
+fn visit_calls(name: &str, expr: &Expr, tail: bool, calls: &mut Vec<bool>) {
+    let Expr::List(items) = expr else { return };
+    let head = match items.first() {
+        Some(Expr::Symbol(s)) => s.as_str(),
+        _ => "",
+    };
+    match (head, &items[..]) {
+        ("quote" | "lambda", _) => {}
+        ("if", [_, test, branches @ ..]) => {
+            visit_calls(name, test, false, calls);
+            for branch in branches {
+                visit_calls(name, branch, tail, calls);
+            }
+        }
+        ("begin", [_, body @ ..]) => {
+            for (i, expr) in body.iter().enumerate() {
+                visit_calls(name, expr, tail && i + 1 == body.len(), calls);
+            }
+        }
+        ("let" | "let*" | "letrec" | "let-values", [_, Expr::Symbol(_), bindings, body @ ..])
+        | ("let" | "let*" | "letrec" | "let-values", [_, bindings, body @ ..])
+        | ("receive", [_, _, bindings, body @ ..]) => {
+            visit_calls(name, bindings, false, calls);
+            for (i, expr) in body.iter().enumerate() {
+                visit_calls(name, expr, tail && i + 1 == body.len(), calls);
+            }
+        }
+        ("match", [_, subject, clauses @ ..]) => {
+            visit_calls(name, subject, false, calls);
+            for clause in clauses {
+                if let Expr::List(clause) = clause {
+                    for (i, expr) in clause.iter().enumerate().skip(1) {
+                        visit_calls(name, expr, tail && i + 1 == clause.len(), calls);
+                    }
+                }
+            }
+        }
+        _ => {
+            if head == name {
+                calls.push(tail);
+            }
+            let args = if head == name { &items[1..] } else { &items[..] };
+            for item in args {
+                visit_calls(name, item, false, calls);
+            }
+        }
+    }
+}
+
+/// The name a top-level `define` binds.
+fn defined_name(expr: &Expr) -> Option<&str> {
+    match expr {
+        Expr::List(items) if items.first() == Some(&Expr::Symbol("define".into())) => match items.get(1) {
+            Some(Expr::Symbol(name)) => Some(name),
+            Some(Expr::List(signature)) => match signature.first() {
+                Some(Expr::Symbol(name)) => Some(name),
+                _ => None,
+            },
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// Names in a parameter list, including `(name default)` entries after
+/// `#:optional` or `#:key` and a rest parameter, and the default expressions.
+fn param_names(params: &Expr) -> (Vec<&str>, Vec<&Expr>) {
+    let mut names = Vec::new();
+    let mut defaults = Vec::new();
+    match params {
+        Expr::Symbol(name) => names.push(name.as_str()),
+        Expr::List(items) => {
+            for item in items {
+                match item {
+                    Expr::Symbol(name) if name != "." => names.push(name),
+                    Expr::List(entry) => {
+                        if let [Expr::Symbol(name), default] = &entry[..] {
+                            names.push(name);
+                            defaults.push(default);
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        _ => {}
+    }
+    (names, defaults)
+}
+
+fn binding_names(bindings: &Expr) -> (Vec<&str>, Vec<&Expr>) {
+    let Expr::List(pairs) = bindings else { return (vec![], vec![]) };
+    pairs.iter().filter_map(|pair| match pair {
+        Expr::List(pair) => match &pair[..] {
+            [Expr::Symbol(name), init] => Some((name.as_str(), init)),
+            _ => None,
+        },
+        _ => None,
+    }).unzip()
+}
+
+/// Every symbol in an expression outside quoted data.
+fn collect_symbols<'a>(expr: &'a Expr, symbols: &mut HashSet<&'a str>) {
+    match expr {
+        Expr::Symbol(name) => {
+            symbols.insert(name);
+        }
+        Expr::List(items) if items.first() == Some(&Expr::Symbol("quote".into())) => {}
+        Expr::List(items) => {
+            for item in items {
+                collect_symbols(item, symbols);
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::default_env;
+    use crate::lexer::tokenize_with_lines;
+    use crate::parser::parse_program;
+
+    #[test]
+    fn test_syntax_errors_become_diagnostics() {
+        let source = "(f 1)\n)\n(g #q";
+        let (_, errors) = crate::parser::parse_with_recovery(source);
+        let messages: Vec<String> = syntax_diagnostics(source, &errors).iter().map(|d| d.to_string()).collect();
+        assert_eq!(messages, vec![
+            "error (line 2): UnexpectedToken(RParen)",
+            "error (line 3): LexError(InvalidToken(\"#Some('q')\"))",
+            "error (line 3): UnexpectedEOF",
+        ]);
+    }
+
+    fn check(source: &str) -> Vec<String> {
+        let tokens = tokenize_with_lines(source).unwrap();
+        let program = parse_program(tokens.iter().map(|(token, _)| token.clone()).collect()).unwrap();
+        check_program(&program, &default_env(), Some(&tokens))
+            .iter().map(|d| d.to_string()).collect()
+    }
+
+    #[test]
+    fn test_audit_library() {
+        let env = default_env();
+        assert_eq!(audit_library(&env), vec![]);
+        let program = parse_program(crate::lexer::tokenize("
+            (define (my-length xs) (if (null? xs) 0 (+ 1 (my-length (cdr xs)))))
+            (define (my-reverse xs acc) (if (null? xs) acc (my-reverse (cdr xs) (cons (car xs) acc))))
+            (define (square x) (* x x))").unwrap()).unwrap();
+        crate::eval::eval_sequence(&program, env.clone()).unwrap();
+        let messages: Vec<String> = audit_library(&env).iter().map(|d| d.to_string()).collect();
+        assert_eq!(messages, vec![
+            "warning: my-length recurses outside tail position, so long inputs can overflow the stack",
+            "warning: my-reverse calls itself, and tail calls are not optimized, so long inputs can overflow the stack",
+        ]);
+    }
+
+    #[test]
+    fn test_redefining_a_builtin_is_a_warning() {
+        assert_eq!(check("(define (car x) x)\n(define car2 1)"), vec![
+            "warning (line 1): definition of car replaces the built-in procedure",
+        ]);
+    }
+
+    #[test]
+    fn test_unused_variables_are_warnings() {
+        let source = "(define (f x y _z)\n  x)\n(let ((a 1) (b 2))\n  (let* ((c a)) c))\n(lambda (p) '(p))";
+        assert_eq!(check(source), vec![
+            "warning (line 1): unused parameter y",
+            "warning (line 3): unused variable b",
+            "warning (line 5): unused parameter p",
+        ]);
+    }
+
+    #[test]
+    fn test_non_tail_recursion_in_loops_is_a_warning() {
+        let source = "(define (count-up i n)\n  (if (< i n) (begin (count-up (+ i 1) n) (count-up i n)) i))";
+        assert_eq!(check(source), vec![
+            "warning (line 1): non-tail recursion in loop count-up: each iteration uses more stack",
+        ]);
+        // Recursion that is not written as a loop is left alone.
+        assert!(check("(define (fib n) (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2)))))").is_empty());
+    }
+
+    #[test]
+    fn test_tail_positions() {
+        let body = |source: &str| parse_program(crate::lexer::tokenize(source).unwrap()).unwrap();
+        assert_eq!(is_tail_recursive("f", &body("(display 1) (if (g) (f 1) (let ((x 1)) (begin x (f x))))")), Some(true));
+        assert_eq!(is_tail_recursive("f", &body("(g (f 1))")), Some(false));
+        assert_eq!(is_tail_recursive("f", &body("(f 1) 2")), Some(false));
+        assert_eq!(is_tail_recursive("f", &body("(lambda () (f 1)) '(f 1)")), None);
+    }
+
+    #[test]
+    fn test_clean_programs_have_no_diagnostics() {
+        assert!(check("(define (f x #:optional (y x)) y) (define (g i) (if (< i 3) (g (+ i 1)) i)) (g 0)").is_empty());
+    }
+}
@@ -0,0 +1,130 @@
+//! DOM built-ins for scripting the page the interpreter runs in, for the
+//! playground's interactive examples:
+//!
+//! ```scheme
+//! (define button (dom-query "#stage button"))
+//! (dom-on button "click" (lambda (el) (dom-set-text! el "Clicked")))
+//! ```
+//!
+//! Elements are `<element>` records. The elements Scheme code has seen, and
+//! the handlers attached to them, live as long as the page.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use wasm_bindgen::prelude::*;
+use web_sys::{Element, Event};
+
+use crate::env::{EvalError, Value};
+use crate::eval::{apply, check_effect};
+use crate::record::{Record, RecordType};
+
+thread_local! {
+    static ELEMENT_TYPE: Rc<RecordType> = Rc::new(RecordType {
+        name: "<element>".into(),
+        fields: vec!["handle".into()],
+        printer: RefCell::new(Some(Value::Function(print_element))),
+    });
+    /// Every element handed to Scheme code; an `<element>` record's handle
+    /// is its index here, so that the same element always gets the same one.
+    static ELEMENTS: RefCell<Vec<Element>> = const { RefCell::new(Vec::new()) };
+}
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_namespace = console, js_name = error)]
+    fn console_error(message: &str);
+}
+
+fn element_to_value(element: Element) -> Value {
+    let handle = ELEMENTS.with(|elements| {
+        let mut elements = elements.borrow_mut();
+        match elements.iter().position(|e| e.is_same_node(Some(&element))) {
+            Some(handle) => handle,
+            None => {
+                elements.push(element);
+                elements.len() - 1
+            }
+        }
+    });
+    Value::Record(Rc::new(Record {
+        rtd: ELEMENT_TYPE.with(Rc::clone),
+        fields: RefCell::new(vec![Value::Number(handle as i64)]),
+    }))
+}
+
+fn element_arg(value: &Value, name: &str) -> Result<Element, EvalError> {
+    let handle = match value {
+        Value::Record(record) if ELEMENT_TYPE.with(|t| Rc::ptr_eq(&record.rtd, t)) => record.fields.borrow()[0].clone(),
+        _ => return Err(EvalError::TypeError(format!("{} expects an element", name))),
+    };
+    let Value::Number(handle) = handle else {
+        return Err(EvalError::TypeError(format!("{} expects an element", name)));
+    };
+    ELEMENTS.with(|elements| elements.borrow().get(handle as usize).cloned())
+        .ok_or_else(|| EvalError::TypeError(format!("{} expects an element", name)))
+}
+
+fn string_arg<'a>(value: &'a Value, name: &str) -> Result<&'a str, EvalError> {
+    match value {
+        Value::String(s) => Ok(s),
+        _ => Err(EvalError::TypeError(format!("{} expects a string", name))),
+    }
+}
+
+fn print_element(args: Vec<Value>) -> Result<Value, EvalError> {
+    let [element] = <[Value; 1]>::try_from(args).map_err(|_| EvalError::ArityMismatch)?;
+    let element = element_arg(&element, "element printer")?;
+    let id = element.id();
+    let name = element.tag_name().to_lowercase();
+    let printed = if id.is_empty() { format!("#<element {}>", name) } else { format!("#<element {}#{}>", name, id) };
+    Ok(Value::String(printed.into()))
+}
+
+/// `(dom-query selector)` returns the first element of the page matching
+/// the CSS selector, or `#f` if none does.
+pub fn builtin_dom_query(args: Vec<Value>) -> Result<Value, EvalError> {
+    let [selector] = <[Value; 1]>::try_from(args).map_err(|_| EvalError::ArityMismatch)?;
+    let selector = string_arg(&selector, "dom-query")?;
+    let document = web_sys::window().and_then(|w| w.document())
+        .ok_or_else(|| EvalError::Other("dom-query: there is no document".into()))?;
+    match document.query_selector(selector) {
+        Ok(Some(element)) => Ok(element_to_value(element)),
+        Ok(None) => Ok(Value::Boolean(false)),
+        Err(_) => Err(EvalError::Other(format!("dom-query: invalid selector {:?}", selector))),
+    }
+}
+
+/// `(dom-set-text! el str)` replaces the contents of `el` with the text `str`.
+pub fn builtin_dom_set_text(args: Vec<Value>) -> Result<Value, EvalError> {
+    let [element, text] = <[Value; 2]>::try_from(args).map_err(|_| EvalError::ArityMismatch)?;
+    let element = element_arg(&element, "dom-set-text!")?;
+    let text = string_arg(&text, "dom-set-text!")?;
+    check_effect("dom-set-text!")?;
+    element.set_text_content(Some(text));
+    Ok(Value::Unspecified)
+}
+
+/// `(dom-on el event handler)` calls `handler` with the element the event
+/// happened on each time `event`, such as `"click"`, fires on `el`. Errors
+/// from the handler go to the console.
+pub fn builtin_dom_on(args: Vec<Value>) -> Result<Value, EvalError> {
+    let [element, event, handler] = <[Value; 3]>::try_from(args).map_err(|_| EvalError::ArityMismatch)?;
+    let element = element_arg(&element, "dom-on")?;
+    let event = string_arg(&event, "dom-on")?;
+    if !matches!(handler, Value::Function(_) | Value::Lambda(_) | Value::Memoized(_) | Value::Combinator(_) | Value::RecordProcedure(_)) {
+        return Err(EvalError::TypeError("dom-on expects a procedure".into()));
+    }
+    check_effect("dom-on")?;
+    let target = element.clone();
+    let callback = Closure::<dyn FnMut(Event)>::new(move |event: Event| {
+        let element = event.target().and_then(|t| t.dyn_into::<Element>().ok()).unwrap_or_else(|| target.clone());
+        if let Err(e) = apply(handler.clone(), vec![element_to_value(element)]) {
+            console_error(&format!("Error in dom-on handler: {:?}", e));
+        }
+    });
+    element.add_event_listener_with_callback(event, callback.as_ref().unchecked_ref())
+        .map_err(|_| EvalError::Other(format!("dom-on: cannot listen for {:?}", event)))?;
+    callback.forget();
+    Ok(Value::Unspecified)
+}
@@ -1,125 +1,1206 @@
-use std::collections::HashMap;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::rc::Rc;
-use std::cell::RefCell;
+use std::cell::{Cell, RefCell};
 use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::mem;
+
+use indexmap::IndexMap;
 
 use crate::ast::Expr;
 use crate::builtins::*;
+use crate::printer::{print_value, PrintOptions, PrintStyle};
+use crate::port::Port;
+use crate::record::{Record, RecordProcedure, RecordType};
+use crate::generator::*;
+use crate::fiber::*;
+use crate::channel::*;
+use crate::mutex::*;
+use crate::weak::*;
+use crate::combinator::*;
+use crate::condition::*;
+use crate::marks::*;
+use crate::eval::SpecialForm;
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone)]
 pub struct Env {
     parent: Option<Rc<Env>>,
-    vars: RefCell<HashMap<String,Value>>,
+    vars: RefCell<Frame>,
+    /// For a global environment from `default_env`, the built-in procedures,
+    /// consulted after its own variables so that programs can redefine them.
+    builtins: Option<Rc<HashMap<String, Value>>>,
+    /// For a global environment, the special forms added with
+    /// `define_special_form`.
+    forms: Option<Box<RefCell<CustomForms>>>,
+}
+
+/// Evaluates a special form added by the embedding application, given the
+/// form's arguments, unevaluated, and the environment the form appears in.
+pub type SpecialFormHandler = Rc<dyn Fn(&[Expr], &Rc<Env>) -> Result<Value, EvalError>>;
+
+#[derive(Clone, Default)]
+struct CustomForms(HashMap<String, SpecialFormHandler>);
+
+impl fmt::Debug for CustomForms {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_set().entries(self.0.keys()).finish()
+    }
+}
+
+/// Number of entries in the global lookup cache.
+const CACHE_SIZE: usize = 512;
+
+/// What one symbol in the program resolved to when it was last looked up
+/// in a global environment.
+#[derive(Debug)]
+struct CacheEntry {
+    /// The address of the symbol's name in the syntax tree, which tells
+    /// occurrences of a symbol apart.
+    site: usize,
+    name: String,
+    global: *const Env,
+    generation: u64,
+    value: Value,
+}
+
+thread_local! {
+    /// Global lookups by symbol occurrence, indexed by `site`. An entry is
+    /// used only if nothing has been defined in a global environment since
+    /// it was stored.
+    static CACHE: RefCell<Vec<Option<CacheEntry>>> = RefCell::new((0..CACHE_SIZE).map(|_| None).collect());
+    /// Bumped by every definition in a global environment, and by creating
+    /// one, which invalidates the whole cache.
+    static GENERATION: Cell<u64> = const { Cell::new(0) };
+    /// The built-in procedures, shared by every `default_env` on the thread.
+    static BUILTINS: Rc<HashMap<String, Value>> = Rc::new(builtin_table());
+    /// Whether any special form has been added on the thread, so that
+    /// procedure calls only look for one when there may be one.
+    static HAS_CUSTOM_FORMS: Cell<bool> = const { Cell::new(false) };
+    /// The name and arity of each built-in, by the address of its function,
+    /// filled in as `BUILTINS` is.
+    static BUILTIN_INFO: RefCell<HashMap<usize, BuiltinInfo>> = RefCell::new(HashMap::new());
+}
+
+/// Frames with more variables than this are kept in a hash map.
+const SMALL_FRAME: usize = 8;
+
+/// The variables of one environment. A procedure call usually binds a few
+/// parameters, which a vector holds with one allocation and finds by
+/// comparing names, without hashing. Frames that outgrow `SMALL_FRAME`, such
+/// as the global environment or a body with many internal `define`s, switch
+/// to a hash map.
+#[derive(Debug, Clone)]
+enum Frame {
+    Small(Vec<(String, Value)>),
+    Large(HashMap<String, Value>),
+}
+
+impl Frame {
+    fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Frame::Small(vars) => vars.iter().find(|(name, _)| name == key).map(|(_, value)| value),
+            Frame::Large(vars) => vars.get(key),
+        }
+    }
+
+    fn insert(&mut self, key: String, value: Value) {
+        match self {
+            Frame::Small(vars) => {
+                if let Some(slot) = vars.iter_mut().find(|(name, _)| *name == key) {
+                    slot.1 = value;
+                } else if vars.len() < SMALL_FRAME {
+                    vars.push((key, value));
+                } else {
+                    let mut map: HashMap<_, _> = mem::take(vars).into_iter().collect();
+                    map.insert(key, value);
+                    *self = Frame::Large(map);
+                }
+            }
+            Frame::Large(vars) => {
+                vars.insert(key, value);
+            }
+        }
+    }
+
+    /// The variables in the order they were defined, for a small frame, or
+    /// sorted by name, for a large one, so that dumps are reproducible.
+    fn bindings(&self) -> Vec<(String, Value)> {
+        match self {
+            Frame::Small(vars) => vars.clone(),
+            Frame::Large(vars) => {
+                let mut bindings: Vec<_> = vars.iter().map(|(k, v)| (k.clone(), v.clone())).collect();
+                bindings.sort_by(|a, b| a.0.cmp(&b.0));
+                bindings
+            }
+        }
+    }
 }
 
 impl Env {
     /// Creates a new, empty global environment with no parent.
     pub fn new() -> Rc<Self> {
+        Self::global_with(None)
+    }
+
+    fn global_with(builtins: Option<Rc<HashMap<String, Value>>>) -> Rc<Self> {
+        GENERATION.with(|g| g.set(g.get() + 1));
         Rc::new(Env {
             parent: None,
-            vars: RefCell::new(HashMap::new()),
+            vars: RefCell::new(Frame::Large(HashMap::new())),
+            builtins,
+            forms: Some(Box::default()),
         })
     }
 
     /// Creates a new environment that extends a parent environment.
     pub fn extend(parent: Rc<Env>) -> Rc<Self> {
+        Self::extend_with_capacity(parent, 0)
+    }
+
+    /// Like `extend`, with room for `capacity` variables, such as the
+    /// parameters of a procedure being called.
+    pub fn extend_with_capacity(parent: Rc<Env>, capacity: usize) -> Rc<Self> {
+        #[cfg(feature = "tracing")]
+        crate::telemetry::count(|c| c.environments += 1);
         Rc::new(Env {
             parent: Some(parent),
-            vars: RefCell::new(HashMap::new()),
+            vars: RefCell::new(Frame::Small(Vec::with_capacity(capacity.min(SMALL_FRAME)))),
+            builtins: None,
+            forms: None,
         })
     }
 
+    /// Returns the outermost (global) environment of the chain.
+    pub fn global(self: &Rc<Self>) -> Rc<Env> {
+        let mut env = self.clone();
+        while let Some(parent) = env.parent.clone() {
+            env = parent;
+        }
+        env
+    }
+
+    /// Adds a special form to the global environment of the chain, so that
+    /// hosts can add their own control constructs. From then on a list whose
+    /// head is `name` is evaluated by calling `handler` with the rest of the
+    /// list; the handler evaluates what it needs with `eval::eval`. Like the
+    /// built-in special forms, it takes precedence over variables named
+    /// `name`. The built-in special forms cannot be replaced.
+    pub fn define_special_form(self: &Rc<Self>, name: &str, handler: SpecialFormHandler) -> Result<(), EvalError> {
+        if SpecialForm::from_name(name).is_some() {
+            return Err(EvalError::Other(format!("{} is a built-in special form", name)));
+        }
+        let global = self.global();
+        let forms = global.forms.as_ref().expect("global environments hold special forms");
+        forms.borrow_mut().0.insert(name.to_string(), handler);
+        HAS_CUSTOM_FORMS.with(|h| h.set(true));
+        Ok(())
+    }
+
+    /// Returns the handler of the special form `name` added to the global
+    /// environment of the chain, if there is one.
+    pub fn special_form(&self, name: &str) -> Option<SpecialFormHandler> {
+        if !HAS_CUSTOM_FORMS.with(|h| h.get()) {
+            return None;
+        }
+        let mut env = self;
+        while let Some(parent) = &env.parent {
+            env = parent;
+        }
+        env.forms.as_ref()?.borrow().0.get(name).cloned()
+    }
+
+    /// Returns the names of the special forms added to the global
+    /// environment of the chain, sorted.
+    pub fn special_form_names(&self) -> Vec<String> {
+        let mut env = self;
+        while let Some(parent) = &env.parent {
+            env = parent;
+        }
+        let mut names: Vec<String> = match &env.forms {
+            Some(forms) => forms.borrow().0.keys().cloned().collect(),
+            None => Vec::new(),
+        };
+        names.sort();
+        names
+    }
+
     /// Defines a new variable or updates an existing one in the current environment.
     pub fn define(&self, key: String, value: Value) {
+        if self.parent.is_none() {
+            GENERATION.with(|g| g.set(g.get() + 1));
+        }
         self.vars.borrow_mut().insert(key, value);
     }
 
+    /// Returns the variables of every environment in the chain except the
+    /// global one, innermost first and sorted by name within each.
+    pub fn local_bindings(&self) -> Vec<(String, Value)> {
+        let mut bindings = Vec::new();
+        let mut env = self;
+        while let Some(parent) = &env.parent {
+            let mut vars = env.vars.borrow().bindings();
+            vars.sort_by(|a, b| a.0.cmp(&b.0));
+            bindings.extend(vars);
+            env = parent;
+        }
+        bindings
+    }
+
+    /// Returns the names of every variable visible here, built-ins
+    /// included, sorted and without repeats.
+    pub fn visible_names(&self) -> Vec<String> {
+        let mut names = Vec::new();
+        let mut env = Some(self);
+        while let Some(e) = env {
+            names.extend(e.vars.borrow().bindings().into_iter().map(|(name, _)| name));
+            if let Some(builtins) = &e.builtins {
+                names.extend(builtins.keys().cloned());
+            }
+            env = e.parent.as_deref();
+        }
+        names.sort();
+        names.dedup();
+        names
+    }
+
     /// Looks up a variable by name, searching parent environments if needed.
     pub fn get(&self, key: &str) -> Option<Value> {
-        self.vars.borrow().get(key).cloned().or_else(|| {
-            self.parent.as_ref()?.get(key)
+        self.vars.borrow().get(key).cloned().or_else(|| match &self.parent {
+            Some(parent) => parent.get(key),
+            None => self.builtins.as_ref()?.get(key).cloned(),
         })
     }
+
+    /// Like `get`, for the evaluation of a symbol in the program, given by
+    /// the name stored in its syntax tree. Once the search reaches the
+    /// global environment, the result is remembered for that occurrence of
+    /// the symbol, so that loops calling built-ins or global procedures only
+    /// search the local frames.
+    pub fn lookup(&self, name: &str) -> Option<Value> {
+        let mut env = self;
+        while let Some(parent) = &env.parent {
+            if let Some(value) = env.vars.borrow().get(name) {
+                return Some(value.clone());
+            }
+            env = parent;
+        }
+        let site = name.as_ptr() as usize;
+        let slot = (site >> 3) % CACHE_SIZE;
+        let generation = GENERATION.with(|g| g.get());
+        let cached = CACHE.with(|c| match &c.borrow()[slot] {
+            Some(entry) if entry.site == site && std::ptr::eq(entry.global, env)
+                && entry.generation == generation && entry.name == *name => Some(entry.value.clone()),
+            _ => None,
+        });
+        #[cfg(feature = "tracing")]
+        crate::telemetry::count(|c| if cached.is_some() { c.cache_hits += 1 } else { c.cache_misses += 1 });
+        if cached.is_some() {
+            return cached;
+        }
+        let value = env.get(name)?;
+        CACHE.with(|c| c.borrow_mut()[slot] = Some(CacheEntry {
+            site,
+            name: name.to_string(),
+            global: env,
+            generation,
+            value: value.clone(),
+        }));
+        Some(value)
+    }
+}
+
+/// Forgets the lookups remembered before the last global definition, which
+/// would otherwise keep the values they found alive (see `weak`).
+pub(crate) fn drop_stale_lookups() {
+    let generation = GENERATION.with(|g| g.get());
+    CACHE.with(|c| c.borrow_mut().iter_mut()
+        .filter(|entry| entry.as_ref().is_some_and(|entry| entry.generation != generation))
+        .for_each(|entry| *entry = None));
 }
 
-#[derive(Debug, Clone, PartialEq)]
+/// The native function behind a built-in procedure.
+pub type BuiltinFn = fn(Vec<Value>) -> Result<Value, EvalError>;
+
+/// A Scheme value. Strings, symbols, and lists are immutable and kept
+/// behind `Rc`, so cloning any value, as every variable reference does,
+/// copies at most a pointer and a count.
+#[derive(Debug, Clone)]
 pub enum Value {
     Number(i64),
+    Rational(i64, i64), // always in lowest terms, see `Number`
+    Float(f64),
     Boolean(bool),
-    String(String),
-    Symbol(String),
-    Function(fn(Vec<Value>) -> Result<Value, EvalError>), // built-in functions
-    Lambda(Lambda), // user-defined functions
-    List(Vec<Value>),
+    String(Rc<str>),
+    Symbol(Rc<str>),
+    Keyword(Rc<str>), // `#:name`, self-evaluating
+    Char(char),
+    Function(BuiltinFn), // built-in functions, see `builtin_info`
+    Lambda(Rc<Lambda>), // user-defined functions, compared by identity
+    Memoized(Rc<Memoized>), // caching wrapper from `memoize`, compared by identity
+    Combinator(Rc<Combinator>), // from `compose`, `const`, `curry`, and the like, compared by identity
+    List(Rc<[Value]>), // immutable, so clones share the elements
+    Vector(Rc<RefCell<Vec<Value>>>), // mutable, shared by reference
+    Bytevector(Rc<RefCell<Vec<u8>>>), // mutable, shared by reference
+    HashTable(Rc<RefCell<IndexMap<Value, Value>>>), // mutable, shared by reference, in insertion order
+    Queue(Rc<RefCell<VecDeque<Value>>>), // mutable deque, compared by identity
+    Record(Rc<Record>), // from `define-record-type`, compared by identity
+    RecordType(Rc<RecordType>),
+    RecordProcedure(Rc<RecordProcedure>), // record constructors, predicates, accessors, and modifiers
+    Port(Rc<Port>), // output destination, compared by identity
+    Promise(Rc<Promise>), // from `delay` and `cons-stream`, compared by identity
+    Generator(Rc<Generator>), // from `make-generator`, compared by identity
+    Fiber(Rc<Fiber>), // from `spawn-fiber`, compared by identity
+    Channel(Rc<Channel>), // from `make-channel`, compared by identity
+    Mutex(Rc<Mutex>), // from `make-mutex`, compared by identity
+    WeakBox(Rc<WeakBox>), // from `make-weak-box`, compared by identity
+    Pid(u64), // process from `spawn` or `self`, see `actor`
+    Condition(Rc<Condition>), // error object from `error` or a failed operation, compared by identity
+    Values(Rc<[Value]>), // multiple return values from `values`
+    Eof, // returned by input procedures at the end of input
+    Unspecified, // result of side-effecting built-ins like `vector-set!`
+}
+
+impl Drop for Value {
+    /// Frees nested lists, vectors, hash tables, and queues with a stack of
+    /// its own, so that dropping deep nesting does not recurse as deeply.
+    /// Only containers this value holds the last reference to are taken
+    /// apart; the rest are freed later, by their last holder.
+    fn drop(&mut self) {
+        let mut pending = Vec::new();
+        take_children(self, &mut pending);
+        while let Some(mut value) = pending.pop() {
+            take_children(&mut value, &mut pending);
+        }
+    }
+}
+
+/// Moves the containers inside `value` to `pending`, if dropping `value`
+/// would free them.
+fn take_children(value: &mut Value, pending: &mut Vec<Value>) {
+    let is_container = |value: &Value| {
+        matches!(value, Value::List(_) | Value::Values(_) | Value::Vector(_) | Value::HashTable(_) | Value::Queue(_))
+    };
+    let take = |item: &mut Value| {
+        if is_container(item) {
+            pending.push(mem::replace(item, Value::Unspecified));
+        }
+    };
+    match value {
+        Value::List(items) | Value::Values(items) => Rc::get_mut(items).into_iter().flatten().for_each(take),
+        Value::Vector(items) => Rc::get_mut(items).into_iter().flat_map(|items| items.get_mut()).for_each(take),
+        Value::Queue(items) => Rc::get_mut(items).into_iter().flat_map(|items| items.get_mut()).for_each(take),
+        Value::HashTable(table) => {
+            if let Some(table) = Rc::get_mut(table) {
+                for (key, value) in table.get_mut().drain(..) {
+                    [key, value].into_iter().filter(is_container).for_each(|item| pending.push(item));
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+impl Value {
+    /// Returns the human-readable representation used by `display` and
+    /// `->string`: strings appear without quotes.
+    pub fn display_string(&self) -> String {
+        let options = PrintOptions { style: PrintStyle::Display, ..Default::default() };
+        print_value(self, &options)
+    }
+
+    /// Returns the machine-readable representation used by `write` and the
+    /// REPL: strings are quoted and escaped.
+    pub fn write_string(&self) -> String {
+        print_value(self, &PrintOptions::default())
+    }
+
+    /// Names the kind of value, for error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Number(_) | Value::Rational(_, _) | Value::Float(_) => "number",
+            Value::Boolean(_) => "boolean",
+            Value::String(_) => "string",
+            Value::Symbol(_) => "symbol",
+            Value::Keyword(_) => "keyword",
+            Value::Char(_) => "char",
+            Value::Function(_) | Value::Lambda(_) | Value::Memoized(_) | Value::Combinator(_) | Value::RecordProcedure(_) => "procedure",
+            Value::List(_) => "list",
+            Value::Vector(_) => "vector",
+            Value::Bytevector(_) => "bytevector",
+            Value::HashTable(_) => "hash table",
+            Value::Queue(_) => "queue",
+            Value::Record(_) => "record",
+            Value::RecordType(_) => "record type",
+            Value::Promise(_) => "promise",
+            Value::Generator(_) => "generator",
+            Value::Fiber(_) => "fiber",
+            Value::Channel(_) => "channel",
+            Value::Mutex(_) => "mutex",
+            Value::WeakBox(_) => "weak box",
+            Value::Pid(_) => "pid",
+            Value::Condition(_) => "error object",
+            Value::Port(_) => "port",
+            Value::Values(_) => "multiple values",
+            Value::Eof => "eof object",
+            Value::Unspecified => "unspecified",
+        }
+    }
 }
 
 impl fmt::Display for Value {
+    /// Formats the value as `write_string` does.
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        match self {
-            Value::Number(n) => write!(f, "{}", n),
-            Value::Boolean(true) => write!(f, "#t"),
-            Value::Boolean(false) => write!(f, "#f"),
-            Value::String(s) => write!(f, "\"{}\"", s),
-            Value::Symbol(s) => write!(f, "{}", s),
-            Value::Function(_) => write!(f, "<builtin-function>"),
-            Value::Lambda(_) => write!(f, "<lambda>"),
-            Value::List(values) => {
-                let contents = values.iter()
-                    .map(|v| format!("{}", v))
-                    .collect::<Vec<_>>()
-                    .join(" ");
-                write!(f, "({})", contents)
+        write!(f, "{}", self.write_string())
+    }
+}
+
+impl PartialEq for Value {
+    /// Structural equality, as used by `equal?`. Compares with an explicit
+    /// stack so deeply nested lists cannot overflow the Rust stack, and
+    /// remembers which pairs of vectors and hash tables it has already
+    /// compared so that circular structures terminate. Hash tables are equal
+    /// when they have equal values for the same keys.
+    fn eq(&self, other: &Self) -> bool {
+        let mut compared = HashSet::new();
+        let mut pending = vec![(Cow::Borrowed(self), Cow::Borrowed(other))];
+        while let Some((a, b)) = pending.pop() {
+            let same = match (a.as_ref(), b.as_ref()) {
+                (Value::List(xs), Value::List(ys)) | (Value::Values(xs), Value::Values(ys)) => xs.len() == ys.len(),
+                (Value::Vector(xs), Value::Vector(ys)) => {
+                    if Rc::ptr_eq(xs, ys) || !compared.insert((Rc::as_ptr(xs).cast::<()>(), Rc::as_ptr(ys).cast::<()>())) {
+                        continue;
+                    }
+                    xs.borrow().len() == ys.borrow().len()
+                }
+                (Value::HashTable(xs), Value::HashTable(ys)) => {
+                    if Rc::ptr_eq(xs, ys) || !compared.insert((Rc::as_ptr(xs).cast::<()>(), Rc::as_ptr(ys).cast::<()>())) {
+                        continue;
+                    }
+                    let (xs, ys) = (xs.borrow(), ys.borrow());
+                    if xs.len() != ys.len() {
+                        return false;
+                    }
+                    for (key, x) in xs.iter() {
+                        match ys.get(key) {
+                            Some(y) => pending.push((Cow::Owned(x.clone()), Cow::Owned(y.clone()))),
+                            None => return false,
+                        }
+                    }
+                    continue;
+                }
+                (Value::Number(x), Value::Number(y)) => x == y,
+                (Value::Rational(n1, d1), Value::Rational(n2, d2)) => n1 == n2 && d1 == d2,
+                // NaN equals itself here, unlike with `=`, so that it can be
+                // found in a hash table.
+                (Value::Float(x), Value::Float(y)) => x == y || x.is_nan() && y.is_nan(),
+                (Value::Boolean(x), Value::Boolean(y)) => x == y,
+                (Value::String(x), Value::String(y)) => x == y,
+                (Value::Symbol(x), Value::Symbol(y)) => x == y,
+                (Value::Keyword(x), Value::Keyword(y)) => x == y,
+                (Value::Char(x), Value::Char(y)) => x == y,
+                (Value::Bytevector(x), Value::Bytevector(y)) => Rc::ptr_eq(x, y) || *x.borrow() == *y.borrow(),
+                (Value::Function(f), Value::Function(g)) => std::ptr::fn_addr_eq(*f, *g),
+                (Value::Lambda(f), Value::Lambda(g)) => Rc::ptr_eq(f, g),
+                (Value::Memoized(f), Value::Memoized(g)) => Rc::ptr_eq(f, g),
+                (Value::Combinator(f), Value::Combinator(g)) => Rc::ptr_eq(f, g),
+                (Value::Promise(p), Value::Promise(q)) => Rc::ptr_eq(p, q),
+                (Value::Generator(g), Value::Generator(h)) => Rc::ptr_eq(g, h),
+                (Value::Fiber(f), Value::Fiber(g)) => Rc::ptr_eq(f, g),
+                (Value::Channel(c), Value::Channel(d)) => Rc::ptr_eq(c, d),
+                (Value::Mutex(m), Value::Mutex(n)) => Rc::ptr_eq(m, n),
+                (Value::WeakBox(b), Value::WeakBox(c)) => Rc::ptr_eq(b, c),
+                (Value::Pid(p), Value::Pid(q)) => p == q,
+                (Value::Condition(c), Value::Condition(d)) => Rc::ptr_eq(c, d),
+                (Value::Port(p), Value::Port(q)) => Rc::ptr_eq(p, q),
+                (Value::Queue(p), Value::Queue(q)) => {
+                    if Rc::ptr_eq(p, q) {
+                        continue;
+                    }
+                    false
+                }
+                (Value::Record(r), Value::Record(s)) => {
+                    if Rc::ptr_eq(r, s) {
+                        continue;
+                    }
+                    false
+                }
+                (Value::RecordType(r), Value::RecordType(s)) => Rc::ptr_eq(r, s),
+                (Value::RecordProcedure(p), Value::RecordProcedure(q)) => Rc::ptr_eq(p, q),
+                (Value::Eof, Value::Eof) => true,
+                (Value::Unspecified, Value::Unspecified) => true,
+                _ => false,
+            };
+            if !same {
+                return false;
+            }
+            pending.extend(children(a).into_iter().zip(children(b)));
+        }
+        true
+    }
+}
+
+/// Every value is equal to itself, NaN included (it is `equal?` to NaN,
+/// though not `=`), so values satisfy the `Eq` contract and any of them can
+/// be a hash key.
+impl Eq for Value {}
+
+impl Hash for Value {
+    /// Hashes consistently with `equal?`. Procedures hash by identity, and
+    /// vectors and hash tables by length only, since their contents can
+    /// change while they are used as keys (and may be circular).
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let mut pending = vec![Cow::Borrowed(self)];
+        while let Some(value) = pending.pop() {
+            mem::discriminant(value.as_ref()).hash(state);
+            match value.as_ref() {
+                Value::Number(n) => n.hash(state),
+                Value::Rational(n, d) => (n, d).hash(state),
+                // 0.0 and -0.0 are equal, as are all NaNs, so they must hash
+                // the same.
+                Value::Float(x) if x.is_nan() => f64::NAN.to_bits().hash(state),
+                Value::Float(x) => (if *x == 0.0 { 0.0 } else { *x }).to_bits().hash(state),
+                Value::Boolean(b) => b.hash(state),
+                Value::String(s) | Value::Symbol(s) | Value::Keyword(s) => s.hash(state),
+                Value::Char(c) => c.hash(state),
+                Value::Function(f) => (*f as usize).hash(state),
+                Value::Lambda(l) => Rc::as_ptr(l).hash(state),
+                Value::Memoized(m) => Rc::as_ptr(m).hash(state),
+                Value::Combinator(c) => Rc::as_ptr(c).hash(state),
+                Value::Promise(p) => Rc::as_ptr(p).hash(state),
+                Value::Generator(g) => Rc::as_ptr(g).hash(state),
+                Value::Fiber(f) => Rc::as_ptr(f).hash(state),
+                Value::Channel(c) => Rc::as_ptr(c).hash(state),
+                Value::Mutex(m) => Rc::as_ptr(m).hash(state),
+                Value::WeakBox(b) => Rc::as_ptr(b).hash(state),
+                Value::Pid(p) => p.hash(state),
+                Value::Condition(c) => Rc::as_ptr(c).hash(state),
+                Value::Port(p) => Rc::as_ptr(p).hash(state),
+                Value::Queue(q) => Rc::as_ptr(q).hash(state),
+                Value::Record(r) => Rc::as_ptr(r).hash(state),
+                Value::RecordType(r) => Rc::as_ptr(r).hash(state),
+                Value::RecordProcedure(p) => Rc::as_ptr(p).hash(state),
+                Value::List(items) | Value::Values(items) => items.len().hash(state),
+                Value::Vector(items) => items.borrow().len().hash(state),
+                Value::Bytevector(bytes) => bytes.borrow().len().hash(state),
+                Value::HashTable(table) => table.borrow().len().hash(state),
+                Value::Eof | Value::Unspecified => {}
+            }
+            if !matches!(value.as_ref(), Value::Vector(_) | Value::HashTable(_) | Value::Queue(_) | Value::Record(_)) {
+                pending.extend(children(value));
             }
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+/// Returns the elements of a list, vector, or multiple-values result, the
+/// keys and values of a hash table (interleaved), the elements of a queue
+/// (front first), the fields of a record,
+/// and nothing for atoms. Elements of an owned list are cloned, which is
+/// cheap; elements of mutable containers are cloned so that no borrow is held while callers walk
+/// the structure.
+pub(crate) fn children(value: Cow<'_, Value>) -> Vec<Cow<'_, Value>> {
+    match value {
+        Cow::Borrowed(Value::List(items) | Value::Values(items)) => items.iter().map(Cow::Borrowed).collect(),
+        Cow::Owned(Value::List(ref items) | Value::Values(ref items)) => items.iter().cloned().map(Cow::Owned).collect(),
+        Cow::Borrowed(Value::Vector(items)) => items.borrow().iter().cloned().map(Cow::Owned).collect(),
+        Cow::Owned(Value::Vector(ref items)) => items.borrow().iter().cloned().map(Cow::Owned).collect(),
+        Cow::Borrowed(Value::HashTable(table)) => table_children(&table.borrow()),
+        Cow::Owned(Value::HashTable(ref table)) => table_children(&table.borrow()),
+        Cow::Borrowed(Value::Queue(items)) => items.borrow().iter().cloned().map(Cow::Owned).collect(),
+        Cow::Owned(Value::Queue(ref items)) => items.borrow().iter().cloned().map(Cow::Owned).collect(),
+        Cow::Borrowed(Value::Record(record)) => record.fields.borrow().iter().cloned().map(Cow::Owned).collect(),
+        Cow::Owned(Value::Record(ref record)) => record.fields.borrow().iter().cloned().map(Cow::Owned).collect(),
+        _ => vec![],
+    }
+}
+
+fn table_children<'a>(table: &IndexMap<Value, Value>) -> Vec<Cow<'a, Value>> {
+    table.iter().flat_map(|(k, v)| [Cow::Owned(k.clone()), Cow::Owned(v.clone())]).collect()
+}
+
+#[derive(Clone)]
 pub struct Lambda {
     pub params: Vec<String>,
+    pub optionals: Vec<(String, Expr)>, // after `#:optional`, with default expressions
+    pub keys: Vec<(String, Expr)>,      // after `#:key`, with default expressions
     pub body: Expr,
     pub env: Rc<Env>
 }
 
+impl fmt::Debug for Lambda {
+    // The captured environment is omitted: it usually contains the lambda itself.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Lambda")
+            .field("params", &self.params)
+            .field("optionals", &self.optionals)
+            .field("keys", &self.keys)
+            .field("body", &self.body)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A procedure wrapped by `memoize`, with its results cached by the
+/// `equal?`-ness of the arguments.
+pub struct Memoized {
+    pub func: Value,
+    cache: RefCell<IndexMap<Vec<Value>, Value>>,
+}
+
+impl Memoized {
+    pub fn new(func: Value) -> Self {
+        Memoized { func, cache: RefCell::new(IndexMap::new()) }
+    }
+
+    /// Returns the cached result for these arguments, if any.
+    pub fn lookup(&self, args: &[Value]) -> Option<Value> {
+        self.cache.borrow().get(args).cloned()
+    }
+
+    pub fn remember(&self, args: Vec<Value>, result: Value) {
+        self.cache.borrow_mut().insert(args, result);
+    }
+
+    /// Returns the cached argument lists and results, in no particular order.
+    pub fn entries(&self) -> Vec<(Vec<Value>, Value)> {
+        self.cache.borrow().iter().map(|(args, result)| (args.clone(), result.clone())).collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.cache.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.cache.borrow().is_empty()
+    }
+
+    pub fn clear(&self) {
+        self.cache.borrow_mut().clear();
+    }
+
+    /// Calls `f` with each cached argument list, without copying them.
+    pub(crate) fn for_each_args(&self, f: impl FnMut(&[Value])) {
+        self.cache.borrow().keys().map(Vec::as_slice).for_each(f);
+    }
+
+    /// Forgets the results for the argument lists `keep` rejects, and
+    /// returns whether there were any.
+    pub(crate) fn retain(&self, mut keep: impl FnMut(&[Value]) -> bool) -> bool {
+        let mut cache = self.cache.borrow_mut();
+        let len = cache.len();
+        cache.retain(|args, _| keep(args));
+        cache.len() < len
+    }
+}
+
+impl fmt::Debug for Memoized {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Memoized")
+            .field("func", &self.func)
+            .field("cached", &self.len())
+            .finish()
+    }
+}
+
+type Thunk = Rc<dyn Fn() -> Result<Value, EvalError>>;
+
+/// A delayed computation whose result is computed by the first `force` and
+/// remembered for later ones.
+pub struct Promise {
+    state: RefCell<PromiseState>,
+}
+
+enum PromiseState {
+    Delayed(Thunk),
+    Forcing,
+    Forced(Value),
+}
+
+impl Promise {
+    pub fn new(thunk: impl Fn() -> Result<Value, EvalError> + 'static) -> Self {
+        Promise { state: RefCell::new(PromiseState::Delayed(Rc::new(thunk))) }
+    }
+
+    /// Returns the promised value, computing it if this is the first call.
+    /// If the computation fails, the next `force` tries again.
+    pub fn force(&self) -> Result<Value, EvalError> {
+        let state = mem::replace(&mut *self.state.borrow_mut(), PromiseState::Forcing);
+        let thunk = match state {
+            PromiseState::Forced(value) => {
+                *self.state.borrow_mut() = PromiseState::Forced(value.clone());
+                return Ok(value);
+            }
+            PromiseState::Delayed(thunk) => thunk,
+            PromiseState::Forcing => return Err(EvalError::Other("Promise forced while it is being forced".into())),
+        };
+        let result = thunk();
+        *self.state.borrow_mut() = match &result {
+            Ok(value) => PromiseState::Forced(value.clone()),
+            Err(_) => PromiseState::Delayed(thunk),
+        };
+        result
+    }
+}
+
+impl fmt::Debug for Promise {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &*self.state.borrow() {
+            PromiseState::Forced(value) => f.debug_tuple("Promise").field(value).finish(),
+            _ => f.debug_struct("Promise").finish_non_exhaustive(),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub enum EvalError {
     UndefinedSymbol(String),
     TypeError(String),
     ArityMismatch,
+    WrongArgumentCount(String), // `car: expected 1 argument, got 3`, for a built-in called with too few or too many
     NotCallable,
+    RecursionLimit,
+    OutOfFuel,
+    OutOfMemory, // see `eval::set_memory_limit`
+    Interrupted, // see `eval::set_interrupt_flag`
+    EffectInPureMode(String), // name of the forbidden operation
+    AssertionFailed(String), // source text of the failed expression, or the failed test and where its values differ
+    DuplicateBinding(String), // name bound twice in one `let`, `letrec`, or parameter list
+    Read(String), // source text that cannot be lexed or parsed
+    File(String), // file that cannot be read
+    Raised(String), // printed form of the object passed to `raise` or `error`, see `condition::error_object`
     Other(String),
 }
 
 
+/// How many arguments a procedure accepts.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Arity {
+    pub min: usize,
+    /// `None` for any number from `min` up.
+    pub max: Option<usize>,
+}
+
+impl Arity {
+    pub const fn exact(n: usize) -> Arity {
+        Arity { min: n, max: Some(n) }
+    }
+
+    pub const fn at_least(min: usize) -> Arity {
+        Arity { min, max: None }
+    }
+
+    pub const fn between(min: usize, max: usize) -> Arity {
+        Arity { min, max: Some(max) }
+    }
+
+    pub fn accepts(self, count: usize) -> bool {
+        count >= self.min && self.max.is_none_or(|max| count <= max)
+    }
+
+    /// The smallest arity accepting every count either accepts.
+    fn union(self, other: Arity) -> Arity {
+        let max = match (self.max, other.max) {
+            (Some(a), Some(b)) => Some(a.max(b)),
+            _ => None,
+        };
+        Arity { min: self.min.min(other.min), max }
+    }
+}
+
+/// Describes the count for an error message, as in "expected 1 argument"
+/// or "expected at least 2 arguments".
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let noun = |n: usize| if n == 1 { "argument" } else { "arguments" };
+        match self.max {
+            Some(max) if max == self.min => write!(f, "{} {}", max, noun(max)),
+            Some(max) if max == self.min + 1 => write!(f, "{} or {} arguments", self.min, max),
+            Some(max) => write!(f, "{} to {} arguments", self.min, max),
+            None => write!(f, "at least {} {}", self.min, noun(self.min)),
+        }
+    }
+}
+
+/// What was registered with a built-in procedure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BuiltinInfo {
+    pub name: &'static str,
+    pub arity: Arity,
+}
+
+/// Returns the name and arity of a built-in of `default_env`, or `None`
+/// for a native function that is not one.
+pub fn builtin_info(f: BuiltinFn) -> Option<BuiltinInfo> {
+    BUILTINS.with(|_| ());
+    BUILTIN_INFO.with(|info| info.borrow().get(&(f as usize)).copied())
+}
+
+/// Registers a built-in procedure, with the number of arguments it takes,
+/// which `eval::apply` checks before calling it.
+fn define_builtin(env: &Env, name: &'static str, arity: Arity, f: BuiltinFn) {
+    env.define(name.into(), Value::Function(f));
+    BUILTIN_INFO.with(|info| {
+        // Functions with identical code may share an address; accept what
+        // either accepts rather than reject a valid call.
+        let mut info = info.borrow_mut();
+        let entry = info.entry(f as usize).or_insert(BuiltinInfo { name, arity });
+        entry.arity = entry.arity.union(arity);
+    });
+}
+
 /// Returns the default global environment with all built-in functions registered.
 pub fn default_env() -> Rc<Env> {
+    Env::global_with(Some(BUILTINS.with(Rc::clone)))
+}
+
+/// A named group of built-in procedures, registered by `default_env` if
+/// it was compiled in.
+pub struct BuiltinGroup {
+    pub name: &'static str,
+    register: fn(&Env),
+}
+
+/// The groups of built-ins compiled in, in the order `default_env`
+/// registers them. Apart from `core`, each comes from the cargo feature of
+/// the same name, so builds such as the browser demo can leave out what
+/// they do not need.
+pub const BUILTIN_GROUPS: &[BuiltinGroup] = &[
+    BuiltinGroup { name: "core", register: register_core },
+    #[cfg(feature = "io")]
+    BuiltinGroup { name: "io", register: register_io },
+    #[cfg(feature = "date")]
+    BuiltinGroup { name: "date", register: register_date },
+    #[cfg(all(feature = "web", browser))]
+    BuiltinGroup { name: "web", register: register_web },
+];
+
+fn builtin_table() -> HashMap<String, Value> {
     let env = Env::new();
+    for group in BUILTIN_GROUPS {
+        (group.register)(&env);
+    }
+    match env.vars.replace(Frame::Small(Vec::new())) {
+        Frame::Large(vars) => vars,
+        Frame::Small(vars) => vars.into_iter().collect(),
+    }
+}
 
-    env.define("+".into(), Value::Function(builtin_add));
-    env.define("-".into(), Value::Function(builtin_sub));
-    env.define("*".into(), Value::Function(builtin_mul));
-    env.define("/".into(), Value::Function(builtin_div));
+/// Returns the names of the groups of built-ins compiled in, as symbols.
+fn builtin_features(args: Vec<Value>) -> Result<Value, EvalError> {
+    if !args.is_empty() {
+        return Err(EvalError::ArityMismatch);
+    }
+    Ok(Value::List(BUILTIN_GROUPS.iter().map(|group| Value::Symbol(group.name.into())).collect()))
+}
 
-    env.define("=".into(), Value::Function(builtin_eq));
-    env.define("<".into(), Value::Function(builtin_lt));
-    env.define(">".into(), Value::Function(builtin_gt));
+fn register_core(env: &Env) {
+    define_builtin(env, "+", Arity::at_least(0), builtin_add);
+    define_builtin(env, "-", Arity::at_least(1), builtin_sub);
+    define_builtin(env, "*", Arity::at_least(0), builtin_mul);
+    define_builtin(env, "/", Arity::at_least(1), builtin_div);
 
-    env.define("and".into(), Value::Function(builtin_and));
-    env.define("or".into(), Value::Function(builtin_or));
-    env.define("not".into(), Value::Function(builtin_not));
+    define_builtin(env, "=", Arity::at_least(1), builtin_eq);
+    define_builtin(env, "<", Arity::at_least(1), builtin_lt);
+    define_builtin(env, ">", Arity::at_least(1), builtin_gt);
+    define_builtin(env, "<=", Arity::at_least(1), builtin_le);
+    define_builtin(env, ">=", Arity::at_least(1), builtin_ge);
+    define_builtin(env, "min", Arity::at_least(1), builtin_min);
+    define_builtin(env, "max", Arity::at_least(1), builtin_max);
+
+    define_builtin(env, "and", Arity::at_least(0), builtin_and);
+    define_builtin(env, "or", Arity::at_least(0), builtin_or);
+    define_builtin(env, "not", Arity::exact(1), builtin_not);
+
+    define_builtin(env, "list", Arity::at_least(0), builtin_list);
+    define_builtin(env, "car", Arity::exact(1), builtin_car);
+    define_builtin(env, "cdr", Arity::exact(1), builtin_cdr);
+    define_builtin(env, "list?", Arity::exact(1), builtin_is_list);
+    define_builtin(env, "proper-list?", Arity::exact(1), builtin_is_list);
+    define_builtin(env, "length", Arity::exact(1), builtin_length);
+    define_builtin(env, "append", Arity::at_least(0), builtin_append);
+    define_builtin(env, "reverse", Arity::exact(1), builtin_reverse);
+    for &(name, f) in CAR_CDR_COMPOSITIONS {
+        define_builtin(env, name, Arity::exact(1), f);
+    }
+    define_builtin(env, "cons", Arity::exact(2), builtin_cons);
+    define_builtin(env, "equal?", Arity::exact(2), builtin_equal);
+    define_builtin(env, "compare", Arity::exact(2), builtin_compare);
+    define_builtin(env, "sort", Arity::between(1, 2), builtin_sort);
+    define_builtin(env, "map", Arity::at_least(2), builtin_map);
+    define_builtin(env, "for-each", Arity::at_least(2), builtin_for_each);
 
-    env.define("list".into(), Value::Function(builtin_list));
-    env.define("car".into(), Value::Function(builtin_car));
-    env.define("cdr".into(), Value::Function(builtin_cdr));
-    env.define("cons".into(), Value::Function(builtin_cons));
+    define_builtin(env, "vector", Arity::at_least(0), builtin_vector);
+    define_builtin(env, "make-vector", Arity::between(1, 2), builtin_make_vector);
+    define_builtin(env, "vector?", Arity::exact(1), builtin_is_vector);
+    define_builtin(env, "vector-length", Arity::exact(1), builtin_vector_length);
+    define_builtin(env, "vector-ref", Arity::exact(2), builtin_vector_ref);
+    define_builtin(env, "vector-set!", Arity::exact(3), builtin_vector_set);
+    define_builtin(env, "vector->list", Arity::exact(1), builtin_vector_to_list);
+    define_builtin(env, "vector-sort!", Arity::between(1, 2), builtin_vector_sort);
+    define_builtin(env, "vector-binary-search", Arity::between(2, 3), builtin_vector_binary_search);
+    define_builtin(env, "subvector", Arity::exact(3), builtin_subvector);
+    define_builtin(env, "vector-copy!", Arity::between(3, 5), builtin_vector_copy);
+    define_builtin(env, "vector-map", Arity::at_least(2), builtin_vector_map);
+    define_builtin(env, "vector-for-each", Arity::at_least(2), builtin_vector_for_each);
 
-    env
+    {
+        use crate::bytevector::*;
+        define_builtin(env, "bytevector", Arity::at_least(0), builtin_bytevector);
+        define_builtin(env, "make-bytevector", Arity::between(1, 2), builtin_make_bytevector);
+        define_builtin(env, "bytevector?", Arity::exact(1), builtin_is_bytevector);
+        define_builtin(env, "bytevector-length", Arity::exact(1), builtin_bytevector_length);
+        define_builtin(env, "bytevector-u8-ref", Arity::exact(2), builtin_bytevector_u8_ref);
+        define_builtin(env, "bytevector-u8-set!", Arity::exact(3), builtin_bytevector_u8_set);
+        define_builtin(env, "utf8->string", Arity::exact(1), builtin_utf8_to_string);
+        define_builtin(env, "string->utf8", Arity::exact(1), builtin_string_to_utf8);
+        define_builtin(env, "open-input-bytevector", Arity::exact(1), builtin_open_input_bytevector);
+        define_builtin(env, "open-output-bytevector", Arity::exact(0), builtin_open_output_bytevector);
+        define_builtin(env, "get-output-bytevector", Arity::exact(1), builtin_get_output_bytevector);
+        define_builtin(env, "read-u8", Arity::exact(1), builtin_read_u8);
+        define_builtin(env, "peek-u8", Arity::exact(1), builtin_peek_u8);
+        define_builtin(env, "read-bytevector", Arity::exact(2), builtin_read_bytevector);
+        define_builtin(env, "write-u8", Arity::exact(2), builtin_write_u8);
+        define_builtin(env, "write-bytevector", Arity::between(2, 4), builtin_write_bytevector);
+    }
+    define_builtin(env, "list->vector", Arity::exact(1), builtin_list_to_vector);
+
+    define_builtin(env, "make-queue", Arity::at_least(0), builtin_make_queue);
+    define_builtin(env, "queue?", Arity::exact(1), builtin_is_queue);
+    define_builtin(env, "queue-empty?", Arity::exact(1), builtin_is_queue_empty);
+    define_builtin(env, "queue-length", Arity::exact(1), builtin_queue_length);
+    define_builtin(env, "enqueue!", Arity::exact(2), builtin_enqueue);
+    define_builtin(env, "dequeue!", Arity::exact(1), builtin_dequeue);
+    define_builtin(env, "queue-front", Arity::exact(1), builtin_queue_front);
+    define_builtin(env, "queue-push-front!", Arity::exact(2), builtin_queue_push_front);
+    define_builtin(env, "queue-pop-back!", Arity::exact(1), builtin_queue_pop_back);
+    define_builtin(env, "queue->list", Arity::exact(1), builtin_queue_to_list);
+
+    define_builtin(env, "record?", Arity::exact(1), builtin_is_record);
+    define_builtin(env, "set-record-type-printer!", Arity::exact(2), builtin_set_record_type_printer);
+
+    define_builtin(env, "make-hash-table", Arity::exact(0), builtin_make_hash_table);
+    define_builtin(env, "hash-table?", Arity::exact(1), builtin_is_hash_table);
+    define_builtin(env, "hash-table-set!", Arity::exact(3), builtin_hash_table_set);
+    define_builtin(env, "hash-table-ref", Arity::between(2, 3), builtin_hash_table_ref);
+    define_builtin(env, "hash-table-ref/default", Arity::exact(3), builtin_hash_table_ref_default);
+    define_builtin(env, "hash-table-contains?", Arity::exact(2), builtin_hash_table_contains);
+    define_builtin(env, "hash-table-delete!", Arity::exact(2), builtin_hash_table_delete);
+    define_builtin(env, "hash-table-update!", Arity::between(3, 4), builtin_hash_table_update);
+    define_builtin(env, "hash-table-update!/default", Arity::exact(4), builtin_hash_table_update_default);
+    define_builtin(env, "hash-table-count", Arity::exact(1), builtin_hash_table_count);
+    define_builtin(env, "hash-table-copy", Arity::exact(1), builtin_hash_table_copy);
+    define_builtin(env, "hash-table-keys", Arity::exact(1), builtin_hash_table_keys);
+    define_builtin(env, "hash-table-values", Arity::exact(1), builtin_hash_table_values);
+    define_builtin(env, "hash-table-for-each", Arity::exact(2), builtin_hash_table_for_each);
+    define_builtin(env, "hash-table->alist", Arity::exact(1), builtin_hash_table_to_alist);
+    define_builtin(env, "alist->hash-table", Arity::exact(1), builtin_alist_to_hash_table);
+
+    define_builtin(env, "number->string", Arity::between(1, 3), builtin_number_to_string);
+    define_builtin(env, "string->number", Arity::between(1, 2), builtin_string_to_number);
+    define_builtin(env, "->string", Arity::exact(1), builtin_to_string);
+    define_builtin(env, "string-append", Arity::at_least(0), builtin_string_append);
+    define_builtin(env, "string?", Arity::exact(1), builtin_is_string);
+    define_builtin(env, "string-length", Arity::exact(1), builtin_string_length);
+    define_builtin(env, "string-ref", Arity::exact(2), builtin_string_ref);
+    define_builtin(env, "substring", Arity::between(2, 3), builtin_substring);
+    define_builtin(env, "string-upcase", Arity::exact(1), builtin_string_upcase);
+    define_builtin(env, "string-downcase", Arity::exact(1), builtin_string_downcase);
+    define_builtin(env, "string->list", Arity::exact(1), builtin_string_to_list);
+    define_builtin(env, "list->string", Arity::exact(1), builtin_list_to_string);
+    define_builtin(env, "keyword?", Arity::exact(1), builtin_is_keyword);
+    define_builtin(env, "keyword->string", Arity::exact(1), builtin_keyword_to_string);
+    define_builtin(env, "string->keyword", Arity::exact(1), builtin_string_to_keyword);
+    define_builtin(env, "format", Arity::at_least(1), builtin_format);
+    define_builtin(env, "display", Arity::between(1, 2), builtin_display);
+    define_builtin(env, "write", Arity::between(1, 2), builtin_write);
+    define_builtin(env, "write-string", Arity::between(1, 2), builtin_write_string);
+    define_builtin(env, "newline", Arity::between(0, 1), builtin_newline);
+    define_builtin(env, "current-output-port", Arity::exact(0), builtin_current_output_port);
+    define_builtin(env, "open-output-string", Arity::exact(0), builtin_open_output_string);
+    define_builtin(env, "get-output-string", Arity::exact(1), builtin_get_output_string);
+    define_builtin(env, "current-input-port", Arity::exact(0), builtin_current_input_port);
+    define_builtin(env, "open-input-string", Arity::exact(1), builtin_open_input_string);
+    define_builtin(env, "read-char", Arity::between(0, 1), builtin_read_char);
+    define_builtin(env, "peek-char", Arity::between(0, 1), builtin_peek_char);
+    define_builtin(env, "read-line", Arity::between(0, 1), builtin_read_line);
+    define_builtin(env, "char-ready?", Arity::between(0, 1), builtin_is_char_ready);
+    define_builtin(env, "eof-object", Arity::exact(0), builtin_eof_object);
+    define_builtin(env, "eof-object?", Arity::exact(1), builtin_is_eof_object);
+    define_builtin(env, "char?", Arity::exact(1), builtin_is_char);
+    define_builtin(env, "procedure-arity", Arity::exact(1), builtin_procedure_arity);
+    define_builtin(env, "colorize", Arity::exact(2), builtin_colorize);
+    define_builtin(env, "with-style", Arity::exact(2), builtin_with_style);
+    define_builtin(env, "color-enabled?", Arity::exact(0), builtin_is_color_enabled);
+    define_builtin(env, "set-color-enabled!", Arity::exact(1), builtin_set_color_enabled);
+
+    define_builtin(env, "exact?", Arity::exact(1), builtin_is_exact);
+    define_builtin(env, "inexact?", Arity::exact(1), builtin_is_inexact);
+    define_builtin(env, "nan?", Arity::exact(1), builtin_is_nan);
+    define_builtin(env, "infinite?", Arity::exact(1), builtin_is_infinite);
+    define_builtin(env, "finite?", Arity::exact(1), builtin_is_finite);
+    define_builtin(env, "exact", Arity::exact(1), builtin_exact);
+    define_builtin(env, "inexact", Arity::exact(1), builtin_inexact);
+    define_builtin(env, "floor", Arity::exact(1), builtin_floor);
+    define_builtin(env, "ceiling", Arity::exact(1), builtin_ceiling);
+    define_builtin(env, "round", Arity::exact(1), builtin_round);
+    define_builtin(env, "truncate", Arity::exact(1), builtin_truncate);
+    define_builtin(env, "floor/", Arity::exact(2), builtin_floor_div);
+    define_builtin(env, "truncate/", Arity::exact(2), builtin_truncate_div);
+    define_builtin(env, "exact-integer-sqrt", Arity::exact(1), builtin_exact_integer_sqrt);
+    define_builtin(env, "bitwise-and", Arity::at_least(0), builtin_bitwise_and);
+    define_builtin(env, "bitwise-ior", Arity::at_least(0), builtin_bitwise_ior);
+    define_builtin(env, "bitwise-xor", Arity::at_least(0), builtin_bitwise_xor);
+    define_builtin(env, "bitwise-not", Arity::exact(1), builtin_bitwise_not);
+    define_builtin(env, "arithmetic-shift", Arity::exact(2), builtin_arithmetic_shift);
+    define_builtin(env, "bit-count", Arity::exact(1), builtin_bit_count);
+
+    define_builtin(env, "memoize", Arity::exact(1), builtin_memoize);
+    define_builtin(env, "memoized?", Arity::exact(1), builtin_is_memoized);
+    define_builtin(env, "memo-cache", Arity::exact(1), builtin_memo_cache);
+    define_builtin(env, "memo-cache-size", Arity::exact(1), builtin_memo_cache_size);
+    define_builtin(env, "memo-clear!", Arity::exact(1), builtin_memo_clear);
+    define_builtin(env, "identity", Arity::exact(1), builtin_identity);
+    define_builtin(env, "const", Arity::exact(1), builtin_const);
+    define_builtin(env, "compose", Arity::at_least(1), builtin_compose);
+    define_builtin(env, "flip", Arity::exact(1), builtin_flip);
+    define_builtin(env, "curry", Arity::at_least(1), builtin_curry);
+    define_builtin(env, "curried", Arity::between(1, 2), builtin_curried);
+
+    define_builtin(env, "force", Arity::exact(1), builtin_force);
+    env.define("the-empty-stream".into(), Value::List(Rc::new([])));
+    define_builtin(env, "stream-null?", Arity::exact(1), builtin_is_stream_null);
+    define_builtin(env, "stream-pair?", Arity::exact(1), builtin_is_stream_pair);
+    define_builtin(env, "stream-car", Arity::exact(1), builtin_stream_car);
+    define_builtin(env, "stream-cdr", Arity::exact(1), builtin_stream_cdr);
+    define_builtin(env, "stream-map", Arity::exact(2), builtin_stream_map);
+    define_builtin(env, "stream-filter", Arity::exact(2), builtin_stream_filter);
+    define_builtin(env, "stream-take", Arity::exact(2), builtin_stream_take);
+    define_builtin(env, "stream->list", Arity::between(1, 2), builtin_stream_to_list);
+    define_builtin(env, "make-generator", Arity::exact(1), builtin_make_generator);
+    define_builtin(env, "yield", Arity::between(0, 1), builtin_yield);
+    define_builtin(env, "generator-next", Arity::exact(1), builtin_generator_next);
+    define_builtin(env, "generator?", Arity::exact(1), builtin_is_generator);
+    define_builtin(env, "generator->stream", Arity::exact(1), builtin_generator_to_stream);
+    define_builtin(env, "spawn-fiber", Arity::exact(1), builtin_spawn_fiber);
+    define_builtin(env, "join", Arity::exact(1), builtin_join);
+    define_builtin(env, "fiber?", Arity::exact(1), builtin_is_fiber);
+    define_builtin(env, "fiber-done?", Arity::exact(1), builtin_fiber_is_done);
+    define_builtin(env, "make-channel", Arity::between(0, 1), builtin_make_channel);
+    define_builtin(env, "channel?", Arity::exact(1), builtin_is_channel);
+    define_builtin(env, "channel-put!", Arity::exact(2), builtin_channel_put);
+    define_builtin(env, "channel-get", Arity::exact(1), builtin_channel_get);
+    define_builtin(env, "select", Arity::at_least(1), builtin_select);
+    define_builtin(env, "make-mutex", Arity::exact(0), builtin_make_mutex);
+    define_builtin(env, "mutex?", Arity::exact(1), builtin_is_mutex);
+    define_builtin(env, "mutex-lock!", Arity::exact(1), builtin_mutex_lock);
+    define_builtin(env, "mutex-unlock!", Arity::exact(1), builtin_mutex_unlock);
+    define_builtin(env, "mutex-locked?", Arity::exact(1), builtin_is_mutex_locked);
+    define_builtin(env, "make-weak-box", Arity::exact(1), builtin_make_weak_box);
+    define_builtin(env, "weak-box?", Arity::exact(1), builtin_is_weak_box);
+    define_builtin(env, "weak-box-value", Arity::between(1, 2), builtin_weak_box_value);
+    define_builtin(env, "set-finalizer!", Arity::exact(2), builtin_set_finalizer);
+    define_builtin(env, "make-weak-hash-table", Arity::exact(0), builtin_make_weak_hash_table);
+    define_builtin(env, "hash-table-weak?", Arity::exact(1), builtin_is_weak_hash_table);
+    define_builtin(env, "collect-garbage", Arity::exact(0), builtin_collect_garbage);
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        use crate::actor::*;
+        define_builtin(env, "spawn", Arity::exact(1), builtin_spawn);
+        define_builtin(env, "send", Arity::exact(2), builtin_send);
+        define_builtin(env, "self", Arity::exact(0), builtin_self);
+    }
+    define_builtin(env, "raise", Arity::exact(1), builtin_raise);
+    define_builtin(env, "error", Arity::at_least(1), builtin_error);
+    define_builtin(env, "error-object?", Arity::exact(1), builtin_is_error_object);
+    define_builtin(env, "condition-message", Arity::exact(1), builtin_condition_message);
+    define_builtin(env, "condition-irritants", Arity::exact(1), builtin_condition_irritants);
+    define_builtin(env, "condition-kind", Arity::exact(1), builtin_condition_kind);
+    define_builtin(env, "current-continuation-marks", Arity::exact(0), builtin_current_continuation_marks);
+    define_builtin(env, "continuation-mark-set->list", Arity::exact(2), builtin_continuation_mark_set_to_list);
+    define_builtin(env, "continuation-mark-set-first", Arity::between(2, 3), builtin_continuation_mark_set_first);
+
+    define_builtin(env, "values", Arity::at_least(0), builtin_values);
+    define_builtin(env, "call-with-values", Arity::exact(2), builtin_call_with_values);
+
+    // Printer limits consulted by the REPL; `#f` means unlimited.
+    env.define("*print-depth*".into(), Value::Boolean(false));
+    env.define("*print-length*".into(), Value::Boolean(false));
+    // Significant digits the REPL prints floats with; `#f` means as many as
+    // it takes to read back as the same float.
+    env.define("*print-precision*".into(), Value::Boolean(false));
+
+    define_builtin(env, "features", Arity::exact(0), builtin_features);
+}
+
+#[cfg(feature = "io")]
+fn register_io(env: &Env) {
+    define_builtin(env, "open-input-file", Arity::exact(1), builtin_open_input_file);
+    define_builtin(env, "open-binary-input-file", Arity::exact(1), crate::bytevector::builtin_open_binary_input_file);
+    define_builtin(env, "sleep", Arity::exact(1), crate::timer::builtin_sleep);
+    define_builtin(env, "after", Arity::exact(2), crate::timer::builtin_after);
+
+    #[cfg(not(browser))]
+    {
+        use crate::terminal::*;
+        define_builtin(env, "terminal-raw-mode!", Arity::exact(1), builtin_terminal_raw_mode);
+        define_builtin(env, "terminal-size", Arity::exact(0), builtin_terminal_size);
+        define_builtin(env, "terminal-clear", Arity::exact(0), builtin_terminal_clear);
+        define_builtin(env, "terminal-move-cursor!", Arity::exact(2), builtin_terminal_move_cursor);
+        define_builtin(env, "terminal-cursor-visible!", Arity::exact(1), builtin_terminal_cursor_visible);
+        define_builtin(env, "on-signal", Arity::exact(2), crate::signal::builtin_on_signal);
+    }
+}
+
+#[cfg(feature = "date")]
+fn register_date(env: &Env) {
+    use crate::date::*;
+    define_builtin(env, "current-date", Arity::between(0, 1), builtin_current_date);
+    define_builtin(env, "make-date", Arity::exact(8), builtin_make_date);
+    define_builtin(env, "date?", Arity::exact(1), builtin_is_date);
+    define_builtin(env, "date-nanosecond", Arity::exact(1), builtin_date_nanosecond);
+    define_builtin(env, "date-second", Arity::exact(1), builtin_date_second);
+    define_builtin(env, "date-minute", Arity::exact(1), builtin_date_minute);
+    define_builtin(env, "date-hour", Arity::exact(1), builtin_date_hour);
+    define_builtin(env, "date-day", Arity::exact(1), builtin_date_day);
+    define_builtin(env, "date-month", Arity::exact(1), builtin_date_month);
+    define_builtin(env, "date-year", Arity::exact(1), builtin_date_year);
+    define_builtin(env, "date-zone-offset", Arity::exact(1), builtin_date_zone_offset);
+    define_builtin(env, "date-week-day", Arity::exact(1), builtin_date_week_day);
+    define_builtin(env, "date-year-day", Arity::exact(1), builtin_date_year_day);
+    define_builtin(env, "date->string", Arity::between(1, 2), builtin_date_to_string);
+    define_builtin(env, "string->date", Arity::exact(2), builtin_string_to_date);
+}
+
+#[cfg(all(feature = "web", browser))]
+fn register_web(env: &Env) {
+    use crate::dom::*;
+    define_builtin(env, "dom-query", Arity::exact(1), builtin_dom_query);
+    define_builtin(env, "dom-set-text!", Arity::exact(2), builtin_dom_set_text);
+    define_builtin(env, "dom-on", Arity::exact(3), builtin_dom_on);
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_drop_very_deep_values() {
+        let mut list = Value::List(Rc::new([]));
+        for _ in 0..100_000 {
+            list = Value::List(Rc::new([Value::Number(1), list]));
+        }
+        let mut vector = Value::Vector(Rc::new(RefCell::new(vec![])));
+        for _ in 0..100_000 {
+            let table = IndexMap::from([(Value::Number(0), vector)]);
+            vector = Value::Vector(Rc::new(RefCell::new(vec![Value::HashTable(Rc::new(RefCell::new(table)))])));
+        }
+        let shared = list.clone();
+        drop(list);
+        assert!(matches!(shared, Value::List(ref items) if items.len() == 2));
+        drop(shared);
+        drop(vector);
+    }
+
     #[test]
     fn test_define_and_get() {
         let env = Env::new();
@@ -154,4 +1235,109 @@ mod tests {
         let env = Env::new();
         assert_eq!(env.get("y"), None);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_global_of_nested_env() {
+        let global = Env::new();
+        let child = Env::extend(Env::extend(global.clone()));
+        assert!(Rc::ptr_eq(&child.global(), &global));
+        assert!(Rc::ptr_eq(&global.global(), &global));
+    }
+
+    #[test]
+    fn test_frames_grow_past_small_size() {
+        let env = Env::extend_with_capacity(Env::new(), 2);
+        for i in 0..SMALL_FRAME * 2 {
+            env.define(format!("v{}", i), Value::Number(i as i64));
+            env.define("v0".into(), Value::Number(-1));
+        }
+        assert!(matches!(*env.vars.borrow(), Frame::Large(_)));
+        assert_eq!(env.get("v0"), Some(Value::Number(-1)));
+        assert_eq!(env.get(&format!("v{}", SMALL_FRAME * 2 - 1)), Some(Value::Number(SMALL_FRAME as i64 * 2 - 1)));
+        assert_eq!(env.local_bindings().len(), SMALL_FRAME * 2);
+    }
+
+    #[test]
+    fn test_builtins_are_shared_and_can_be_redefined() {
+        let (a, b) = (default_env(), default_env());
+        assert!(Rc::ptr_eq(a.builtins.as_ref().unwrap(), b.builtins.as_ref().unwrap()));
+        assert_eq!(Env::new().get("+"), None);
+        a.define("+".into(), Value::Number(1));
+        assert_eq!(a.get("+"), Some(Value::Number(1)));
+        assert!(matches!(b.get("+"), Some(Value::Function(_))));
+    }
+
+    #[test]
+    fn test_builtin_groups_follow_features() {
+        let features = builtin_features(vec![]).unwrap().to_string();
+        assert!(features.starts_with("(core"));
+        assert_eq!(features.contains("io"), cfg!(feature = "io"));
+        assert_eq!(default_env().get("sleep").is_some(), cfg!(feature = "io"));
+        assert_eq!(default_env().get("current-date").is_some(), cfg!(feature = "date"));
+    }
+
+    #[test]
+    fn test_cached_lookups_follow_definitions_and_local_bindings() {
+        use crate::eval::eval_sequence;
+        use crate::lexer::tokenize;
+        use crate::parser::parse_program;
+
+        let env = default_env();
+        let run = |source: &str| eval_sequence(&parse_program(tokenize(source).unwrap()).unwrap(), env.clone()).unwrap();
+        run("(define (add a b) (+ a b)) (define (shadow? flag) (if flag (define + -) #f) (+ 5 1))");
+        assert_eq!(run("(list (shadow? #f) (shadow? #t) (shadow? #f))"), run("'(6 4 6)"));
+        assert_eq!(run("(add 1 2)"), Value::Number(3));
+        assert_eq!(run("(define (+ a b) (* a b)) (add 1 2)"), Value::Number(2));
+    }
+
+    fn hash_of(value: &Value) -> u64 {
+        let mut hasher = std::collections::hash_map::DefaultHasher::new();
+        value.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn test_values_stay_small() {
+        assert_eq!(std::mem::size_of::<Value>(), 24);
+    }
+
+    #[test]
+    fn test_equal_values_hash_equally() {
+        let list = || Value::List(Rc::new([Value::Number(1), Value::String("a".into()), Value::Float(-0.0)]));
+        assert_eq!(hash_of(&list()), hash_of(&list()));
+        assert_eq!(hash_of(&Value::Float(0.0)), hash_of(&Value::Float(-0.0)));
+    }
+
+    #[test]
+    fn test_values_as_hash_set_keys() {
+        let mut set = HashSet::new();
+        set.insert(Value::List(Rc::new([Value::Symbol("a".into())])));
+        set.insert(Value::Number(1));
+        assert!(set.contains(&Value::List(Rc::new([Value::Symbol("a".into())]))));
+        assert!(!set.contains(&Value::Float(1.0)));
+        assert!(!set.contains(&Value::String("a".into())));
+    }
+
+    #[test]
+    fn test_lambdas_compare_by_identity() {
+        let make = || Value::Lambda(Rc::new(Lambda {
+            params: vec![],
+            optionals: vec![],
+            keys: vec![],
+            body: Expr::Number(1),
+            env: Env::new(),
+        }));
+        let (a, b) = (make(), make());
+        assert_ne!(a, b);
+        assert_eq!(a, a.clone());
+        assert_eq!(hash_of(&a), hash_of(&a.clone()));
+    }
+
+    #[test]
+    fn test_display_and_write_strings() {
+        let value = Value::List(Rc::new([Value::String("hi".into()), Value::Float(2.0), Value::Unspecified]));
+        assert_eq!(value.display_string(), "(hi 2.0 #<unspecified>)");
+        assert_eq!(value.write_string(), "(\"hi\" 2.0 #<unspecified>)");
+        assert_eq!(format!("{}", value), value.write_string());
+    }
+}
@@ -1,12 +1,272 @@
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, HashSet};
 use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 
-use crate::env::{Env, EvalError, Value, Lambda};
+use crate::env::{builtin_info, Env, EvalError, Value, Lambda, Promise};
 use crate::ast::Expr;
+use crate::number::Number;
+use crate::compiled;
+use crate::lexer::{tokenize_with_options, LexOptions};
+use crate::parser::parse_program;
+use crate::condition::{eval_guard, in_guard};
+use crate::limits::{eval_with_limits, eval_with_timeout};
+use crate::marks::eval_with_continuation_mark;
+use crate::mutex::eval_with_mutex;
+use crate::pattern::eval_match;
+use crate::printer::{find_difference, print_value, PrintOptions, PrintStyle};
+use crate::record::eval_define_record_type;
+use crate::resolver::{file_resolver, read_file, read_text_file};
+
+/// Default maximum nesting depth of `eval`, chosen to fit in a 1 MiB stack
+/// (the WebAssembly default) in release builds. Debug builds use several
+/// times more stack per level.
+pub const DEFAULT_RECURSION_LIMIT: usize = 1_000;
+
+thread_local! {
+    static DEPTH: Cell<usize> = const { Cell::new(0) };
+    static RECURSION_LIMIT: Cell<usize> = const { Cell::new(DEFAULT_RECURSION_LIMIT) };
+    static HOOKS: RefCell<Option<Rc<dyn EvalHooks>>> = const { RefCell::new(None) };
+    static PURE_MODE: Cell<bool> = const { Cell::new(false) };
+    static FUEL: Cell<Option<u64>> = const { Cell::new(None) };
+    static MEMORY_LIMIT: Cell<Option<MemoryLimit>> = const { Cell::new(None) };
+    static INTERRUPT: RefCell<Option<Arc<AtomicBool>>> = const { RefCell::new(None) };
+    static ERROR_HANDLER: RefCell<Option<ErrorHandler>> = const { RefCell::new(None) };
+    /// Set when the handler aborts, so that the expressions the error passes
+    /// through on its way out are not offered it again.
+    static ABORTED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// How to continue after an error, as chosen by an error handler.
+#[derive(Debug)]
+pub enum Restart {
+    /// Evaluate the failing expression again.
+    Retry,
+    /// Use this value as the result of the failing expression.
+    Return(Value),
+    /// Let the error propagate.
+    Abort,
+}
+
+/// Called with the expression that failed, the environment it was evaluated
+/// in, and the error, before the error propagates.
+pub type ErrorHandler = Rc<dyn Fn(&Expr, &Rc<Env>, &EvalError) -> Restart>;
+
+/// Installs a handler for errors on the current thread, replacing any
+/// installed before; `None` removes it. The handler runs at the innermost
+/// expression that fails, while its environment is still available, so it
+/// can inspect the environment and choose a `Restart`. It is not called for
+/// errors that `guard` handles, or for running out of fuel or past the
+/// recursion limit.
+pub fn set_error_handler(handler: Option<ErrorHandler>) {
+    ERROR_HANDLER.with(|h| *h.borrow_mut() = handler);
+}
+
+fn error_handler() -> Option<ErrorHandler> {
+    ERROR_HANDLER.with(|h| h.borrow().clone())
+}
+
+/// Callbacks invoked as the interpreter runs, for tracing, coverage,
+/// auditing, and similar tools. Every method does nothing by default.
+pub trait EvalHooks {
+    /// Called before an expression is evaluated.
+    fn on_eval_enter(&self, _expr: &Expr, _env: &Rc<Env>) {}
+
+    /// Called after an expression is evaluated, with its result.
+    fn on_eval_exit(&self, _expr: &Expr, _result: &Result<Value, EvalError>) {}
+
+    /// Called before a procedure is applied to its evaluated arguments.
+    fn on_apply(&self, _func: &Value, _args: &[Value]) {}
+
+    /// Called when a `lambda` is evaluated. The closure holds its own copy
+    /// of `source`, the body expressions as written.
+    fn on_lambda(&self, _source: &[Expr], _lambda: &Rc<Lambda>) {}
+}
+
+/// Installs hooks for evaluation on the current thread, replacing any
+/// installed before. `None` removes them.
+pub fn set_hooks(hooks: Option<Rc<dyn EvalHooks>>) {
+    HOOKS.with(|h| *h.borrow_mut() = hooks);
+}
+
+fn hooks() -> Option<Rc<dyn EvalHooks>> {
+    HOOKS.with(|h| h.borrow().clone())
+}
+
+/// Sets the maximum nesting depth of `eval` on the current thread.
+///
+/// Evaluation that nests deeper fails with `EvalError::RecursionLimit`
+/// instead of overflowing the Rust stack. Raise it only if the thread's
+/// stack is large enough to hold the extra frames.
+pub fn set_recursion_limit(limit: usize) {
+    RECURSION_LIMIT.with(|l| l.set(limit));
+}
+
+/// Returns the maximum nesting depth of `eval` on the current thread.
+pub fn recursion_limit() -> usize {
+    RECURSION_LIMIT.with(|l| l.get())
+}
+
+/// Turns pure mode on or off for evaluation on the current thread.
+///
+/// In pure mode every operation with an effect outside the interpreter
+/// (writing to standard output, reading files with `load` or `include`)
+/// fails with `EvalError::EffectInPureMode`, so the result of evaluation
+/// depends only on the program. Mutating values the program created itself,
+/// such as vectors, hash tables, and string ports, is still allowed.
+///
+/// Combined with `set_fuel`, this makes it safe to evaluate untrusted
+/// expressions such as user-defined formulas.
+pub fn set_pure_mode(pure: bool) {
+    PURE_MODE.with(|p| p.set(pure));
+}
+
+/// Returns whether pure mode is on for the current thread.
+pub fn is_pure_mode() -> bool {
+    PURE_MODE.with(|p| p.get())
+}
+
+/// Fails with `EvalError::EffectInPureMode` if pure mode is on. Every
+/// builtin with an external effect calls this before acting.
+pub(crate) fn check_effect(operation: &str) -> Result<(), EvalError> {
+    if is_pure_mode() {
+        return Err(EvalError::EffectInPureMode(operation.to_string()));
+    }
+    Ok(())
+}
+
+/// Limits the number of expressions the current thread may evaluate.
+///
+/// Each call to `eval` uses one unit of fuel; once it runs out, evaluation
+/// fails with `EvalError::OutOfFuel`. `None` removes the limit. Unlike a
+/// timeout, the point at which fuel runs out is the same on every run.
+pub fn set_fuel(fuel: Option<u64>) {
+    FUEL.with(|f| f.set(fuel));
+}
+
+/// Returns the fuel left on the current thread, or `None` if unlimited.
+pub fn remaining_fuel() -> Option<u64> {
+    FUEL.with(|f| f.get())
+}
+
+/// A limit on the memory a program may use, which built-ins that allocate
+/// in bulk, such as `make-vector`, check before allocating.
+#[derive(Debug, Clone, Copy)]
+pub struct MemoryLimit {
+    /// The most bytes that may be in use.
+    pub ceiling: usize,
+    /// Reports the bytes in use, typically from a counting global allocator.
+    pub in_use: fn() -> usize,
+}
+
+/// Limits the memory built-ins on the current thread may allocate in bulk:
+/// one that would take the memory in use past the ceiling fails with
+/// `EvalError::OutOfMemory` instead. `None` removes the limit. Allocations
+/// made a little at a time are not checked, so this does not replace
+/// measuring the memory in use while the program runs.
+pub fn set_memory_limit(limit: Option<MemoryLimit>) {
+    MEMORY_LIMIT.with(|m| m.set(limit));
+}
+
+/// Makes a vector of `len` copies of `fill` for a built-in, failing with
+/// `EvalError::OutOfMemory` rather than aborting the process if that would
+/// go over the limit set by `set_memory_limit` or the allocator cannot
+/// provide the memory.
+pub(crate) fn filled_vec<T: Clone>(fill: T, len: usize) -> Result<Vec<T>, EvalError> {
+    let bytes = len.saturating_mul(size_of::<T>());
+    if let Some(limit) = MEMORY_LIMIT.with(Cell::get)
+        && (limit.in_use)().saturating_add(bytes) > limit.ceiling
+    {
+        return Err(EvalError::OutOfMemory);
+    }
+    let mut items = Vec::new();
+    items.try_reserve_exact(len).map_err(|_| EvalError::OutOfMemory)?;
+    items.resize(len, fill);
+    Ok(items)
+}
+
+fn consume_fuel() -> Result<(), EvalError> {
+    FUEL.with(|f| match f.get() {
+        None => Ok(()),
+        Some(0) => Err(EvalError::OutOfFuel),
+        Some(n) => {
+            f.set(Some(n - 1));
+            Ok(())
+        }
+    })
+}
+
+/// Lets another thread stop evaluation on the current thread: once `flag`
+/// is set, the next expression fails with `EvalError::Interrupted`, and the
+/// flag is cleared. `None` removes the flag.
+pub fn set_interrupt_flag(flag: Option<Arc<AtomicBool>>) {
+    INTERRUPT.with(|i| *i.borrow_mut() = flag);
+}
+
+fn check_interrupt() -> Result<(), EvalError> {
+    INTERRUPT.with(|i| match &*i.borrow() {
+        Some(flag) if flag.load(Ordering::Relaxed) && flag.swap(false, Ordering::Relaxed) => Err(EvalError::Interrupted),
+        _ => Ok(()),
+    })
+}
+
+/// The longest that built-ins which block, such as `sleep`, wait at a time
+/// before checking `check_wait` again.
+pub(crate) const WAIT_SLICE: std::time::Duration = std::time::Duration::from_millis(10);
+
+/// Fails with `EvalError::Interrupted` if evaluation has been interrupted
+/// (see `set_interrupt_flag`) or the time given to `with-limits` is up. Code
+/// that blocks calls it every `WAIT_SLICE`, since `eval` only checks between
+/// expressions.
+pub(crate) fn check_wait() -> Result<(), EvalError> {
+    check_interrupt()?;
+    #[cfg(not(browser))]
+    crate::limits::check_deadline()?;
+    Ok(())
+}
+
+/// The current `eval` nesting depth, counted against the recursion limit.
+pub(crate) fn depth() -> usize {
+    DEPTH.with(|d| d.get())
+}
+
+/// Replaces the nesting depth, for code that switches between stacks.
+pub(crate) fn set_depth(depth: usize) {
+    DEPTH.with(|d| d.set(depth));
+}
+
+/// Tracks one level of `eval` nesting, released when dropped.
+struct DepthGuard;
+
+impl DepthGuard {
+    fn enter() -> Result<DepthGuard, EvalError> {
+        let depth = DEPTH.with(|d| d.get());
+        if depth >= recursion_limit() {
+            return Err(EvalError::RecursionLimit);
+        }
+        DEPTH.with(|d| d.set(depth + 1));
+        Ok(DepthGuard)
+    }
+}
+
+impl Drop for DepthGuard {
+    fn drop(&mut self) {
+        DEPTH.with(|d| d.set(d.get() - 1));
+    }
+}
 
 /// Evaluates a Scheme expression in the given environment.
 ///
-/// Supports literals (number, boolean, string), variable lookup, and
-/// special forms: `define`, `lambda`, `begin`, `if`, and `let`.
+/// Supports literals (number, boolean, string, keyword, hash table), variable
+/// lookup, and
+/// special forms: `define`, `define-record-type`, `lambda`, `begin`, `if`, `let`, `let*`, `letrec`,
+/// `let-values`, `receive`, `match`, `assert`, `quote`, `delay`, `cons-stream`,
+/// `load`, `include`, and `include-ci`.
+///
+/// `begin` evaluates its body in the current environment, so definitions
+/// inside a top-level `begin` (or an included file) become top-level
+/// definitions visible to later forms.
 /// Function calls are evaluated as applications of built-in or user-defined
 /// functions (lambdas).
 ///
@@ -16,31 +276,253 @@ use crate::ast::Expr;
 ///
 /// # Returns
 /// * `Ok(Value)` if evaluation succeeds.
-/// * `Err(EvalError)` on undefined symbols, type errors, arity mismatches, or invalid calls.
+/// * `Err(EvalError)` on undefined symbols, type errors, arity mismatches, invalid calls,
+///   or nesting deeper than the recursion limit (see `set_recursion_limit`).
+///
+/// Installed `EvalHooks` are told about every expression evaluated.
+/// Each call uses one unit of fuel if a limit is set (see `set_fuel`), stops
+/// if interrupted (see `set_interrupt_flag`), and first runs the handlers of any signals that have arrived (see `on-signal`)
+/// and any timers that are due (see `after`). Every so often it also switches fibers (see `spawn-fiber`)
+/// and runs the finalizers of freed objects (see `set-finalizer!`).
 pub fn eval(expr: &Expr, env: Rc<Env>) -> Result<Value, EvalError> {
+    #[cfg(feature = "tracing")]
+    let _span = {
+        crate::telemetry::count(|c| c.evaluations += 1);
+        tracing::trace_span!("eval", expr = %expr).entered()
+    };
+    consume_fuel()?;
+    check_interrupt()?;
+    #[cfg(not(browser))]
+    crate::limits::check_deadline()?;
+    crate::fiber::checkpoint()?;
+    crate::weak::check_finalizers()?;
+    #[cfg(all(feature = "io", not(browser)))]
+    crate::signal::run_pending_handlers()?;
+    #[cfg(all(feature = "io", not(browser)))]
+    crate::timer::run_due_timers()?;
+    let Some(handler) = error_handler() else { return eval_with_hooks(expr, env) };
+    ABORTED.with(|a| a.set(false));
+    loop {
+        let error = match eval_with_hooks(expr, env.clone()) {
+            Err(error) => error,
+            result => return result,
+        };
+        if ABORTED.with(|a| a.get()) || in_guard() || matches!(error, EvalError::RecursionLimit | EvalError::OutOfFuel | EvalError::OutOfMemory | EvalError::Interrupted) {
+            return Err(error);
+        }
+        match handler(expr, &env, &error) {
+            Restart::Retry => consume_fuel()?,
+            Restart::Return(value) => return Ok(value),
+            Restart::Abort => {
+                ABORTED.with(|a| a.set(true));
+                return Err(error);
+            }
+        }
+    }
+}
+
+fn eval_with_hooks(expr: &Expr, env: Rc<Env>) -> Result<Value, EvalError> {
+    match hooks() {
+        None => eval_without_hooks(expr, env),
+        Some(hooks) => {
+            hooks.on_eval_enter(expr, &env);
+            let result = eval_without_hooks(expr, env);
+            hooks.on_eval_exit(expr, &result);
+            result
+        }
+    }
+}
+
+fn eval_without_hooks(expr: &Expr, env: Rc<Env>) -> Result<Value, EvalError> {
     match expr {
         Expr::Number(n) => Ok(Value::Number(*n)),
+        Expr::Rational(n, d) => Ok(Number::rational(*n, *d)?.into()),
+        Expr::Float(x) => Ok(Value::Float(*x)),
         Expr::Boolean(b) => Ok(Value::Boolean(*b)),
-        Expr::String(s) => Ok(Value::String(s.clone())),
-        Expr::Symbol(s) => env.get(s).ok_or_else(|| EvalError::UndefinedSymbol(s.clone())),
+        Expr::String(s) => Ok(Value::String(s.as_str().into())),
+        Expr::Symbol(s) => env.lookup(s).ok_or_else(|| EvalError::UndefinedSymbol(s.clone())),
+        Expr::Keyword(k) => Ok(Value::Keyword(k.as_str().into())),
+        Expr::Char(c) => Ok(Value::Char(*c)),
+        Expr::Bytevector(_) => Ok(quote_expr(expr)),
+        Expr::Vector(_) | Expr::HashTable(_) => match expr.find_error() {
+            Some(message) => Err(EvalError::Read(message.into())),
+            None => Ok(quote_expr(expr)),
+        },
+        Expr::Error(message) => Err(EvalError::Read(message.clone())),
         Expr::List(list) => {
             if list.is_empty() {
-                return Ok(Value::List(vec![]));
+                return Ok(Value::List(Rc::new([])));
             }
 
-            match &list[0] {
-                Expr::Symbol(s) if s == "define" => eval_define(&list, env),
-                Expr::Symbol(s) if s == "lambda" => eval_lambda(&list, env),
-                Expr::Symbol(s) if s == "begin" => eval_begin(&list, env),
-                Expr::Symbol(s) if s == "if" => eval_if(&list, env),
-                Expr::Symbol(s) if s == "let" => eval_let(&list, env),
-                _ => eval_application(&list, env),
+            let _depth = DepthGuard::enter()?;
+
+            let form = match &list[0] {
+                Expr::Symbol(s) => SpecialForm::from_name(s),
+                _ => None,
+            };
+            match form {
+                Some(SpecialForm::Define) => eval_define(list, env),
+                Some(SpecialForm::Lambda) => eval_lambda(list, env),
+                Some(SpecialForm::Begin) => eval_begin(list, env),
+                Some(SpecialForm::If) => eval_if(list, env),
+                Some(SpecialForm::Let) => eval_let(list, env),
+                Some(SpecialForm::LetStar) => eval_let_star(list, env),
+                Some(SpecialForm::Letrec) => eval_letrec(list, env),
+                Some(SpecialForm::LetValues) => eval_let_values(list, env),
+                Some(SpecialForm::Receive) => eval_receive(list, env),
+                Some(SpecialForm::Match) => eval_match(list, env),
+                Some(SpecialForm::DefineRecordType) => eval_define_record_type(list, env),
+                Some(SpecialForm::Assert) => eval_assert(list, env),
+                Some(SpecialForm::TestEqual) => eval_test_equal(list, env),
+                Some(SpecialForm::Guard) => eval_guard(list, env),
+                Some(SpecialForm::Quote) => eval_quote(list),
+                Some(SpecialForm::Delay) => eval_delay(list, env),
+                Some(SpecialForm::ConsStream) => eval_cons_stream(list, env),
+                Some(SpecialForm::Load) => eval_load(list, env),
+                Some(SpecialForm::Include) => eval_include(list, env),
+                Some(SpecialForm::IncludeCi) => eval_include_ci(list, env),
+                Some(SpecialForm::WithContinuationMark) => eval_with_continuation_mark(list, env),
+                Some(SpecialForm::WithLimits) => eval_with_limits(list, env),
+                Some(SpecialForm::WithTimeout) => eval_with_timeout(list, env),
+                Some(SpecialForm::WithMutex) => eval_with_mutex(list, env),
+                None => match &list[0] {
+                    Expr::Symbol(s) => match env.special_form(s) {
+                        Some(handler) => handler(&list[1..], &env),
+                        None => eval_application(list, env),
+                    },
+                    _ => eval_application(list, env),
+                },
             }
         }
     }
 }
 
+/// The special forms: lists whose head names one of these are evaluated by
+/// their own rules instead of as procedure calls. Embedders can add more
+/// with `Env::define_special_form`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpecialForm {
+    Define,
+    Lambda,
+    Begin,
+    If,
+    Let,
+    LetStar,
+    Letrec,
+    LetValues,
+    Receive,
+    Match,
+    DefineRecordType,
+    Assert,
+    TestEqual,
+    Guard,
+    Quote,
+    Delay,
+    ConsStream,
+    Load,
+    Include,
+    IncludeCi,
+    WithContinuationMark,
+    WithLimits,
+    WithTimeout,
+    WithMutex,
+}
+
+impl SpecialForm {
+    /// Every special form, in the order declared.
+    pub const ALL: [SpecialForm; 24] = [
+        SpecialForm::Define, SpecialForm::Lambda, SpecialForm::Begin, SpecialForm::If, SpecialForm::Let,
+        SpecialForm::LetStar, SpecialForm::Letrec, SpecialForm::LetValues, SpecialForm::Receive,
+        SpecialForm::Match, SpecialForm::DefineRecordType, SpecialForm::Assert, SpecialForm::TestEqual,
+        SpecialForm::Guard, SpecialForm::Quote, SpecialForm::Delay, SpecialForm::ConsStream,
+        SpecialForm::Load, SpecialForm::Include, SpecialForm::IncludeCi, SpecialForm::WithContinuationMark,
+        SpecialForm::WithLimits, SpecialForm::WithTimeout, SpecialForm::WithMutex,
+    ];
+
+    /// Returns the special form a symbol names, if any. String comparison
+    /// checks the length first, so most procedure names are rejected
+    /// without comparing their bytes.
+    pub fn from_name(name: &str) -> Option<SpecialForm> {
+        Some(match name {
+            "define" => SpecialForm::Define,
+            "lambda" => SpecialForm::Lambda,
+            "begin" => SpecialForm::Begin,
+            "if" => SpecialForm::If,
+            "let" => SpecialForm::Let,
+            "let*" => SpecialForm::LetStar,
+            "letrec" => SpecialForm::Letrec,
+            "let-values" => SpecialForm::LetValues,
+            "receive" => SpecialForm::Receive,
+            "match" => SpecialForm::Match,
+            "define-record-type" => SpecialForm::DefineRecordType,
+            "assert" => SpecialForm::Assert,
+            "test-equal" => SpecialForm::TestEqual,
+            "guard" => SpecialForm::Guard,
+            "quote" => SpecialForm::Quote,
+            "delay" => SpecialForm::Delay,
+            "cons-stream" => SpecialForm::ConsStream,
+            "load" => SpecialForm::Load,
+            "include" => SpecialForm::Include,
+            "include-ci" => SpecialForm::IncludeCi,
+            "with-continuation-mark" => SpecialForm::WithContinuationMark,
+            "with-limits" => SpecialForm::WithLimits,
+            "with-timeout" => SpecialForm::WithTimeout,
+            "with-mutex" => SpecialForm::WithMutex,
+            _ => return None,
+        })
+    }
+
+    /// The symbol that introduces the form.
+    pub fn name(self) -> &'static str {
+        match self {
+            SpecialForm::Define => "define",
+            SpecialForm::Lambda => "lambda",
+            SpecialForm::Begin => "begin",
+            SpecialForm::If => "if",
+            SpecialForm::Let => "let",
+            SpecialForm::LetStar => "let*",
+            SpecialForm::Letrec => "letrec",
+            SpecialForm::LetValues => "let-values",
+            SpecialForm::Receive => "receive",
+            SpecialForm::Match => "match",
+            SpecialForm::DefineRecordType => "define-record-type",
+            SpecialForm::Assert => "assert",
+            SpecialForm::TestEqual => "test-equal",
+            SpecialForm::Guard => "guard",
+            SpecialForm::Quote => "quote",
+            SpecialForm::Delay => "delay",
+            SpecialForm::ConsStream => "cons-stream",
+            SpecialForm::Load => "load",
+            SpecialForm::Include => "include",
+            SpecialForm::IncludeCi => "include-ci",
+            SpecialForm::WithContinuationMark => "with-continuation-mark",
+            SpecialForm::WithLimits => "with-limits",
+            SpecialForm::WithTimeout => "with-timeout",
+            SpecialForm::WithMutex => "with-mutex",
+        }
+    }
+}
+
 fn eval_define(list: &[Expr], env: Rc<Env>) -> Result<Value, EvalError> {
+    if list.len() < 3 {
+        return Err(EvalError::ArityMismatch);
+    }
+
+    // `(define (name params...) body...)` is shorthand for defining a lambda.
+    if let Expr::List(signature) = &list[1] {
+        let name = match signature.first() {
+            Some(Expr::Symbol(sym)) => sym.clone(),
+            _ => return Err(EvalError::TypeError("Expected procedure name in define".into())),
+        };
+        let params = Expr::List(signature[1..].to_vec());
+        let value = make_lambda(&params, &list[2..], env.clone())?;
+        env.define(name, value.clone());
+        return Ok(value);
+    }
+
+    if list.len() != 3 {
+        return Err(EvalError::ArityMismatch);
+    }
     let name = match &list[1] {
         Expr::Symbol(sym) => sym.clone(),
         _ => return Err(EvalError::TypeError("Expected symbol after define".into())),
@@ -51,15 +533,70 @@ fn eval_define(list: &[Expr], env: Rc<Env>) -> Result<Value, EvalError> {
 }
 
 fn eval_lambda(list: &[Expr], env: Rc<Env>) -> Result<Value, EvalError> {
-    let params = match &list[1] {
-        Expr::List(p) => p.iter().map(|x| match x {
-            Expr::Symbol(s) => Ok(s.clone()),
-            _ => Err(EvalError::TypeError("Expected symbol in parameter list".into())),
-        }).collect::<Result<Vec<_>, _>>()?,
+    if list.len() < 3 {
+        return Err(EvalError::ArityMismatch);
+    }
+    make_lambda(&list[1], &list[2..], env)
+}
+
+/// Builds a closure. A body of several expressions is evaluated in sequence,
+/// as if wrapped in `begin`.
+///
+/// Parameters after `#:optional` may be left out by the caller, and
+/// parameters after `#:key` are passed as `#:name value`. Either kind may be
+/// written `(name default)`; without a default it is bound to `#f`.
+fn make_lambda(params: &Expr, body: &[Expr], env: Rc<Env>) -> Result<Value, EvalError> {
+    #[derive(PartialEq, PartialOrd)]
+    enum Section { Required, Optional, Key }
+
+    let items = match params {
+        Expr::List(p) => p,
         _ => return Err(EvalError::TypeError("Expected list of params".into())),
     };
-    let body = list[2].clone();
-    Ok(Value::Lambda(Lambda { params, body, env }))
+    let (mut params, mut optionals, mut keys) = (Vec::new(), Vec::new(), Vec::new());
+    let mut section = Section::Required;
+    for item in items {
+        let (name, default) = match item {
+            Expr::Keyword(k) if k == "optional" && section < Section::Optional => {
+                section = Section::Optional;
+                continue;
+            }
+            Expr::Keyword(k) if k == "key" && section < Section::Key => {
+                section = Section::Key;
+                continue;
+            }
+            Expr::Symbol(s) if s == "." => {
+                return Err(EvalError::TypeError("Rest parameters are not supported; use #:optional or pass a list".into()));
+            }
+            Expr::Symbol(s) => (s.clone(), Expr::Boolean(false)),
+            Expr::List(pair) if section != Section::Required && pair.len() == 2 => match &pair[0] {
+                Expr::Symbol(s) => (s.clone(), pair[1].clone()),
+                _ => return Err(EvalError::TypeError("Expected symbol in parameter list".into())),
+            },
+            _ => return Err(EvalError::TypeError("Expected symbol in parameter list".into())),
+        };
+        match section {
+            Section::Required => params.push(name),
+            Section::Optional => optionals.push((name, default)),
+            Section::Key => keys.push((name, default)),
+        }
+    }
+    check_distinct(params.iter().chain(optionals.iter().chain(&keys).map(|(name, _)| name)))?;
+    let lambda_body = match body {
+        [single] => single.clone(),
+        _ => {
+            let mut sequence = vec![Expr::Symbol("begin".into())];
+            sequence.extend_from_slice(body);
+            Expr::List(sequence)
+        }
+    };
+    let lambda = Rc::new(Lambda { params, optionals, keys, body: lambda_body, env });
+    #[cfg(feature = "tracing")]
+    crate::telemetry::count(|c| c.closures += 1);
+    if let Some(hooks) = hooks() {
+        hooks.on_lambda(body, &lambda);
+    }
+    Ok(Value::Lambda(lambda))
 }
 
 fn eval_begin(list: &[Expr], env: Rc<Env>) -> Result<Value, EvalError> {
@@ -83,31 +620,332 @@ fn eval_if(list: &[Expr], env: Rc<Env>) -> Result<Value, EvalError> {
 }
 
 fn eval_let(list: &[Expr], env: Rc<Env>) -> Result<Value, EvalError> {
-    if list.len() != 3 {
+    if list.len() < 3 {
+        return Err(EvalError::ArityMismatch);
+    }
+
+    let bindings = parse_bindings(&list[1], "let")?;
+    check_distinct(bindings.iter().map(|(name, _)| name))?;
+
+    let new_env = Env::extend_with_capacity(env.clone(), bindings.len());
+    for (name, init) in bindings {
+        let value = eval(init, env.clone())?;
+        new_env.define(name, value);
+    }
+
+    eval_sequence(&list[2..], new_env)
+}
+
+/// `let*` binds sequentially, so each initializer sees the bindings before
+/// it. As in R7RS, a name may be bound more than once; later bindings
+/// shadow earlier ones.
+fn eval_let_star(list: &[Expr], env: Rc<Env>) -> Result<Value, EvalError> {
+    if list.len() < 3 {
+        return Err(EvalError::ArityMismatch);
+    }
+
+    let mut new_env = Env::extend(env);
+    for (name, init) in parse_bindings(&list[1], "let*")? {
+        let value = eval(init, new_env.clone())?;
+        new_env = Env::extend(new_env);
+        new_env.define(name, value);
+    }
+
+    eval_sequence(&list[2..], new_env)
+}
+
+/// `letrec` evaluates its initializers in the new environment, so they can
+/// refer to each other (typically for mutually recursive procedures).
+fn eval_letrec(list: &[Expr], env: Rc<Env>) -> Result<Value, EvalError> {
+    if list.len() < 3 {
+        return Err(EvalError::ArityMismatch);
+    }
+
+    let bindings = parse_bindings(&list[1], "letrec")?;
+    check_distinct(bindings.iter().map(|(name, _)| name))?;
+
+    let new_env = Env::extend_with_capacity(env, bindings.len());
+    for (name, _) in &bindings {
+        new_env.define(name.clone(), Value::Unspecified);
+    }
+    for (name, init) in bindings {
+        let value = eval(init, new_env.clone())?;
+        new_env.define(name, value);
+    }
+
+    eval_sequence(&list[2..], new_env)
+}
+
+/// `(receive formals expr body...)` binds the values returned by `expr`.
+///
+/// `(receive)` and `(receive seconds default)`, where `seconds` is written
+/// as a number or variable rather than a list, take an actor's message
+/// instead.
+fn eval_receive(list: &[Expr], env: Rc<Env>) -> Result<Value, EvalError> {
+    #[cfg(not(target_arch = "wasm32"))]
+    if matches!(list, [_] | [_, Expr::Number(_) | Expr::Float(_) | Expr::Symbol(_), _]) {
+        return crate::actor::eval_receive_message(list, env);
+    }
+    if list.len() < 4 {
+        return Err(EvalError::ArityMismatch);
+    }
+
+    let formals = parse_formals(&list[1])?;
+    check_distinct(formals.names())?;
+
+    let values = eval(&list[2], env.clone())?;
+    let new_env = Env::extend(env);
+    formals.bind(values, &new_env)?;
+
+    eval_sequence(&list[3..], new_env)
+}
+
+/// `(let-values ((formals expr) ...) body...)` binds the values of each
+/// `expr`, all evaluated in the enclosing environment.
+fn eval_let_values(list: &[Expr], env: Rc<Env>) -> Result<Value, EvalError> {
+    if list.len() < 3 {
         return Err(EvalError::ArityMismatch);
     }
 
     let bindings = match &list[1] {
-        Expr::List(pairs) => pairs,
-        _ => return Err(EvalError::TypeError("Expected list of bindings in let".into())),
+        Expr::List(pairs) => pairs.iter().map(|pair| match pair {
+            Expr::List(pair_vec) if pair_vec.len() == 2 => Ok((parse_formals(&pair_vec[0])?, &pair_vec[1])),
+            _ => Err(EvalError::TypeError("Invalid binding in let-values".into())),
+        }).collect::<Result<Vec<_>, _>>()?,
+        _ => return Err(EvalError::TypeError("Expected list of bindings in let-values".into())),
     };
+    check_distinct(bindings.iter().flat_map(|(formals, _)| formals.names()))?;
 
     let new_env = Env::extend(env.clone());
-    for pair in bindings {
-        match pair {
-            Expr::List(pair_vec) if pair_vec.len() == 2 => {
-                let name = match &pair_vec[0] {
-                    Expr::Symbol(s) => s.clone(),
-                    _ => return Err(EvalError::TypeError("Expected symbol in let binding".into())),
-                };
-                let value = eval(&pair_vec[1], env.clone())?;
-                new_env.define(name, value);
-            }
-            _ => return Err(EvalError::TypeError("Invalid binding in let".into())),
+    for (formals, init) in bindings {
+        let values = eval(init, env.clone())?;
+        formals.bind(values, &new_env)?;
+    }
+
+    eval_sequence(&list[2..], new_env)
+}
+
+/// The variables receiving multiple values: `(a b)`, `(a b . rest)`, or a
+/// lone `rest` that collects every value into a list.
+struct Formals {
+    names: Vec<String>,
+    rest: Option<String>,
+}
+
+impl Formals {
+    fn names(&self) -> impl Iterator<Item = &String> {
+        self.names.iter().chain(&self.rest)
+    }
+
+    /// Binds `values` (a single value counts as one) in `env`.
+    fn bind(self, values: Value, env: &Env) -> Result<(), EvalError> {
+        let mut values = match values {
+            Value::Values(ref values) => values.to_vec(),
+            value => vec![value],
+        };
+        if values.len() < self.names.len() || (self.rest.is_none() && values.len() > self.names.len()) {
+            return Err(EvalError::ArityMismatch);
+        }
+        let extra = values.split_off(self.names.len());
+        for (name, value) in self.names.into_iter().zip(values) {
+            env.define(name, value);
+        }
+        if let Some(rest) = self.rest {
+            env.define(rest, Value::List(extra.into()));
+        }
+        Ok(())
+    }
+}
+
+fn parse_formals(formals: &Expr) -> Result<Formals, EvalError> {
+    let symbol = |expr: &Expr| match expr {
+        Expr::Symbol(s) if s != "." => Ok(s.clone()),
+        _ => Err(EvalError::TypeError("Expected symbol in formals".into())),
+    };
+    match formals {
+        Expr::Symbol(_) => Ok(Formals { names: vec![], rest: Some(symbol(formals)?) }),
+        Expr::List(items) => match items.iter().position(|item| *item == Expr::Symbol(".".into())) {
+            Some(dot) if dot + 2 == items.len() => Ok(Formals {
+                names: items[..dot].iter().map(symbol).collect::<Result<_, _>>()?,
+                rest: Some(symbol(&items[dot + 1])?),
+            }),
+            Some(_) => Err(EvalError::TypeError("Expected one symbol after . in formals".into())),
+            None => Ok(Formals { names: items.iter().map(symbol).collect::<Result<_, _>>()?, rest: None }),
+        },
+        _ => Err(EvalError::TypeError("Expected formals list".into())),
+    }
+}
+
+/// Splits a `((name init) ...)` binding list into names and initializers.
+fn parse_bindings<'a>(bindings: &'a Expr, form: &str) -> Result<Vec<(String, &'a Expr)>, EvalError> {
+    let pairs = match bindings {
+        Expr::List(pairs) => pairs,
+        _ => return Err(EvalError::TypeError(format!("Expected list of bindings in {}", form))),
+    };
+
+    pairs.iter().map(|pair| match pair {
+        Expr::List(pair_vec) if pair_vec.len() == 2 => match &pair_vec[0] {
+            Expr::Symbol(s) => Ok((s.clone(), &pair_vec[1])),
+            _ => Err(EvalError::TypeError(format!("Expected symbol in {} binding", form))),
+        },
+        _ => Err(EvalError::TypeError(format!("Invalid binding in {}", form))),
+    }).collect()
+}
+
+/// Rejects a binding list or parameter list that names the same variable twice.
+pub(crate) fn check_distinct<'a>(names: impl Iterator<Item = &'a String>) -> Result<(), EvalError> {
+    let mut seen = HashSet::new();
+    for name in names {
+        if !seen.insert(name) {
+            return Err(EvalError::DuplicateBinding(name.clone()));
+        }
+    }
+    Ok(())
+}
+
+fn eval_assert(list: &[Expr], env: Rc<Env>) -> Result<Value, EvalError> {
+    if list.len() != 2 {
+        return Err(EvalError::ArityMismatch);
+    }
+    match eval(&list[1], env)? {
+        Value::Boolean(false) => Err(EvalError::AssertionFailed(list[1].to_string())),
+        _ => Ok(Value::Unspecified),
+    }
+}
+
+/// `(test-equal [name] expected expr)` fails unless the value of `expr` is
+/// `equal?` to the value of `expected`. The failure names the test (or
+/// quotes the form) and shows where the values first differ.
+fn eval_test_equal(list: &[Expr], env: Rc<Env>) -> Result<Value, EvalError> {
+    let (name, expected, tested) = match list {
+        [_, expected, tested] => (None, expected, tested),
+        [_, name, expected, tested] => (Some(name), expected, tested),
+        _ => return Err(EvalError::ArityMismatch),
+    };
+    let label = match name {
+        Some(name) => print_value(&eval(name, env.clone())?, &PrintOptions { style: PrintStyle::Display, ..Default::default() }),
+        None => Expr::List(list.to_vec()).to_string(),
+    };
+    let expected = eval(expected, env.clone())?;
+    let actual = eval(tested, env.clone())?;
+    match find_difference(&expected, &actual, &tested.to_string(), &PrintOptions::from_env(&env)) {
+        Some(difference) => Err(EvalError::AssertionFailed(format!("{}: {}", label, difference))),
+        None => Ok(Value::Unspecified),
+    }
+}
+
+/// `(load "file")` evaluates every form in the file in the global environment.
+fn eval_load(list: &[Expr], env: Rc<Env>) -> Result<Value, EvalError> {
+    check_effect("load")?;
+    let forms = read_source_file(list, &LexOptions::default())?;
+    eval_sequence(&forms, env.global())
+}
+
+/// `(include "file")` splices the file's forms in place, as if they were
+/// written inside a `begin` at the point of the `include`.
+fn eval_include(list: &[Expr], env: Rc<Env>) -> Result<Value, EvalError> {
+    check_effect("include")?;
+    let forms = read_source_file(list, &LexOptions::default())?;
+    eval_sequence(&forms, env)
+}
+
+/// `(include-ci "file")` is `include` for code written without regard to
+/// case: the file's symbols are read in lower case.
+fn eval_include_ci(list: &[Expr], env: Rc<Env>) -> Result<Value, EvalError> {
+    check_effect("include-ci")?;
+    let forms = read_source_file(list, &LexOptions { fold_case: true, ..LexOptions::default() })?;
+    eval_sequence(&forms, env)
+}
+
+pub(crate) fn eval_sequence(forms: &[Expr], env: Rc<Env>) -> Result<Value, EvalError> {
+    let mut result = Value::Unspecified;
+    for form in forms {
+        result = eval(form, env.clone())?;
+    }
+    Ok(result)
+}
+
+/// Reads and parses the file named by a `load` or `include` form, through
+/// the installed `FileResolver` if there is one. Compiled programs (`.scmc`)
+/// are decoded instead, skipping lexing and parsing.
+fn read_source_file(list: &[Expr], options: &LexOptions) -> Result<Vec<Expr>, EvalError> {
+    let path = match list {
+        [_, Expr::String(path)] => path,
+        [_, _] => return Err(EvalError::TypeError("Expected file name string".into())),
+        _ => return Err(EvalError::ArityMismatch),
+    };
+    let path_ref = std::path::Path::new(path);
+    if path_ref.extension().is_some_and(|ext| ext == compiled::EXTENSION) {
+        if file_resolver().is_none() {
+            return compiled::load_compiled(path_ref).map_err(EvalError::File);
+        }
+        return compiled::decode(&read_file(path)?)
+            .map_err(|e| EvalError::File(format!("Cannot load {}: {:?}", path, e)));
+    }
+    let source = read_text_file(path)?;
+    let tokens = tokenize_with_options(&source, options)
+        .map_err(|e| EvalError::Read(format!("Lex error in {}: {:?}", path, e)))?;
+    parse_program(tokens.into_iter().map(|(token, _)| token).collect())
+        .map_err(|e| EvalError::Read(format!("Parse error in {}: {:?}", path, e)))
+}
+
+fn eval_quote(list: &[Expr]) -> Result<Value, EvalError> {
+    if list.len() != 2 {
+        return Err(EvalError::ArityMismatch);
+    }
+    if let Some(message) = list[1].find_error() {
+        return Err(EvalError::Read(message.into()));
+    }
+    Ok(quote_expr(&list[1]))
+}
+
+/// Converts source syntax into the data it denotes, without evaluating it.
+/// `(delay expr)` returns a promise to evaluate `expr` when forced.
+fn eval_delay(list: &[Expr], env: Rc<Env>) -> Result<Value, EvalError> {
+    match list {
+        [_, expr] => Ok(Value::Promise(Rc::new(delayed(expr.clone(), env)))),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// `(cons-stream a b)` builds a stream whose first element is `a` and whose
+/// rest is `b`, evaluated only when the rest is first needed.
+fn eval_cons_stream(list: &[Expr], env: Rc<Env>) -> Result<Value, EvalError> {
+    match list {
+        [_, head, tail] => {
+            let head = eval(head, env.clone())?;
+            let tail = delayed(tail.clone(), env);
+            Ok(Value::List([head, Value::Promise(Rc::new(tail))].into()))
         }
+        _ => Err(EvalError::ArityMismatch),
     }
+}
 
-    eval(&list[2], new_env)
+fn delayed(expr: Expr, env: Rc<Env>) -> Promise {
+    Promise::new(move || eval(&expr, env.clone()))
+}
+
+pub(crate) fn quote_expr(expr: &Expr) -> Value {
+    match expr {
+        Expr::Number(n) => Value::Number(*n),
+        // The reader makes no zero denominators.
+        Expr::Rational(n, d) => Number::rational(*n, *d).map_or(Value::Unspecified, Value::from),
+        Expr::Float(x) => Value::Float(*x),
+        Expr::Boolean(b) => Value::Boolean(*b),
+        Expr::String(s) => Value::String(s.as_str().into()),
+        Expr::Symbol(s) => Value::Symbol(s.as_str().into()),
+        Expr::Keyword(k) => Value::Keyword(k.as_str().into()),
+        Expr::Char(c) => Value::Char(*c),
+        Expr::List(items) => Value::List(items.iter().map(quote_expr).collect()),
+        Expr::Vector(items) => Value::Vector(Rc::new(RefCell::new(items.iter().map(quote_expr).collect()))),
+        Expr::Bytevector(bytes) => Value::Bytevector(Rc::new(RefCell::new(bytes.clone()))),
+        Expr::HashTable(entries) => {
+            let table = entries.iter().map(|(key, value)| (quote_expr(key), quote_expr(value))).collect();
+            Value::HashTable(Rc::new(RefCell::new(table)))
+        }
+        // Quoting checks for errors first; see `find_error`.
+        Expr::Error(_) => Value::Unspecified,
+    }
 }
 
 fn eval_application(list: &[Expr], env: Rc<Env>) -> Result<Value, EvalError> {
@@ -116,22 +954,102 @@ fn eval_application(list: &[Expr], env: Rc<Env>) -> Result<Value, EvalError> {
         .map(|arg| eval(arg, env.clone()))
         .collect::<Result<Vec<_>, _>>()?;
 
-    match func_val {
-        Value::Function(f) => f(arg_vals),
+    apply(func_val, arg_vals)
+}
+
+/// Applies a procedure to already-evaluated arguments.
+///
+/// Used for ordinary function calls and by built-ins that receive
+/// procedures as arguments, such as `call-with-values`.
+pub fn apply(func: Value, args: Vec<Value>) -> Result<Value, EvalError> {
+    #[cfg(feature = "tracing")]
+    let _span = {
+        crate::telemetry::count(|c| c.applications += 1);
+        tracing::debug_span!("apply", procedure = %func).entered()
+    };
+    if let Some(hooks) = hooks() {
+        hooks.on_apply(&func, &args);
+    }
+    match &func {
+        Value::Function(f) => match builtin_info(*f) {
+            Some(info) if !info.arity.accepts(args.len()) => Err(EvalError::WrongArgumentCount(
+                format!("{}: expected {}, got {}", info.name, info.arity, args.len()),
+            )),
+            _ => f(args),
+        },
         Value::Lambda(l) => {
-            if l.params.len() != arg_vals.len() {
-                return Err(EvalError::ArityMismatch);
-            }
-            let new_env = Env::extend(l.env);
-            for (k, v) in l.params.iter().zip(arg_vals.into_iter()) {
-                new_env.define(k.clone(), v);
-            }
+            let new_env = Env::extend_with_capacity(l.env.clone(), l.params.len() + l.optionals.len() + l.keys.len());
+            bind_arguments(l, args, &new_env)?;
             eval(&l.body, new_env)
         }
+        Value::RecordProcedure(p) => p.call(args),
+        Value::Combinator(c) => c.call(args),
+        Value::Memoized(m) => {
+            if let Some(result) = m.lookup(&args) {
+                return Ok(result);
+            }
+            let result = apply(m.func.clone(), args.clone())?;
+            m.remember(args, result.clone());
+            Ok(result)
+        }
         _ => Err(EvalError::NotCallable),
     }
 }
 
+/// Binds a call's arguments to a lambda's parameters in `env`.
+///
+/// Keyword arguments follow the positional ones. Defaults of omitted
+/// optional and keyword parameters are evaluated in `env`, so they can refer
+/// to the parameters before them.
+fn bind_arguments(lambda: &Lambda, mut args: Vec<Value>, env: &Rc<Env>) -> Result<(), EvalError> {
+    let split = if lambda.keys.is_empty() {
+        args.len()
+    } else {
+        args.iter().position(|arg| matches!(arg, Value::Keyword(_))).unwrap_or(args.len())
+    };
+    let keyword_args = args.split_off(split);
+    if args.len() < lambda.params.len() || args.len() > lambda.params.len() + lambda.optionals.len() {
+        return Err(EvalError::ArityMismatch);
+    }
+
+    let mut positional = args.into_iter();
+    for (name, value) in lambda.params.iter().zip(positional.by_ref()) {
+        env.define(name.clone(), value);
+    }
+    for (name, default) in &lambda.optionals {
+        let value = match positional.next() {
+            Some(value) => value,
+            None => eval(default, env.clone())?,
+        };
+        env.define(name.clone(), value);
+    }
+
+    if !keyword_args.len().is_multiple_of(2) {
+        return Err(EvalError::ArityMismatch);
+    }
+    let mut supplied = HashMap::new();
+    let mut keyword_args = keyword_args.into_iter();
+    while let (Some(key), Some(value)) = (keyword_args.next(), keyword_args.next()) {
+        let name = match key {
+            Value::Keyword(ref k) if lambda.keys.iter().any(|(name, _)| **name == **k) => k.clone(),
+            Value::Keyword(ref k) => return Err(EvalError::TypeError(format!("Unknown keyword argument #:{}", k))),
+            other => return Err(EvalError::TypeError(format!("Expected keyword argument, got {}", other))),
+        };
+        if supplied.contains_key(&name) {
+            return Err(EvalError::TypeError(format!("Keyword argument #:{} given twice", name)));
+        }
+        supplied.insert(name, value);
+    }
+    for (name, default) in &lambda.keys {
+        let value = match supplied.remove(name.as_str()) {
+            Some(value) => value,
+            None => eval(default, env.clone())?,
+        };
+        env.define(name.clone(), value);
+    }
+    Ok(())
+}
+
 
 
 #[cfg(test)]
@@ -148,12 +1066,93 @@ mod tests {
         eval(&ast, env)
     }
 
+    #[test]
+    fn test_special_form_names() {
+        for form in SpecialForm::ALL {
+            assert_eq!(SpecialForm::from_name(form.name()), Some(form));
+        }
+        for name in ["", "i", "iff", "lets", "list", "lambda!", "Define", "defined", "car", "test-equals"] {
+            assert_eq!(SpecialForm::from_name(name), None, "{}", name);
+        }
+    }
+
+    #[test]
+    fn test_custom_special_form() {
+        let env = default_env();
+        // `(transaction body ...)` evaluates its body and reports how many
+        // forms ran; the body is not evaluated as arguments would be.
+        let log = Rc::new(RefCell::new(Vec::new()));
+        let seen = log.clone();
+        let local = Env::extend(env.clone());
+        local.define_special_form("transaction", Rc::new(move |body: &[Expr], env: &Rc<Env>| {
+            seen.borrow_mut().push(body.len());
+            eval_sequence(body, env.clone())
+        })).unwrap();
+        let run = |source: &str| eval(&parse(tokenize(source).unwrap()).unwrap(), env.clone());
+        assert_eq!(run("(let ((x 2)) (transaction (define y (* x 3)) (+ y 1)))").unwrap(), Value::Number(7));
+        // Variables do not shadow the form, as for built-in special forms.
+        assert_eq!(run("(let ((transaction car)) (transaction 5))").unwrap(), Value::Number(5));
+        assert_eq!(*log.borrow(), vec![2, 1]);
+        assert_eq!(env.special_form_names(), vec!["transaction".to_string()]);
+        assert!(matches!(
+            eval(&parse(tokenize("(transaction 1)").unwrap()).unwrap(), default_env()),
+            Err(EvalError::UndefinedSymbol(_))
+        ));
+        assert!(env.define_special_form("if", Rc::new(|_: &[Expr], _: &Rc<Env>| Ok(Value::Unspecified))).is_err());
+    }
+
+    /// The list procedures must loop rather than recurse, or these would
+    /// overflow the stack. Slow in debug builds; run with `--ignored`.
+    #[test]
+    #[ignore]
+    fn test_list_procedures_on_a_million_elements() {
+        let result = eval_expr("
+            (begin
+              (define xs (vector->list (make-vector 1000000 1)))
+              (define total (make-vector 1 0))
+              (for-each (lambda (x) (vector-set! total 0 (+ x (vector-ref total 0)))) xs)
+              (list (length (map (lambda (x) (+ x 1)) xs))
+                    (length (append xs xs))
+                    (length (reverse xs))
+                    (length (append (reverse (list 1 2)) xs))
+                    (vector-ref total 0)
+                    (car (map + xs xs))))").unwrap();
+        assert_eq!(result.to_string(), "(1000000 2000000 1000000 1000002 1000000 2)");
+    }
+
+    #[test]
+    fn test_builtin_arity_errors() {
+        let message = |source: &str| match eval_expr(source) {
+            Err(EvalError::WrongArgumentCount(message)) => message,
+            other => panic!("{:?}", other),
+        };
+        assert_eq!(message("(car '(1) '(2) '(3))"), "car: expected 1 argument, got 3");
+        assert_eq!(message("(substring \"abc\")"), "substring: expected 2 or 3 arguments, got 1");
+        assert_eq!(message("(vector-copy! (vector))"), "vector-copy!: expected 3 to 5 arguments, got 1");
+        assert_eq!(message("(map car)"), "map: expected at least 2 arguments, got 1");
+        // Higher-order built-ins get the same check when they call one.
+        assert_eq!(message("(map cons '(1 2))"), "cons: expected 2 arguments, got 1");
+        let result = eval_expr("
+            (begin
+              (define-record-type point (make-point x y) point? (x point-x set-point-x!) (y point-y))
+              (map procedure-arity (list car + display make-point set-point-x! (lambda (a #:optional b) a) (memoize cons))))
+        ").unwrap();
+        assert_eq!(result.to_string(), "((1 1) (0 #f) (1 2) (2 2) (2 2) (1 2) (2 2))");
+    }
+
     #[test]
     fn test_eval_number() {
         let result = eval_expr("42").unwrap();
         assert_eq!(result, Value::Number(42));
     }
 
+    #[test]
+    fn test_eval_float_literals_mix_with_integers() {
+        assert_eq!(eval_expr("-2.5").unwrap(), Value::Float(-2.5));
+        let result = eval_expr("(list (+ 1 .5) (* 2 1.5) (- 1.5 2) (< 1 1.5 2) '(2.0))").unwrap();
+        assert_eq!(result.write_string(), "(1.5 3.0 -0.5 #t (2.0))");
+    }
+
      #[test]
     fn test_eval_boolean() {
         let result = eval_expr("#t").unwrap();
@@ -479,11 +1478,11 @@ mod tests {
         let ast = parse(tokens).unwrap();
         let env = default_env();
         let result = eval(&ast, env).unwrap();
-        assert_eq!(result, Value::List(vec![
+        assert_eq!(result, Value::List(Rc::new([
             Value::Number(1),
             Value::Number(2),
             Value::Number(3),
-        ]));
+        ])));
     }
 
     #[test]
@@ -501,7 +1500,7 @@ mod tests {
         let ast = parse(tokens).unwrap();
         let env = default_env();
         let result = eval(&ast, env).unwrap();
-        assert_eq!(result, Value::List(vec![Value::Number(20), Value::Number(30)]));
+        assert_eq!(result, Value::List(Rc::new([Value::Number(20), Value::Number(30)])));
     }
 
     #[test]
@@ -512,8 +1511,776 @@ mod tests {
         let result = eval(&ast, env).unwrap();
         assert_eq!(
             result,
-            Value::List(vec![Value::Number(5), Value::Number(6), Value::Number(7)])
+            Value::List(Rc::new([Value::Number(5), Value::Number(6), Value::Number(7)]))
         );
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn test_builtin_number_to_string_radix() {
+        let result = eval_expr("(number->string 255 16)").unwrap();
+        assert_eq!(result, Value::String("ff".into()));
+    }
+
+    #[test]
+    fn test_builtin_string_to_number_round_trip() {
+        let result = eval_expr("(string->number (number->string 1234 8) 8)").unwrap();
+        assert_eq!(result, Value::Number(1234));
+    }
+
+    #[test]
+    fn test_builtin_string_to_number_returns_false() {
+        let result = eval_expr("(string->number \"twelve\")").unwrap();
+        assert_eq!(result, Value::Boolean(false));
+    }
+
+    #[test]
+    fn test_builtin_floor_div_with_call_with_values() {
+        let result = eval_expr("(call-with-values (lambda () (floor/ 17 (- 0 5))) list)").unwrap();
+        assert_eq!(result, Value::List(Rc::new([Value::Number(-4), Value::Number(-3)])));
+    }
+
+    #[test]
+    fn test_builtin_exact_inexact_round_trip() {
+        let result = eval_expr("(exact (inexact 12))").unwrap();
+        assert_eq!(result, Value::Number(12));
+    }
+
+    #[test]
+    fn test_builtin_call_with_values_lambda_consumer() {
+        let result = eval_expr("(call-with-values (lambda () (values 1 2)) (lambda (a b) (- a b)))").unwrap();
+        assert_eq!(result, Value::Number(-1));
+    }
+
+    #[test]
+    fn test_builtin_mixed_arithmetic_contagion() {
+        let result = eval_expr("(+ (/ 1 2) (string->number \"0.25\"))").unwrap();
+        assert_eq!(result, Value::Float(0.75));
+    }
+
+    #[test]
+    fn test_builtin_rational_arithmetic() {
+        let result = eval_expr("(* (/ 2 3) 3)").unwrap();
+        assert_eq!(result, Value::Number(2));
+    }
+
+    #[test]
+    fn test_builtin_max_of_mixed() {
+        let result = eval_expr("(max 1 (/ 7 2) 2)").unwrap();
+        assert_eq!(result, Value::Rational(7, 2));
+    }
+
+    #[test]
+    fn test_builtin_equal_lists() {
+        let result = eval_expr("(equal? (list 1 (list 2 3)) (cons 1 (list (list 2 3))))").unwrap();
+        assert_eq!(result, Value::Boolean(true));
+    }
+
+    #[test]
+    fn test_builtin_vector_length_after_list_conversion() {
+        let result = eval_expr("(vector-length (list->vector (list 1 2 3)))").unwrap();
+        assert_eq!(result, Value::Number(3));
+    }
+
+    #[test]
+    fn test_circular_vector_prints() {
+        let tokens = tokenize("(begin (define v (make-vector 2 0)) (vector-set! v 1 v) v)").unwrap();
+        let ast = parse(tokens).unwrap();
+        let result = eval(&ast, default_env()).unwrap();
+        assert_eq!(format!("{}", result), "#0=#(0 #0#)");
+    }
+
+    #[test]
+    fn test_error_handler_restarts_failing_expression() {
+        let offered = Rc::new(RefCell::new(Vec::new()));
+        let log = offered.clone();
+        set_error_handler(Some(Rc::new(move |expr: &Expr, env: &Rc<Env>, _: &EvalError| {
+            log.borrow_mut().push(expr.to_string());
+            match expr.to_string().as_str() {
+                "y" => Restart::Return(env.get("x").unwrap()),
+                "z" => {
+                    env.global().define("z".into(), Value::Number(100));
+                    Restart::Retry
+                }
+                _ => Restart::Abort,
+            }
+        })));
+        let result = eval_expr("((lambda (x) (+ x y z)) 7)");
+        let aborted = eval_expr("(+ 1 (car 1))");
+        let guarded = eval_expr("(guard (e (#t 0)) (car 1))");
+        set_error_handler(None);
+        assert_eq!(result.unwrap(), Value::Number(114));
+        assert!(matches!(aborted, Err(EvalError::TypeError(_))));
+        assert_eq!(guarded.unwrap(), Value::Number(0));
+        assert_eq!(*offered.borrow(), vec!["y", "z", "(car 1)"]);
+    }
+
+    #[test]
+    fn test_recursion_limit_is_catchable() {
+        set_recursion_limit(100);
+        let env = default_env();
+        let define = parse(tokenize("(define f (lambda (n) (if (= n 0) 0 (+ 1 (f (- n 1))))))").unwrap()).unwrap();
+        eval(&define, env.clone()).unwrap();
+
+        let deep = parse(tokenize("(f 1000)").unwrap()).unwrap();
+        assert!(matches!(eval(&deep, env.clone()), Err(EvalError::RecursionLimit)));
+
+        // The depth counter unwinds with the error, so shallow calls still work.
+        let shallow = parse(tokenize("(f 10)").unwrap()).unwrap();
+        assert_eq!(eval(&shallow, env).unwrap(), Value::Number(10));
+    }
+
+    #[test]
+    fn test_pure_mode_rejects_effects() {
+        set_pure_mode(true);
+        assert!(matches!(eval_expr("(display 1)"), Err(EvalError::EffectInPureMode(_))));
+        assert!(matches!(eval_expr("(newline)"), Err(EvalError::EffectInPureMode(_))));
+        assert!(matches!(eval_expr("(format #t \"x\")"), Err(EvalError::EffectInPureMode(_))));
+        assert!(matches!(eval_expr("(load \"missing.scm\")"), Err(EvalError::EffectInPureMode(_))));
+        #[cfg(feature = "date")]
+        assert!(matches!(eval_expr("(current-date)"), Err(EvalError::EffectInPureMode(_))));
+
+        // Effects confined to values the program created are allowed.
+        let result = eval_expr("(begin (define p (open-output-string)) (display 42 p) (get-output-string p))");
+        assert_eq!(result.unwrap(), Value::String("42".into()));
+        set_pure_mode(false);
+        assert!(!is_pure_mode());
+    }
+
+    #[test]
+    fn test_fuel_limits_evaluation_steps() {
+        set_fuel(Some(4));
+        assert_eq!(eval_expr("(+ 1 2)").unwrap(), Value::Number(3));
+        assert_eq!(remaining_fuel(), Some(0));
+        assert!(matches!(eval_expr("1"), Err(EvalError::OutOfFuel)));
+
+        set_fuel(Some(100));
+        let looping = "(begin (define f (lambda (n) (f n))) (f 1))";
+        assert!(matches!(eval_expr(looping), Err(EvalError::OutOfFuel)));
+        set_fuel(None);
+        assert_eq!(eval_expr("(+ 1 2)").unwrap(), Value::Number(3));
+    }
+
+    #[test]
+    fn test_interrupt_flag_stops_evaluation_once() {
+        let flag = Arc::new(AtomicBool::new(false));
+        set_interrupt_flag(Some(flag.clone()));
+        let setter = {
+            let flag = flag.clone();
+            std::thread::spawn(move || flag.store(true, Ordering::Relaxed))
+        };
+        setter.join().unwrap();
+        let looping = "(begin (define f (lambda (n) (f n))) (f 1))";
+        assert!(matches!(eval_expr(looping), Err(EvalError::Interrupted)));
+        assert!(!flag.load(Ordering::Relaxed));
+        assert_eq!(eval_expr("(+ 1 2)").unwrap(), Value::Number(3));
+        set_interrupt_flag(None);
+    }
+
+    #[test]
+    fn test_recursion_limit_can_be_raised() {
+        set_recursion_limit(20);
+        assert!(matches!(eval_expr("(+ 1 (+ 1 (+ 1 (+ 1 1))))"), Ok(Value::Number(5))));
+        set_recursion_limit(2);
+        assert!(matches!(eval_expr("(+ 1 (+ 1 (+ 1 (+ 1 1))))"), Err(EvalError::RecursionLimit)));
+        assert_eq!(recursion_limit(), 2);
+    }
+
+    #[test]
+    fn test_assert_passes() {
+        let result = eval_expr("(assert (< 1 2))").unwrap();
+        assert_eq!(result, Value::Unspecified);
+    }
+
+    #[test]
+    fn test_assert_failure_includes_source_text() {
+        let result = eval_expr("(let ((x 5)) (assert (= x (+ 2 2))))");
+        assert!(matches!(result, Err(EvalError::AssertionFailed(text)) if text == "(= x (+ 2 2))"));
+    }
+
+    #[test]
+    fn test_test_equal_reports_first_difference() {
+        assert_eq!(eval_expr("(test-equal '(1 (2 3)) (list 1 (list 2 3)))").unwrap(), Value::Unspecified);
+        let result = eval_expr("(test-equal \"nested\" '(a b (c (d e))) (list 'a 'b '(c (d x))))");
+        assert!(matches!(result, Err(EvalError::AssertionFailed(text))
+            if text == "nested: at (cadr (cadr (caddr (list (quote a) (quote b) (quote (c (d x))))))), expected e but got x"));
+        let result = eval_expr("(begin (define *print-length* 2) (test-equal '(1 2) (list 1 2 3 4 5)))");
+        assert!(matches!(result, Err(EvalError::AssertionFailed(text))
+            if text == "(test-equal (quote (1 2)) (list 1 2 3 4 5)): at (cddr (list 1 2 3 4 5)), expected () but got (3 4 ...)"));
+    }
+
+    #[test]
+    fn test_assert_arity() {
+        let result = eval_expr("(assert #t #t)");
+        assert!(matches!(result, Err(EvalError::ArityMismatch)));
+    }
+
+    #[test]
+    fn test_quote_list() {
+        let result = eval_expr("'(a (1 \"b\"))").unwrap();
+        assert_eq!(result, Value::List(Rc::new([
+            Value::Symbol("a".into()),
+            Value::List(Rc::new([Value::Number(1), Value::String("b".into())])),
+        ])));
+    }
+
+    #[test]
+    fn test_quote_symbol_is_not_evaluated() {
+        let result = eval_expr("(quote undefined-name)").unwrap();
+        assert_eq!(result, Value::Symbol("undefined-name".into()));
+    }
+
+    #[test]
+    fn test_builtin_map_single_list() {
+        let result = eval_expr("(map (lambda (x) (* x x)) '(1 2 3))").unwrap();
+        assert_eq!(result, Value::List(Rc::new([Value::Number(1), Value::Number(4), Value::Number(9)])));
+    }
+
+    #[test]
+    fn test_builtin_map_multiple_lists() {
+        let result = eval_expr("(map + '(1 2) '(10 20) '(100 200))").unwrap();
+        assert_eq!(result, Value::List(Rc::new([Value::Number(111), Value::Number(222)])));
+    }
+
+    #[test]
+    fn test_builtin_map_length_mismatch() {
+        let result = eval_expr("(map + '(1 2) '(10))");
+        assert!(matches!(result, Err(EvalError::Other(_))));
+    }
+
+    #[test]
+    fn test_builtin_for_each_runs_in_order() {
+        let tokens = tokenize("(begin (define v (make-vector 1 0)) (for-each (lambda (x y) (vector-set! v 0 (- (* 10 (vector-ref v 0)) (- x y)))) '(1 2 3) '(0 0 0)) (vector-ref v 0))").unwrap();
+        let ast = parse(tokens).unwrap();
+        let result = eval(&ast, default_env()).unwrap();
+        assert_eq!(result, Value::Number(-123));
+    }
+
+    #[test]
+    fn test_define_procedure_shorthand() {
+        let tokens = tokenize("(begin (define (square x) (* x x)) (square 7))").unwrap();
+        let ast = parse(tokens).unwrap();
+        let result = eval(&ast, default_env()).unwrap();
+        assert_eq!(result, Value::Number(49));
+    }
+
+    #[test]
+    fn test_lambda_body_with_several_expressions() {
+        let result = eval_expr("((lambda (x) (define y (* x 2)) (+ x y)) 5)").unwrap();
+        assert_eq!(result, Value::Number(15));
+    }
+
+    #[test]
+    fn test_mutual_recursion_with_forward_reference() {
+        let env = default_env();
+        for source in [
+            "(define (my-even? n) (if (= n 0) #t (my-odd? (- n 1))))",
+            "(define (my-odd? n) (if (= n 0) #f (my-even? (- n 1))))",
+        ] {
+            eval(&parse(tokenize(source).unwrap()).unwrap(), env.clone()).unwrap();
+        }
+        let ast = parse(tokenize("(list (my-even? 10) (my-odd? 7) (my-even? 3))").unwrap()).unwrap();
+        let result = eval(&ast, env).unwrap();
+        assert_eq!(result, Value::List(Rc::new([Value::Boolean(true), Value::Boolean(true), Value::Boolean(false)])));
+    }
+
+    #[test]
+    fn test_forward_reference_in_top_level_begin() {
+        let result = eval_expr("
+            (begin
+                (define (ping n) (if (= n 0) 'ping (pong (- n 1))))
+                (define (pong n) (if (= n 0) 'pong (ping (- n 1))))
+                (ping 5))
+        ").unwrap();
+        assert_eq!(result, Value::Symbol("pong".into()));
+    }
+
+    #[test]
+    fn test_forward_reference_undefined_at_call_time() {
+        let result = eval_expr("(begin (define (f) (g)) (f))");
+        assert!(matches!(result, Err(EvalError::UndefinedSymbol(sym)) if sym == "g"));
+    }
+
+    #[test]
+    fn test_define_missing_value() {
+        let result = eval_expr("(define x)");
+        assert!(matches!(result, Err(EvalError::ArityMismatch)));
+    }
+
+    fn write_temp_source(name: &str, source: &str) -> String {
+        let path = std::env::temp_dir().join(format!("scheme-rs-{}-{}.scm", std::process::id(), name));
+        std::fs::write(&path, source).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn test_top_level_begin_defines_are_global() {
+        let env = default_env();
+        let ast = parse(tokenize("(begin (define x 1) (define y 2))").unwrap()).unwrap();
+        eval(&ast, env.clone()).unwrap();
+        assert_eq!(env.get("x"), Some(Value::Number(1)));
+        let ast = parse(tokenize("(+ x y)").unwrap()).unwrap();
+        assert_eq!(eval(&ast, env).unwrap(), Value::Number(3));
+    }
+
+    #[test]
+    fn test_nested_begin_splices_into_top_level() {
+        let env = default_env();
+        let ast = parse(tokenize("(begin (begin (define a 10)) (define b (+ a 1)))").unwrap()).unwrap();
+        eval(&ast, env.clone()).unwrap();
+        assert_eq!(env.get("b"), Some(Value::Number(11)));
+    }
+
+    #[test]
+    fn test_begin_inside_lambda_stays_local() {
+        let env = default_env();
+        let ast = parse(tokenize("((lambda () (begin (define hidden 1)) hidden))").unwrap()).unwrap();
+        assert_eq!(eval(&ast, env.clone()).unwrap(), Value::Number(1));
+        assert_eq!(env.get("hidden"), None);
+    }
+
+    #[test]
+    fn test_load_defines_in_global_env() {
+        let path = write_temp_source("load", "(begin (define loaded-a 1) (define loaded-b 2)) (define loaded-c (+ loaded-a loaded-b))");
+        let env = default_env();
+        let source = format!("((lambda () (load {:?})))", path);
+        let result = eval(&parse(tokenize(&source).unwrap()).unwrap(), env.clone()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(result, Value::Number(3));
+        assert_eq!(env.get("loaded-c"), Some(Value::Number(3)));
+    }
+
+    #[test]
+    fn test_include_splices_into_local_scope() {
+        let path = write_temp_source("include", "(define included 5) (* included 2)");
+        let env = default_env();
+        let source = format!("((lambda () (include {:?}) (+ included 1)))", path);
+        let result = eval(&parse(tokenize(&source).unwrap()).unwrap(), env.clone()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(result, Value::Number(6));
+        assert_eq!(env.get("included"), None);
+    }
+
+    #[test]
+    fn test_include_ci_folds_symbol_case() {
+        let path = write_temp_source("include-ci", "(DEFINE (Twice X) (* 2 x)) (Define Greeting \"Keep Case\")");
+        let env = default_env();
+        let source = format!("(begin (include-ci {:?}) (TWICE 4))", path);
+        let result = eval(&parse(tokenize(&source).unwrap()).unwrap(), env.clone());
+        std::fs::remove_file(&path).unwrap();
+        // Only the included file is folded; `TWICE` here is still undefined.
+        assert!(matches!(result, Err(EvalError::UndefinedSymbol(name)) if name == "TWICE"));
+        assert!(env.get("twice").is_some());
+        assert_eq!(env.get("greeting"), Some(Value::String("Keep Case".into())));
+    }
+
+    #[test]
+    fn test_load_missing_file() {
+        let result = eval_expr("(load \"/nonexistent/scheme-rs-missing.scm\")");
+        assert!(matches!(result, Err(EvalError::File(_))));
+    }
+
+    #[test]
+    fn test_equal_procedures_by_identity() {
+        let result = eval_expr("(let ((f (lambda (x) x)) (g (lambda (x) x))) (list (equal? f f) (equal? f g) (equal? car car)))").unwrap();
+        assert_eq!(result, Value::List(Rc::new([Value::Boolean(true), Value::Boolean(false), Value::Boolean(true)])));
+    }
+
+    #[test]
+    fn test_builtin_to_string_with_string_append() {
+        let result = eval_expr("(string-append \"x = \" (->string '(1 \"two\" #t)))").unwrap();
+        assert_eq!(result, Value::String("x = (1 two #t)".into()));
+    }
+
+    #[test]
+    fn test_builtin_format_interpolates() {
+        let result = eval_expr("(format \"~a + ~a = ~d\" 1 2 (+ 1 2))").unwrap();
+        assert_eq!(result, Value::String("1 + 2 = 3".into()));
+    }
+
+    #[test]
+    fn test_let_duplicate_binding() {
+        let result = eval_expr("(let ((x 1) (x 2)) x)");
+        assert!(matches!(result, Err(EvalError::DuplicateBinding(name)) if name == "x"));
+    }
+
+    #[test]
+    fn test_lambda_duplicate_parameter() {
+        let result = eval_expr("(lambda (a b a) a)");
+        assert!(matches!(result, Err(EvalError::DuplicateBinding(name)) if name == "a"));
+    }
+
+    #[test]
+    fn test_define_shorthand_duplicate_parameter() {
+        let result = eval_expr("(define (f x x) x)");
+        assert!(matches!(result, Err(EvalError::DuplicateBinding(name)) if name == "x"));
+    }
+
+    #[test]
+    fn test_rest_parameters_are_rejected() {
+        for source in ["(define (f . args) args)", "(define (f a . rest) rest)", "(lambda (a . b) b)"] {
+            assert!(matches!(eval_expr(source), Err(EvalError::TypeError(message)) if message.starts_with("Rest parameters")), "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_let_star_sequential_bindings() {
+        let result = eval_expr("(let* ((x 1) (y (+ x 1)) (x (* y 10))) (list x y))").unwrap();
+        assert_eq!(result, Value::List(Rc::new([Value::Number(20), Value::Number(2)])));
+    }
+
+    #[test]
+    fn test_letrec_mutual_recursion() {
+        let result = eval_expr("
+            (letrec ((ev? (lambda (n) (if (= n 0) #t (od? (- n 1)))))
+                     (od? (lambda (n) (if (= n 0) #f (ev? (- n 1))))))
+              (ev? 12))
+        ").unwrap();
+        assert_eq!(result, Value::Boolean(true));
+    }
+
+    #[test]
+    fn test_letrec_duplicate_binding() {
+        let result = eval_expr("(letrec ((f 1) (g 2) (f 3)) f)");
+        assert!(matches!(result, Err(EvalError::DuplicateBinding(name)) if name == "f"));
+    }
+
+    #[test]
+    fn test_let_body_with_several_expressions() {
+        let result = eval_expr("(let ((x 1)) (define y 2) (+ x y))").unwrap();
+        assert_eq!(result, Value::Number(3));
+    }
+
+    #[test]
+    fn test_optional_parameters() {
+        let result = eval_expr("
+            (begin
+              (define (f x #:optional (y 10) z) (list x y z))
+              (list (f 1) (f 1 2) (f 1 2 3)))
+        ").unwrap();
+        assert_eq!(result.to_string(), "((1 10 #f) (1 2 #f) (1 2 3))");
+        assert!(matches!(eval_expr("((lambda (x #:optional y) x) 1 2 3)"), Err(EvalError::ArityMismatch)));
+        assert!(matches!(eval_expr("((lambda (x #:optional y) x))"), Err(EvalError::ArityMismatch)));
+    }
+
+    #[test]
+    fn test_optional_default_sees_earlier_parameters() {
+        let result = eval_expr("((lambda (x #:optional (y (* x 2))) y) 21)").unwrap();
+        assert_eq!(result, Value::Number(42));
+    }
+
+    #[test]
+    fn test_keyword_parameters() {
+        let result = eval_expr("
+            (begin
+              (define (connect host #:key (port 80) secure) (list host port secure))
+              (list (connect \"a\") (connect \"b\" #:secure #t #:port 443)))
+        ").unwrap();
+        assert_eq!(result.to_string(), r#"(("a" 80 #f) ("b" 443 #t))"#);
+    }
+
+    #[test]
+    fn test_keyword_argument_errors() {
+        let f = "(define (f #:key (a 1)) a)";
+        let call = |args: &str| eval_expr(&format!("(begin {} (f {}))", f, args));
+        assert!(matches!(call("#:b 2"), Err(EvalError::TypeError(_))));
+        assert!(matches!(call("#:a 2 #:a 3"), Err(EvalError::TypeError(_))));
+        assert!(matches!(call("#:a"), Err(EvalError::ArityMismatch)));
+        assert!(matches!(call("1"), Err(EvalError::ArityMismatch)));
+    }
+
+    #[test]
+    fn test_keywords_are_self_evaluating() {
+        assert_eq!(eval_expr("#:port").unwrap(), Value::Keyword("port".into()));
+        assert_eq!(eval_expr("(list #:port 1)").unwrap().to_string(), "(#:port 1)");
+    }
+
+    #[test]
+    fn test_vector_bytevector_and_rational_literals() {
+        assert_eq!(eval_expr("(list (vector-ref #(1 (a b)) 1) (+ 1/2 1/3) 6/3)").unwrap().to_string(), "((a b) 5/6 2)");
+        assert_eq!(eval_expr("(list '#(x #u8(1 2)) '|a b|)").unwrap().write_string(), "(#(x #u8(1 2)) |a b|)");
+        // Each evaluation makes a new vector, so changing one leaves the literal alone.
+        let source = "(begin (define (f) #(0)) (vector-set! (f) 0 1) (f))";
+        assert_eq!(eval_expr(source).unwrap().to_string(), "#(0)");
+    }
+
+    #[test]
+    fn test_chars_are_self_evaluating() {
+        assert_eq!(eval_expr("#\\newline").unwrap(), Value::Char('\n'));
+        assert_eq!(eval_expr("'(#\\a #\\space)").unwrap().to_string(), "(#\\a #\\space)");
+        assert_eq!(eval_expr("(->string #\\a)").unwrap(), Value::String("a".into()));
+    }
+
+    #[test]
+    fn test_duplicate_optional_and_key_names() {
+        let result = eval_expr("(lambda (x #:optional y #:key (x 1)) x)");
+        assert!(matches!(result, Err(EvalError::DuplicateBinding(name)) if name == "x"));
+    }
+
+    #[test]
+    fn test_receive_with_rest() {
+        let result = eval_expr("(receive (a b . rest) (values 1 2 3 4) (list a b rest))").unwrap();
+        assert_eq!(result.to_string(), "(1 2 (3 4))");
+    }
+
+    #[test]
+    fn test_receive_single_symbol_collects_all_values() {
+        assert_eq!(eval_expr("(receive all (values 1 2) all)").unwrap().to_string(), "(1 2)");
+        assert_eq!(eval_expr("(receive (x) 5 x)").unwrap(), Value::Number(5));
+    }
+
+    #[test]
+    fn test_receive_arity_mismatch() {
+        assert!(matches!(eval_expr("(receive (a b) (values 1 2 3) a)"), Err(EvalError::ArityMismatch)));
+        assert!(matches!(eval_expr("(receive (a b . c) (values 1) a)"), Err(EvalError::ArityMismatch)));
+    }
+
+    #[test]
+    fn test_let_values() {
+        let result = eval_expr("
+            (let ((x 10))
+              (let-values (((q r) (floor/ 7 2)) ((x . more) (values x 20 30)))
+                (list q r x more)))
+        ").unwrap();
+        assert_eq!(result.to_string(), "(3 1 10 (20 30))");
+    }
+
+    #[test]
+    fn test_let_values_duplicate_binding() {
+        let result = eval_expr("(let-values (((a b) (values 1 2)) ((a) 3)) a)");
+        assert!(matches!(result, Err(EvalError::DuplicateBinding(name)) if name == "a"));
+    }
+
+    #[test]
+    fn test_delay_is_forced_once() {
+        let result = eval_expr("
+            (begin
+              (define count (vector 0))
+              (define p (delay (begin (vector-set! count 0 (+ (vector-ref count 0) 1)) 42)))
+              (list (force p) (force p) (vector-ref count 0)))
+        ").unwrap();
+        assert_eq!(result.to_string(), "(42 42 1)");
+    }
+
+    #[test]
+    fn test_infinite_stream() {
+        let result = eval_expr("
+            (begin
+              (define (integers-from n) (cons-stream n (integers-from (+ n 1))))
+              (define naturals (integers-from 0))
+              (define evens (stream-filter (lambda (n) (= (- n (* 2 (floor (/ n 2)))) 0)) naturals))
+              (stream->list (stream-take 4 (stream-map (lambda (n) (* n n)) evens))))
+        ").unwrap();
+        assert_eq!(result.to_string(), "(0 4 16 36)");
+    }
+
+    #[test]
+    fn test_long_stream_does_not_nest_evaluation() {
+        let result = eval_expr("
+            (begin
+              (define (integers-from n) (cons-stream n (integers-from (+ n 1))))
+              (stream-car (stream-cdr (stream-filter (lambda (n) (> n 5000)) (integers-from 0)))))
+        ").unwrap();
+        assert_eq!(result, Value::Number(5002));
+    }
+
+    #[test]
+    fn test_stream_accessors() {
+        let result = eval_expr("
+            (let ((s (cons-stream 1 the-empty-stream)))
+              (list (stream-pair? s) (stream-null? (stream-cdr s)) (stream-car s) (stream-pair? (list 1 2))))
+        ").unwrap();
+        assert_eq!(result.to_string(), "(#t #t 1 #f)");
+        assert!(matches!(eval_expr("(stream-car the-empty-stream)"), Err(EvalError::TypeError(_))));
+    }
+
+    #[test]
+    fn test_memoized_fibonacci() {
+        let result = eval_expr("
+            (begin
+              (define fib
+                (memoize (lambda (n) (if (< n 2) n (+ (fib (- n 1)) (fib (- n 2)))))))
+              (list (fib 80) (memo-cache-size fib) (memoized? fib)))
+        ").unwrap();
+        assert_eq!(result.to_string(), "(23416728348467685 81 #t)");
+    }
+
+    #[test]
+    fn test_memo_cache_entries() {
+        let result = eval_expr("
+            (begin
+              (define square (memoize (lambda (x) (* x x))))
+              (square 3)
+              (square 3)
+              (memo-cache square))
+        ").unwrap();
+        assert_eq!(result.to_string(), "(((3) 9))");
+    }
+
+    #[test]
+    fn test_hash_table_literal() {
+        let result = eval_expr("(hash-table-ref #hash((port . 80) (\"host\" . \"example.org\")) 'port)").unwrap();
+        assert_eq!(result, Value::Number(80));
+    }
+
+    #[test]
+    fn test_hash_table_literal_is_fresh_each_time() {
+        let result = eval_expr("
+            (begin
+              (define (config) #hash((a . 1)))
+              (hash-table-set! (config) 'a 2)
+              (hash-table-ref (config) 'a))
+        ").unwrap();
+        assert_eq!(result, Value::Number(1));
+    }
+
+    #[test]
+    fn test_hash_table_round_trips_through_write() {
+        let table = eval_expr("
+            (let ((t (make-hash-table)))
+              (hash-table-set! t 'name \"scheme\")
+              (hash-table-set! t '(1 2) '(#t \"x\"))
+              t)
+        ").unwrap();
+        let written = table.write_string();
+        assert!(written.starts_with("#hash(("));
+        assert_eq!(eval_expr(&written).unwrap(), table);
+    }
+
+    #[test]
+    fn test_hash_table_keeps_insertion_order() {
+        let result = eval_expr("
+            (let ((t (make-hash-table)))
+              (for-each (lambda (k) (hash-table-set! t k (* k k))) '(5 3 9 1 7))
+              (hash-table-delete! t 9)
+              (hash-table-set! t 3 0)
+              (hash-table-set! t 9 81)
+              (list (hash-table-keys t) (hash-table->alist (hash-table-copy t)) t))
+        ").unwrap();
+        assert_eq!(result.to_string(), "((5 3 1 7 9) ((5 25) (3 0) (1 1) (7 49) (9 81)) #hash((5 . 25) (3 . 0) (1 . 1) (7 . 49) (9 . 81)))");
+    }
+
+    #[test]
+    fn test_hash_table_word_count() {
+        let result = eval_expr("
+            (let ((counts (make-hash-table)))
+              (for-each (lambda (word) (hash-table-update!/default counts word (lambda (n) (+ n 1)) 0))
+                        '(a b a c a b))
+              (list (hash-table-ref counts 'a) (hash-table-ref counts 'b) (hash-table-count counts)))
+        ").unwrap();
+        assert_eq!(result.to_string(), "(3 2 3)");
+    }
+
+    #[test]
+    fn test_hash_table_update_with_failure_thunk_and_for_each() {
+        let result = eval_expr("
+            (let ((t (alist->hash-table '((x 1) (y 2))))
+                  (total (vector 0)))
+              (hash-table-update! t 'z (lambda (v) (* v 10)) (lambda () 3))
+              (hash-table-for-each t (lambda (k v) (vector-set! total 0 (+ v (vector-ref total 0)))))
+              (vector-ref total 0))
+        ").unwrap();
+        assert_eq!(result, Value::Number(33));
+    }
+
+    #[test]
+    fn test_sort_with_comparator_is_stable() {
+        let result = eval_expr("
+            (sort '((b 2) (a 1) (c 2) (d 1))
+                  (lambda (x y) (< (car (cdr x)) (car (cdr y)))))
+        ").unwrap();
+        assert_eq!(result.to_string(), "((a 1) (d 1) (b 2) (c 2))");
+    }
+
+    #[test]
+    fn test_queue_breadth_first_search() {
+        let result = eval_expr("
+            (begin
+              (define graph #hash((a . (b c)) (b . (d)) (c . (d e)) (d . ()) (e . ())))
+              (define (bfs start)
+                (let ((queue (make-queue start)) (seen (make-hash-table)) (order (make-queue)))
+                  (hash-table-set! seen start #t)
+                  (define (visit node)
+                    (enqueue! order node)
+                    (for-each (lambda (next)
+                                (if (hash-table-contains? seen next)
+                                    #f
+                                    (begin (hash-table-set! seen next #t) (enqueue! queue next))))
+                              (hash-table-ref graph node)))
+                  (define (loop)
+                    (if (queue-empty? queue) (queue->list order) (begin (visit (dequeue! queue)) (loop))))
+                  (loop)))
+              (bfs 'a))
+        ").unwrap();
+        assert_eq!(result.to_string(), "(a b c d e)");
+    }
+
+    #[test]
+    fn test_string_builder_loop() {
+        let result = eval_expr("
+            (let ((out (open-output-string)))
+              (define (loop i)
+                (if (< i 5)
+                    (begin (write-string (number->string i) out) (loop (+ i 1)))
+                    (get-output-string out)))
+              (loop 0))
+        ").unwrap();
+        assert_eq!(result, Value::String("01234".into()));
+    }
+
+    #[derive(Default)]
+    struct Recorder {
+        events: RefCell<Vec<String>>,
+    }
+
+    impl EvalHooks for Recorder {
+        fn on_eval_enter(&self, expr: &Expr, _env: &Rc<Env>) {
+            self.events.borrow_mut().push(format!("enter {}", expr));
+        }
+
+        fn on_eval_exit(&self, expr: &Expr, result: &Result<Value, EvalError>) {
+            let result = match result {
+                Ok(value) => value.to_string(),
+                Err(e) => format!("{:?}", e),
+            };
+            self.events.borrow_mut().push(format!("exit {} => {}", expr, result));
+        }
+
+        fn on_apply(&self, func: &Value, args: &[Value]) {
+            let args: Vec<_> = args.iter().map(Value::to_string).collect();
+            self.events.borrow_mut().push(format!("apply {} {}", func, args.join(" ")));
+        }
+    }
+
+    #[test]
+    fn test_eval_hooks_trace_evaluation() {
+        let recorder = Rc::new(Recorder::default());
+        set_hooks(Some(recorder.clone()));
+        let result = eval_expr("(+ 1 2)");
+        set_hooks(None);
+        assert_eq!(result.unwrap(), Value::Number(3));
+        assert_eq!(*recorder.events.borrow(), vec![
+            "enter (+ 1 2)",
+            "enter +",
+            "exit + => <builtin-function>",
+            "enter 1",
+            "exit 1 => 1",
+            "enter 2",
+            "exit 2 => 2",
+            "apply <builtin-function> 1 2",
+            "exit (+ 1 2) => 3",
+        ]);
+    }
+
+    #[test]
+    fn test_eval_hooks_see_errors_and_can_be_removed() {
+        let recorder = Rc::new(Recorder::default());
+        set_hooks(Some(recorder.clone()));
+        let _ = eval_expr("undefined-name");
+        set_hooks(None);
+        let _ = eval_expr("(+ 1 2)");
+        assert_eq!(*recorder.events.borrow(), vec![
+            "enter undefined-name",
+            "exit undefined-name => UndefinedSymbol(\"undefined-name\")",
+        ]);
+    }
+}
@@ -0,0 +1,147 @@
+//! A C interface for embedding the interpreter, declared in
+//! `include/scheme_rs.h`. Each context has its own global environment, and
+//! must be used on the thread that created it.
+
+use std::ffi::{c_char, c_int, CStr, CString};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::rc::Rc;
+
+use crate::env::{default_env, Env};
+use crate::eval::eval_sequence;
+use crate::lexer::tokenize;
+use crate::parser::parse_program;
+use crate::printer::{print_value, PrintOptions};
+
+/// The evaluation succeeded and the result is the printed value.
+pub const SCHEME_OK: c_int = 0;
+/// The source could not be read; the result is the lex or parse error.
+pub const SCHEME_READ_ERROR: c_int = 1;
+/// Evaluation failed; the result is the error.
+pub const SCHEME_EVAL_ERROR: c_int = 2;
+/// A pointer argument was null, or the source was not UTF-8.
+pub const SCHEME_INVALID_ARGUMENT: c_int = 3;
+/// The interpreter panicked. The context should be freed.
+pub const SCHEME_PANIC: c_int = 4;
+
+/// An interpreter session, opaque to C.
+pub struct SchemeContext {
+    env: Rc<Env>,
+}
+
+/// Creates a context with the default built-ins. Free it with `scheme_free`.
+#[unsafe(no_mangle)]
+pub extern "C" fn scheme_new() -> *mut SchemeContext {
+    Box::into_raw(Box::new(SchemeContext { env: default_env() }))
+}
+
+/// Evaluates the expressions in `source`, a NUL-terminated UTF-8 string,
+/// and returns one of the `SCHEME_` codes. Unless the code is
+/// `SCHEME_INVALID_ARGUMENT`, `*result` is set to a new string: the printed
+/// value of the last expression, or the error. Free it with
+/// `scheme_string_free`. Definitions persist in the context between calls.
+///
+/// # Safety
+///
+/// `context` must come from `scheme_new` and not have been freed, `source`
+/// must be a NUL-terminated string, and `result` must be valid for writes.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn scheme_eval(context: *mut SchemeContext, source: *const c_char, result: *mut *mut c_char) -> c_int {
+    if context.is_null() || source.is_null() || result.is_null() {
+        return SCHEME_INVALID_ARGUMENT;
+    }
+    // SAFETY: the caller guarantees both pointers are valid.
+    let (context, source) = unsafe { (&*context, CStr::from_ptr(source)) };
+    let Ok(source) = source.to_str() else { return SCHEME_INVALID_ARGUMENT };
+    let (code, text) = catch_unwind(AssertUnwindSafe(|| evaluate(context, source)))
+        .unwrap_or_else(|_| (SCHEME_PANIC, "Interpreter panicked".to_string()));
+    // Strings printed by the interpreter can contain NUL; C would stop there.
+    let text = CString::new(text.replace('\0', "\\x0;")).expect("NUL bytes were replaced");
+    // SAFETY: the caller guarantees `result` is valid for writes.
+    unsafe { *result = text.into_raw() };
+    code
+}
+
+fn evaluate(context: &SchemeContext, source: &str) -> (c_int, String) {
+    let program = match tokenize(source) {
+        Ok(tokens) => match parse_program(tokens) {
+            Ok(program) => program,
+            Err(e) => return (SCHEME_READ_ERROR, format!("Parse error: {:?}", e)),
+        },
+        Err(e) => return (SCHEME_READ_ERROR, format!("Lex error: {:?}", e)),
+    };
+    match eval_sequence(&program, context.env.clone()) {
+        Ok(value) => (SCHEME_OK, print_value(&value, &PrintOptions::from_env(&context.env))),
+        Err(e) => (SCHEME_EVAL_ERROR, format!("Eval error: {:?}", e)),
+    }
+}
+
+/// Frees a string returned by `scheme_eval`. Null is ignored.
+///
+/// # Safety
+///
+/// `string` must be null or come from `scheme_eval`, and not have been
+/// freed already.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn scheme_string_free(string: *mut c_char) {
+    if !string.is_null() {
+        // SAFETY: the caller guarantees the string came from `CString::into_raw`.
+        drop(unsafe { CString::from_raw(string) });
+    }
+}
+
+/// Frees a context. Null is ignored.
+///
+/// # Safety
+///
+/// `context` must be null or come from `scheme_new`, and not have been
+/// freed already.
+#[unsafe(no_mangle)]
+pub unsafe extern "C" fn scheme_free(context: *mut SchemeContext) {
+    if !context.is_null() {
+        // SAFETY: the caller guarantees the context came from `Box::into_raw`.
+        drop(unsafe { Box::from_raw(context) });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ptr;
+
+    fn eval_c(context: *mut SchemeContext, source: &str) -> (c_int, String) {
+        let source = CString::new(source).unwrap();
+        let mut result = ptr::null_mut();
+        // SAFETY: all pointers are valid, and the result is freed below.
+        unsafe {
+            let code = scheme_eval(context, source.as_ptr(), &mut result);
+            let text = CStr::from_ptr(result).to_str().unwrap().to_string();
+            scheme_string_free(result);
+            (code, text)
+        }
+    }
+
+    #[test]
+    fn test_eval_keeps_definitions_and_reports_errors() {
+        let context = scheme_new();
+        assert_eq!(eval_c(context, "(define (square x) (* x x))").0, SCHEME_OK);
+        assert_eq!(eval_c(context, "(list (square 4) \"a\")"), (SCHEME_OK, "(16 \"a\")".to_string()));
+        assert_eq!(eval_c(context, "(car 1)").0, SCHEME_EVAL_ERROR);
+        assert_eq!(eval_c(context, "(+ 1").0, SCHEME_READ_ERROR);
+        // SAFETY: the context came from `scheme_new`.
+        unsafe { scheme_free(context) };
+    }
+
+    #[test]
+    fn test_null_arguments_are_rejected() {
+        let mut result = ptr::null_mut();
+        // SAFETY: null pointers are checked before use.
+        let code = unsafe { scheme_eval(ptr::null_mut(), c"1".as_ptr(), &mut result) };
+        assert_eq!(code, SCHEME_INVALID_ARGUMENT);
+        assert!(result.is_null());
+        // SAFETY: null is ignored.
+        unsafe {
+            scheme_free(ptr::null_mut());
+            scheme_string_free(ptr::null_mut());
+        }
+    }
+}
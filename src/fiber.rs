@@ -0,0 +1,400 @@
+//! Fibers: lightweight threads inside one interpreter, for programs that
+//! are easier to write as several activities taking turns, such as a
+//! producer and a consumer.
+//!
+//! `(spawn-fiber thunk)` queues a fiber that calls `thunk`, `(yield)` lets
+//! the others run, and `(join fiber)` waits for a fiber to finish and
+//! returns its result, or raises what it raised. Fibers only switch at
+//! evaluator checkpoints: at `yield` and `join`, and every `SLICE`
+//! expressions, so one that never yields cannot hold up the rest. The code
+//! that spawned them, outside any fiber, runs the queue at the same points.
+//!
+//! Natively each fiber runs on its own stack. WebAssembly has no way to
+//! switch stacks, so there a fiber runs to completion once it starts, and
+//! fibers that wait for each other cannot make progress.
+
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::condition::{error_object, raise, Condition};
+use crate::env::{EvalError, Value};
+use crate::eval::apply;
+
+#[cfg(not(target_arch = "wasm32"))]
+use corosensei::{stack::DefaultStack, Coroutine, CoroutineResult, Yielder};
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::eval::{depth, set_depth};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::generator::running_generators;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::marks::swap_marks;
+
+/// How many expressions are evaluated between checkpoints.
+pub const SLICE: u32 = 1000;
+
+/// Each fiber's stack, deep enough for the recursion limit in debug builds.
+/// Pages are only used as it grows.
+#[cfg(not(target_arch = "wasm32"))]
+const STACK_SIZE: usize = 16 * 1024 * 1024;
+
+#[cfg(not(target_arch = "wasm32"))]
+type Body = Coroutine<(), (), Result<Value, EvalError>, DefaultStack>;
+
+/// The fiber running on the current thread, if any.
+#[cfg(not(target_arch = "wasm32"))]
+struct Current {
+    yielder: *const Yielder<(), ()>,
+    /// How many generators were running when the fiber was resumed; it can
+    /// only switch when no more are, since their stacks sit on top of its own.
+    generators: usize,
+}
+
+thread_local! {
+    /// The fibers waiting for their turn, in order.
+    static QUEUE: RefCell<Queue> = const { RefCell::new(Queue(VecDeque::new())) };
+    /// How many of the fibers in the queue are waiting in `wait_until`.
+    static WAITING: Cell<usize> = const { Cell::new(0) };
+    /// Counts fibers that stopped waiting in `wait_until`.
+    static WOKEN: Cell<u64> = const { Cell::new(0) };
+    /// Expressions left until the next checkpoint.
+    static CLOCK: Cell<u32> = const { Cell::new(SLICE) };
+    static NEXT_ID: Cell<u64> = const { Cell::new(1) };
+    /// The id of the fiber running, or 0 outside them.
+    static RUNNING_ID: Cell<u64> = const { Cell::new(0) };
+    #[cfg(not(target_arch = "wasm32"))]
+    static CURRENT: RefCell<Option<Current>> = const { RefCell::new(None) };
+}
+
+struct Queue(VecDeque<Rc<Fiber>>);
+
+impl Drop for Queue {
+    /// Fibers still waiting when the thread ends are leaked rather than
+    /// unwound, since unwinding them would use thread-local state that is
+    /// already gone.
+    fn drop(&mut self) {
+        self.0.drain(..).for_each(std::mem::forget);
+    }
+}
+
+/// A fiber from `spawn-fiber`.
+pub struct Fiber {
+    id: u64,
+    state: RefCell<FiberState>,
+}
+
+enum FiberState {
+    /// Waiting for its turn, with its evaluation depth and continuation
+    /// marks, which are its own.
+    #[cfg(not(target_arch = "wasm32"))]
+    Suspended(Box<Body>, usize, Vec<(Value, Value)>),
+    /// Not started yet.
+    #[cfg(target_arch = "wasm32")]
+    Pending(Value),
+    Running,
+    /// Finished, with its result or the error object of its failure.
+    Done(Result<Value, Value>),
+}
+
+impl fmt::Debug for Fiber {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Fiber").finish_non_exhaustive()
+    }
+}
+
+impl Fiber {
+    #[cfg(not(target_arch = "wasm32"))]
+    fn new(procedure: Value) -> Result<Fiber, EvalError> {
+        let stack = DefaultStack::new(STACK_SIZE)
+            .map_err(|e| EvalError::Other(format!("spawn-fiber: cannot allocate a stack: {}", e)))?;
+        let body = Coroutine::with_stack(stack, move |yielder: &Yielder<(), ()>, ()| {
+            CURRENT.with(|c| *c.borrow_mut() = Some(Current { yielder, generators: running_generators() }));
+            let result = apply(procedure, vec![]);
+            CURRENT.with(|c| *c.borrow_mut() = None);
+            result
+        });
+        Ok(Fiber { id: next_id(), state: RefCell::new(FiberState::Suspended(Box::new(body), 0, Vec::new())) })
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn new(procedure: Value) -> Result<Fiber, EvalError> {
+        Ok(Fiber { id: next_id(), state: RefCell::new(FiberState::Pending(procedure)) })
+    }
+
+    fn is_done(&self) -> bool {
+        matches!(*self.state.borrow(), FiberState::Done(_))
+    }
+
+    /// Runs the fiber until it next switches, queueing it again if it did
+    /// not finish. Only errors that no `guard` could handle, such as
+    /// running out of fuel, are returned; others finish the fiber.
+    fn resume(self: &Rc<Self>) -> Result<(), EvalError> {
+        let state = std::mem::replace(&mut *self.state.borrow_mut(), FiberState::Running);
+        #[cfg(not(target_arch = "wasm32"))]
+        let result = {
+            let FiberState::Suspended(mut body, body_depth, marks) = state else {
+                *self.state.borrow_mut() = state;
+                return Ok(());
+            };
+            let caller_depth = depth();
+            set_depth(body_depth);
+            let caller_marks = swap_marks(marks);
+            let caller_id = RUNNING_ID.with(|r| r.replace(self.id));
+            let result = body.resume(());
+            RUNNING_ID.with(|r| r.set(caller_id));
+            let marks = swap_marks(caller_marks);
+            let body_depth = depth();
+            set_depth(caller_depth);
+            match result {
+                CoroutineResult::Yield(()) => {
+                    *self.state.borrow_mut() = FiberState::Suspended(body, body_depth, marks);
+                    QUEUE.with(|q| q.borrow_mut().0.push_back(self.clone()));
+                    return Ok(());
+                }
+                CoroutineResult::Return(result) => result,
+            }
+        };
+        #[cfg(target_arch = "wasm32")]
+        let result = {
+            let FiberState::Pending(procedure) = state else {
+                *self.state.borrow_mut() = state;
+                return Ok(());
+            };
+            let caller_id = RUNNING_ID.with(|r| r.replace(self.id));
+            let result = apply(procedure, vec![]);
+            RUNNING_ID.with(|r| r.set(caller_id));
+            result
+        };
+        let (done, uncaught) = match result {
+            Ok(value) => (Ok(value), None),
+            Err(error) => match error_object(&error) {
+                Some(object) => (Err(object), None),
+                None => (Err(Value::Condition(Rc::new(Condition {
+                    kind: "error",
+                    message: "Fiber stopped".into(),
+                    irritants: vec![],
+                }))), Some(error)),
+            },
+        };
+        *self.state.borrow_mut() = FiberState::Done(done);
+        uncaught.map_or(Ok(()), Err)
+    }
+}
+
+impl Drop for Fiber {
+    /// Dropping a suspended fiber unwinds its stack at its own depth, so the
+    /// caller's is unchanged.
+    fn drop(&mut self) {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let FiberState::Suspended(body, body_depth, _) = std::mem::replace(self.state.get_mut(), FiberState::Running) {
+            let caller_depth = depth();
+            set_depth(body_depth);
+            drop(body);
+            set_depth(caller_depth);
+        }
+    }
+}
+
+fn next_id() -> u64 {
+    NEXT_ID.with(|n| n.replace(n.get() + 1))
+}
+
+/// Identifies the fiber running on the current thread, or the code outside
+/// them as 0, for resources that fibers hold, such as mutexes.
+pub(crate) fn running_fiber() -> u64 {
+    RUNNING_ID.with(|r| r.get())
+}
+
+/// Whether a fiber is running on the current thread, as opposed to the
+/// code outside them that runs the queue.
+fn in_fiber() -> bool {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        CURRENT.with(|c| c.borrow().is_some())
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        false
+    }
+}
+
+/// Suspends the running fiber until its next turn.
+#[cfg(not(target_arch = "wasm32"))]
+fn suspend() -> Result<(), EvalError> {
+    let current = CURRENT.with(|c| c.borrow_mut().take()).expect("suspend is called in a fiber");
+    if running_generators() != current.generators {
+        CURRENT.with(|c| *c.borrow_mut() = Some(current));
+        return Err(EvalError::Other("yield: a fiber cannot switch inside a generator".into()));
+    }
+    let yielder = current.yielder;
+    // SAFETY: the yielder lives on the stack of the fiber running this
+    // code, and no generator's stack is on top of it.
+    unsafe { (*yielder).suspend(()) };
+    CURRENT.with(|c| *c.borrow_mut() = Some(Current { yielder, generators: running_generators() }));
+    Ok(())
+}
+
+/// Gives every fiber waiting at the start one turn, from outside them.
+fn run_queue() -> Result<(), EvalError> {
+    let waiting = QUEUE.with(|q| q.borrow().0.len());
+    for _ in 0..waiting {
+        let Some(fiber) = QUEUE.with(|q| q.borrow_mut().0.pop_front()) else { break };
+        fiber.resume()?;
+    }
+    Ok(())
+}
+
+/// Lets other fibers run: the running fiber suspends, or, outside them,
+/// each waiting fiber gets a turn.
+pub(crate) fn yield_fiber() -> Result<(), EvalError> {
+    CLOCK.with(|c| c.set(SLICE));
+    #[cfg(not(target_arch = "wasm32"))]
+    if in_fiber() {
+        return suspend();
+    }
+    run_queue()
+}
+
+/// Lets other fibers run until `ready` returns true, for operations that
+/// wait for them, such as `join` and taking from an empty channel. Fails,
+/// naming the operation, if every fiber is waiting as well, since then
+/// nothing could make it true.
+pub(crate) fn wait_until(name: &str, ready: impl Fn() -> bool) -> Result<(), EvalError> {
+    if in_fiber() {
+        while !ready() {
+            WAITING.with(|w| w.set(w.get() + 1));
+            let result = yield_fiber();
+            WAITING.with(|w| w.set(w.get() - 1));
+            result?;
+        }
+        WOKEN.with(|w| w.set(w.get() + 1));
+        return Ok(());
+    }
+    while !ready() {
+        // When only waiting fibers are queued, each gets a turn to see if
+        // what it waits for has happened; if none has, none ever will.
+        let all_waiting = QUEUE.with(|q| q.borrow().0.len()) == WAITING.with(|w| w.get());
+        let woken = WOKEN.with(|w| w.get());
+        yield_fiber()?;
+        if all_waiting && woken == WOKEN.with(|w| w.get()) && !ready() {
+            return Err(EvalError::Other(format!("{}: every fiber is waiting, so this would wait forever", name)));
+        }
+    }
+    Ok(())
+}
+
+/// Called by `eval` before each expression; switches fibers every `SLICE`
+/// expressions.
+pub(crate) fn checkpoint() -> Result<(), EvalError> {
+    let due = CLOCK.with(|c| match c.get() {
+        0 => true,
+        n => {
+            c.set(n - 1);
+            false
+        }
+    });
+    if !due {
+        return Ok(());
+    }
+    CLOCK.with(|c| c.set(SLICE));
+    if QUEUE.with(|q| q.borrow().0.is_empty()) {
+        return Ok(());
+    }
+    #[cfg(not(target_arch = "wasm32"))]
+    if let Some(generators) = CURRENT.with(|c| c.borrow().as_ref().map(|c| c.generators)) {
+        // Inside a generator the fiber waits for its next `yield` or `join`.
+        return if generators == running_generators() { suspend() } else { Ok(()) };
+    }
+    run_queue()
+}
+
+fn fiber_arg<'a>(value: &'a Value, name: &str) -> Result<&'a Rc<Fiber>, EvalError> {
+    match value {
+        Value::Fiber(fiber) => Ok(fiber),
+        other => Err(EvalError::TypeError(format!("{}: expected a fiber, got {}", name, other.type_name()))),
+    }
+}
+
+/// `(spawn-fiber thunk)` queues a fiber calling `thunk` and returns it.
+pub fn builtin_spawn_fiber(args: Vec<Value>) -> Result<Value, EvalError> {
+    let [thunk] = <[Value; 1]>::try_from(args).map_err(|_| EvalError::ArityMismatch)?;
+    let fiber = Rc::new(Fiber::new(thunk)?);
+    QUEUE.with(|q| q.borrow_mut().0.push_back(fiber.clone()));
+    Ok(Value::Fiber(fiber))
+}
+
+/// `(join fiber)` lets other fibers run until `fiber` finishes, then
+/// returns its result, or raises again what it raised.
+pub fn builtin_join(args: Vec<Value>) -> Result<Value, EvalError> {
+    let fiber = fiber_arg(&args[0], "join")?;
+    if matches!(*fiber.state.borrow(), FiberState::Running) {
+        return Err(EvalError::Other("join: a fiber cannot wait for itself".into()));
+    }
+    wait_until("join", || fiber.is_done())?;
+    match &*fiber.state.borrow() {
+        FiberState::Done(Ok(value)) => Ok(value.clone()),
+        FiberState::Done(Err(object)) => Err(raise(object.clone())),
+        _ => unreachable!("the fiber is done"),
+    }
+}
+
+/// Returns true if the argument is a fiber.
+pub fn builtin_is_fiber(args: Vec<Value>) -> Result<Value, EvalError> {
+    Ok(Value::Boolean(matches!(args[0], Value::Fiber(_))))
+}
+
+/// `(fiber-done? fiber)` returns whether the fiber has finished.
+pub fn builtin_fiber_is_done(args: Vec<Value>) -> Result<Value, EvalError> {
+    Ok(Value::Boolean(fiber_arg(&args[0], "fiber-done?")?.is_done()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{assert_fails, assert_prints};
+
+    #[test]
+    fn test_fibers_take_turns() {
+        assert_prints("
+            (define log (make-queue))
+            (define (worker name n)
+              (lambda ()
+                (define (loop i)
+                  (if (< i n)
+                      (begin (enqueue! log (list name i)) (yield) (loop (+ i 1)))
+                      name))
+                (loop 0)))
+            (define a (spawn-fiber (worker 'a 3)))
+            (define b (spawn-fiber (worker 'b 2)))
+            (list (join a) (join b) (fiber-done? a) (queue->list log))",
+            "(a b #t ((a 0) (b 0) (a 1) (b 1) (a 2)))");
+    }
+
+    #[test]
+    fn test_fibers_switch_at_checkpoints() {
+        // Neither fiber yields, but the first is paused before it finishes
+        // counting, so the second sees a count in between.
+        assert_prints("
+            (define counter (make-vector 1 0))
+            (define (count-to n)
+              (vector-for-each (lambda (x) (vector-set! counter 0 (+ 1 (vector-ref counter 0)))) (make-vector n 0)))
+            (define counting (spawn-fiber (lambda () (count-to 300) 'counted)))
+            (define peek (spawn-fiber (lambda () (vector-ref counter 0))))
+            (define seen (join peek))
+            (list (join counting) (< 0 seen 300))",
+            "(counted #t)");
+    }
+
+    #[test]
+    fn test_fiber_errors_are_raised_by_join() {
+        assert_prints("
+            (define f (spawn-fiber (lambda () (error \"broken\" 1))))
+            (guard (e (#t (list (condition-message e) (condition-irritants e)))) (join f))",
+            "(\"broken\" (1))");
+        assert_prints("(define f (spawn-fiber (lambda () (join f)))) (guard (e (#t (condition-message e))) (join f))",
+            "\"join: a fiber cannot wait for itself\"");
+        assert_fails("(join 1)", "type");
+        assert_fails("(join (spawn-fiber (lambda () (generator-next (make-generator (lambda () (yield)))))))", "error");
+    }
+}
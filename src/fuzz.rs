@@ -0,0 +1,120 @@
+use crate::ast::Expr;
+use crate::env::{default_env, Value};
+use crate::eval::{eval_sequence, is_pure_mode, remaining_fuel, set_fuel, set_pure_mode};
+use crate::lexer::tokenize;
+use crate::parser::parse_program;
+
+/// Reads `input` as a sequence of data, writes them back out, and reads the
+/// result again, returning an error describing the difference if the two
+/// readings disagree. Input that does not read is not an error.
+///
+/// Meant to be called from a fuzzer with arbitrary text, treating `Err` as
+/// a failure: whatever the reader accepts, the writer must reproduce.
+pub fn roundtrip_datum(input: &str) -> Result<(), String> {
+    let Some(data) = read_data(input) else { return Ok(()) };
+    let printed = print_data(&data);
+    match read_data(&printed) {
+        Some(reread) if reread == data => Ok(()),
+        Some(reread) => Err(format!("{:?} was written as {:?} and read back as {:?}", data, printed, reread)),
+        None => Err(format!("{:?} was written as {:?}, which does not read", data, printed)),
+    }
+}
+
+/// Evaluates `source` in a fresh environment, in pure mode and with at most
+/// `fuel` evaluation steps, and returns the value of the last form or a
+/// description of the error. Neither the program nor its failure can touch
+/// the outside world or run for long, so any input is safe to try; a panic
+/// is a bug.
+pub fn eval_bounded(source: &str, fuel: u64) -> Result<Value, String> {
+    let tokens = tokenize(source).map_err(|e| format!("{:?}", e))?;
+    let program = parse_program(tokens).map_err(|e| format!("{:?}", e))?;
+    let (was_pure, old_fuel) = (is_pure_mode(), remaining_fuel());
+    set_pure_mode(true);
+    set_fuel(Some(fuel));
+    let result = eval_sequence(&program, default_env());
+    set_pure_mode(was_pure);
+    set_fuel(old_fuel);
+    result.map_err(|e| format!("{:?}", e))
+}
+
+fn read_data(input: &str) -> Option<Vec<Expr>> {
+    parse_program(tokenize(input).ok()?).ok()
+}
+
+fn print_data(data: &[Expr]) -> String {
+    data.iter().map(|expr| expr.to_string()).collect::<Vec<_>>().join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    /// Literal data the reader produces.
+    fn atom() -> impl Strategy<Value = Expr> {
+        prop_oneof![
+            any::<i64>().prop_map(Expr::Number),
+            (any::<i64>(), 1..i64::MAX).prop_map(|(n, d)| Expr::Rational(n, d)),
+            any::<f64>().prop_filter("finite", |x| x.is_finite()).prop_map(Expr::Float),
+            any::<bool>().prop_map(Expr::Boolean),
+            any::<String>().prop_map(Expr::String),
+            "[a-z!$%&*/<=>?^_~+-][a-z0-9!$%&*/<=>?^_~+.-]{0,8}".prop_map(Expr::Symbol),
+            "[a-z][a-z0-9-]{0,8}".prop_map(Expr::Keyword),
+            any::<char>().prop_map(Expr::Char),
+            prop::collection::vec(any::<u8>(), 0..4).prop_map(Expr::Bytevector),
+        ]
+        .prop_filter("the dot, which is not a symbol", |expr| *expr != Expr::Symbol(".".into()))
+    }
+
+    fn datum() -> impl Strategy<Value = Expr> {
+        atom().prop_recursive(4, 64, 8, |inner| {
+            prop_oneof![
+                prop::collection::vec(inner.clone(), 0..8).prop_map(Expr::List),
+                prop::collection::vec(inner.clone(), 0..8).prop_map(Expr::Vector),
+                prop::collection::vec((inner.clone(), inner), 0..4).prop_map(Expr::HashTable),
+            ]
+        })
+    }
+
+    /// Forms made mostly of calls to common procedures and special forms, so
+    /// that evaluation gets past looking up names.
+    fn form() -> impl Strategy<Value = Expr> {
+        const NAMES: &[&str] = &[
+            "define", "lambda", "let", "if", "quote", "begin", "set!", "f", "x",
+            "car", "cdr", "cons", "list", "apply", "map", "vector", "vector-ref",
+            "+", "-", "*", "/", "<", "=", "string-append", "string->symbol", "length",
+        ];
+        let leaf = prop_oneof![
+            atom(),
+            prop::sample::select(NAMES).prop_map(|name| Expr::Symbol(name.into())),
+        ];
+        leaf.prop_recursive(5, 64, 6, |inner| prop::collection::vec(inner, 0..6).prop_map(Expr::List))
+    }
+
+    proptest! {
+        #[test]
+        fn test_printed_data_read_back_unchanged(data in prop::collection::vec(datum(), 1..4)) {
+            let printed = print_data(&data);
+            prop_assert_eq!(read_data(&printed), Some(data), "printed as {:?}", printed);
+        }
+
+        #[test]
+        fn test_arbitrary_text_round_trips(input in "[()#\\\\'\"a-z0-9 :;.\n]{0,40}") {
+            prop_assert_eq!(roundtrip_datum(&input), Ok(()));
+        }
+
+        #[test]
+        fn test_bounded_evaluation_never_panics(program in prop::collection::vec(form(), 1..4)) {
+            let _ = eval_bounded(&print_data(&program), 1000);
+        }
+    }
+
+    #[test]
+    fn test_bounded_evaluation_stops() {
+        assert_eq!(eval_bounded("(+ 1 2)", 100), Ok(Value::Number(3)));
+        assert_eq!(eval_bounded("(define (f) (f)) (f)", 100), Err("OutOfFuel".into()));
+        assert!(eval_bounded("(display 1)", 100).unwrap_err().contains("EffectInPureMode"));
+        assert!(!is_pure_mode());
+        assert_eq!(remaining_fuel(), None);
+    }
+}
@@ -0,0 +1,252 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::env::{EvalError, Promise, Value};
+
+#[cfg(not(target_arch = "wasm32"))]
+use corosensei::{stack::DefaultStack, Coroutine, CoroutineResult, Yielder};
+
+#[cfg(not(target_arch = "wasm32"))]
+use crate::eval::{apply, depth, set_depth};
+
+/// Each generator body runs on its own stack, deep enough for the
+/// recursion limit in debug builds. Pages are only used as it grows.
+#[cfg(not(target_arch = "wasm32"))]
+const STACK_SIZE: usize = 16 * 1024 * 1024;
+
+#[cfg(not(target_arch = "wasm32"))]
+type Body = Coroutine<(), Value, Result<Value, EvalError>, DefaultStack>;
+
+#[cfg(not(target_arch = "wasm32"))]
+thread_local! {
+    /// The yielders of the generators currently running, innermost last.
+    /// `yield` suspends the innermost one.
+    static YIELDERS: RefCell<Vec<*const Yielder<(), Value>>> = const { RefCell::new(Vec::new()) };
+}
+
+/// How many generator bodies are running on the current thread, one inside
+/// another.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn running_generators() -> usize {
+    YIELDERS.with(|y| y.borrow().len())
+}
+
+/// A procedure run as a coroutine by `make-generator`: each `generator-next`
+/// runs it up to its next `yield`.
+pub struct Generator {
+    state: RefCell<GeneratorState>,
+}
+
+#[cfg_attr(target_arch = "wasm32", allow(dead_code))]
+enum GeneratorState {
+    /// Waiting for `generator-next`, with the evaluation depth of the
+    /// suspended body.
+    #[cfg(not(target_arch = "wasm32"))]
+    Suspended(Box<Body>, usize),
+    Running,
+    Done,
+}
+
+impl fmt::Debug for Generator {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Generator").finish_non_exhaustive()
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Generator {
+    fn new(procedure: Value) -> Result<Self, EvalError> {
+        let stack = DefaultStack::new(STACK_SIZE)
+            .map_err(|e| EvalError::Other(format!("make-generator: cannot allocate a stack: {}", e)))?;
+        let body = Coroutine::with_stack(stack, move |yielder: &Yielder<(), Value>, ()| {
+            YIELDERS.with(|y| y.borrow_mut().push(yielder));
+            let result = apply(procedure, vec![]);
+            YIELDERS.with(|y| y.borrow_mut().pop());
+            result
+        });
+        Ok(Generator { state: RefCell::new(GeneratorState::Suspended(Box::new(body), 0)) })
+    }
+
+    /// Runs the body to its next `yield` and returns the yielded value, or
+    /// the eof object once the body has returned. An error in the body
+    /// finishes the generator.
+    pub fn next(&self) -> Result<Value, EvalError> {
+        let state = std::mem::replace(&mut *self.state.borrow_mut(), GeneratorState::Running);
+        let (mut body, body_depth) = match state {
+            GeneratorState::Suspended(body, depth) => (body, depth),
+            GeneratorState::Running => {
+                return Err(EvalError::Other("generator-next: generator is already running".into()));
+            }
+            GeneratorState::Done => {
+                *self.state.borrow_mut() = GeneratorState::Done;
+                return Ok(Value::Eof);
+            }
+        };
+        // The body counts its own nesting from where it was suspended, so
+        // that its frames do not count against the caller's.
+        let caller_depth = depth();
+        set_depth(body_depth);
+        let result = body.resume(());
+        let body_depth = depth();
+        set_depth(caller_depth);
+        match result {
+            CoroutineResult::Yield(value) => {
+                *self.state.borrow_mut() = GeneratorState::Suspended(body, body_depth);
+                Ok(value)
+            }
+            CoroutineResult::Return(result) => {
+                *self.state.borrow_mut() = GeneratorState::Done;
+                result.map(|_| Value::Eof)
+            }
+        }
+    }
+}
+
+#[cfg(target_arch = "wasm32")]
+impl Generator {
+    fn new(_procedure: Value) -> Result<Self, EvalError> {
+        Err(EvalError::Other("make-generator: not supported on this platform".into()))
+    }
+
+    pub fn next(&self) -> Result<Value, EvalError> {
+        Ok(Value::Eof)
+    }
+}
+
+impl Drop for Generator {
+    /// Dropping a suspended body unwinds its stack, releasing its nesting
+    /// depth; it is unwound at its own depth so the caller's is unchanged.
+    fn drop(&mut self) {
+        #[cfg(not(target_arch = "wasm32"))]
+        if let GeneratorState::Suspended(body, body_depth) = std::mem::replace(self.state.get_mut(), GeneratorState::Done) {
+            let caller_depth = depth();
+            set_depth(body_depth);
+            drop(body);
+            set_depth(caller_depth);
+        }
+    }
+}
+
+/// `(make-generator thunk)` returns a generator that runs `thunk` lazily:
+/// each `(generator-next gen)` continues it until it calls `(yield value)`
+/// and returns that value.
+pub fn builtin_make_generator(args: Vec<Value>) -> Result<Value, EvalError> {
+    match <[Value; 1]>::try_from(args) {
+        Ok([procedure]) => Ok(Value::Generator(Rc::new(Generator::new(procedure)?))),
+        Err(_) => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// `(yield value)` suspends the innermost running generator, making
+/// `value` the result of the `generator-next` that resumed it. `(yield)`,
+/// with no value, lets other fibers run instead (see `fiber`).
+pub fn builtin_yield(args: Vec<Value>) -> Result<Value, EvalError> {
+    if args.is_empty() {
+        return crate::fiber::yield_fiber().map(|()| Value::Unspecified);
+    }
+    let [value] = <[Value; 1]>::try_from(args).map_err(|_| EvalError::ArityMismatch)?;
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        let yielder = YIELDERS.with(|y| y.borrow_mut().pop())
+            .ok_or_else(|| EvalError::Other("yield: not inside a generator".into()))?;
+        // SAFETY: the yielder lives on the stack of the coroutine that is
+        // running this code, which is the innermost one until it suspends.
+        unsafe { (*yielder).suspend(value) };
+        YIELDERS.with(|y| y.borrow_mut().push(yielder));
+        Ok(Value::Unspecified)
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        let _ = value;
+        Err(EvalError::Other("yield: not inside a generator".into()))
+    }
+}
+
+/// `(generator-next gen)` returns the next value of a generator, or the eof
+/// object once it is exhausted.
+pub fn builtin_generator_next(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [Value::Generator(generator)] => generator.next(),
+        [_] => Err(EvalError::TypeError("generator-next expects a generator".into())),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// Returns true if the argument is a generator.
+pub fn builtin_is_generator(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [value] => Ok(Value::Boolean(matches!(value, Value::Generator(_)))),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// `(generator->stream gen)` returns a stream of the values a generator
+/// has yet to produce, pulling each from the generator when the stream is
+/// forced that far.
+pub fn builtin_generator_to_stream(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [Value::Generator(generator)] => generator_stream(generator.clone()),
+        [_] => Err(EvalError::TypeError("generator->stream expects a generator".into())),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+fn generator_stream(generator: Rc<Generator>) -> Result<Value, EvalError> {
+    match generator.next()? {
+        Value::Eof => Ok(Value::List(Rc::new([]))),
+        head => {
+            let tail = Promise::new(move || generator_stream(generator.clone()));
+            Ok(Value::List([head, Value::Promise(Rc::new(tail))].into()))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::builtin_generator_next;
+    use crate::env::{default_env, EvalError, Value};
+    use crate::testing::eval_source;
+
+    #[test]
+    fn test_generators_produce_values_lazily() {
+        let source = "
+            (define reached (make-vector 1 #f))
+            (define (count-from n)
+              (define (loop i)
+                (vector-set! reached 0 i)
+                (yield i)
+                (if (< i (+ n 2)) (loop (+ i 1)) 'done))
+              (make-generator (lambda () (loop n))))
+            (define g (count-from 5))
+            (define first (generator-next g))
+            (define reached-first (vector-ref reached 0))
+            (list first reached-first (generator-next g) (generator-next g) (generator-next g) (eof-object? (generator-next g)))";
+        assert_eq!(eval_source(source, &default_env()).unwrap().to_string(), "(5 5 6 7 #<eof> #t)");
+    }
+
+    #[test]
+    fn test_generators_nest_and_feed_streams() {
+        let source = "
+            (define (numbers) (make-generator (lambda () (yield 1) (yield 2) (yield 3))))
+            (define doubled
+              (let ((inner (numbers)))
+                (define (loop x)
+                  (if (eof-object? x) #f (begin (yield (* 2 x)) (loop (generator-next inner)))))
+                (make-generator (lambda () (loop (generator-next inner))))))
+            (define s (generator->stream doubled))
+            (list (stream-car s) (stream-car (stream-cdr s)) (stream-car (stream-cdr (stream-cdr s))) (stream-null? (stream-cdr (stream-cdr (stream-cdr s)))))";
+        assert_eq!(eval_source(source, &default_env()).unwrap().to_string(), "(2 4 6 #t)");
+    }
+
+    #[test]
+    fn test_generator_errors() {
+        assert!(matches!(eval_source("(yield 1)", &default_env()), Err(EvalError::Other(_))));
+        let generator = eval_source("(make-generator (lambda () (car 1)))", &default_env()).unwrap();
+        assert!(matches!(builtin_generator_next(vec![generator.clone()]), Err(EvalError::TypeError(_))));
+        assert_eq!(builtin_generator_next(vec![generator]).unwrap(), Value::Eof);
+        // Abandoning a suspended generator leaves evaluation depth balanced.
+        assert_eq!(eval_source("(define (f) (generator-next (make-generator (lambda () (yield 1))))) (f) (f)", &default_env()).unwrap(), Value::Number(1));
+        assert_eq!(crate::eval::depth(), 0);
+    }
+}
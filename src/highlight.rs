@@ -0,0 +1,148 @@
+//! Token classes for syntax highlighting, shared by the `--highlight` CLI
+//! mode, the browser playground, and editor integrations.
+
+use crate::eval::SpecialForm;
+use crate::lexer::{tokenize_with_spans, Lexeme, Token};
+use crate::style;
+
+pub use crate::lexer::Span;
+
+/// How a span of source text should be highlighted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TokenClass {
+    /// The name of a special form, such as `define` or `if`.
+    Keyword,
+    Symbol,
+    Number,
+    /// A string literal.
+    String,
+    /// A boolean, character, `#:keyword`, or reader extension literal.
+    Constant,
+    Comment,
+    /// The `'` before a quoted datum.
+    Quote,
+    /// An opening or closing parenthesis, with how many lists enclose it,
+    /// so that matching pairs can share a color.
+    Paren { depth: usize },
+    /// Text that cannot be read, or a parenthesis that closes nothing.
+    Invalid,
+}
+
+impl TokenClass {
+    /// The class's name, as used by the browser playground's CSS.
+    pub fn name(self) -> &'static str {
+        match self {
+            TokenClass::Keyword => "keyword",
+            TokenClass::Symbol => "symbol",
+            TokenClass::Number => "number",
+            TokenClass::String => "string",
+            TokenClass::Constant => "constant",
+            TokenClass::Comment => "comment",
+            TokenClass::Quote => "quote",
+            TokenClass::Paren { .. } => "paren",
+            TokenClass::Invalid => "invalid",
+        }
+    }
+
+    /// The terminal styles `highlight` uses; parentheses cycle through
+    /// colors by depth.
+    fn styles(self) -> &'static [&'static str] {
+        const PARENS: [&[&str]; 4] = [&["blue"], &["yellow"], &["magenta"], &["cyan"]];
+        match self {
+            TokenClass::Keyword => &["bold", "magenta"],
+            TokenClass::Symbol => &[],
+            TokenClass::Number | TokenClass::Constant => &["cyan"],
+            TokenClass::String => &["green"],
+            TokenClass::Comment => &["gray"],
+            TokenClass::Quote => &["yellow"],
+            TokenClass::Paren { depth } => PARENS[depth % PARENS.len()],
+            TokenClass::Invalid => &["red", "underline"],
+        }
+    }
+}
+
+/// Classifies every token and comment in `input`, in order. Whitespace is
+/// left out. Unlike reading the program, this never fails: text that cannot
+/// be read is classed as `Invalid` and classification carries on after it.
+pub fn classify_tokens(input: &str) -> Vec<(Span, TokenClass)> {
+    let mut depth = 0;
+    tokenize_with_spans(input).into_iter().map(|(lexeme, span)| {
+        let class = match lexeme {
+            Lexeme::Token(Token::LParen | Token::HashTableOpen | Token::VectorOpen | Token::BytevectorOpen) => {
+                depth += 1;
+                TokenClass::Paren { depth: depth - 1 }
+            }
+            Lexeme::Token(Token::RParen) if depth > 0 => {
+                depth -= 1;
+                TokenClass::Paren { depth }
+            }
+            Lexeme::Token(Token::RParen) | Lexeme::Error(_) => TokenClass::Invalid,
+            Lexeme::Token(Token::Symbol(name)) if SpecialForm::from_name(&name).is_some() => TokenClass::Keyword,
+            Lexeme::Token(Token::Symbol(_)) => TokenClass::Symbol,
+            Lexeme::Token(Token::Number(_) | Token::Rational(..) | Token::Float(_)) => TokenClass::Number,
+            Lexeme::Token(Token::String(_)) => TokenClass::String,
+            Lexeme::Token(Token::Boolean(_) | Token::Char(_) | Token::Keyword(_) | Token::Datum(_)) => TokenClass::Constant,
+            Lexeme::Token(Token::Quote) => TokenClass::Quote,
+            Lexeme::Comment => TokenClass::Comment,
+        };
+        (span, class)
+    }).collect()
+}
+
+/// Returns `input` with its tokens colored for a terminal, when styled
+/// output is on (see `style::colors_enabled`), and unchanged otherwise.
+pub fn highlight(input: &str) -> String {
+    let mut out = String::with_capacity(input.len());
+    let mut last = 0;
+    for (span, class) in classify_tokens(input) {
+        out.push_str(&input[last..span.start]);
+        let text = &input[span.start..span.end];
+        match class.styles() {
+            [] => out.push_str(text),
+            styles => out.push_str(&style::paint(text, styles).expect("known styles")),
+        }
+        last = span.end;
+    }
+    out.push_str(&input[last..]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_tokens() {
+        let input = "(define (f x) ; doubles\n  '(#t \"s\" 2)) )";
+        let classes: Vec<_> = classify_tokens(input).into_iter()
+            .map(|(span, class)| (&input[span.start..span.end], class))
+            .collect();
+        assert_eq!(classes, vec![
+            ("(", TokenClass::Paren { depth: 0 }),
+            ("define", TokenClass::Keyword),
+            ("(", TokenClass::Paren { depth: 1 }),
+            ("f", TokenClass::Symbol),
+            ("x", TokenClass::Symbol),
+            (")", TokenClass::Paren { depth: 1 }),
+            ("; doubles", TokenClass::Comment),
+            ("'", TokenClass::Quote),
+            ("(", TokenClass::Paren { depth: 1 }),
+            ("#t", TokenClass::Constant),
+            ("\"s\"", TokenClass::String),
+            ("2", TokenClass::Number),
+            (")", TokenClass::Paren { depth: 1 }),
+            (")", TokenClass::Paren { depth: 0 }),
+            (")", TokenClass::Invalid),
+        ]);
+    }
+
+    #[test]
+    fn test_highlight_keeps_the_source_text() {
+        let input = "(if #t\n    \"yes\" ; comment\n    #bad)";
+        style::set_colors_enabled(Some(false));
+        assert_eq!(highlight(input), input);
+        style::set_colors_enabled(Some(true));
+        assert_eq!(highlight("(if 1)"), "\x1b[34m(\x1b[0m\x1b[1;35mif\x1b[0m \x1b[36m1\x1b[0m\x1b[34m)\x1b[0m");
+        style::set_colors_enabled(None);
+    }
+}
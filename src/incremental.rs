@@ -0,0 +1,255 @@
+use std::cell::Cell;
+use std::collections::VecDeque;
+use std::io::{self, BufRead, BufReader, Read};
+use std::rc::Rc;
+
+use crate::env::{Env, EvalError, Value};
+use crate::eval::eval;
+use crate::lexer::{tokenize_with_lines, LexError};
+use crate::parser::{parse_expr, ParseError};
+
+/// The lines (counting from 1) that a top-level form spans.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Span {
+    pub start_line: usize,
+    pub end_line: usize,
+}
+
+#[derive(Debug)]
+pub enum FormError {
+    Io(io::Error),
+    Lex(LexError),
+    Parse(ParseError),
+    Eval(EvalError),
+}
+
+/// The outcome of one top-level form read by `eval_reader`.
+#[derive(Debug)]
+pub struct FormResult {
+    pub span: Span,
+    pub result: Result<Value, FormError>,
+}
+
+/// Evaluates the top-level forms of a source one at a time as they are read.
+///
+/// Only the text of the forms currently being evaluated is held in memory,
+/// so arbitrarily large generated files can be run. The returned iterator
+/// yields one `FormResult` per form, in order, after evaluating it.
+///
+/// An error in one form does not stop the rest: a lex or parse error skips
+/// to the next line on which all parentheses are balanced, and an
+/// evaluation error moves on to the next form. Only a read error ends the
+/// iteration early.
+pub fn eval_reader<R: Read>(reader: R, env: Rc<Env>) -> EvalReader<R> {
+    EvalReader {
+        reader: BufReader::new(reader),
+        env,
+        lines_read: 0,
+        pending: VecDeque::new(),
+        done: false,
+    }
+}
+
+/// Iterator over the results of evaluating a source, from `eval_reader`.
+pub struct EvalReader<R> {
+    reader: BufReader<R>,
+    env: Rc<Env>,
+    lines_read: usize,
+    pending: VecDeque<FormResult>,
+    done: bool,
+}
+
+impl<R: Read> Iterator for EvalReader<R> {
+    type Item = FormResult;
+
+    fn next(&mut self) -> Option<FormResult> {
+        loop {
+            if let Some(result) = self.pending.pop_front() {
+                return Some(result);
+            }
+            if self.done {
+                return None;
+            }
+            self.eval_next_chunk();
+        }
+    }
+}
+
+impl<R: Read> EvalReader<R> {
+    /// Reads lines up to the end of the next complete top-level form (or the
+    /// end of the input), then evaluates every form they contain.
+    fn eval_next_chunk(&mut self) {
+        let start_line = self.lines_read + 1;
+        let mut chunk = String::new();
+        let mut balance = Balance::default();
+        loop {
+            let before = chunk.len();
+            match self.reader.read_line(&mut chunk) {
+                Ok(0) => {
+                    self.done = true;
+                    break;
+                }
+                Ok(_) => {
+                    self.lines_read += 1;
+                    balance.feed(&chunk[before..]);
+                    if balance.is_complete() {
+                        break;
+                    }
+                }
+                Err(e) => {
+                    let span = Span { start_line, end_line: self.lines_read.max(start_line) };
+                    self.pending.push_back(FormResult { span, result: Err(FormError::Io(e)) });
+                    self.done = true;
+                    return;
+                }
+            }
+        }
+
+        let tokens = match tokenize_with_lines(&chunk) {
+            Ok(tokens) => tokens,
+            Err(e) => {
+                let span = Span { start_line, end_line: self.lines_read.max(start_line) };
+                self.pending.push_back(FormResult { span, result: Err(FormError::Lex(e)) });
+                return;
+            }
+        };
+        let lines: Vec<usize> = tokens.iter().map(|(_, line)| start_line + line - 1).collect();
+
+        // Peeking pulls a token through `inspect`, so after peeking at the
+        // first token of a form, `consumed - 1` is its index; after parsing
+        // the form, `consumed - 1` is the index of its last token.
+        let consumed = Cell::new(0);
+        let mut tokens = tokens.into_iter()
+            .map(|(token, _)| token)
+            .inspect(|_| consumed.set(consumed.get() + 1))
+            .peekable();
+        while tokens.peek().is_some() {
+            let start_line = lines[consumed.get() - 1];
+            match parse_expr(&mut tokens) {
+                Ok(expr) => {
+                    let span = Span { start_line, end_line: lines[consumed.get() - 1] };
+                    let result = eval(&expr, self.env.clone()).map_err(FormError::Eval);
+                    self.pending.push_back(FormResult { span, result });
+                }
+                Err(e) => {
+                    let span = Span { start_line, end_line: *lines.last().unwrap() };
+                    self.pending.push_back(FormResult { span, result: Err(FormError::Parse(e)) });
+                    return;
+                }
+            }
+        }
+    }
+}
+
+/// Tracks whether the text read so far ends between top-level forms: no
+/// open parentheses, unterminated string, or dangling quote.
+#[derive(Default)]
+struct Balance {
+    depth: i64,
+    in_string: bool,
+    escaped: bool,
+    quoted: bool,
+    after_hash: bool,
+}
+
+impl Balance {
+    fn feed(&mut self, line: &str) {
+        for c in line.chars() {
+            if self.in_string {
+                if self.escaped {
+                    self.escaped = false;
+                } else if c == '\\' {
+                    self.escaped = true;
+                } else if c == '"' {
+                    self.in_string = false;
+                }
+                continue;
+            }
+            if self.after_hash && c == '\\' {
+                // A character literal like `#\(`; the next character is taken
+                // as is, even a parenthesis or quote.
+                self.after_hash = false;
+                self.escaped = true;
+                continue;
+            }
+            if std::mem::take(&mut self.escaped) {
+                self.quoted = false;
+                continue;
+            }
+            self.after_hash = c == '#';
+            match c {
+                ';' => break,
+                '"' => self.in_string = true,
+                '(' => self.depth += 1,
+                ')' => self.depth -= 1,
+                '\'' => {
+                    self.quoted = true;
+                    continue;
+                }
+                c if c.is_whitespace() => continue,
+                _ => {}
+            }
+            self.quoted = false;
+        }
+    }
+
+    /// A stray `)` makes the depth negative; the parser reports it.
+    fn is_complete(&self) -> bool {
+        !self.in_string && self.depth <= 0 && !self.quoted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::default_env;
+
+    fn run(source: &str) -> Vec<FormResult> {
+        eval_reader(source.as_bytes(), default_env()).collect()
+    }
+
+    fn span(start_line: usize, end_line: usize) -> Span {
+        Span { start_line, end_line }
+    }
+
+    #[test]
+    fn test_forms_are_evaluated_in_order_with_spans() {
+        let results = run("(define x 1)\n\n(define (f y)\n  (+ x y))\n(f 2) (f 3)\n");
+        let spans: Vec<_> = results.iter().map(|r| r.span).collect();
+        assert_eq!(spans, vec![span(1, 1), span(3, 4), span(5, 5), span(5, 5)]);
+        assert_eq!(results[2].result.as_ref().unwrap(), &Value::Number(3));
+        assert_eq!(results[3].result.as_ref().unwrap(), &Value::Number(4));
+    }
+
+    #[test]
+    fn test_errors_do_not_stop_later_forms() {
+        let results = run("(car 1)\n)\n\"unterminated\n\n");
+        assert!(matches!(results[0].result, Err(FormError::Eval(EvalError::TypeError(_)))));
+        assert!(matches!(results[1].result, Err(FormError::Parse(ParseError::UnexpectedToken(_)))));
+        assert!(matches!(results[2].result, Err(FormError::Lex(LexError::UnterminatedString))));
+        assert_eq!(results[2].span, span(3, 4));
+
+        let results = run("(+ 1\n2)\n)\n(* 2 3)");
+        assert_eq!(results[0].result.as_ref().unwrap(), &Value::Number(3));
+        assert!(results[1].result.is_err());
+        assert_eq!(results[2].result.as_ref().unwrap(), &Value::Number(6));
+        assert_eq!(results[2].span, span(4, 4));
+    }
+
+    #[test]
+    fn test_strings_comments_and_quotes_do_not_end_forms() {
+        let results = run("(list \"a ) b\" ; )\n #\\) 1)\n'\n(x y)\n");
+        assert_eq!(results.len(), 2);
+        assert_eq!(results[0].span, span(1, 2));
+        assert_eq!(results[0].result.as_ref().unwrap().to_string(), "(\"a ) b\" #\\) 1)");
+        assert_eq!(results[1].span, span(3, 4));
+    }
+
+    #[test]
+    fn test_unbalanced_input_reports_end_of_file() {
+        let results = run("(define x 1)\n(+ x");
+        assert!(results[0].result.is_ok());
+        assert!(matches!(results[1].result, Err(FormError::Parse(ParseError::UnexpectedEOF))));
+        assert_eq!(results[1].span, span(2, 2));
+    }
+}
@@ -0,0 +1,647 @@
+//! A small JSON reader and writer, for the `--server` protocol and for
+//! showing interpreter state in host applications (`Value::to_json`).
+
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::fmt;
+use std::rc::Rc;
+
+use indexmap::IndexMap;
+
+use crate::env::Value;
+use crate::number::{format_float, Number};
+
+/// A JSON value. Objects keep their keys in order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    /// The value of a key of an object, or `None` for anything else.
+    pub fn get(&self, key: &str) -> Option<&Json> {
+        match self {
+            Json::Object(entries) => entries.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Json::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    /// Writes the value over several lines, indented by two spaces per
+    /// level, for people to read. Empty arrays and objects stay on one line.
+    pub fn pretty(&self) -> String {
+        let mut out = String::new();
+        self.write_pretty(&mut out, 0);
+        out
+    }
+
+    fn write_pretty(&self, out: &mut String, indent: usize) {
+        let pad = |out: &mut String, indent: usize| out.extend(std::iter::repeat_n("  ", indent));
+        match self {
+            Json::Array(items) if !items.is_empty() => {
+                out.push_str("[\n");
+                for (i, item) in items.iter().enumerate() {
+                    pad(out, indent + 1);
+                    item.write_pretty(out, indent + 1);
+                    out.push_str(if i + 1 < items.len() { ",\n" } else { "\n" });
+                }
+                pad(out, indent);
+                out.push(']');
+            }
+            Json::Object(entries) if !entries.is_empty() => {
+                out.push_str("{\n");
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    pad(out, indent + 1);
+                    out.push_str(&format!("{}: ", Json::String(key.clone())));
+                    value.write_pretty(out, indent + 1);
+                    out.push_str(if i + 1 < entries.len() { ",\n" } else { "\n" });
+                }
+                pad(out, indent);
+                out.push('}');
+            }
+            other => out.push_str(&other.to_string()),
+        }
+    }
+}
+
+/// How `Value::to_json` and `Value::from_json` map the values JSON has no
+/// type for.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonOptions {
+    /// Writes symbols, keywords, characters, vectors, bytevectors, queues,
+    /// floats, rationals, integers too large for a JSON number, hash tables,
+    /// and values with no JSON form as objects with a single key naming the
+    /// type, such as `{"symbol": "x"}`, so that `from_json` can read them
+    /// back. Without it they become plain strings, arrays, and
+    /// numbers, which is easier to display but loses the type.
+    pub tagged: bool,
+}
+
+/// The largest integer a JSON number holds exactly, as in JavaScript.
+const SAFE_INTEGER: u64 = (1 << 53) - 1;
+
+fn tag(name: &str, value: Json) -> Json {
+    Json::Object(vec![(name.to_string(), value)])
+}
+
+impl Value {
+    /// Converts the value to JSON:
+    ///
+    /// | Scheme | JSON | tagged |
+    /// |---|---|---|
+    /// | integer | number | beyond 2^53, `{"integer": "digits"}` |
+    /// | float | number (non-finite floats are `null`) | `{"float": x}`, or `{"float": "+inf.0"}` |
+    /// | rational | number | `{"rational": [n, d]}` |
+    /// | boolean, string | boolean, string | |
+    /// | symbol | string | `{"symbol": "name"}` |
+    /// | keyword | string `"#:name"` | `{"keyword": "name"}` |
+    /// | character | one-character string | `{"char": "c"}` |
+    /// | list, multiple values | array | |
+    /// | vector | array | `{"vector": [...]}` |
+    /// | bytevector | array | `{"bytevector": [...]}` |
+    /// | queue | array | `{"queue": [...]}` |
+    /// | hash table | object keyed by the keys' `display` form | `{"hash": [[key, value], ...]}` |
+    /// | unspecified, end of file | `null` | |
+    /// | anything else | its printed form, such as `"<builtin-function>"` | `{"object": "..."}` |
+    ///
+    /// Untagged, integers beyond 2^53 lose precision, as in JavaScript.
+    /// Nesting is followed with a stack of its own rather than by recursion,
+    /// so deep structures convert like shallow ones; a structure that
+    /// contains itself has no JSON form and is an error.
+    pub fn to_json(&self, options: &JsonOptions) -> Result<Json, String> {
+        // The containers being converted, innermost last, and the
+        // addresses of the mutable ones among them.
+        let mut stack: Vec<Pending> = Vec::new();
+        let mut open = HashSet::new();
+        let mut value = self.clone();
+        loop {
+            let mut result = match json_node(&value, options) {
+                Node::Leaf(json) => Some(json),
+                Node::Container(pending) => {
+                    if let Some(id) = pending.id
+                        && !open.insert(id)
+                    {
+                        return Err("Cannot convert a structure that contains itself to JSON".into());
+                    }
+                    stack.push(pending);
+                    None
+                }
+            };
+            // Hand the result to the innermost container, finishing those
+            // with no children left, until one has another child to convert.
+            loop {
+                let Some(top) = stack.last_mut() else {
+                    return Ok(result.expect("the value itself has been converted"));
+                };
+                top.done.extend(result.take());
+                if let Some(child) = top.children.next() {
+                    value = child;
+                    break;
+                }
+                let top = stack.pop().expect("the stack has a top");
+                if let Some(id) = top.id {
+                    open.remove(&id);
+                }
+                result = Some((top.finish)(top.done));
+            }
+        }
+    }
+
+    /// Converts JSON to a value, the reverse of `to_json`: numbers that are
+    /// whole and in range become integers and others floats, arrays become
+    /// lists, objects become hash tables with string keys, and `null`
+    /// becomes the unspecified value. With `options.tagged`, the single-key
+    /// objects `to_json` writes are read back as the values they stand for,
+    /// except `{"object": ...}`, which stays a string.
+    pub fn from_json(json: &Json, options: &JsonOptions) -> Value {
+        match json {
+            Json::Null => Value::Unspecified,
+            Json::Bool(b) => Value::Boolean(*b),
+            Json::Number(x) if x.fract() == 0.0 && x.abs() < 9.2e18 => Value::Number(*x as i64),
+            Json::Number(x) => Value::Float(*x),
+            Json::String(s) => Value::String(s.as_str().into()),
+            Json::Array(items) => Value::List(items.iter().map(|item| Value::from_json(item, options)).collect()),
+            Json::Object(entries) => {
+                if options.tagged
+                    && let [(tag, value)] = &entries[..]
+                    && let Some(value) = from_tagged(tag, value, options)
+                {
+                    return value;
+                }
+                let table = entries.iter()
+                    .map(|(key, value)| (Value::String(key.as_str().into()), Value::from_json(value, options)))
+                    .collect::<IndexMap<_, _>>();
+                Value::HashTable(Rc::new(RefCell::new(table)))
+            }
+        }
+    }
+}
+
+/// A value being converted to JSON by `Value::to_json`.
+enum Node {
+    Leaf(Json),
+    Container(Pending),
+}
+
+/// A container whose children are being converted.
+struct Pending {
+    /// The address of a mutable container, which might contain itself.
+    id: Option<*const ()>,
+    children: std::vec::IntoIter<Value>,
+    done: Vec<Json>,
+    /// Builds the container's JSON from its children's.
+    finish: Box<dyn FnOnce(Vec<Json>) -> Json>,
+}
+
+fn container(id: Option<*const ()>, children: Vec<Value>, finish: Box<dyn FnOnce(Vec<Json>) -> Json>) -> Node {
+    Node::Container(Pending { id, children: children.into_iter(), done: Vec::new(), finish })
+}
+
+/// Converts a value that holds no others, or starts converting a container.
+fn json_node(value: &Value, options: &JsonOptions) -> Node {
+    let tagged = options.tagged;
+    let array = move |name: &'static str| -> Box<dyn FnOnce(Vec<Json>) -> Json> {
+        if tagged { Box::new(move |items| tag(name, Json::Array(items))) } else { Box::new(Json::Array) }
+    };
+    Node::Leaf(match value {
+        Value::Number(n) => integer_json(*n, tagged),
+        Value::Float(x) if tagged && x.is_finite() => tag("float", Json::Number(*x)),
+        Value::Float(x) if tagged => tag("float", format_float(*x).into()),
+        Value::Float(x) => Json::Number(*x),
+        Value::Rational(n, d) if tagged => tag("rational", Json::Array(vec![integer_json(*n, true), integer_json(*d, true)])),
+        Value::Rational(n, d) => Json::Number(*n as f64 / *d as f64),
+        Value::Boolean(b) => Json::Bool(*b),
+        Value::String(s) => Json::from(&**s),
+        Value::Symbol(s) if tagged => tag("symbol", Json::from(&**s)),
+        Value::Symbol(s) => Json::from(&**s),
+        Value::Keyword(k) if tagged => tag("keyword", Json::from(&**k)),
+        Value::Keyword(k) => Json::String(format!("#:{}", k)),
+        Value::Char(c) if tagged => tag("char", Json::String(c.to_string())),
+        Value::Char(c) => Json::String(c.to_string()),
+        Value::Bytevector(bytes) => {
+            let items = Json::Array(bytes.borrow().iter().map(|&b| Json::Number(b as f64)).collect());
+            if tagged { tag("bytevector", items) } else { items }
+        }
+        Value::List(items) | Value::Values(items) => return container(None, items.to_vec(), Box::new(Json::Array)),
+        Value::Vector(items) => return container(Some(Rc::as_ptr(items).cast()), items.borrow().clone(), array("vector")),
+        Value::Queue(items) => {
+            return container(Some(Rc::as_ptr(items).cast()), items.borrow().iter().cloned().collect(), array("queue"));
+        }
+        Value::HashTable(table) if tagged => {
+            let children = table.borrow().iter().flat_map(|(k, v)| [k.clone(), v.clone()]).collect();
+            let finish = |items: Vec<Json>| {
+                let mut items = items.into_iter();
+                let mut pairs = Vec::new();
+                while let (Some(key), Some(value)) = (items.next(), items.next()) {
+                    pairs.push(Json::Array(vec![key, value]));
+                }
+                tag("hash", Json::Array(pairs))
+            };
+            return container(Some(Rc::as_ptr(table).cast()), children, Box::new(finish));
+        }
+        Value::HashTable(table) => {
+            let (keys, values): (Vec<String>, Vec<Value>) = table.borrow().iter().map(|(k, v)| (k.display_string(), v.clone())).unzip();
+            let finish = |values: Vec<Json>| Json::Object(keys.into_iter().zip(values).collect());
+            return container(Some(Rc::as_ptr(table).cast()), values, Box::new(finish));
+        }
+        Value::Unspecified | Value::Eof => Json::Null,
+        other if tagged => tag("object", other.write_string().into()),
+        other => other.write_string().into(),
+    })
+}
+
+/// An integer as a JSON number, or tagged as a string of digits if it is
+/// too large for a number to hold exactly.
+fn integer_json(n: i64, tagged: bool) -> Json {
+    if tagged && n.unsigned_abs() > SAFE_INTEGER {
+        tag("integer", n.to_string().into())
+    } else {
+        Json::Number(n as f64)
+    }
+}
+
+/// Reads a single-key object written by `to_json` with `tagged`, or `None`
+/// if `tag` and `value` are not one.
+fn from_tagged(tag: &str, value: &Json, options: &JsonOptions) -> Option<Value> {
+    let items = |json: &Json| match json {
+        Json::Array(items) => Some(items.iter().map(|item| Value::from_json(item, options)).collect::<Vec<_>>()),
+        _ => None,
+    };
+    Some(match (tag, value) {
+        ("symbol", Json::String(s)) => Value::Symbol(s.as_str().into()),
+        ("keyword", Json::String(s)) => Value::Keyword(s.as_str().into()),
+        ("char", Json::String(s)) if s.chars().count() == 1 => Value::Char(s.chars().next()?),
+        ("object", Json::String(s)) => Value::String(s.as_str().into()),
+        ("integer", Json::String(s)) => Value::Number(s.parse().ok()?),
+        ("float", Json::Number(x)) => Value::Float(*x),
+        ("float", Json::String(s)) => match Number::parse(s, 10)? {
+            Number::Real(x) => Value::Float(x),
+            _ => return None,
+        },
+        ("vector", json) => Value::Vector(Rc::new(RefCell::new(items(json)?))),
+        ("queue", json) => Value::Queue(Rc::new(RefCell::new(items(json)?.into()))),
+        ("bytevector", Json::Array(bytes)) => {
+            let byte = |json: &Json| match json {
+                Json::Number(x) if x.fract() == 0.0 && (0.0..256.0).contains(x) => Some(*x as u8),
+                _ => None,
+            };
+            Value::Bytevector(Rc::new(RefCell::new(bytes.iter().map(byte).collect::<Option<_>>()?)))
+        }
+        ("rational", json) => match items(json)?[..] {
+            [Value::Number(n), Value::Number(d)] => Number::rational(n, d).ok()?.into(),
+            _ => return None,
+        },
+        ("hash", json) => {
+            let mut table = IndexMap::new();
+            for pair in items(json)? {
+                let Value::List(ref pair) = pair else { return None };
+                let [key, value] = <[Value; 2]>::try_from(pair.to_vec()).ok()?;
+                table.insert(key, value);
+            }
+            Value::HashTable(Rc::new(RefCell::new(table)))
+        }
+        _ => return None,
+    })
+}
+
+impl From<&str> for Json {
+    fn from(s: &str) -> Json {
+        Json::String(s.to_string())
+    }
+}
+
+impl From<String> for Json {
+    fn from(s: String) -> Json {
+        Json::String(s)
+    }
+}
+
+/// Writes the value compactly, on one line.
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Json::Null => write!(f, "null"),
+            Json::Bool(b) => write!(f, "{}", b),
+            // Integers print without a fraction; JSON has no infinities or NaN.
+            Json::Number(x) if x.fract() == 0.0 && x.abs() < 1e15 => write!(f, "{}", *x as i64),
+            Json::Number(x) if x.is_finite() => write!(f, "{}", x),
+            Json::Number(_) => write!(f, "null"),
+            Json::String(s) => write_string(f, s),
+            Json::Array(items) => {
+                write!(f, "[")?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                write!(f, "]")
+            }
+            Json::Object(entries) => {
+                write!(f, "{{")?;
+                for (i, (key, value)) in entries.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ",")?;
+                    }
+                    write_string(f, key)?;
+                    write!(f, ":{}", value)?;
+                }
+                write!(f, "}}")
+            }
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    write!(f, "\"")?;
+    for c in s.chars() {
+        match c {
+            '"' => write!(f, "\\\"")?,
+            '\\' => write!(f, "\\\\")?,
+            '\n' => write!(f, "\\n")?,
+            '\r' => write!(f, "\\r")?,
+            '\t' => write!(f, "\\t")?,
+            c if c < ' ' => write!(f, "\\u{:04x}", c as u32)?,
+            c => write!(f, "{}", c)?,
+        }
+    }
+    write!(f, "\"")
+}
+
+/// Reads a JSON value, which must make up the whole of `text` apart from
+/// surrounding whitespace.
+pub fn parse(text: &str) -> Result<Json, String> {
+    let mut parser = Parser { text, position: 0 };
+    let value = parser.value()?;
+    parser.skip_whitespace();
+    if parser.position < text.len() {
+        return Err(format!("Unexpected text at byte {}", parser.position));
+    }
+    Ok(value)
+}
+
+struct Parser<'a> {
+    text: &'a str,
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        let rest = &self.text[self.position..];
+        self.position += rest.len() - rest.trim_start_matches([' ', '\t', '\n', '\r']).len();
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.text.as_bytes().get(self.position).copied()
+    }
+
+    fn expect(&mut self, c: u8) -> Result<(), String> {
+        self.skip_whitespace();
+        if self.peek() == Some(c) {
+            self.position += 1;
+            Ok(())
+        } else {
+            Err(format!("Expected '{}' at byte {}", c as char, self.position))
+        }
+    }
+
+    fn literal(&mut self, word: &str, value: Json) -> Result<Json, String> {
+        if self.text[self.position..].starts_with(word) {
+            self.position += word.len();
+            Ok(value)
+        } else {
+            Err(format!("Unexpected text at byte {}", self.position))
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, String> {
+        self.skip_whitespace();
+        match self.peek() {
+            Some(b'{') => self.object(),
+            Some(b'[') => self.array(),
+            Some(b'"') => self.string().map(Json::String),
+            Some(b't') => self.literal("true", Json::Bool(true)),
+            Some(b'f') => self.literal("false", Json::Bool(false)),
+            Some(b'n') => self.literal("null", Json::Null),
+            Some(b'-' | b'0'..=b'9') => self.number(),
+            Some(_) => Err(format!("Unexpected text at byte {}", self.position)),
+            None => Err("Unexpected end of input".into()),
+        }
+    }
+
+    fn object(&mut self) -> Result<Json, String> {
+        self.expect(b'{')?;
+        let mut entries = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b'}') {
+            self.position += 1;
+            return Ok(Json::Object(entries));
+        }
+        loop {
+            self.skip_whitespace();
+            if self.peek() != Some(b'"') {
+                return Err(format!("Expected a key at byte {}", self.position));
+            }
+            let key = self.string()?;
+            self.expect(b':')?;
+            entries.push((key, self.value()?));
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b'}') => {
+                    self.position += 1;
+                    return Ok(Json::Object(entries));
+                }
+                _ => return Err(format!("Expected ',' or '}}' at byte {}", self.position)),
+            }
+        }
+    }
+
+    fn array(&mut self) -> Result<Json, String> {
+        self.expect(b'[')?;
+        let mut items = Vec::new();
+        self.skip_whitespace();
+        if self.peek() == Some(b']') {
+            self.position += 1;
+            return Ok(Json::Array(items));
+        }
+        loop {
+            items.push(self.value()?);
+            self.skip_whitespace();
+            match self.peek() {
+                Some(b',') => self.position += 1,
+                Some(b']') => {
+                    self.position += 1;
+                    return Ok(Json::Array(items));
+                }
+                _ => return Err(format!("Expected ',' or ']' at byte {}", self.position)),
+            }
+        }
+    }
+
+    fn number(&mut self) -> Result<Json, String> {
+        let start = self.position;
+        let rest = &self.text[start..];
+        let len = rest.find(|c: char| !matches!(c, '0'..='9' | '-' | '+' | '.' | 'e' | 'E')).unwrap_or(rest.len());
+        self.position += len;
+        rest[..len].parse().map(Json::Number).map_err(|_| format!("Bad number at byte {}", start))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        self.position += 1; // the opening quote
+        let mut out = String::new();
+        let mut chars = self.text[self.position..].char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '"' => {
+                    self.position += i + 1;
+                    return Ok(out);
+                }
+                '\\' => match chars.next().map(|(_, c)| c) {
+                    Some('"') => out.push('"'),
+                    Some('\\') => out.push('\\'),
+                    Some('/') => out.push('/'),
+                    Some('b') => out.push('\u{8}'),
+                    Some('f') => out.push('\u{c}'),
+                    Some('n') => out.push('\n'),
+                    Some('r') => out.push('\r'),
+                    Some('t') => out.push('\t'),
+                    Some('u') => {
+                        let high = hex_unit(&mut chars).ok_or("Bad \\u escape")?;
+                        let code = if (0xD800..0xDC00).contains(&high) {
+                            // A surrogate pair: the low half must follow as another escape.
+                            let low = match (chars.next(), chars.next()) {
+                                (Some((_, '\\')), Some((_, 'u'))) => hex_unit(&mut chars),
+                                _ => None,
+                            };
+                            match low {
+                                Some(low) if (0xDC00..0xE000).contains(&low) => 0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00),
+                                _ => return Err("Unpaired surrogate in \\u escape".into()),
+                            }
+                        } else {
+                            high
+                        };
+                        out.push(char::from_u32(code).ok_or("Bad \\u escape")?);
+                    }
+                    _ => return Err(format!("Bad escape in string at byte {}", self.position + i)),
+                },
+                c => out.push(c),
+            }
+        }
+        Err("Unterminated string".into())
+    }
+}
+
+/// The four hex digits of a `\u` escape.
+fn hex_unit(chars: &mut impl Iterator<Item = (usize, char)>) -> Option<u32> {
+    let hex: String = (0..4).map(|_| chars.next().map(|(_, c)| c)).collect::<Option<_>>()?;
+    u32::from_str_radix(&hex, 16).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let text = r#"{"id":7,"op":"evaluate","code":"(display \"hi\\n\")","tags":[true,null,-1.5,2e3],"empty":{}}"#;
+        let value = parse(text).unwrap();
+        assert_eq!(value.get("id"), Some(&Json::Number(7.0)));
+        assert_eq!(value.get("code").and_then(Json::as_str), Some("(display \"hi\\n\")"));
+        assert_eq!(value.to_string(), text.replace("2e3", "2000"));
+        assert_eq!(parse(r#" "\u00e9\ud83e\udd80\t" "#).unwrap(), Json::String("é🦀\t".into()));
+    }
+
+    #[test]
+    fn test_value_json_mappings() {
+        let value = crate::eval::eval(
+            &crate::parser::parse(crate::lexer::tokenize(
+                "(list 1 (/ 3 4) \"s\" 'sym #:k #\\c (vector #t) (let ((h (make-hash-table))) (hash-table-set! h 'b 2) (hash-table-set! h \"a\" 1) h) car)"
+            ).unwrap()).unwrap(),
+            crate::env::default_env(),
+        ).unwrap();
+        let plain = value.to_json(&JsonOptions::default()).unwrap();
+        assert_eq!(plain.to_string(), r##"[1,0.75,"s","sym","#:k","c",[true],{"b":2,"a":1},"<builtin-function>"]"##);
+        let tagged = value.to_json(&JsonOptions { tagged: true }).unwrap();
+        assert_eq!(tagged.to_string(), concat!(
+            r#"[1,{"rational":[3,4]},"s",{"symbol":"sym"},{"keyword":"k"},{"char":"c"},{"vector":[true]},"#,
+            r##"{"hash":[[{"symbol":"b"},2],["a",1]]},{"object":"<builtin-function>"}]"##,
+        ));
+        let back = Value::from_json(&tagged, &JsonOptions { tagged: true });
+        // Everything reads back but the procedure, which stays its printed form.
+        assert_eq!(back.to_json(&JsonOptions { tagged: true }).unwrap().to_string(),
+            tagged.to_string().replace(r#"{"object":"<builtin-function>"}"#, r#""<builtin-function>""#));
+        assert_eq!(Value::from_json(&parse("[1.5, null]").unwrap(), &JsonOptions::default()),
+            Value::List(Rc::new([Value::Float(1.5), Value::Unspecified])));
+        assert_eq!(parse("{\"a\": [1, {}], \"b\": []}").unwrap().pretty(), "{\n  \"a\": [\n    1,\n    {}\n  ],\n  \"b\": []\n}");
+    }
+
+    #[test]
+    fn test_tagged_json_reads_back_the_same_values() {
+        let tagged = JsonOptions { tagged: true };
+        let queue = Value::Queue(Rc::new(RefCell::new([Value::Number(1), Value::Float(-0.5)].into())));
+        let values = [
+            Value::Float(2.0),
+            Value::Float(f64::NEG_INFINITY),
+            Value::Float(f64::NAN),
+            Value::Number(i64::MAX),
+            Value::Number(-(1 << 53)),
+            Value::Rational(i64::MIN + 1, 3),
+            Value::Bytevector(Rc::new(RefCell::new(vec![0, 255]))),
+        ];
+        for value in values {
+            let json = parse(&value.to_json(&tagged).unwrap().to_string()).unwrap();
+            assert_eq!(Value::from_json(&json, &tagged), value, "{}", json);
+        }
+        let json = queue.to_json(&tagged).unwrap();
+        assert_eq!(json.to_string(), r#"{"queue":[1,{"float":-0.5}]}"#);
+        assert!(matches!(Value::from_json(&json, &tagged), Value::Queue(ref items) if items.borrow().len() == 2));
+        assert_eq!(Value::Float(2.0).to_json(&JsonOptions::default()).unwrap().to_string(), "2");
+    }
+
+    #[test]
+    fn test_deep_and_cyclic_structures() {
+        let mut deep = Value::Number(0);
+        for _ in 0..10_000 {
+            deep = Value::List(Rc::new([deep]));
+        }
+        let json = deep.to_json(&JsonOptions::default()).unwrap();
+        let mut depth = 0;
+        let mut json = &json;
+        while let Json::Array(items) = json {
+            depth += 1;
+            json = &items[0];
+        }
+        assert_eq!(depth, 10_000);
+
+        let vector = Value::Vector(Rc::new(RefCell::new(vec![Value::Number(1)])));
+        let shared = Value::List(Rc::new([vector.clone(), vector.clone()]));
+        assert_eq!(shared.to_json(&JsonOptions::default()).unwrap().to_string(), "[[1],[1]]");
+        if let Value::Vector(items) = &vector {
+            items.borrow_mut().push(shared.clone());
+        }
+        assert!(vector.to_json(&JsonOptions::default()).is_err());
+        // Break the cycle so that the vector is freed.
+        if let Value::Vector(items) = &vector {
+            items.borrow_mut().clear();
+        }
+    }
+
+    #[test]
+    fn test_errors() {
+        for text in ["", "{", "[1,]", "{\"a\" 1}", "\"abc", "tru", "1 2", "\"\\ud800\""] {
+            assert!(parse(text).is_err(), "{}", text);
+        }
+    }
+}
@@ -0,0 +1,241 @@
+//! Running programs for an autograder: `judge` evaluates a submission
+//! under limits on fuel, time, and memory, capturing what it prints and its
+//! final value, and reports the outcome as JSON. The CLI runs it as
+//! `scheme_rs judge FILE`.
+
+use std::sync::atomic::{AtomicBool, AtomicU8, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::env::{default_env, EvalError};
+use crate::eval::{eval, set_fuel, set_interrupt_flag, set_memory_limit, MemoryLimit};
+use crate::json::Json;
+use crate::lexer::tokenize;
+use crate::parser::parse_program;
+use crate::port::capture_stdout;
+use crate::printer::{print_value, PrintOptions};
+
+/// The resources a program may use; `None` is unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// Expressions it may evaluate (see `eval::set_fuel`).
+    pub fuel: Option<u64>,
+    /// How long it may run.
+    pub time: Option<Duration>,
+    /// Bytes of memory it may allocate beyond what was in use when it
+    /// started, as measured by the probe given to `judge`.
+    pub memory: Option<usize>,
+}
+
+/// A limit a program ran into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Limit {
+    Fuel,
+    Time,
+    Memory,
+}
+
+impl Limit {
+    pub fn name(self) -> &'static str {
+        match self {
+            Limit::Fuel => "fuel",
+            Limit::Time => "time",
+            Limit::Memory => "memory",
+        }
+    }
+}
+
+/// How a judged program ended.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    /// It finished, with the printed value of its last form.
+    Ok(String),
+    /// It could not be read, or failed while running.
+    Error(String),
+    /// It was stopped for exceeding a limit.
+    LimitExceeded(Limit),
+}
+
+/// The result of `judge`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Report {
+    pub outcome: Outcome,
+    /// What the program wrote to standard output, up to where it ended.
+    pub output: String,
+    /// How long it ran.
+    pub elapsed: Duration,
+}
+
+impl Report {
+    /// The report as a JSON object with a `status` of `"ok"`, `"error"`,
+    /// or `"limit"`, the `limit` hit, the printed `value`, the `error`
+    /// message, the captured `output`, and `elapsed_ms`. Fields that do not
+    /// apply are `null`.
+    pub fn to_json(&self) -> Json {
+        let (status, limit, value, error) = match &self.outcome {
+            Outcome::Ok(value) => ("ok", Json::Null, value.clone().into(), Json::Null),
+            Outcome::Error(message) => ("error", Json::Null, Json::Null, message.clone().into()),
+            Outcome::LimitExceeded(limit) => ("limit", limit.name().into(), Json::Null, Json::Null),
+        };
+        Json::Object(vec![
+            ("status".into(), status.into()),
+            ("limit".into(), limit),
+            ("value".into(), value),
+            ("error".into(), error),
+            ("output".into(), self.output.clone().into()),
+            ("elapsed_ms".into(), Json::Number(self.elapsed.as_secs_f64() * 1000.0)),
+        ])
+    }
+}
+
+/// Runs the program in `source` in a fresh global environment under
+/// `limits`. The time and memory limits are enforced by a watchdog thread
+/// that interrupts evaluation (see `eval::set_interrupt_flag`), checking
+/// every millisecond, and built-ins that allocate in bulk also check the
+/// memory limit before allocating (see `eval::set_memory_limit`).
+/// `memory_in_use` reports the bytes allocated in the process, typically
+/// from a counting global allocator, and without it the memory limit is not
+/// enforced.
+pub fn judge(source: &str, limits: &Limits, memory_in_use: Option<fn() -> usize>) -> Report {
+    let start = Instant::now();
+    let program = match tokenize(source) {
+        Ok(tokens) => parse_program(tokens).map_err(|e| format!("Parse error: {:?}", e)),
+        Err(e) => Err(format!("Lex error: {:?}", e)),
+    };
+    let program = match program {
+        Ok(program) => program,
+        Err(message) => return Report { outcome: Outcome::Error(message), output: String::new(), elapsed: start.elapsed() },
+    };
+
+    let memory = limits.memory.zip(memory_in_use).map(|(limit, probe)| (probe() + limit, probe));
+    let interrupt = Arc::new(AtomicBool::new(false));
+    let done = Arc::new(AtomicBool::new(false));
+    // The limit the watchdog stopped the program for, as 1 + its index.
+    let stopped_for = Arc::new(AtomicU8::new(0));
+    let watchdog = (limits.time.is_some() || limits.memory.is_some()).then(|| {
+        let (interrupt, done, stopped_for) = (interrupt.clone(), done.clone(), stopped_for.clone());
+        let deadline = limits.time.map(|time| start + time);
+        thread::spawn(move || {
+            while !done.load(Ordering::Relaxed) {
+                let hit = if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                    Some(Limit::Time)
+                } else if memory.is_some_and(|(ceiling, probe)| probe() > ceiling) {
+                    Some(Limit::Memory)
+                } else {
+                    None
+                };
+                if let Some(limit) = hit {
+                    stopped_for.store(limit as u8 + 1, Ordering::Relaxed);
+                    interrupt.store(true, Ordering::Relaxed);
+                    break;
+                }
+                thread::sleep(Duration::from_millis(1));
+            }
+        })
+    });
+
+    set_fuel(limits.fuel);
+    set_memory_limit(memory.map(|(ceiling, in_use)| MemoryLimit { ceiling, in_use }));
+    set_interrupt_flag(Some(interrupt));
+    let env = default_env();
+    let (result, output) = capture_stdout(|| {
+        let mut last = Ok(crate::env::Value::Unspecified);
+        for form in &program {
+            last = eval(form, env.clone());
+            if last.is_err() {
+                break;
+            }
+        }
+        last
+    });
+    set_interrupt_flag(None);
+    set_memory_limit(None);
+    set_fuel(None);
+    done.store(true, Ordering::Relaxed);
+    if let Some(watchdog) = watchdog {
+        watchdog.join().expect("the watchdog does not panic");
+    }
+
+    let outcome = match result {
+        Ok(value) => Outcome::Ok(print_value(&value, &PrintOptions::from_env(&env))),
+        Err(EvalError::OutOfFuel) => Outcome::LimitExceeded(Limit::Fuel),
+        Err(EvalError::OutOfMemory) => Outcome::LimitExceeded(Limit::Memory),
+        Err(EvalError::Interrupted) => match stopped_for.load(Ordering::Relaxed) {
+            0 => Outcome::Error("Interrupted".into()),
+            n => Outcome::LimitExceeded([Limit::Fuel, Limit::Time, Limit::Memory][n as usize - 1]),
+        },
+        Err(e) => Outcome::Error(format!("Eval error: {:?}", e)),
+    };
+    Report { outcome, output, elapsed: start.elapsed() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_judge_reports_value_and_output() {
+        let report = judge("(define (f x) (* x 2)) (display \"hi\") (f 21)", &Limits::default(), None);
+        assert_eq!(report.outcome, Outcome::Ok("42".into()));
+        assert_eq!(report.output, "hi");
+        let json = report.to_json();
+        assert_eq!(json.get("status"), Some(&Json::from("ok")));
+        assert_eq!(json.get("limit"), Some(&Json::Null));
+
+        let report = judge("(display 1) (car 1) (display 2)", &Limits::default(), None);
+        assert!(matches!(report.outcome, Outcome::Error(ref m) if m.starts_with("Eval error")));
+        assert_eq!(report.output, "1");
+        assert_eq!(judge("(car", &Limits::default(), None).to_json().get("error"), Some(&Json::from("Parse error: UnexpectedEOF")));
+    }
+
+    #[test]
+    fn test_judge_enforces_limits() {
+        // A billion calls, without deep recursion.
+        let slow = "(define v (make-vector 1000 0)) \
+            (vector-for-each (lambda (x) (vector-for-each (lambda (y) (vector-for-each (lambda (z) z) v)) v)) v)";
+        let fuel = Limits { fuel: Some(1000), ..Limits::default() };
+        assert_eq!(judge(slow, &fuel, None).outcome, Outcome::LimitExceeded(Limit::Fuel));
+        let time = Limits { time: Some(Duration::from_millis(50)), ..Limits::default() };
+        let report = judge(slow, &time, None);
+        assert_eq!(report.outcome, Outcome::LimitExceeded(Limit::Time));
+        assert_eq!(report.to_json().get("limit"), Some(&Json::from("time")));
+
+        // A probe that reports ever more memory in use.
+        fn growing() -> usize {
+            static CALLS: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+            CALLS.fetch_add(1, Ordering::Relaxed) * 1_000_000
+        }
+        let memory = Limits { memory: Some(5_000_000), ..Limits::default() };
+        assert_eq!(judge(slow, &memory, Some(growing)).outcome, Outcome::LimitExceeded(Limit::Memory));
+        // After a program is stopped, the next runs normally.
+        assert_eq!(judge("(+ 1 2)", &time, None).outcome, Outcome::Ok("3".into()));
+    }
+
+    #[test]
+    fn test_judge_checks_memory_before_large_allocations() {
+        fn idle() -> usize {
+            1_000_000
+        }
+        let memory = Limits { memory: Some(10_000_000), ..Limits::default() };
+        for source in ["(make-vector 100000000000 0)", "(make-bytevector 100000000000)"] {
+            let report = judge(source, &memory, Some(idle));
+            assert_eq!(report.outcome, Outcome::LimitExceeded(Limit::Memory));
+            assert_eq!(report.to_json().get("limit"), Some(&Json::from("memory")));
+        }
+        assert_eq!(judge("(vector-length (make-vector 1000 0))", &memory, Some(idle)).outcome, Outcome::Ok("1000".into()));
+        // Without a limit, an allocation that cannot succeed is reported
+        // rather than aborting the process.
+        let report = judge("(make-vector 1000000000000000000)", &Limits::default(), None);
+        assert_eq!(report.outcome, Outcome::LimitExceeded(Limit::Memory));
+    }
+
+    #[cfg(feature = "io")]
+    #[test]
+    fn test_judge_time_limit_stops_sleep() {
+        let time = Limits { time: Some(Duration::from_millis(300)), ..Limits::default() };
+        let report = judge("(sleep 5)", &time, None);
+        assert_eq!(report.outcome, Outcome::LimitExceeded(Limit::Time));
+        assert!(report.elapsed < Duration::from_secs(2));
+    }
+}
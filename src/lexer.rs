@@ -1,14 +1,92 @@
+use alloc::format;
+use alloc::rc::Rc;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::cell::Cell;
+use core::fmt;
+
+use crate::ast::Expr;
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
     LParen,
     RParen,
+    Quote,
+    HashTableOpen,   // `#hash(`
+    VectorOpen,      // `#(`
+    BytevectorOpen,  // `#u8(`
     Number(i64),
+    Rational(i64, i64), // `3/4`, `-1/2`, with a positive denominator, not reduced
+    Float(f64), // `3.14`, `.5`, `-2.0`, `6.022e23`, `+inf.0`, `+nan.0`
     Symbol(String),
     String(String),
     Boolean(bool),
+    Keyword(String), // `#:name`, without the `#:`
+    Char(char),      // `#\a`, `#\space`, `#\x41`
+    Datum(Expr),     // read by a reader extension, see `ReaderExtensions`
+}
+
+/// Characters written by name, as in `#\space`.
+const CHAR_NAMES: &[(&str, char)] = &[
+    ("space", ' '),
+    ("newline", '\n'),
+    ("tab", '\t'),
+    ("return", '\r'),
+    ("null", '\0'),
+    ("alarm", '\x07'),
+    ("backspace", '\x08'),
+    ("delete", '\x7f'),
+    ("escape", '\x1b'),
+];
+
+/// Writes a character the way the lexer reads it: `#\a`, `#\space`.
+pub fn char_literal(c: char) -> String {
+    match CHAR_NAMES.iter().find(|(_, named)| *named == c) {
+        Some((name, _)) => format!("#\\{}", name),
+        None => format!("#\\{}", c),
+    }
+}
+
+/// Writes a string the way the lexer reads it: quoted, with the characters
+/// it treats specially escaped.
+pub fn escape_string(s: &str) -> String {
+    escape(s, '"')
 }
 
-#[derive(Debug, PartialEq)]
+/// Writes a symbol the way the lexer reads it: as it is if its name reads
+/// back as the same symbol, and otherwise between bars, escaped like a
+/// string, as in `|a b|` and `|12|`.
+pub fn symbol_literal(name: &str) -> String {
+    match tokenize(name).as_deref() {
+        Ok([Token::Symbol(read)]) if read == name => name.into(),
+        _ => escape(name, '|'),
+    }
+}
+
+/// Writes `s` between `quote`s, escaping the quote and the characters the
+/// lexer treats specially.
+fn escape(s: &str, quote: char) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push(quote);
+    for c in s.chars() {
+        match c {
+            c if c == quote => {
+                out.push('\\');
+                out.push(c);
+            }
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            c if c.is_control() => out.push_str(&format!("\\x{:x};", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push(quote);
+    out
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum LexError {
     UnterminatedString,
     TestError,
@@ -21,23 +99,90 @@ pub enum LexError {
 /// numbers, booleans, string literals, and skips comments and whitespace.
 /// Returns a `LexError` if any invalid token is encountered.
 pub fn tokenize(input: &str) -> Result<Vec<Token>, LexError> {
+    Ok(tokenize_with_lines(input)?.into_iter().map(|(token, _)| token).collect())
+}
+
+/// Settings that change how source text is read.
+#[derive(Debug, Clone, Default)]
+pub struct LexOptions {
+    /// Reads symbols and keywords in lower case, so `Define` and `DEFINE`
+    /// mean `define`, for legacy code that assumes case-insensitivity.
+    /// String literals are left as written.
+    pub fold_case: bool,
+    /// Literal syntax added by the embedding application.
+    pub extensions: ReaderExtensions,
+}
+
+/// Reads the text of a `#name"text"` literal into the expression it stands
+/// for, or explains why it cannot.
+pub type ReaderExtension = Rc<dyn Fn(&str) -> Result<Expr, String>>;
+
+/// Literal syntax an application adds to the reader: `#name"text"`, where
+/// the handler registered for `name` turns the string into an expression.
+/// The expression is used as if it had been written in place of the
+/// literal, so it can be a datum or code that builds a value:
+///
+/// ```ignore
+/// let mut options = LexOptions::default();
+/// options.extensions.register("date", Rc::new(|text: &str| Ok(Expr::List(vec![
+///     Expr::Symbol("string->date".into()), Expr::String(text.into()), Expr::String("~Y-~m-~d".into()),
+/// ]))));
+/// let tokens = tokenize_with_options("#date\"2024-01-01\"", &options)?;
+/// ```
+#[derive(Clone, Default)]
+pub struct ReaderExtensions {
+    handlers: Vec<(String, ReaderExtension)>,
+}
+
+impl ReaderExtensions {
+    /// Makes `#name"text"` read as `handler` says, replacing any handler
+    /// registered for `name` before. Names are letters, digits, `-`, and
+    /// `_`, starting with a letter; `t`, `f`, and `hash` are taken.
+    pub fn register(&mut self, name: &str, handler: ReaderExtension) -> Result<(), LexError> {
+        let valid = name.starts_with(|c: char| c.is_alphabetic()) && name.chars().all(is_extension_char);
+        if !valid || matches!(name, "t" | "f" | "hash") {
+            return Err(LexError::InvalidToken(format!("#{}", name)));
+        }
+        self.handlers.retain(|(n, _)| n != name);
+        self.handlers.push((name.into(), handler));
+        Ok(())
+    }
+
+    fn get(&self, name: &str) -> Option<&ReaderExtension> {
+        self.handlers.iter().find(|(n, _)| n == name).map(|(_, handler)| handler)
+    }
+}
+
+impl fmt::Debug for ReaderExtensions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_list().entries(self.handlers.iter().map(|(name, _)| name)).finish()
+    }
+}
+
+fn is_extension_char(c: char) -> bool {
+    c.is_alphanumeric() || c == '-' || c == '_'
+}
+
+/// Like `tokenize`, but pairs each token with the line (counting from 1)
+/// that it starts on.
+pub fn tokenize_with_lines(input: &str) -> Result<Vec<(Token, usize)>, LexError> {
+    tokenize_with_options(input, &LexOptions::default())
+}
+
+/// Like `tokenize_with_lines`, reading the input as `options` describes.
+pub fn tokenize_with_options(input: &str, options: &LexOptions) -> Result<Vec<(Token, usize)>, LexError> {
     let mut tokens = Vec::new();
-    let mut chars = input.chars().peekable();
+    let newlines = Cell::new(0);
+    let mut chars = input.chars()
+        .inspect(|&c| if c == '\n' { newlines.set(newlines.get() + 1) })
+        .peekable();
 
     while let Some(&ch) = chars.peek() {
-        let token_result = match ch {
-            '(' => parse_lparen(&mut chars),
-            ')' => parse_rparen(&mut chars),
-            ';' => skip_comment(&mut chars),
-            ch if ch.is_whitespace() => skip_whitespace(&mut chars),
-            '"' => parse_string_literal(&mut chars),
-            '#' => parse_boolean(&mut chars),
-            ch if ch.is_ascii_digit() => parse_number(&mut chars),
-            _ => parse_symbol(&mut chars),
-        };
-
-        match token_result {
-            Some(Ok(token)) => tokens.push(token),
+        // Tokens never start with a newline, so the peeked character is on this line.
+        let line = newlines.get() + 1;
+        match next_token(ch, &mut chars, &options.extensions) {
+            Some(Ok(token)) if options.fold_case => tokens.push((fold_case(token), line)),
+            Some(Ok(token)) => tokens.push((token, line)),
             Some(Err(e)) => return Err(e),
             None => {} // intentionally skipped (whitespace or comment)
         }
@@ -46,6 +191,74 @@ pub fn tokenize(input: &str) -> Result<Vec<Token>, LexError> {
     Ok(tokens)
 }
 
+/// A byte range of source text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+}
+
+/// What `tokenize_with_spans` found in a span of the source.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Lexeme {
+    Token(Token),
+    Comment,
+    /// Text that could not be read; reading carries on after it.
+    Error(LexError),
+}
+
+/// Like `tokenize`, but pairs each token with the span of the input it was
+/// read from, includes comments, and instead of stopping at the first error
+/// reports it and carries on. For tools such as syntax highlighters, which
+/// must cope with code that is being edited.
+pub fn tokenize_with_spans(input: &str) -> Vec<(Lexeme, Span)> {
+    let mut lexemes = Vec::new();
+    let consumed = Cell::new(0);
+    let mut chars = input.chars()
+        .inspect(|c| consumed.set(consumed.get() + c.len_utf8()))
+        .peekable();
+
+    while let Some(&ch) = chars.peek() {
+        // Peeking reads one character ahead, so it is counted already.
+        let start = consumed.get() - ch.len_utf8();
+        let result = next_token(ch, &mut chars, &ReaderExtensions::default());
+        let next_len = chars.peek().map_or(0, |c| c.len_utf8());
+        let mut end = consumed.get() - next_len;
+        let lexeme = match result {
+            Some(Ok(token)) => Lexeme::Token(token),
+            Some(Err(e)) => Lexeme::Error(e),
+            None if ch == ';' => {
+                // The newline ending a comment is not part of it.
+                end -= input[..end].ends_with('\n') as usize;
+                Lexeme::Comment
+            }
+            None => continue,
+        };
+        lexemes.push((lexeme, Span { start, end }));
+    }
+    lexemes
+}
+
+/// Reads the token starting with `ch`, the next character. Gives `None` for
+/// whitespace and comments, which are skipped.
+fn next_token<I>(ch: char, chars: &mut core::iter::Peekable<I>, extensions: &ReaderExtensions) -> Option<Result<Token, LexError>>
+where
+    I: Iterator<Item = char>,
+{
+    match ch {
+        '(' => parse_lparen(chars),
+        ')' => parse_rparen(chars),
+        '\'' => parse_quote(chars),
+        ';' => skip_comment(chars),
+        ch if ch.is_whitespace() => skip_whitespace(chars),
+        '"' => parse_string_literal(chars),
+        '|' => parse_bar_symbol(chars),
+        '#' => parse_boolean(chars, extensions),
+        ch if ch.is_ascii_digit() => parse_number(chars),
+        _ => parse_symbol(chars),
+    }
+}
+
 
 
 
@@ -65,6 +278,14 @@ where
     Some(Ok(Token::RParen))
 }
 
+fn parse_quote<I>(chars: &mut I) -> Option<Result<Token, LexError>>
+where
+    I: Iterator<Item = char>,
+{
+    chars.next();
+    Some(Ok(Token::Quote))
+}
+
 fn skip_whitespace<I>(chars: &mut I) -> Option<Result<Token, LexError>>
 where
     I: Iterator<Item = char>,
@@ -77,7 +298,7 @@ fn skip_comment<I>(chars: &mut I) -> Option<Result<Token, LexError>>
 where
     I: Iterator<Item = char>,
 {
-    while let Some(c) = chars.next() {
+    for c in chars.by_ref() {
         if c == '\n' {
             break;
         }
@@ -86,37 +307,82 @@ where
 }
 
 fn parse_string_literal<I>(chars: &mut I) -> Option<Result<Token, LexError>>
+where
+    I: Iterator<Item = char>,
+{
+    Some(read_delimited(chars).map(Token::String))
+}
+
+/// Reads a symbol written between bars, as in `|a b|`, with the escapes of
+/// a string literal.
+fn parse_bar_symbol<I>(chars: &mut I) -> Option<Result<Token, LexError>>
+where
+    I: Iterator<Item = char>,
+{
+    Some(read_delimited(chars).map(Token::Symbol))
+}
+
+/// Reads text between a pair of the next character, which is `"` or `|`,
+/// replacing escapes such as `\n` with the characters they stand for.
+fn read_delimited<I>(chars: &mut I) -> Result<String, LexError>
 where
     I: Iterator<Item = char>,
 {
     let mut string = String::new();
-    chars.next(); // consume first quote
+    let quote = chars.next(); // consume first quote
     while let Some(c) = chars.next() {
-        if c == '"' {
-            return Some(Ok(Token::String(string)));
+        if Some(c) == quote {
+            return Ok(string);
         } else if c == '\\' {
             match chars.next() {
                 Some('n') => string.push('\n'),
                 Some('t') => string.push('\t'),
+                Some('r') => string.push('\r'),
+                Some('a') => string.push('\x07'),
+                Some('b') => string.push('\x08'),
                 Some('"') => string.push('"'),
                 Some('\\') => string.push('\\'),
-                Some(escaped) => return Some(Err(LexError::InvalidToken(format!("\\{}", escaped)))),
-                None => return Some(Err(LexError::UnterminatedString)),
+                Some('|') => string.push('|'),
+                Some('x') => string.push(parse_hex_escape(chars)?),
+                Some(escaped) => return Err(LexError::InvalidToken(format!("\\{}", escaped))),
+                None => return Err(LexError::UnterminatedString),
             }
         } else {
             string.push(c);
         }
     }
-    Some(Err(LexError::UnterminatedString))
+    Err(LexError::UnterminatedString)
 }
 
-fn parse_number<I>(chars: &mut std::iter::Peekable<I>) -> Option<Result<Token, LexError>>
+/// Reads the rest of a `\xHHHH;` string escape after the `x`: hexadecimal
+/// digits naming a Unicode scalar value, ended by a semicolon.
+fn parse_hex_escape<I>(chars: &mut I) -> Result<char, LexError>
+where
+    I: Iterator<Item = char>,
+{
+    let mut digits = String::new();
+    for c in chars.by_ref() {
+        if c == ';' {
+            return u32::from_str_radix(&digits, 16).ok()
+                .and_then(char::from_u32)
+                .ok_or_else(|| LexError::InvalidToken(format!("\\x{};", digits)));
+        }
+        if !c.is_ascii_hexdigit() {
+            return Err(LexError::InvalidToken(format!("\\x{}{}", digits, c)));
+        }
+        digits.push(c);
+    }
+    Err(LexError::UnterminatedString)
+}
+
+fn parse_number<I>(chars: &mut core::iter::Peekable<I>) -> Option<Result<Token, LexError>>
 where
     I: Iterator<Item = char>,
 {
     let mut num_str = String::new();
     while let Some(&next) = chars.peek() {
-        if next.is_ascii_digit() {
+        let exponent_sign = (next == '-' || next == '+') && num_str.ends_with(['e', 'E']);
+        if next.is_ascii_digit() || matches!(next, '.' | 'e' | 'E' | '/') || exponent_sign {
             num_str.push(next);
             chars.next();
         } else {
@@ -124,37 +390,217 @@ where
         }
     }
 
-    match num_str.parse::<i64>() {
-        Ok(n) => Some(Ok(Token::Number(n))),
-        Err(_) => Some(Err(LexError::InvalidToken(num_str))),
+    numeral(&num_str).or(Some(Err(LexError::InvalidToken(num_str))))
+}
+
+/// Reads `text` as a number, if it is written as one: digits with an
+/// optional sign, making an integer, or with a decimal point or an
+/// exponent as well, making a float, as in `-5`, `3.14`, `.5`, `2.`, and
+/// `6.022e23`, or two integers with a slash between, making a rational,
+/// as in `-3/4`. A float too large to represent and a zero denominator are
+/// errors; infinities and NaN are written `+inf.0`, `-inf.0`, and `+nan.0`.
+fn numeral(text: &str) -> Option<Result<Token, LexError>> {
+    match text {
+        "+inf.0" => return Some(Ok(Token::Float(f64::INFINITY))),
+        "-inf.0" => return Some(Ok(Token::Float(f64::NEG_INFINITY))),
+        "+nan.0" | "-nan.0" => return Some(Ok(Token::Float(f64::NAN))),
+        _ => {}
+    }
+    let digits = |s: &str| s.bytes().all(|b| b.is_ascii_digit());
+    let unsigned = text.strip_prefix(['-', '+']).unwrap_or(text);
+    let invalid = || LexError::InvalidToken(text.into());
+    if let Some((numerator, denominator)) = unsigned.split_once('/') {
+        if numerator.is_empty() || denominator.is_empty() || !digits(numerator) || !digits(denominator) {
+            return None;
+        }
+        // With its sign.
+        let numerator = &text[..text.len() - denominator.len() - 1];
+        return Some(match (numerator.parse::<i64>(), denominator.parse::<i64>()) {
+            (Ok(n), Ok(d)) if d != 0 => Ok(Token::Rational(n, d)),
+            _ => Err(invalid()),
+        });
+    }
+    let (mantissa, exponent) = match unsigned.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => (mantissa, Some(exponent)),
+        None => (unsigned, None),
+    };
+    let (whole, fraction) = match mantissa.split_once('.') {
+        Some((whole, fraction)) => (whole, Some(fraction)),
+        None => (mantissa, None),
+    };
+    if !digits(whole) || !fraction.is_none_or(digits) || whole.len() + fraction.map_or(0, str::len) == 0 {
+        return None;
+    }
+    if let Some(exponent) = exponent {
+        let exponent = exponent.strip_prefix(['-', '+']).unwrap_or(exponent);
+        if exponent.is_empty() || !digits(exponent) {
+            return None;
+        }
+    }
+    if fraction.is_none() && exponent.is_none() {
+        return Some(text.parse::<i64>().map(Token::Number).map_err(|_| invalid()));
     }
+    Some(text.parse::<f64>().ok().filter(|x| x.is_finite()).map(Token::Float).ok_or_else(invalid))
 }
 
-fn parse_boolean<I>(chars: &mut std::iter::Peekable<I>) -> Option<Result<Token, LexError>>
+fn parse_boolean<I>(chars: &mut core::iter::Peekable<I>, extensions: &ReaderExtensions) -> Option<Result<Token, LexError>>
 where
     I: Iterator<Item = char>,
 {
     chars.next(); // consume #
+    if !extensions.handlers.is_empty() && chars.peek().is_some_and(|c| c.is_alphabetic()) {
+        return parse_extension(chars, extensions);
+    }
     match chars.next() {
         Some('t') => Some(Ok(Token::Boolean(true))),
         Some('f') => Some(Ok(Token::Boolean(false))),
+        Some('(') => Some(Ok(Token::VectorOpen)),
+        Some('h') => match (read_name(chars).as_str(), chars.next()) {
+            ("ash", Some('(')) => Some(Ok(Token::HashTableOpen)),
+            (name, _) => Some(Err(LexError::InvalidToken(format!("#h{}", name)))),
+        },
+        Some('u') => match (read_name(chars).as_str(), chars.next()) {
+            ("8", Some('(')) => Some(Ok(Token::BytevectorOpen)),
+            (name, _) => Some(Err(LexError::InvalidToken(format!("#u{}", name)))),
+        },
+        Some('\\') => parse_char(chars),
+        Some(prefix @ ('x' | 'X' | 'o' | 'O' | 'b' | 'B' | 'd' | 'D')) => {
+            Some(radix_literal(&format!("{}{}", prefix, read_name(chars))))
+        }
+        Some(':') => match read_name(chars) {
+            name if name.is_empty() => Some(Err(LexError::InvalidToken("#:".into()))),
+            name => Some(Ok(Token::Keyword(name))),
+        },
         other => Some(Err(LexError::InvalidToken(format!("#{:?}", other)))),
     }
 }
 
-fn parse_symbol<I>(chars: &mut std::iter::Peekable<I>) -> Option<Result<Token, LexError>>
+/// Reads the rest of a `#name...` form after the `#` when reader extensions
+/// are registered: a registered `#name"text"` literal, or one of the
+/// built-in forms that start with a letter.
+fn parse_extension<I>(chars: &mut core::iter::Peekable<I>, extensions: &ReaderExtensions) -> Option<Result<Token, LexError>>
+where
+    I: Iterator<Item = char>,
+{
+    let mut name = String::new();
+    while let Some(&c) = chars.peek().filter(|&&c| is_extension_char(c)) {
+        name.push(c);
+        chars.next();
+    }
+    match (name.as_str(), chars.peek()) {
+        (_, Some('"')) if extensions.get(&name).is_some() => {
+            let text = match parse_string_literal(chars)? {
+                Ok(Token::String(text)) => text,
+                Ok(_) => unreachable!("string literals read as strings"),
+                Err(e) => return Some(Err(e)),
+            };
+            let handler = extensions.get(&name).expect("checked above");
+            Some(handler(&text)
+                .map(Token::Datum)
+                .map_err(|message| LexError::InvalidToken(format!("#{}{}: {}", name, escape_string(&text), message))))
+        }
+        ("t", _) => Some(Ok(Token::Boolean(true))),
+        ("f", _) => Some(Ok(Token::Boolean(false))),
+        ("hash", Some('(')) => {
+            chars.next();
+            Some(Ok(Token::HashTableOpen))
+        }
+        ("u8", Some('(')) => {
+            chars.next();
+            Some(Ok(Token::BytevectorOpen))
+        }
+        (prefix, _) if prefix.starts_with(['x', 'X', 'o', 'O', 'b', 'B', 'd', 'D']) => {
+            Some(radix_literal(&format!("{}{}", name, read_name(chars))))
+        }
+        _ => Some(Err(LexError::InvalidToken(format!("#{}", name)))),
+    }
+}
+
+/// Reads a number written with a radix prefix, given the text after the
+/// `#`: `x1F`, `o17`, `b1010`, or `d42`, with an optional sign before the
+/// digits. A `#d` number may also be a float, as in `#d2.5`.
+fn radix_literal(text: &str) -> Result<Token, LexError> {
+    let (prefix, digits) = text.split_at(1);
+    let (radix, base) = match prefix {
+        "x" | "X" => (16, "hexadecimal"),
+        "o" | "O" => (8, "octal"),
+        "b" | "B" => (2, "binary"),
+        _ => (10, "decimal"),
+    };
+    let error = |message: String| LexError::InvalidToken(format!("#{}: {}", text, message));
+    let unsigned = digits.strip_prefix(['-', '+']).unwrap_or(digits);
+    if unsigned.is_empty() {
+        return Err(error(format!("expected {} digits", base)));
+    }
+    if radix == 10
+        && let Some(token) = numeral(digits)
+    {
+        return token.map_err(|_| error("out of range".into()));
+    }
+    if let Some(c) = unsigned.chars().find(|c| !c.is_digit(radix)) {
+        return Err(error(format!("{:?} is not valid in {}", c, base)));
+    }
+    i64::from_str_radix(digits, radix).map(Token::Number).map_err(|_| error("out of range".into()))
+}
+
+/// Reads the rest of a character literal after `#\`. A single character is
+/// taken literally, even a delimiter like `(`; longer names are looked up in
+/// `CHAR_NAMES` or read as `x` and a hexadecimal code point.
+fn parse_char<I>(chars: &mut core::iter::Peekable<I>) -> Option<Result<Token, LexError>>
+where
+    I: Iterator<Item = char>,
+{
+    let Some(first) = chars.next() else {
+        return Some(Err(LexError::InvalidToken("#\\".into())));
+    };
+    let rest = read_name(chars);
+    if rest.is_empty() {
+        return Some(Ok(Token::Char(first)));
+    }
+    let name = format!("{}{}", first, rest);
+    let named = CHAR_NAMES.iter().find(|(n, _)| *n == name).map(|(_, c)| *c);
+    let hex = || name.strip_prefix('x')
+        .and_then(|digits| u32::from_str_radix(digits, 16).ok())
+        .and_then(char::from_u32);
+    match named.or_else(hex) {
+        Some(c) => Some(Ok(Token::Char(c))),
+        None => Some(Err(LexError::InvalidToken(format!("#\\{}", name)))),
+    }
+}
+
+/// Reads a symbol, or a number starting with a sign or a decimal point,
+/// such as `-5` or `.5`; a sign or point on its own, or followed by
+/// anything but a number, is part of a symbol.
+fn parse_symbol<I>(chars: &mut core::iter::Peekable<I>) -> Option<Result<Token, LexError>>
 where
     I: Iterator<Item = char>,
 {
-    let mut sym = String::new();
+    let name = read_name(chars);
+    numeral(&name).or(Some(Ok(Token::Symbol(name))))
+}
+
+fn fold_case(token: Token) -> Token {
+    match token {
+        Token::Symbol(name) => Token::Symbol(name.to_lowercase()),
+        Token::Keyword(name) => Token::Keyword(name.to_lowercase()),
+        other => other,
+    }
+}
+
+/// Reads characters up to the next delimiter.
+fn read_name<I>(chars: &mut core::iter::Peekable<I>) -> String
+where
+    I: Iterator<Item = char>,
+{
+    let mut name = String::new();
     while let Some(&c) = chars.peek() {
-        if c.is_whitespace() || c == '(' || c == ')' {
+        if c.is_whitespace() || c == '(' || c == ')' || c == '\'' {
             break;
         }
-        sym.push(c);
+        name.push(c);
         chars.next();
     }
-    Some(Ok(Token::Symbol(sym)))
+    name
 }
 
 
@@ -227,6 +673,130 @@ mod tests {
         assert_eq!(result, expected);
     }
 
+    #[test]
+    fn test_tokenize_signed_numbers() {
+        let input = "-5 +7 -0 - + -x ->list -9223372036854775808";
+        let expected = vec![
+            Token::Number(-5),
+            Token::Number(7),
+            Token::Number(0),
+            Token::Symbol("-".to_string()),
+            Token::Symbol("+".to_string()),
+            Token::Symbol("-x".to_string()),
+            Token::Symbol("->list".to_string()),
+            Token::Number(i64::MIN),
+        ];
+        let result = tokenize(input).unwrap();
+        assert_eq!(result, expected);
+        assert!(tokenize("-99999999999999999999").is_err());
+    }
+
+    #[test]
+    fn test_tokenize_floats() {
+        let input = "3.25 .5 -2.0 +0.25 2. -.5 . ... -. 1.x";
+        let expected = vec![
+            Token::Float(3.25),
+            Token::Float(0.5),
+            Token::Float(-2.0),
+            Token::Float(0.25),
+            Token::Float(2.0),
+            Token::Float(-0.5),
+            Token::Symbol(".".to_string()),
+            Token::Symbol("...".to_string()),
+            Token::Symbol("-.".to_string()),
+            Token::Float(1.0),
+            Token::Symbol("x".to_string()),
+        ];
+        let result = tokenize(input).unwrap();
+        assert_eq!(result, expected);
+        assert!(tokenize("1.2.3").is_err());
+    }
+
+    #[test]
+    fn test_tokenize_rationals_vectors_and_bar_symbols() {
+        let input = "1/3 -6/4 +1/2 1 /3 and/or #(1) #u8(255) |a b| |\\|\\x41;|";
+        let expected = vec![
+            Token::Rational(1, 3),
+            Token::Rational(-6, 4),
+            Token::Rational(1, 2),
+            Token::Number(1),
+            Token::Symbol("/3".into()),
+            Token::Symbol("and/or".into()),
+            Token::VectorOpen,
+            Token::Number(1),
+            Token::RParen,
+            Token::BytevectorOpen,
+            Token::Number(255),
+            Token::RParen,
+            Token::Symbol("a b".into()),
+            Token::Symbol("|A".into()),
+        ];
+        assert_eq!(tokenize(input).unwrap(), expected);
+        for bad in ["1/0", "1/", "1/2/3", "#u9(", "|a"] {
+            assert!(tokenize(bad).is_err(), "{}", bad);
+        }
+        assert_eq!(symbol_literal("a b"), "|a b|");
+        assert_eq!(symbol_literal("12"), "|12|");
+        assert_eq!(symbol_literal("list->vector"), "list->vector");
+    }
+
+    #[test]
+    fn test_tokenize_scientific_notation() {
+        let input = "1e10 6.022e23 2.5E-3 -1e2 +.5e+1 2.e1 e10 -e2";
+        let expected = vec![
+            Token::Float(1e10),
+            Token::Float(6.022e23),
+            Token::Float(2.5e-3),
+            Token::Float(-100.0),
+            Token::Float(5.0),
+            Token::Float(20.0),
+            Token::Symbol("e10".to_string()),
+            Token::Symbol("-e2".to_string()),
+        ];
+        let result = tokenize(input).unwrap();
+        assert_eq!(result, expected);
+        assert!(tokenize("1e-x").is_err());
+        assert!(tokenize("1e400").is_err());
+        assert!(tokenize("1e").is_err());
+    }
+
+    #[test]
+    fn test_tokenize_radix_prefixes() {
+        let result = tokenize("#x1F #XfF #o17 #b1010 #b-101 #d42 #d2.5 #x-8000000000000000").unwrap();
+        assert_eq!(result, vec![
+            Token::Number(31),
+            Token::Number(255),
+            Token::Number(15),
+            Token::Number(10),
+            Token::Number(-5),
+            Token::Number(42),
+            Token::Float(2.5),
+            Token::Number(i64::MIN),
+        ]);
+        let error = |input: &str| match tokenize(input) {
+            Err(LexError::InvalidToken(message)) => message,
+            other => panic!("expected an invalid token, got {:?}", other),
+        };
+        assert_eq!(error("#b102"), "#b102: '2' is not valid in binary");
+        assert_eq!(error("#o8"), "#o8: '8' is not valid in octal");
+        assert_eq!(error("#x"), "#x: expected hexadecimal digits");
+        assert_eq!(error("#x1.5"), "#x1.5: '.' is not valid in hexadecimal");
+        assert_eq!(error("#x10000000000000000"), "#x10000000000000000: out of range");
+    }
+
+    #[test]
+    fn test_tokenize_special_floats() {
+        let result = tokenize("+inf.0 -inf.0 inf.0 +inf").unwrap();
+        assert_eq!(result, vec![
+            Token::Float(f64::INFINITY),
+            Token::Float(f64::NEG_INFINITY),
+            Token::Symbol("inf.0".to_string()),
+            Token::Symbol("+inf".to_string()),
+        ]);
+        let result = tokenize("+nan.0 -nan.0").unwrap();
+        assert!(result.iter().all(|token| matches!(token, Token::Float(x) if x.is_nan())));
+    }
+
     #[test]
     fn test_tokenize_booleans() {
         let input = "#t #f";
@@ -286,9 +856,9 @@ mod tests {
 
     #[test]
     fn test_tokenize_invalid_boolean() {
-        let input = "#x";
+        let input = "#q";
         let result = tokenize(input);
-        assert_eq!(result, Err(LexError::InvalidToken("#Some('x')".into())));
+        assert_eq!(result, Err(LexError::InvalidToken("#Some('q')".into())));
     }
 
     #[test]
@@ -298,5 +868,139 @@ mod tests {
         assert_eq!(result, Err(LexError::InvalidToken("\\q".into())));
     }
 
+    #[test]
+    fn test_tokenize_unicode_strings_and_identifiers() {
+        let tokens = tokenize(r#"(define λ "caf\xe9; \x1F600;\r\|") 😀-count"#).unwrap();
+        assert_eq!(tokens, vec![
+            Token::LParen,
+            Token::Symbol("define".into()),
+            Token::Symbol("λ".into()),
+            Token::String("café 😀\r|".into()),
+            Token::RParen,
+            Token::Symbol("😀-count".into()),
+        ]);
+        assert_eq!(tokenize(r#""\xD800;""#), Err(LexError::InvalidToken("\\xD800;".into())));
+        assert_eq!(tokenize(r#""\x41""#), Err(LexError::InvalidToken("\\x41\"".into())));
+    }
 
-}
\ No newline at end of file
+    #[test]
+    fn test_tokenize_quote() {
+        let input = "'(a 'b)";
+        let expected = vec![
+            Token::Quote,
+            Token::LParen,
+            Token::Symbol("a".into()),
+            Token::Quote,
+            Token::Symbol("b".into()),
+            Token::RParen,
+        ];
+        let result = tokenize(input).unwrap();
+        assert_eq!(result, expected);
+    }
+
+    #[test]
+    fn test_tokenize_keyword() {
+        let input = "(f #:port 80)";
+        let expected = vec![
+            Token::LParen,
+            Token::Symbol("f".into()),
+            Token::Keyword("port".into()),
+            Token::Number(80),
+            Token::RParen,
+        ];
+        assert_eq!(tokenize(input).unwrap(), expected);
+        assert_eq!(tokenize("#: x"), Err(LexError::InvalidToken("#:".into())));
+    }
+
+    #[test]
+    fn test_tokenize_hash_table_literal() {
+        let expected = vec![
+            Token::HashTableOpen,
+            Token::LParen,
+            Token::Symbol("a".into()),
+            Token::Symbol(".".into()),
+            Token::Number(1),
+            Token::RParen,
+            Token::RParen,
+        ];
+        assert_eq!(tokenize("#hash((a . 1))").unwrap(), expected);
+        assert_eq!(tokenize("#hashy"), Err(LexError::InvalidToken("#hashy".into())));
+    }
+
+    #[test]
+    fn test_tokenize_with_lines() {
+        let input = "(define x\n  \"a\nb\") ; comment\n\n'x";
+        let lines: Vec<usize> = tokenize_with_lines(input).unwrap().into_iter().map(|(_, line)| line).collect();
+        assert_eq!(lines, vec![1, 1, 1, 2, 3, 5, 5]);
+    }
+
+    #[test]
+    fn test_fold_case_lowercases_symbols_and_keywords_only() {
+        let options = LexOptions { fold_case: true, ..LexOptions::default() };
+        let tokens: Vec<_> = tokenize_with_options("(Define #:Key \"MiXed\")", &options).unwrap()
+            .into_iter().map(|(token, _)| token).collect();
+        assert_eq!(tokens, vec![
+            Token::LParen,
+            Token::Symbol("define".into()),
+            Token::Keyword("key".into()),
+            Token::String("MiXed".into()),
+            Token::RParen,
+        ]);
+    }
+
+    #[test]
+    fn test_tokenize_chars() {
+        let tokens = tokenize(r"#\a #\space #\( #\x41 #\λ").unwrap();
+        assert_eq!(tokens, vec![
+            Token::Char('a'),
+            Token::Char(' '),
+            Token::Char('('),
+            Token::Char('A'),
+            Token::Char('λ'),
+        ]);
+        assert!(matches!(tokenize(r"#\bogus"), Err(LexError::InvalidToken(_))));
+        assert_eq!(char_literal(' '), r"#\space");
+        assert_eq!(char_literal('z'), r"#\z");
+    }
+
+    #[test]
+    fn test_reader_extensions() {
+        let mut options = LexOptions::default();
+        options.extensions.register("rx", Rc::new(|text: &str| match text {
+            "" => Err("empty pattern".into()),
+            _ => Ok(Expr::List(alloc::vec![Expr::Symbol("regexp".into()), Expr::String(text.into())])),
+        })).unwrap();
+        let tokens = tokenize_with_options("(#rx\"a+\" #t #f #hash() #\\x)", &options).unwrap();
+        let tokens: Vec<Token> = tokens.into_iter().map(|(token, _)| token).collect();
+        assert_eq!(tokens, alloc::vec![
+            Token::LParen,
+            Token::Datum(Expr::List(alloc::vec![Expr::Symbol("regexp".into()), Expr::String("a+".into())])),
+            Token::Boolean(true),
+            Token::Boolean(false),
+            Token::HashTableOpen,
+            Token::RParen,
+            Token::Char('x'),
+            Token::RParen,
+        ]);
+        assert!(matches!(tokenize_with_options("#rx\"\"", &options), Err(LexError::InvalidToken(_))));
+        assert!(matches!(tokenize_with_options("#date\"2024\"", &options), Err(LexError::InvalidToken(_))));
+        assert!(options.extensions.register("hash", Rc::new(|_: &str| Ok(Expr::Number(0)))).is_err());
+        assert!(options.extensions.register("9x", Rc::new(|_: &str| Ok(Expr::Number(0)))).is_err());
+        assert_eq!(format!("{:?}", options.extensions), "[\"rx\"]");
+    }
+
+    #[test]
+    fn test_tokenize_with_spans_recovers_from_errors() {
+        let input = "(λ #q) ; note\n\"open";
+        let lexemes = tokenize_with_spans(input);
+        let pieces: Vec<_> = lexemes.iter().map(|(lexeme, span)| (lexeme.clone(), &input[span.start..span.end])).collect();
+        assert_eq!(pieces, vec![
+            (Lexeme::Token(Token::LParen), "("),
+            (Lexeme::Token(Token::Symbol("λ".into())), "λ"),
+            (Lexeme::Error(LexError::InvalidToken("#Some('q')".into())), "#q"),
+            (Lexeme::Token(Token::RParen), ")"),
+            (Lexeme::Comment, "; note"),
+            (Lexeme::Error(LexError::UnterminatedString), "\"open"),
+        ]);
+    }
+}
@@ -1,53 +1,102 @@
-use wasm_bindgen::prelude::*;
-use std::rc::Rc;
+#![cfg_attr(not(feature = "std"), no_std)]
 
-use crate::env::{default_env, Env};
-use crate::eval::eval;
-use crate::lexer::tokenize;
-use crate::parser::parse;
+// The reader (`lexer`, `parser`, and `ast`) only needs `alloc`; everything
+// else, starting with the evaluator's thread-local state, needs `std`.
+extern crate alloc;
 
 pub mod lexer;
 pub mod parser;
 pub mod ast;
+#[cfg(feature = "std")]
 pub mod eval;
+#[cfg(feature = "std")]
 pub mod env;
+#[cfg(feature = "std")]
 pub mod builtins;
+#[cfg(feature = "std")]
+pub mod number;
+#[cfg(feature = "std")]
+pub mod printer;
+#[cfg(feature = "std")]
+pub mod pattern;
+#[cfg(feature = "std")]
+pub mod record;
+#[cfg(feature = "std")]
+pub mod port;
+#[cfg(feature = "std")]
+pub mod coverage;
+#[cfg(feature = "std")]
+pub mod diagnostics;
+#[cfg(feature = "std")]
+pub mod report;
+#[cfg(feature = "std")]
+pub mod testing;
+#[cfg(feature = "std")]
+pub mod style;
+#[cfg(feature = "std")]
+pub mod compiled;
+#[cfg(feature = "std")]
+pub mod incremental;
+#[cfg(feature = "std")]
+pub mod fuzz;
+#[cfg(feature = "std")]
+pub mod generator;
+#[cfg(feature = "std")]
+pub mod fiber;
+#[cfg(feature = "std")]
+pub mod channel;
+#[cfg(feature = "std")]
+pub mod mutex;
+#[cfg(feature = "std")]
+pub mod weak;
+#[cfg(feature = "std")]
+pub mod combinator;
+#[cfg(feature = "std")]
+pub mod condition;
+#[cfg(feature = "std")]
+pub mod marks;
+#[cfg(feature = "std")]
+pub mod limits;
+#[cfg(feature = "io")]
+pub mod timer;
+#[cfg(feature = "date")]
+pub mod date;
+#[cfg(feature = "std")]
+pub mod bytevector;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub mod parallel;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub mod actor;
+#[cfg(all(feature = "std", not(browser)))]
+pub mod bench;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub mod ffi;
+#[cfg(feature = "std")]
+pub mod json;
+#[cfg(feature = "std")]
+pub mod datum;
+#[cfg(feature = "std")]
+pub mod resolver;
+#[cfg(feature = "std")]
+pub mod highlight;
+#[cfg(feature = "std")]
+pub mod structure;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub mod server;
+#[cfg(all(feature = "std", not(target_arch = "wasm32")))]
+pub mod judge;
+#[cfg(feature = "python")]
+pub mod python;
+#[cfg(all(feature = "web", browser))]
+pub mod dom;
+#[cfg(feature = "tracing")]
+pub mod telemetry;
+#[cfg(all(feature = "io", not(browser)))]
+pub mod terminal;
+#[cfg(all(feature = "io", not(browser)))]
+pub mod signal;
+#[cfg(all(feature = "std", not(target_os = "wasi")))]
+mod context;
 
-/// Persistent REPL context
-#[wasm_bindgen]
-pub struct EvalContext {
-    env: Rc<Env>,
-}
-
-#[wasm_bindgen]
-impl EvalContext {
-    #[wasm_bindgen(constructor)]
-    pub fn new() -> EvalContext {
-        EvalContext {
-            env: default_env(),
-        }
-    }
-
-    pub fn eval_line(&self, input: &str) -> String {
-        let trimmed = input.trim();
-
-        if trimmed == "exit" || trimmed == "quit" {
-            return "👋 Goodbye and thanks for all the fish!".to_string();
-        }
-
-        let tokens = match tokenize(trimmed) {
-            Ok(t) => t,
-            Err(e) => return format!("Lex error: {:?}", e),
-        };
-
-        let ast = match parse(tokens) {
-            Ok(a) => a,
-            Err(e) => return format!("Parse error: {:?}", e),
-        };
-
-        match eval(&ast, self.env.clone()) {
-            Ok(val) => format!("{}", val),
-            Err(e) => format!("Eval error: {:?}", e),
-        }
-    }
-}
+#[cfg(all(feature = "std", not(target_os = "wasi")))]
+pub use context::EvalContext;
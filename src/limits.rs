@@ -0,0 +1,208 @@
+//! Limits on part of a program: `(with-limits (steps n seconds s) body ...)`
+//! evaluates the body with a budget of its own, so that a script can call
+//! untrusted or runaway code and carry on when it is stopped.
+//!
+//! Steps are units of fuel (see `eval::set_fuel`), taken from any budget
+//! already in force: a body that finishes leaves the enclosing budget with
+//! what it did not use, and a body stopped by the enclosing budget rather
+//! than its own stops the enclosing code too. The same goes for time, which
+//! is measured natively but not in the browser, where there is no clock to
+//! check between expressions.
+//!
+//! When the body goes over its own limit, the result is the value of the
+//! `default` expression if one was given, and otherwise a condition of kind
+//! `limit` is raised, which `guard` can handle like any other error.
+
+use std::rc::Rc;
+
+#[cfg(not(browser))]
+use std::cell::Cell;
+#[cfg(not(browser))]
+use std::time::{Duration, Instant};
+
+use crate::ast::Expr;
+use crate::condition::raise_condition;
+use crate::env::{Env, EvalError, Value};
+use crate::eval::{eval, eval_sequence, remaining_fuel, set_fuel};
+use crate::number::Number;
+
+#[cfg(not(browser))]
+thread_local! {
+    /// When the innermost `with-limits` with a time limit runs out, taking
+    /// the enclosing ones into account.
+    static DEADLINE: Cell<Option<Instant>> = const { Cell::new(None) };
+}
+
+/// Fails with `EvalError::Interrupted` once the time given to the
+/// innermost `with-limits` is up. Called by `eval` before each expression.
+#[cfg(not(browser))]
+pub(crate) fn check_deadline() -> Result<(), EvalError> {
+    match DEADLINE.with(|d| d.get()) {
+        Some(deadline) if Instant::now() >= deadline => Err(EvalError::Interrupted),
+        _ => Ok(()),
+    }
+}
+
+/// What a `with-limits` form allows its body.
+#[derive(Default)]
+struct Budget<'a> {
+    steps: Option<u64>,
+    seconds: Option<f64>,
+    default: Option<&'a Expr>,
+}
+
+/// `(with-limits (option value ...) body ...)`, where the options are
+/// `steps` (expressions the body may evaluate), `seconds` (how long it may
+/// run), and `default` (an expression evaluated for the result if the body
+/// goes over a limit).
+pub fn eval_with_limits(list: &[Expr], env: Rc<Env>) -> Result<Value, EvalError> {
+    let [_, Expr::List(options), body @ ..] = list else { return Err(EvalError::ArityMismatch) };
+    if body.is_empty() {
+        return Err(EvalError::ArityMismatch);
+    }
+    if options.len() % 2 != 0 {
+        return Err(EvalError::TypeError("with-limits: expected (option value ...)".into()));
+    }
+    let mut budget = Budget::default();
+    for pair in options.chunks(2) {
+        match &pair[0] {
+            Expr::Symbol(s) if s == "steps" => budget.steps = Some(steps_arg(&eval(&pair[1], env.clone())?)?),
+            Expr::Symbol(s) if s == "seconds" => budget.seconds = Some(seconds_arg(&eval(&pair[1], env.clone())?)?),
+            Expr::Symbol(s) if s == "default" => budget.default = Some(&pair[1]),
+            other => return Err(EvalError::TypeError(format!("with-limits: unknown option {}", other))),
+        }
+    }
+    run_limited(&budget, body, env)
+}
+
+/// `(with-timeout seconds body ...)` is `(with-limits (seconds seconds) body ...)`.
+pub fn eval_with_timeout(list: &[Expr], env: Rc<Env>) -> Result<Value, EvalError> {
+    let [_, seconds, body @ ..] = list else { return Err(EvalError::ArityMismatch) };
+    if body.is_empty() {
+        return Err(EvalError::ArityMismatch);
+    }
+    let seconds = seconds_arg(&eval(seconds, env.clone())?)?;
+    run_limited(&Budget { seconds: Some(seconds), ..Budget::default() }, body, env)
+}
+
+fn steps_arg(value: &Value) -> Result<u64, EvalError> {
+    match Number::try_from(value).ok().and_then(Number::as_integer) {
+        Some(n) if n >= 0 => Ok(n as u64),
+        _ => Err(EvalError::TypeError(format!("with-limits: steps must be a non-negative integer, got {}", value))),
+    }
+}
+
+fn seconds_arg(value: &Value) -> Result<f64, EvalError> {
+    match Number::try_from(value).map(Number::to_f64) {
+        Ok(x) if x >= 0.0 && x.is_finite() => Ok(x),
+        _ => Err(EvalError::TypeError(format!("with-limits: seconds must be a non-negative number, got {}", value))),
+    }
+}
+
+fn run_limited(budget: &Budget, body: &[Expr], env: Rc<Env>) -> Result<Value, EvalError> {
+    let outer_fuel = remaining_fuel();
+    let fuel = match (budget.steps, outer_fuel) {
+        (Some(steps), Some(outer)) => Some(steps.min(outer)),
+        (steps, outer) => steps.or(outer),
+    };
+    set_fuel(fuel);
+
+    #[cfg(not(browser))]
+    let (outer_deadline, deadline) = {
+        let outer = DEADLINE.with(|d| d.get());
+        let own = budget.seconds.map(|s| Instant::now() + Duration::from_secs_f64(s));
+        let deadline = match (own, outer) {
+            (Some(own), Some(outer)) => Some(own.min(outer)),
+            (own, outer) => own.or(outer),
+        };
+        DEADLINE.with(|d| d.set(deadline));
+        (outer, own)
+    };
+    #[cfg(browser)]
+    if budget.seconds.is_some() {
+        set_fuel(outer_fuel);
+        return Err(EvalError::Other("with-limits: time limits are not available in the browser".into()));
+    }
+
+    let result = eval_sequence(body, env.clone());
+
+    let used = fuel.zip(remaining_fuel()).map_or(0, |(given, left)| given - left);
+    set_fuel(outer_fuel.map(|outer| outer - used));
+    #[cfg(not(browser))]
+    DEADLINE.with(|d| d.set(outer_deadline));
+
+    let exceeded = match &result {
+        // Out of the steps given to this body, not just those left outside.
+        Err(EvalError::OutOfFuel) if budget.steps.is_some_and(|steps| outer_fuel.is_none_or(|outer| steps < outer)) => {
+            ("Step limit exceeded", Value::Number(budget.steps.unwrap_or(0) as i64))
+        }
+        #[cfg(not(browser))]
+        Err(EvalError::Interrupted) if deadline.is_some_and(|own| {
+            Instant::now() >= own && outer_deadline.is_none_or(|outer| own < outer)
+        }) => {
+            ("Time limit exceeded", Value::Float(budget.seconds.unwrap_or(0.0)))
+        }
+        _ => return result,
+    };
+    match budget.default {
+        Some(default) => eval(default, env),
+        None => Err(raise_condition("limit", exceeded.0.into(), vec![exceeded.1])),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::eval::set_fuel;
+    use crate::testing::{assert_fails, assert_prints, eval_to_string};
+    use crate::env::default_env;
+
+    const LOOP: &str = "(define (spin n) (if (= n 0) 'done (begin (+ 1 1) (spin (- n 1)))))";
+
+    #[test]
+    fn test_step_limits() {
+        assert_prints(&format!("{} (with-limits (steps 100000) (spin 10))", LOOP), "done");
+        assert_prints(&format!("{} (with-limits (steps 100 default 'stopped) (spin 900))", LOOP), "stopped");
+        assert_prints(
+            &format!("{} (guard (e (#t (list (condition-kind e) (condition-message e) (condition-irritants e))))
+                (with-limits (steps 100) (spin 900)))", LOOP),
+            "(limit \"Step limit exceeded\" (100))",
+        );
+        assert_fails("(with-limits (steps (- 0 1)) 1)", "type");
+        assert_fails("(with-limits (steps 1 colour 2) 1)", "type");
+        assert_fails("(with-limits (steps 10))", "arity");
+    }
+
+    #[test]
+    fn test_step_limits_draw_on_the_enclosing_budget() {
+        let env = default_env();
+        crate::testing::eval_source(LOOP, &env).unwrap();
+        // The enclosing budget runs out first, so nothing may handle it.
+        set_fuel(Some(200));
+        let result = eval_to_string("(with-limits (steps 100000 default 'stopped) (spin 900))", &env);
+        assert_eq!(result, Err("fuel: Out of fuel".into()));
+        // Steps the body used are gone from the enclosing budget.
+        set_fuel(Some(1000));
+        assert_eq!(eval_to_string("(with-limits (steps 500) (spin 5))", &env), Ok("done".into()));
+        let left = crate::eval::remaining_fuel().unwrap();
+        set_fuel(None);
+        assert!(left < 1000 - 5 * 5, "{} steps left", left);
+    }
+
+    #[cfg(not(browser))]
+    #[test]
+    fn test_time_limits() {
+        let setup = "(define v (make-vector 100000 0))";
+        let slow = "(vector-for-each (lambda (x) (vector-for-each (lambda (y) y) v)) v)";
+        assert_prints(&format!("{} (with-timeout (/ 1 20) (+ 1 2))", setup), "3");
+        assert_prints(&format!("{} (with-limits (seconds (/ 1 20) default 'late) {})", setup, slow), "late");
+        assert_prints(
+            &format!("{} (guard (e (#t (condition-message e))) (with-timeout (/ 1 20) {}))", setup, slow),
+            "\"Time limit exceeded\"",
+        );
+        // The inner limit is longer, so the outer one stops the body.
+        assert_prints(
+            &format!("{} (with-limits (seconds (/ 1 20) default 'outer) (with-limits (seconds 60 default 'inner) {}))", setup, slow),
+            "outer",
+        );
+    }
+}
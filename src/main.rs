@@ -1,12 +1,125 @@
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::fs::File;
 use std::io::{self, Write};
+use std::path::Path;
+use std::process::ExitCode;
+use std::rc::Rc;
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::atomic::{AtomicBool, Ordering};
+#[cfg(not(target_arch = "wasm32"))]
+use std::sync::Arc;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Duration;
 
-use scheme_rs::env::default_env;
-use scheme_rs::eval::eval;
-use scheme_rs::lexer::tokenize;
-use scheme_rs::parser::parse;
+use scheme_rs::compiled;
+use scheme_rs::coverage::eval_with_coverage;
+use scheme_rs::diagnostics::{audit_library, check_program, Diagnostic};
+use scheme_rs::bench;
+use scheme_rs::ast::Expr;
+use scheme_rs::env::{default_env, Env, EvalError, Value};
+use scheme_rs::eval::{eval, set_error_handler, Restart};
+#[cfg(not(target_arch = "wasm32"))]
+use scheme_rs::eval::set_interrupt_flag;
+#[cfg(not(target_arch = "wasm32"))]
+use scheme_rs::judge::{judge, Limits};
+use scheme_rs::lexer::{tokenize, tokenize_with_lines};
+use scheme_rs::parser::{parse, parse_program};
+use scheme_rs::printer::{print_value, PrintOptions};
+use scheme_rs::report::{eval_with_report, read_with_report, ErrorReport};
+use scheme_rs::style;
+#[cfg(feature = "io")]
+use scheme_rs::terminal::restore_terminal;
 
-fn main() {
-    let env = default_env(); // REPL uses a persistent environment
+const USAGE: &str = "\
+Usage: scheme_rs                            start the REPL
+       scheme_rs --debug                    start the REPL, entering a debug REPL where errors occur
+       scheme_rs --server [ADDRESS]         answer JSON requests on stdio, or on a TCP address such as 127.0.0.1:7888
+       scheme_rs --highlight FILE           print FILE with syntax highlighting
+       scheme_rs --coverage FILE            run FILE, then print it annotated with hit counts
+       scheme_rs --coverage=lcov FILE       run FILE, then print LCOV coverage data
+       scheme_rs --timeout DURATION FILE    run FILE, stopping it with exit status 124 after DURATION (such as 5s or 500ms)
+       scheme_rs judge FILE [--fuel N] [--time DURATION] [--memory BYTES]
+                                            run FILE under limits, printing a JSON report of its value, output, and errors
+       scheme_rs --replay FILE              evaluate the forms of a session recorded with ,record, printing each value
+       scheme_rs --audit [FILE]...          load each FILE, then report procedures that would overflow on long lists
+       scheme_rs --format json ...          print errors and diagnostics as JSON objects, one per line, for tools
+       scheme_rs compile FILE [-o OUT]      parse FILE and save it for fast `load` (default OUT: FILE.scmc)
+       scheme_rs bench DIR [--runs N] [--save FILE] [--baseline FILE]
+                                            time each .scm file in DIR, optionally saving or comparing medians";
+
+thread_local! {
+    /// Whether errors and diagnostics are printed as JSON, by `--format json`.
+    static JSON_OUTPUT: Cell<bool> = const { Cell::new(false) };
+}
+
+fn json_output() -> bool {
+    JSON_OUTPUT.with(Cell::get)
+}
+
+fn main() -> ExitCode {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mut args: Vec<&str> = args.iter().map(String::as_str).collect();
+    if let ["--format", format, ..] = args[..] {
+        match format {
+            "json" => JSON_OUTPUT.with(|j| j.set(true)),
+            "text" => {}
+            _ => {
+                eprintln!("{}", USAGE);
+                return ExitCode::FAILURE;
+            }
+        }
+        args.drain(..2);
+    }
+    match args[..] {
+        [] => {
+            repl();
+            ExitCode::SUCCESS
+        }
+        ["--debug"] => {
+            let level = Rc::new(Cell::new(0));
+            set_error_handler(Some(Rc::new(move |expr: &Expr, env: &Rc<Env>, error: &EvalError| {
+                debug_repl(expr, env, error, &level)
+            })));
+            repl();
+            ExitCode::SUCCESS
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        ["--server"] => report_io(scheme_rs::server::serve(io::BufReader::new(io::stdin()), io::stdout())),
+        #[cfg(not(target_arch = "wasm32"))]
+        ["--server", address] => serve_tcp(address),
+        ["--highlight", path] => print_highlighted(path),
+        ["--coverage", path] => run_with_coverage(path, false),
+        ["--coverage=lcov", path] => run_with_coverage(path, true),
+        ["--audit", ref paths @ ..] => audit(paths),
+        ["--replay", path] => replay(path),
+        #[cfg(not(target_arch = "wasm32"))]
+        ["judge", path, ref options @ ..] => run_judge(path, options),
+        #[cfg(not(target_arch = "wasm32"))]
+        ["--timeout", duration, path] => match parse_duration(duration) {
+            Some(limit) => run_with_timeout(path, limit),
+            None => {
+                eprintln!("Invalid duration {:?}: expected a number of seconds, optionally with s, ms, or m", duration);
+                ExitCode::FAILURE
+            }
+        },
+        ["compile", path] => compile(path, &Path::new(path).with_extension(compiled::EXTENSION)),
+        ["compile", path, "-o", output] => compile(path, Path::new(output)),
+        ["bench", dir, ref options @ ..] => bench(dir, options),
+        _ => {
+            eprintln!("{}", USAGE);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn repl() {
+    let mut session = Session {
+        env: default_env(), // REPL uses a persistent environment
+        recording: None,
+        definitions: HashMap::new(),
+        history: Vec::new(),
+    };
     let stdin = io::stdin();
     let mut stdout = io::stdout();
 
@@ -27,18 +140,631 @@ fn main() {
         if trimmed == "exit" || trimmed == "quit" {
             break;
         }
+        if let Some(command) = trimmed.strip_prefix(',') {
+            run_command(command, &mut session);
+            continue;
+        }
 
         match tokenize(trimmed) {
             Ok(tokens) => match parse(tokens) {
-                Ok(ast) => match eval(&ast, env.clone()) {
-                    Ok(result) => println!("{}", result),
-                    Err(e) => eprintln!("Eval error: {:?}", e),
-                },
-                Err(e) => eprintln!("Parse error: {:?}", e),
+                Ok(ast) => {
+                    session.evaluate(&ast, trimmed, true);
+                }
+                Err(e) => report_error("Parse error", e),
             },
-            Err(e) => eprintln!("Lex error: {:?}", e),
+            Err(e) => report_error("Lex error", e),
         }
     }
+    if let Some(recording) = session.recording {
+        recording.finish();
+    }
 
     println!("👋 Goodbye and thanks for all the fish!");
 }
+
+/// The state of a REPL session.
+struct Session {
+    env: Rc<Env>,
+    recording: Option<Recording>,
+    /// The source text of each top-level definition, by name, for `,edit`.
+    definitions: HashMap<String, String>,
+    /// The source text of every form that evaluated successfully, in order,
+    /// for `,save-session`.
+    history: Vec<String>,
+}
+
+impl Session {
+    /// Evaluates a form read from `source`, printing its value if `echo`,
+    /// and on success records it and remembers the source of what it
+    /// defines. Returns whether it succeeded.
+    fn evaluate(&mut self, ast: &Expr, source: &str, echo: bool) -> bool {
+        for diagnostic in check_program(std::slice::from_ref(ast), &self.env, None) {
+            report_diagnostic(&diagnostic);
+        }
+        let result = eval(ast, self.env.clone());
+        #[cfg(feature = "io")]
+        restore_terminal();
+        match result {
+            Ok(result) => {
+                if echo {
+                    println!("{}", print_value(&result, &PrintOptions::from_env(&self.env)));
+                }
+                if let Some(recording) = &mut self.recording {
+                    recording.add(source);
+                }
+                if let Some(name) = defined_name(ast) {
+                    self.definitions.insert(name.to_string(), source.to_string());
+                }
+                self.history.push(source.to_string());
+                true
+            }
+            Err(e) => {
+                report_error("Eval error", e);
+                false
+            }
+        }
+    }
+
+    /// Saves the session to a file that `,load-session` can resume from.
+    /// Values such as procedures cannot be written out in general, so the
+    /// file holds the forms that built the session, to be evaluated again.
+    fn save(&self, path: &str) {
+        let mut text = String::from(";; scheme_rs session: the forms to evaluate to resume it\n");
+        for form in &self.history {
+            text.push_str(form);
+            text.push('\n');
+        }
+        match std::fs::write(path, text) {
+            Ok(()) => println!("Saved {} forms to {}", self.history.len(), path),
+            Err(e) => eprintln!("Cannot write {}: {}", path, e),
+        }
+    }
+
+    /// Resumes a session saved with `,save-session` by evaluating its forms
+    /// here, without printing their values, stopping at the first error.
+    fn load(&mut self, path: &str) {
+        let source = match std::fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(e) => return eprintln!("Cannot read {}: {}", path, e),
+        };
+        let program = match tokenize(&source).map(parse_program) {
+            Ok(Ok(program)) => program,
+            Ok(Err(e)) => return report_error("Parse error", e),
+            Err(e) => return report_error("Lex error", e),
+        };
+        let mut loaded = 0;
+        for form in &program {
+            if !self.evaluate(form, &form.to_string(), false) {
+                break;
+            }
+            loaded += 1;
+        }
+        println!("Loaded {} of {} forms from {}", loaded, program.len(), path);
+    }
+
+    /// Opens the source of a definition in `$EDITOR` (or `vi`), then
+    /// evaluates what was saved, if it changed.
+    fn edit(&mut self, name: &str) {
+        let Some(source) = self.definitions.get(name).cloned() else {
+            eprintln!("No definition of {} made in this session", name);
+            return;
+        };
+        let path = std::env::temp_dir().join(format!("scheme_rs_edit_{}.scm", std::process::id()));
+        if let Err(e) = std::fs::write(&path, format!("{}\n", source)) {
+            eprintln!("Cannot write {}: {}", path.display(), e);
+            return;
+        }
+        let editor = std::env::var("EDITOR").unwrap_or_else(|_| "vi".to_string());
+        let mut words = editor.split_whitespace();
+        let status = std::process::Command::new(words.next().unwrap_or("vi")).args(words).arg(&path).status();
+        let edited = std::fs::read_to_string(&path);
+        let _ = std::fs::remove_file(&path);
+        match status {
+            Ok(status) if status.success() => {}
+            Ok(status) => return eprintln!("{} exited with {}", editor, status),
+            Err(e) => return eprintln!("Cannot run {}: {}", editor, e),
+        }
+        let edited = match edited {
+            Ok(edited) => edited,
+            Err(e) => return eprintln!("Cannot read {}: {}", path.display(), e),
+        };
+        let edited = edited.trim();
+        if edited == source {
+            println!("No changes");
+            return;
+        }
+        match tokenize(edited).map(parse_program) {
+            Ok(Ok(program)) => {
+                for form in &program {
+                    let text = if program.len() == 1 { edited.to_string() } else { form.to_string() };
+                    self.evaluate(form, &text, true);
+                }
+            }
+            Ok(Err(e)) => report_error("Parse error", e),
+            Err(e) => report_error("Lex error", e),
+        }
+    }
+}
+
+/// The name a top-level `define` binds.
+fn defined_name(expr: &Expr) -> Option<&str> {
+    let Expr::List(items) = expr else { return None };
+    match &items[..] {
+        [Expr::Symbol(define), Expr::Symbol(name), ..] if define == "define" => Some(name),
+        [Expr::Symbol(define), Expr::List(signature), ..] if define == "define" => match signature.first() {
+            Some(Expr::Symbol(name)) => Some(name),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// The file a REPL session is being recorded to, by `,record`.
+struct Recording {
+    path: String,
+    file: File,
+    forms: usize,
+}
+
+impl Recording {
+    /// Appends a form that evaluated successfully. Each is written at once,
+    /// so that the recording survives the REPL crashing.
+    fn add(&mut self, form: &str) {
+        match writeln!(self.file, "{}", form) {
+            Ok(()) => self.forms += 1,
+            Err(e) => eprintln!("Cannot write to {}: {}", self.path, e),
+        }
+    }
+
+    fn finish(self) {
+        println!("Recorded {} forms to {}", self.forms, self.path);
+    }
+}
+
+/// Runs a REPL command, written with a leading comma:
+/// `,record FILE` starts saving the forms that evaluate successfully to
+/// FILE, for `--replay`, and `,stop-record` stops. `,edit NAME` opens
+/// the source of a definition made in the session in `$EDITOR` and
+/// evaluates it again when the editor exits. `,save-session FILE` saves
+/// the forms evaluated so far and `,load-session FILE` evaluates them again.
+fn run_command(command: &str, session: &mut Session) {
+    let (name, argument) = command.split_once(char::is_whitespace).unwrap_or((command, ""));
+    let argument = argument.trim();
+    let recording = &mut session.recording;
+    match name {
+        "record" if argument.is_empty() => eprintln!("Usage: ,record FILE"),
+        "record" => match File::create(argument) {
+            Ok(file) => {
+                if let Some(previous) = recording.replace(Recording { path: argument.to_string(), file, forms: 0 }) {
+                    previous.finish();
+                }
+                println!("Recording to {}", argument);
+            }
+            Err(e) => eprintln!("Cannot create {}: {}", argument, e),
+        },
+        "stop-record" => match recording.take() {
+            Some(recording) => recording.finish(),
+            None => eprintln!("Not recording"),
+        },
+        "edit" if argument.is_empty() => eprintln!("Usage: ,edit NAME"),
+        "edit" => session.edit(argument),
+        "save-session" | "load-session" if argument.is_empty() => eprintln!("Usage: ,{} FILE", name),
+        "save-session" => session.save(argument),
+        "load-session" => session.load(argument),
+        _ => eprintln!(
+            "Unknown command ,{} (available: ,record FILE, ,stop-record, ,edit NAME, \
+             ,save-session FILE, ,load-session FILE)",
+            name
+        ),
+    }
+}
+
+/// Runs a nested REPL where an error occurred, evaluating input in the
+/// environment of the failing expression, until a restart is chosen:
+/// `:retry` evaluates the expression again, `:return EXPR` uses the value
+/// of EXPR in its place, and `:abort` (or the end of input) lets the error
+/// continue to the top level. `:env` lists the local variables.
+fn debug_repl(expr: &Expr, env: &Rc<Env>, error: &EvalError, level: &Rc<Cell<usize>>) -> Restart {
+    level.set(level.get() + 1);
+    report_error("Error", error);
+    eprintln!("  in {}", expr);
+    eprintln!("  restarts: :retry, :return EXPR, :abort (:env lists local variables)");
+    let restart = loop {
+        print!("debug {}> ", level.get());
+        io::stdout().flush().unwrap();
+        let mut input = String::new();
+        if io::stdin().read_line(&mut input).map_or(true, |n| n == 0) {
+            break Restart::Abort;
+        }
+        let trimmed = input.trim();
+        let (command, argument) = trimmed.split_once(char::is_whitespace).unwrap_or((trimmed, ""));
+        match command {
+            "" => {}
+            ":retry" => break Restart::Retry,
+            ":abort" => break Restart::Abort,
+            ":env" => {
+                for (name, value) in env.local_bindings() {
+                    println!("{} = {}", name, print_value(&value, &PrintOptions::from_env(env)));
+                }
+            }
+            ":return" => match eval_input(argument, env) {
+                Ok(value) => break Restart::Return(value),
+                Err(message) => eprintln!("{}", message),
+            },
+            _ => match eval_input(trimmed, env) {
+                Ok(value) => println!("{}", print_value(&value, &PrintOptions::from_env(env))),
+                Err(message) => eprintln!("{}", message),
+            },
+        }
+    };
+    level.set(level.get() - 1);
+    restart
+}
+
+fn eval_input(input: &str, env: &Rc<Env>) -> Result<Value, String> {
+    let tokens = tokenize(input).map_err(|e| format!("Lex error: {:?}", e))?;
+    let ast = parse(tokens).map_err(|e| format!("Parse error: {:?}", e))?;
+    eval(&ast, env.clone()).map_err(|e| format!("Eval error: {:?}", e))
+}
+
+/// Prints an error in the REPL, highlighted when colors are enabled.
+fn report_error(kind: &str, error: impl std::fmt::Debug) {
+    let label = style::paint(&format!("{}:", kind), &["bold", "red"]).expect("known styles");
+    eprintln!("{} {:?}", label, error);
+}
+
+/// Reads and evaluates a program, passing the value of each form to
+/// `each_value`, until the first error. Fails with the syntax errors of a
+/// program that cannot be read, or the error that stopped it.
+fn run_source(source: &str, env: Rc<Env>, each_value: impl FnMut(&Value)) -> Result<(), Vec<ErrorReport>> {
+    let (program, tokens) = read_with_report(source)?;
+    eval_with_report(&program, &tokens, env, each_value).map(drop).map_err(|report| vec![report])
+}
+
+/// Prints why a program failed: as a JSON object on one line with
+/// `--format json`, and otherwise as a message followed by the expressions
+/// it happened in.
+fn report_failure(report: &ErrorReport) {
+    if json_output() {
+        eprintln!("{}", report.to_json());
+        return;
+    }
+    let label = style::paint("Error:", &["bold", "red"]).expect("known styles");
+    match report.line {
+        Some(line) => eprintln!("{} {} (line {})", label, report.message, line),
+        None => eprintln!("{} {}", label, report.message),
+    }
+    for frame in &report.stack {
+        match frame.line {
+            Some(line) => eprintln!("  in {} (line {})", frame.expr, line),
+            None => eprintln!("  in {}", frame.expr),
+        }
+    }
+}
+
+/// Prints a warning in the REPL, separately from errors.
+fn report_diagnostic(diagnostic: &Diagnostic) {
+    if json_output() {
+        eprintln!("{}", diagnostic.to_json());
+        return;
+    }
+    eprintln!("{}", style::paint(&diagnostic.to_string(), &["yellow"]).expect("known styles"));
+}
+
+/// Answers JSON requests from one connection at a time, each in a new
+/// session, until the process is stopped.
+#[cfg(not(target_arch = "wasm32"))]
+fn serve_tcp(address: &str) -> ExitCode {
+    let listener = match std::net::TcpListener::bind(address) {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("Cannot listen on {}: {}", address, e);
+            return ExitCode::FAILURE;
+        }
+    };
+    eprintln!("Listening on {}", address);
+    for stream in listener.incoming() {
+        let result = stream.and_then(|stream| {
+            let input = io::BufReader::new(stream.try_clone()?);
+            scheme_rs::server::serve(input, stream)
+        });
+        if let Err(e) = result {
+            eprintln!("Connection failed: {}", e);
+        }
+    }
+    ExitCode::SUCCESS
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn report_io(result: io::Result<()>) -> ExitCode {
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Prints a source file colored by token class.
+fn print_highlighted(path: &str) -> ExitCode {
+    match std::fs::read_to_string(path) {
+        Ok(source) => {
+            print!("{}", scheme_rs::highlight::highlight(&source));
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            eprintln!("Cannot read {}: {}", path, e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Runs a source file while recording which expressions are evaluated, then
+/// prints the coverage report to standard output. The report is printed even
+/// if evaluation fails part way.
+fn run_with_coverage(path: &str, lcov: bool) -> ExitCode {
+    let source = match std::fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("Cannot read {}: {}", path, e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let tokens = match tokenize_with_lines(&source) {
+        Ok(tokens) => tokens,
+        Err(e) => {
+            eprintln!("Lex error: {:?}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let program = match parse_program(tokens.iter().map(|(token, _)| token.clone()).collect()) {
+        Ok(program) => program,
+        Err(e) => {
+            eprintln!("Parse error: {:?}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let (coverage, result) = eval_with_coverage(&program, &tokens, default_env());
+    if lcov {
+        print!("{}", coverage.lcov(path));
+    } else {
+        print!("{}", coverage.annotate(&source));
+    }
+    match result {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Eval error: {:?}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// The exit status of a program stopped by `--timeout`, as with the
+/// `timeout` command, so that graders can tell it from a failure.
+#[cfg(not(target_arch = "wasm32"))]
+const TIMED_OUT: u8 = 124;
+
+/// Parses a duration such as `5s`, `500ms`, `2m`, or `1.5` (seconds).
+#[cfg(not(target_arch = "wasm32"))]
+fn parse_duration(text: &str) -> Option<Duration> {
+    let (number, unit) = match text.find(|c: char| c.is_ascii_alphabetic()) {
+        Some(i) => text.split_at(i),
+        None => (text, "s"),
+    };
+    let number: f64 = number.parse().ok()?;
+    let seconds = match unit {
+        "ms" => number / 1000.0,
+        "s" => number,
+        "m" => number * 60.0,
+        _ => return None,
+    };
+    Duration::try_from_secs_f64(seconds).ok()
+}
+
+/// Runs a program, interrupting it if it takes longer than `limit`, even
+/// while it sleeps or waits for input or a message.
+#[cfg(not(target_arch = "wasm32"))]
+fn run_with_timeout(path: &str, limit: Duration) -> ExitCode {
+    let source = match std::fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("Cannot read {}: {}", path, e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let interrupt = Arc::new(AtomicBool::new(false));
+    let timer = interrupt.clone();
+    std::thread::spawn(move || {
+        std::thread::sleep(limit);
+        timer.store(true, Ordering::Relaxed);
+    });
+    set_interrupt_flag(Some(interrupt));
+    let result = run_source(&source, default_env(), |_| {});
+    set_interrupt_flag(None);
+    io::stdout().flush().ok();
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(reports) if reports[0].kind == "interrupted" => {
+            let message = format!("Timed out after {:?}", limit);
+            report_failure(&ErrorReport { kind: "timeout".into(), message, ..reports[0].clone() });
+            ExitCode::from(TIMED_OUT)
+        }
+        Err(reports) => {
+            reports.iter().for_each(report_failure);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Counts the bytes allocated by the process, for `judge --memory`.
+#[cfg(not(target_arch = "wasm32"))]
+struct CountingAllocator;
+
+#[cfg(not(target_arch = "wasm32"))]
+static ALLOCATED: std::sync::atomic::AtomicUsize = std::sync::atomic::AtomicUsize::new(0);
+
+#[cfg(not(target_arch = "wasm32"))]
+unsafe impl std::alloc::GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: std::alloc::Layout) -> *mut u8 {
+        ALLOCATED.fetch_add(layout.size(), Ordering::Relaxed);
+        unsafe { std::alloc::System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: std::alloc::Layout) {
+        ALLOCATED.fetch_sub(layout.size(), Ordering::Relaxed);
+        unsafe { std::alloc::System.dealloc(ptr, layout) }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+/// Parses a number of bytes, optionally with a `K`, `M`, or `G` suffix.
+#[cfg(not(target_arch = "wasm32"))]
+fn parse_bytes(text: &str) -> Option<usize> {
+    let (number, scale) = match text.strip_suffix(['K', 'k']) {
+        Some(number) => (number, 1 << 10),
+        None => match text.strip_suffix(['M', 'm']) {
+            Some(number) => (number, 1 << 20),
+            None => match text.strip_suffix(['G', 'g']) {
+                Some(number) => (number, 1 << 30),
+                None => (text, 1),
+            },
+        },
+    };
+    number.parse::<usize>().ok()?.checked_mul(scale)
+}
+
+/// Judges a program under the limits in `options` and prints the JSON
+/// report. Fails only if the options or the file cannot be read.
+#[cfg(not(target_arch = "wasm32"))]
+fn run_judge(path: &str, options: &[&str]) -> ExitCode {
+    let mut limits = Limits::default();
+    for option in options.chunks(2) {
+        let parsed = match option {
+            ["--fuel", n] => n.parse().ok().map(|n| limits.fuel = Some(n)),
+            ["--time", duration] => parse_duration(duration).map(|d| limits.time = Some(d)),
+            ["--memory", bytes] => parse_bytes(bytes).map(|b| limits.memory = Some(b)),
+            _ => None,
+        };
+        if parsed.is_none() {
+            eprintln!("{}", USAGE);
+            return ExitCode::FAILURE;
+        }
+    }
+    let source = match std::fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("Cannot read {}: {}", path, e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let report = judge(&source, &limits, Some(|| ALLOCATED.load(Ordering::Relaxed)));
+    println!("{}", report.to_json());
+    ExitCode::SUCCESS
+}
+
+/// Evaluates the forms of a recorded session in order, printing each value
+/// as the REPL did, and stops at the first error.
+fn replay(path: &str) -> ExitCode {
+    let source = match std::fs::read_to_string(path) {
+        Ok(source) => source,
+        Err(e) => {
+            eprintln!("Cannot read {}: {}", path, e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let env = default_env();
+    let options = PrintOptions::from_env(&env);
+    match run_source(&source, env, |value| println!("{}", print_value(value, &options))) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(reports) => {
+            reports.iter().for_each(report_failure);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Loads library files into a fresh environment, then reports the
+/// procedures that call themselves, which long inputs can overflow; fails
+/// if there are any.
+fn audit(paths: &[&str]) -> ExitCode {
+    let env = default_env();
+    for path in paths {
+        let source = match std::fs::read_to_string(path) {
+            Ok(source) => source,
+            Err(e) => {
+                eprintln!("Cannot read {}: {}", path, e);
+                return ExitCode::FAILURE;
+            }
+        };
+        if let Err(reports) = run_source(&source, env.clone(), |_| {}) {
+            reports.iter().for_each(report_failure);
+            return ExitCode::FAILURE;
+        }
+    }
+    let diagnostics = audit_library(&env);
+    for diagnostic in &diagnostics {
+        report_diagnostic(diagnostic);
+    }
+    if diagnostics.is_empty() { ExitCode::SUCCESS } else { ExitCode::FAILURE }
+}
+
+/// Compiles a source file so that `load` can skip lexing and parsing it.
+fn compile(path: &str, output: &Path) -> ExitCode {
+    match compiled::compile_file(Path::new(path), output) {
+        Ok(_) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Runs the benchmarks in a directory and prints their timings. `--save`
+/// writes the medians as a baseline; `--baseline` compares against one.
+fn bench(dir: &str, options: &[&str]) -> ExitCode {
+    let mut runs = 5;
+    let mut save = None;
+    let mut baseline = None;
+    for pair in options.chunks(2) {
+        match pair {
+            ["--runs", n] if n.parse::<usize>().is_ok_and(|n| n > 0) => runs = n.parse().unwrap(),
+            ["--save", path] => save = Some(*path),
+            ["--baseline", path] => baseline = Some(*path),
+            _ => {
+                eprintln!("{}", USAGE);
+                return ExitCode::FAILURE;
+            }
+        }
+    }
+    let baseline = match baseline.map(|path| {
+        std::fs::read_to_string(path)
+            .map_err(|e| format!("Cannot read {}: {}", path, e))
+            .and_then(|text| bench::parse_baseline(&text))
+    }).transpose() {
+        Ok(baseline) => baseline,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    let results = match bench::run_dir(Path::new(dir), runs) {
+        Ok(results) => results,
+        Err(e) => {
+            eprintln!("{}", e);
+            return ExitCode::FAILURE;
+        }
+    };
+    print!("{}", bench::report(&results, baseline.as_ref()));
+    if let Some(path) = save
+        && let Err(e) = std::fs::write(path, bench::baseline_json(&results))
+    {
+        eprintln!("Cannot write {}: {}", path, e);
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,150 @@
+//! Continuation marks: key-value annotations on the evaluation in
+//! progress, which code and tools can read back while it runs.
+//!
+//! `(with-continuation-mark key value body ...)` evaluates the body with a
+//! mark from `key` to `value` on the dynamic context, removed again when
+//! the body finishes or fails. Scheme code reads the marks with
+//! `current-continuation-marks`, `continuation-mark-set->list`, and
+//! `continuation-mark-set-first`; debuggers, profilers, and other tools
+//! call `continuation_marks` from their evaluation hooks (see
+//! `eval::set_hooks`), for instance to attribute time to the request or
+//! source position a program marked.
+//!
+//! Tail calls are not optimized, so unlike in Racket a mark set in tail
+//! position adds to the marks of the enclosing expression instead of
+//! replacing a mark with the same key.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::ast::Expr;
+use crate::env::{Env, EvalError, Value};
+use crate::eval::{eval, eval_sequence};
+
+thread_local! {
+    /// The marks in effect, outermost first.
+    static MARKS: RefCell<Vec<(Value, Value)>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Returns the marks in effect on the current thread, innermost first.
+pub fn continuation_marks() -> Vec<(Value, Value)> {
+    MARKS.with(|m| m.borrow().iter().rev().cloned().collect())
+}
+
+/// Returns the values of the marks with `key` in effect on the current
+/// thread, innermost first.
+pub fn continuation_mark_values(key: &Value) -> Vec<Value> {
+    MARKS.with(|m| m.borrow().iter().rev().filter(|(k, _)| k == key).map(|(_, v)| v.clone()).collect())
+}
+
+/// Replaces the marks in effect, for switching between fibers, which each
+/// have their own.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn swap_marks(marks: Vec<(Value, Value)>) -> Vec<(Value, Value)> {
+    MARKS.with(|m| std::mem::replace(&mut *m.borrow_mut(), marks))
+}
+
+/// `(with-continuation-mark key value body ...)`
+pub fn eval_with_continuation_mark(list: &[Expr], env: Rc<Env>) -> Result<Value, EvalError> {
+    let [_, key, value, body @ ..] = list else { return Err(EvalError::ArityMismatch) };
+    if body.is_empty() {
+        return Err(EvalError::ArityMismatch);
+    }
+    let key = eval(key, env.clone())?;
+    let value = eval(value, env.clone())?;
+    MARKS.with(|m| m.borrow_mut().push((key, value)));
+    let result = eval_sequence(body, env);
+    MARKS.with(|m| m.borrow_mut().pop());
+    result
+}
+
+/// `(current-continuation-marks)` returns the marks in effect as a list of
+/// `(key value)` lists, innermost first.
+pub fn builtin_current_continuation_marks(_args: Vec<Value>) -> Result<Value, EvalError> {
+    Ok(marks_to_list(continuation_marks()))
+}
+
+fn marks_to_list(marks: Vec<(Value, Value)>) -> Value {
+    Value::List(marks.into_iter().map(|(key, value)| Value::List([key, value].into())).collect())
+}
+
+/// The marks a procedure was given: a list from `current-continuation-marks`,
+/// or `#f` for the marks in effect now.
+fn mark_set(value: &Value, name: &str) -> Result<Vec<(Value, Value)>, EvalError> {
+    match value {
+        Value::Boolean(false) => Ok(continuation_marks()),
+        Value::List(marks) => marks.iter().map(|mark| match mark {
+            Value::List(pair) if pair.len() == 2 => Ok((pair[0].clone(), pair[1].clone())),
+            _ => Err(EvalError::TypeError(format!("{}: expected a mark set, got {}", name, value.write_string()))),
+        }).collect(),
+        other => Err(EvalError::TypeError(format!("{}: expected a mark set, got {}", name, other.type_name()))),
+    }
+}
+
+/// `(continuation-mark-set->list marks key)` returns the values of the
+/// marks with `key`, innermost first. `marks` may be `#f` for the current
+/// marks.
+pub fn builtin_continuation_mark_set_to_list(args: Vec<Value>) -> Result<Value, EvalError> {
+    let marks = mark_set(&args[0], "continuation-mark-set->list")?;
+    Ok(Value::List(marks.into_iter().filter(|(k, _)| *k == args[1]).map(|(_, v)| v).collect()))
+}
+
+/// `(continuation-mark-set-first marks key [default])` returns the value of
+/// the innermost mark with `key`, or `default` (`#f` if not given).
+pub fn builtin_continuation_mark_set_first(args: Vec<Value>) -> Result<Value, EvalError> {
+    let marks = mark_set(&args[0], "continuation-mark-set-first")?;
+    let default = args.get(2).cloned().unwrap_or(Value::Boolean(false));
+    Ok(marks.into_iter().find(|(k, _)| *k == args[1]).map_or(default, |(_, v)| v))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::default_env;
+    use crate::testing::eval_source;
+
+    #[test]
+    fn test_marks_follow_the_dynamic_context() {
+        let result = eval_source("
+            (define (where) (continuation-mark-set->list (current-continuation-marks) 'where))
+            (define (handler) (with-continuation-mark 'where 'handler (where)))
+            (list
+              (with-continuation-mark 'where 'request
+                (with-continuation-mark 'user \"ann\"
+                  (list (handler) (continuation-mark-set-first #f 'user))))
+              (where)
+              (continuation-mark-set-first #f 'user 'nobody))", &default_env()).unwrap();
+        assert_eq!(result.to_string(), "(((handler request) \"ann\") () nobody)");
+    }
+
+    #[test]
+    fn test_marks_are_removed_when_the_body_fails() {
+        let result = eval_source("
+            (guard (e (#t (current-continuation-marks)))
+              (with-continuation-mark 'k 1 (car 1)))", &default_env()).unwrap();
+        assert_eq!(result.to_string(), "()");
+        assert!(continuation_marks().is_empty());
+    }
+
+    #[test]
+    fn test_tools_read_marks_during_evaluation() {
+        use crate::eval::{set_hooks, EvalHooks};
+        use std::cell::RefCell;
+
+        #[derive(Default)]
+        struct Seen(RefCell<Vec<Value>>);
+        impl EvalHooks for Seen {
+            fn on_apply(&self, func: &Value, _args: &[Value]) {
+                if matches!(func, Value::Lambda(_)) {
+                    self.0.borrow_mut().extend(continuation_mark_values(&Value::Symbol("phase".into())));
+                }
+            }
+        }
+        let seen = Rc::new(Seen::default());
+        set_hooks(Some(seen.clone()));
+        let result = eval_source("(define (f) 1) (with-continuation-mark 'phase 'setup (f))", &default_env());
+        set_hooks(None);
+        result.unwrap();
+        assert_eq!(*seen.0.borrow(), vec![Value::Symbol("setup".into())]);
+    }
+}
@@ -0,0 +1,150 @@
+//! Mutexes for fibers, which can be switched at any checkpoint and so
+//! interleave their updates to shared data: `(mutex-lock! m)` waits until
+//! no other fiber holds `m`, letting the others run meanwhile, and
+//! `(with-mutex m body ...)` holds `m` while evaluating the body.
+//!
+//! Waiting goes through the fiber scheduler (see `fiber::wait_until`), so
+//! a deadlock, with every fiber waiting for a mutex another holds, fails
+//! instead of hanging, as does a fiber locking a mutex it already holds.
+//! Actors on other threads share no values, so they have no use for these.
+
+use std::cell::Cell;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::ast::Expr;
+use crate::env::{Env, EvalError, Value};
+use crate::eval::{eval, eval_sequence};
+use crate::fiber::{running_fiber, wait_until};
+
+/// A mutex from `make-mutex`.
+#[derive(Default)]
+pub struct Mutex {
+    /// The fiber holding it (see `fiber::running_fiber`).
+    owner: Cell<Option<u64>>,
+}
+
+impl fmt::Debug for Mutex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Mutex").field("owner", &self.owner.get()).finish()
+    }
+}
+
+impl Mutex {
+    fn lock(&self) -> Result<(), EvalError> {
+        let me = running_fiber();
+        if self.owner.get() == Some(me) {
+            return Err(EvalError::Other("mutex-lock!: this fiber already holds the mutex, so it would wait forever".into()));
+        }
+        wait_until("mutex-lock!", || self.owner.get().is_none())?;
+        self.owner.set(Some(me));
+        Ok(())
+    }
+
+    fn unlock(&self) -> Result<(), EvalError> {
+        if self.owner.get() != Some(running_fiber()) {
+            return Err(EvalError::Other("mutex-unlock!: the mutex is not held by this fiber".into()));
+        }
+        self.owner.set(None);
+        Ok(())
+    }
+}
+
+fn mutex_arg<'a>(value: &'a Value, name: &str) -> Result<&'a Rc<Mutex>, EvalError> {
+    match value {
+        Value::Mutex(mutex) => Ok(mutex),
+        other => Err(EvalError::TypeError(format!("{}: expected a mutex, got {}", name, other.type_name()))),
+    }
+}
+
+/// `(make-mutex)` returns a new, unlocked mutex.
+pub fn builtin_make_mutex(_args: Vec<Value>) -> Result<Value, EvalError> {
+    Ok(Value::Mutex(Rc::default()))
+}
+
+/// Returns true if the argument is a mutex.
+pub fn builtin_is_mutex(args: Vec<Value>) -> Result<Value, EvalError> {
+    Ok(Value::Boolean(matches!(args[0], Value::Mutex(_))))
+}
+
+/// `(mutex-lock! m)` waits until no other fiber holds the mutex, then
+/// takes it.
+pub fn builtin_mutex_lock(args: Vec<Value>) -> Result<Value, EvalError> {
+    mutex_arg(&args[0], "mutex-lock!")?.lock()?;
+    Ok(Value::Boolean(true))
+}
+
+/// `(mutex-unlock! m)` releases a mutex the calling fiber holds.
+pub fn builtin_mutex_unlock(args: Vec<Value>) -> Result<Value, EvalError> {
+    mutex_arg(&args[0], "mutex-unlock!")?.unlock()?;
+    Ok(Value::Boolean(true))
+}
+
+/// `(mutex-locked? m)` returns whether any fiber holds the mutex.
+pub fn builtin_is_mutex_locked(args: Vec<Value>) -> Result<Value, EvalError> {
+    Ok(Value::Boolean(mutex_arg(&args[0], "mutex-locked?")?.owner.get().is_some()))
+}
+
+/// `(with-mutex m body ...)` evaluates the body holding the mutex, which is
+/// released however the body finishes.
+pub fn eval_with_mutex(list: &[Expr], env: Rc<Env>) -> Result<Value, EvalError> {
+    let [_, mutex, body @ ..] = list else { return Err(EvalError::ArityMismatch) };
+    if body.is_empty() {
+        return Err(EvalError::ArityMismatch);
+    }
+    let mutex = eval(mutex, env.clone())?;
+    let mutex = mutex_arg(&mutex, "with-mutex")?;
+    mutex.lock()?;
+    let result = eval_sequence(body, env);
+    mutex.unlock()?;
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{assert_fails, assert_prints};
+
+    #[test]
+    fn test_mutex_keeps_updates_whole() {
+        // Each fiber reads the balance, lets the others run, and writes it
+        // back; without the mutex all but one of the deposits would be lost.
+        let source = |locked: bool| format!("
+            (define balance (make-vector 1 0))
+            (define m (make-mutex))
+            (define (deposit)
+              (let ((old (vector-ref balance 0)))
+                (yield)
+                (vector-set! balance 0 (+ old 10))))
+            (define fibers
+              (map (lambda (i) (spawn-fiber (lambda () {}))) (list 1 2 3)))
+            (for-each join fibers)
+            (list (vector-ref balance 0) (mutex-locked? m))",
+            if locked { "(with-mutex m (deposit))" } else { "(deposit)" });
+        assert_prints(&source(true), "(30 #f)");
+        assert_prints(&source(false), "(10 #f)");
+    }
+
+    #[test]
+    fn test_mutex_misuse_and_deadlock_fail() {
+        assert_prints("
+            (define m (make-mutex))
+            (list (mutex? m) (mutex-lock! m) (mutex-locked? m)
+                  (guard (e (#t (condition-message e))) (mutex-lock! m))
+                  (mutex-unlock! m) (mutex-locked? m))",
+            "(#t #t #t \"mutex-lock!: this fiber already holds the mutex, so it would wait forever\" #t #f)");
+        assert_fails("(mutex-unlock! (make-mutex))", "error");
+        assert_prints("
+            (define m (make-mutex))
+            (guard (e (#t 'released)) (with-mutex m (car 1)))
+            (mutex-locked? m)",
+            "#f");
+        // Each fiber holds one mutex and waits for the other's.
+        assert_fails("
+            (define a (make-mutex))
+            (define b (make-mutex))
+            (define (both first second) (lambda () (with-mutex first (yield) (with-mutex second 'done))))
+            (define f (spawn-fiber (both a b)))
+            (define g (spawn-fiber (both b a)))
+            (join f)", "error");
+    }
+}
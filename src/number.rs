@@ -0,0 +1,486 @@
+use std::cmp::Ordering;
+use std::fmt;
+
+use crate::env::{EvalError, Value};
+
+/// A Scheme number, used by the numeric built-ins.
+///
+/// Arithmetic follows the usual contagion rules: an operation involving an
+/// inexact (`Real`) number produces an inexact result, and exact integers
+/// combine with rationals to produce rationals. Rationals are always kept
+/// in lowest terms with a positive denominator greater than one, so an
+/// exact result that happens to be whole is an `Integer`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Number {
+    Integer(i64),
+    Rational(i64, i64),
+    Real(f64),
+}
+
+impl Number {
+    /// Builds an exact number from a numerator and denominator, reducing it
+    /// to lowest terms. Errors on a zero denominator or if the result does
+    /// not fit in 64 bits.
+    pub fn rational(num: i64, den: i64) -> Result<Number, EvalError> {
+        Number::from_ratio(num as i128, den as i128)
+    }
+
+    fn from_ratio(num: i128, den: i128) -> Result<Number, EvalError> {
+        if den == 0 {
+            return Err(EvalError::Other("Division by zero".into()));
+        }
+        let divisor = gcd(num, den) * den.signum();
+        let (num, den) = (num / divisor, den / divisor);
+        let fit = |n: i128| i64::try_from(n).map_err(|_| EvalError::Other("Integer overflow".into()));
+        if den == 1 {
+            Ok(Number::Integer(fit(num)?))
+        } else {
+            Ok(Number::Rational(fit(num)?, fit(den)?))
+        }
+    }
+
+    /// Returns the exact numerator and denominator, or `None` for inexact numbers.
+    fn ratio(self) -> Option<(i128, i128)> {
+        match self {
+            Number::Integer(n) => Some((n as i128, 1)),
+            Number::Rational(n, d) => Some((n as i128, d as i128)),
+            Number::Real(_) => None,
+        }
+    }
+
+    pub fn is_exact(self) -> bool {
+        !matches!(self, Number::Real(_))
+    }
+
+    pub fn is_zero(self) -> bool {
+        match self {
+            Number::Integer(n) => n == 0,
+            Number::Rational(..) => false,
+            Number::Real(x) => x == 0.0,
+        }
+    }
+
+    pub fn to_f64(self) -> f64 {
+        match self {
+            Number::Integer(n) => n as f64,
+            Number::Rational(n, d) => n as f64 / d as f64,
+            Number::Real(x) => x,
+        }
+    }
+
+    /// Returns the number as an exact integer, if it is one.
+    pub fn as_integer(self) -> Option<i64> {
+        match self {
+            Number::Integer(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    pub fn checked_add(self, other: Number) -> Result<Number, EvalError> {
+        match (self.ratio(), other.ratio()) {
+            (Some((a, b)), Some((c, d))) => Number::from_ratio(a * d + c * b, b * d),
+            _ => Ok(Number::Real(self.to_f64() + other.to_f64())),
+        }
+    }
+
+    pub fn checked_sub(self, other: Number) -> Result<Number, EvalError> {
+        match (self.ratio(), other.ratio()) {
+            (Some((a, b)), Some((c, d))) => Number::from_ratio(a * d - c * b, b * d),
+            _ => Ok(Number::Real(self.to_f64() - other.to_f64())),
+        }
+    }
+
+    pub fn checked_mul(self, other: Number) -> Result<Number, EvalError> {
+        match (self.ratio(), other.ratio()) {
+            (Some((a, b)), Some((c, d))) => Number::from_ratio(a * c, b * d),
+            _ => Ok(Number::Real(self.to_f64() * other.to_f64())),
+        }
+    }
+
+    /// Divides two numbers. Exact division by zero is an error; inexact
+    /// division follows IEEE semantics.
+    pub fn checked_div(self, other: Number) -> Result<Number, EvalError> {
+        match (self.ratio(), other.ratio()) {
+            (Some((a, b)), Some((c, d))) => Number::from_ratio(a * d, b * c),
+            _ => Ok(Number::Real(self.to_f64() / other.to_f64())),
+        }
+    }
+
+    /// Compares two numbers by value, regardless of exactness.
+    /// Returns `None` if either is NaN.
+    pub fn compare(self, other: Number) -> Option<Ordering> {
+        match (self.ratio(), other.ratio()) {
+            (Some((a, b)), Some((c, d))) => Some((a * d).cmp(&(c * b))),
+            _ => self.to_f64().partial_cmp(&other.to_f64()),
+        }
+    }
+
+    /// Converts to an exact number. Errors for infinities, NaN, and floats
+    /// whose exact value does not fit in 64 bits.
+    pub fn to_exact(self) -> Result<Number, EvalError> {
+        let x = match self {
+            Number::Real(x) => x,
+            exact => return Ok(exact),
+        };
+        let no_exact = || EvalError::Other(format!("No exact representation for {}", format_float(x)));
+        if !x.is_finite() {
+            return Err(no_exact());
+        }
+        let (mut num, mut den) = (x, 1i64);
+        while num.fract() != 0.0 {
+            den = den.checked_mul(2).ok_or_else(no_exact)?;
+            num *= 2.0;
+        }
+        if num < i64::MIN as f64 || num >= i64::MAX as f64 {
+            return Err(no_exact());
+        }
+        Number::rational(num as i64, den)
+    }
+
+    pub fn to_inexact(self) -> Number {
+        Number::Real(self.to_f64())
+    }
+
+    pub fn floor(self) -> Number {
+        self.round_with(|n, d| n.div_euclid(d), f64::floor)
+    }
+
+    pub fn ceiling(self) -> Number {
+        self.round_with(|n, d| {
+            let floor = n.div_euclid(d);
+            if n.rem_euclid(d) == 0 { floor } else { floor + 1 }
+        }, f64::ceil)
+    }
+
+    pub fn truncate(self) -> Number {
+        self.round_with(|n, d| n / d, f64::trunc)
+    }
+
+    /// Rounds to the nearest integer, choosing the even one on ties.
+    pub fn round(self) -> Number {
+        self.round_with(|n, d| {
+            let (floor, rem) = (n.div_euclid(d), n.rem_euclid(d));
+            match rem.cmp(&(d - rem)) {
+                Ordering::Less => floor,
+                Ordering::Greater => floor + 1,
+                Ordering::Equal => floor + floor.rem_euclid(2),
+            }
+        }, f64::round_ties_even)
+    }
+
+    /// Rounds a rational with an integer function of its (positive) denominator,
+    /// or a real with a float function. Integers are returned unchanged.
+    fn round_with(self, exact: fn(i64, i64) -> i64, inexact: fn(f64) -> f64) -> Number {
+        match self {
+            Number::Integer(n) => Number::Integer(n),
+            Number::Rational(n, d) => Number::Integer(exact(n, d)),
+            Number::Real(x) => Number::Real(inexact(x)),
+        }
+    }
+
+    /// Writes the number in the given radix. Inexact numbers can only be
+    /// written in radix 10.
+    pub fn to_string_radix(self, radix: u32) -> Result<String, EvalError> {
+        match self {
+            Number::Integer(n) => Ok(format_radix(n, radix)),
+            Number::Rational(n, d) => Ok(format!("{}/{}", format_radix(n, radix), format_radix(d, radix))),
+            Number::Real(x) if radix == 10 => Ok(format_float(x)),
+            Number::Real(_) => Err(EvalError::Other("Inexact numbers can only be written in radix 10".into())),
+        }
+    }
+
+    /// Parses numeric text in the given default radix.
+    ///
+    /// Accepts `#x`, `#o`, `#b`, `#d`, `#e`, and `#i` prefixes, integers,
+    /// fractions like `1/3`, and (in radix 10) decimals with exponents.
+    /// Returns `None` if the text is not a number.
+    pub fn parse(text: &str, default_radix: u32) -> Option<Number> {
+        let mut radix = None;
+        let mut exactness = None;
+        let mut rest = text;
+        while let Some(prefixed) = rest.strip_prefix('#') {
+            let mut chars = prefixed.chars();
+            let duplicate = match chars.next()?.to_ascii_lowercase() {
+                'x' => radix.replace(16).is_some(),
+                'o' => radix.replace(8).is_some(),
+                'b' => radix.replace(2).is_some(),
+                'd' => radix.replace(10).is_some(),
+                'e' => exactness.replace(true).is_some(),
+                'i' => exactness.replace(false).is_some(),
+                _ => return None,
+            };
+            if duplicate {
+                return None;
+            }
+            rest = chars.as_str();
+        }
+        let radix = radix.unwrap_or(default_radix);
+
+        let unsigned = rest.strip_prefix(['+', '-']).unwrap_or(rest);
+        if unsigned.is_empty() {
+            return None;
+        }
+        let is_digits = |s: &str| !s.is_empty() && s.chars().all(|c| c.is_digit(radix));
+
+        let number = if let Some(x) = special_float(rest) {
+            Number::Real(x)
+        } else if is_digits(unsigned) {
+            match i64::from_str_radix(rest, radix) {
+                Ok(n) => Number::Integer(n),
+                Err(_) if radix == 10 => Number::Real(rest.parse::<f64>().ok()?),
+                Err(_) => return None,
+            }
+        } else if let Some((num, den)) = rest.split_once('/') {
+            if !is_digits(unsigned.split_once('/')?.0) || !is_digits(den) {
+                return None;
+            }
+            let num = i64::from_str_radix(num, radix).ok()?;
+            let den = i64::from_str_radix(den, radix).ok()?;
+            Number::rational(num, den).ok()?
+        } else if radix == 10 && is_decimal(unsigned) {
+            Number::Real(rest.parse::<f64>().ok()?)
+        } else {
+            return None;
+        };
+
+        match exactness {
+            Some(true) => number.to_exact().ok(),
+            Some(false) => Some(number.to_inexact()),
+            None => Some(number),
+        }
+    }
+}
+
+impl TryFrom<&Value> for Number {
+    type Error = EvalError;
+
+    fn try_from(value: &Value) -> Result<Self, Self::Error> {
+        match value {
+            Value::Number(n) => Ok(Number::Integer(*n)),
+            Value::Rational(n, d) => Ok(Number::Rational(*n, *d)),
+            Value::Float(x) => Ok(Number::Real(*x)),
+            _ => Err(EvalError::TypeError("Expected number".into())),
+        }
+    }
+}
+
+impl From<Number> for Value {
+    fn from(number: Number) -> Self {
+        match number {
+            Number::Integer(n) => Value::Number(n),
+            Number::Rational(n, d) => Value::Rational(n, d),
+            Number::Real(x) => Value::Float(x),
+        }
+    }
+}
+
+impl fmt::Display for Number {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Number::Integer(n) => write!(f, "{}", n),
+            Number::Rational(n, d) => write!(f, "{}/{}", n, d),
+            Number::Real(x) => write!(f, "{}", format_float(*x)),
+        }
+    }
+}
+
+/// Formats a float the way Scheme prints inexact numbers: always with a
+/// decimal point or exponent, so `1.0` never reads back as an integer, and
+/// infinities and NaN as `+inf.0`, `-inf.0`, and `+nan.0`.
+pub fn format_float(x: f64) -> String {
+    match x {
+        x if x.is_nan() => "+nan.0".into(),
+        f64::INFINITY => "+inf.0".into(),
+        f64::NEG_INFINITY => "-inf.0".into(),
+        x => format!("{:?}", x),
+    }
+}
+
+/// Reads `+inf.0`, `-inf.0`, `+nan.0`, and `-nan.0`.
+fn special_float(text: &str) -> Option<f64> {
+    match text {
+        "+inf.0" => Some(f64::INFINITY),
+        "-inf.0" => Some(f64::NEG_INFINITY),
+        "+nan.0" | "-nan.0" => Some(f64::NAN),
+        _ => None,
+    }
+}
+
+/// Formats a number in fixed-point notation with `digits` digits after the
+/// decimal point, or without an exponent but otherwise as `format_float`
+/// does if `digits` is `None`.
+pub fn format_fixed(x: f64, digits: Option<usize>) -> String {
+    if !x.is_finite() {
+        return format_float(x);
+    }
+    match digits {
+        Some(digits) => format!("{:.*}", digits, x),
+        None => {
+            let text = format!("{}", x);
+            if text.contains('.') { text } else { text + ".0" }
+        }
+    }
+}
+
+/// Formats a number in scientific notation with `digits` digits after the
+/// decimal point, or as few as read back as the same float if `digits` is
+/// `None`.
+pub fn format_scientific(x: f64, digits: Option<usize>) -> String {
+    if !x.is_finite() {
+        return format_float(x);
+    }
+    match digits {
+        Some(digits) => format!("{:.*e}", digits, x),
+        None => format!("{:e}", x),
+    }
+}
+
+/// Rounds a float to `digits` significant digits, at least one.
+pub fn round_significant(x: f64, digits: usize) -> f64 {
+    format!("{:.*e}", digits.max(1) - 1, x).parse().unwrap_or(x)
+}
+
+/// Writes an integer in the given radix using lowercase digits.
+fn format_radix(n: i64, radix: u32) -> String {
+    let mut magnitude = n.unsigned_abs();
+    let mut digits = Vec::new();
+    loop {
+        let digit = (magnitude % radix as u64) as u32;
+        digits.push(std::char::from_digit(digit, radix).unwrap());
+        magnitude /= radix as u64;
+        if magnitude == 0 {
+            break;
+        }
+    }
+    if n < 0 {
+        digits.push('-');
+    }
+    digits.iter().rev().collect()
+}
+
+/// Checks for decimal syntax: digits with an optional point and exponent.
+fn is_decimal(text: &str) -> bool {
+    let (mantissa, exponent) = match text.find(['e', 'E']) {
+        Some(i) => (&text[..i], Some(&text[i + 1..])),
+        None => (text, None),
+    };
+
+    let all_digits = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+    let (int_part, frac_part) = mantissa.split_once('.').unwrap_or((mantissa, ""));
+    if int_part.is_empty() && frac_part.is_empty() {
+        return false;
+    }
+    if !all_digits(int_part) || !all_digits(frac_part) {
+        return false;
+    }
+
+    match exponent {
+        None => true,
+        Some(exp) => {
+            let exp = exp.strip_prefix(['+', '-']).unwrap_or(exp);
+            !exp.is_empty() && all_digits(exp)
+        }
+    }
+}
+
+fn gcd(a: i128, b: i128) -> i128 {
+    let (mut a, mut b) = (a.abs(), b.abs());
+    while b != 0 {
+        (a, b) = (b, a % b);
+    }
+    a.max(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rational_reduces_to_lowest_terms() {
+        assert_eq!(Number::rational(6, -4).unwrap(), Number::Rational(-3, 2));
+        assert_eq!(Number::rational(8, 4).unwrap(), Number::Integer(2));
+    }
+
+    #[test]
+    fn test_rational_zero_denominator() {
+        assert!(matches!(Number::rational(1, 0), Err(EvalError::Other(_))));
+    }
+
+    #[test]
+    fn test_integer_plus_rational_is_rational() {
+        let result = Number::Integer(1).checked_add(Number::Rational(1, 2)).unwrap();
+        assert_eq!(result, Number::Rational(3, 2));
+    }
+
+    #[test]
+    fn test_exact_plus_inexact_is_inexact() {
+        let result = Number::Rational(1, 2).checked_add(Number::Real(0.25)).unwrap();
+        assert_eq!(result, Number::Real(0.75));
+    }
+
+    #[test]
+    fn test_exact_division_produces_rational() {
+        let result = Number::Integer(1).checked_div(Number::Integer(3)).unwrap();
+        assert_eq!(result, Number::Rational(1, 3));
+    }
+
+    #[test]
+    fn test_integer_overflow_is_an_error() {
+        let result = Number::Integer(i64::MAX).checked_add(Number::Integer(1));
+        assert!(matches!(result, Err(EvalError::Other(_))));
+    }
+
+    #[test]
+    fn test_compare_across_exactness() {
+        assert_eq!(Number::Integer(1).compare(Number::Real(1.0)), Some(Ordering::Equal));
+        assert_eq!(Number::Rational(1, 3).compare(Number::Real(0.5)), Some(Ordering::Less));
+        assert_eq!(Number::Real(f64::NAN).compare(Number::Integer(0)), None);
+    }
+
+    #[test]
+    fn test_to_exact_from_float() {
+        assert_eq!(Number::Real(0.5).to_exact().unwrap(), Number::Rational(1, 2));
+        assert_eq!(Number::Real(-3.0).to_exact().unwrap(), Number::Integer(-3));
+        assert!(Number::Real(f64::INFINITY).to_exact().is_err());
+    }
+
+    #[test]
+    fn test_rational_rounding() {
+        let x = Number::Rational(-5, 2);
+        assert_eq!(x.floor(), Number::Integer(-3));
+        assert_eq!(x.ceiling(), Number::Integer(-2));
+        assert_eq!(x.truncate(), Number::Integer(-2));
+        assert_eq!(x.round(), Number::Integer(-2));
+        assert_eq!(Number::Rational(7, 2).round(), Number::Integer(4));
+    }
+
+    #[test]
+    fn test_parse_fraction() {
+        assert_eq!(Number::parse("-6/4", 10), Some(Number::Rational(-3, 2)));
+        assert_eq!(Number::parse("#x1/a", 10), Some(Number::Rational(1, 10)));
+        assert_eq!(Number::parse("1/0", 10), None);
+        assert_eq!(Number::parse("1/-2", 10), None);
+    }
+
+    #[test]
+    fn test_parse_exact_decimal() {
+        assert_eq!(Number::parse("#e1.25", 10), Some(Number::Rational(5, 4)));
+    }
+
+    #[test]
+    fn test_special_floats_round_trip() {
+        for text in ["+inf.0", "-inf.0", "+nan.0"] {
+            let number = Number::parse(text, 10).unwrap();
+            assert_eq!(number.to_string(), text);
+        }
+        assert_eq!(Number::parse("-nan.0", 10).unwrap().to_string(), "+nan.0");
+        assert_eq!(Number::parse("inf.0", 10), None);
+        assert_eq!(format_fixed(f64::NEG_INFINITY, Some(2)), "-inf.0");
+    }
+
+    #[test]
+    fn test_to_string_radix() {
+        assert_eq!(Number::Rational(-1, 16).to_string_radix(16).unwrap(), "-1/10");
+        assert!(Number::Real(1.5).to_string_radix(2).is_err());
+    }
+}
@@ -0,0 +1,234 @@
+use std::cell::RefCell;
+use std::collections::HashSet;
+use std::rc::Rc;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use crate::ast::Expr;
+use crate::env::{default_env, EvalError, Value};
+use crate::eval::{eval, eval_sequence, is_pure_mode, recursion_limit, set_pure_mode, set_recursion_limit};
+
+/// Stack size of each worker thread, enough for the default recursion limit
+/// in debug builds.
+pub(crate) const WORKER_STACK_SIZE: usize = 8 * 1024 * 1024;
+
+/// A value that can cross threads: plain data, copied out of a worker's
+/// heap. Procedures, ports, promises, queues, and records stay behind.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Datum {
+    Number(i64),
+    Rational(i64, i64),
+    Float(f64),
+    Boolean(bool),
+    String(String),
+    Symbol(String),
+    Keyword(String),
+    Char(char),
+    Pid(u64),
+    List(Vec<Datum>),
+    Vector(Vec<Datum>),
+    HashTable(Vec<(Datum, Datum)>),
+    Values(Vec<Datum>),
+    Eof,
+    Unspecified,
+}
+
+impl Datum {
+    /// Copies a value out of the interpreter. Fails with a type error for
+    /// values that are not plain data and for cyclic vectors or tables.
+    pub fn from_value(value: &Value) -> Result<Datum, EvalError> {
+        copy_datum(value, &mut HashSet::new())
+    }
+
+    /// Rebuilds the value in the current thread. Vectors and hash tables
+    /// become fresh, unshared objects.
+    pub fn into_value(self) -> Value {
+        match self {
+            Datum::Number(n) => Value::Number(n),
+            Datum::Rational(n, d) => Value::Rational(n, d),
+            Datum::Float(f) => Value::Float(f),
+            Datum::Boolean(b) => Value::Boolean(b),
+            Datum::String(s) => Value::String(s.into()),
+            Datum::Symbol(s) => Value::Symbol(s.into()),
+            Datum::Keyword(k) => Value::Keyword(k.into()),
+            Datum::Char(c) => Value::Char(c),
+            Datum::Pid(pid) => Value::Pid(pid),
+            Datum::List(items) => Value::List(items.into_iter().map(Datum::into_value).collect()),
+            Datum::Vector(items) => {
+                let items = items.into_iter().map(Datum::into_value).collect();
+                Value::Vector(Rc::new(RefCell::new(items)))
+            }
+            Datum::HashTable(entries) => {
+                let table = entries.into_iter()
+                    .map(|(key, value)| (key.into_value(), value.into_value()))
+                    .collect();
+                Value::HashTable(Rc::new(RefCell::new(table)))
+            }
+            Datum::Values(items) => Value::Values(items.into_iter().map(Datum::into_value).collect()),
+            Datum::Eof => Value::Eof,
+            Datum::Unspecified => Value::Unspecified,
+        }
+    }
+}
+
+/// `open` holds the containers being copied, to detect cycles.
+fn copy_datum(value: &Value, open: &mut HashSet<*const ()>) -> Result<Datum, EvalError> {
+    let datum = match value {
+        Value::Number(n) => Datum::Number(*n),
+        Value::Rational(n, d) => Datum::Rational(*n, *d),
+        Value::Float(f) => Datum::Float(*f),
+        Value::Boolean(b) => Datum::Boolean(*b),
+        Value::String(s) => Datum::String(s.to_string()),
+        Value::Symbol(s) => Datum::Symbol(s.to_string()),
+        Value::Keyword(k) => Datum::Keyword(k.to_string()),
+        Value::Char(c) => Datum::Char(*c),
+        Value::Pid(pid) => Datum::Pid(*pid),
+        Value::List(items) => Datum::List(copy_all(items, open)?),
+        Value::Values(items) => Datum::Values(copy_all(items, open)?),
+        Value::Eof => Datum::Eof,
+        Value::Unspecified => Datum::Unspecified,
+        Value::Vector(items) => {
+            let ptr = Rc::as_ptr(items) as *const ();
+            enter_container(ptr, open)?;
+            let datum = Datum::Vector(copy_all(&items.borrow(), open)?);
+            open.remove(&ptr);
+            datum
+        }
+        Value::HashTable(table) => {
+            let ptr = Rc::as_ptr(table) as *const ();
+            enter_container(ptr, open)?;
+            let entries = table.borrow().iter()
+                .map(|(key, value)| Ok((copy_datum(key, open)?, copy_datum(value, open)?)))
+                .collect::<Result<Vec<_>, EvalError>>()?;
+            open.remove(&ptr);
+            Datum::HashTable(entries)
+        }
+        other => {
+            return Err(EvalError::TypeError(format!(
+                "Cannot send a {} between threads", other.type_name()
+            )));
+        }
+    };
+    Ok(datum)
+}
+
+fn copy_all(items: &[Value], open: &mut HashSet<*const ()>) -> Result<Vec<Datum>, EvalError> {
+    items.iter().map(|item| copy_datum(item, open)).collect()
+}
+
+fn enter_container(ptr: *const (), open: &mut HashSet<*const ()>) -> Result<(), EvalError> {
+    if !open.insert(ptr) {
+        return Err(EvalError::TypeError("Cannot send a cyclic value between threads".into()));
+    }
+    Ok(())
+}
+
+/// Evaluates independent expressions on a pool of worker threads, returning
+/// one result per expression in the original order.
+///
+/// Every worker has its own global environment: a fresh `default_env` in
+/// which the `prelude` forms are evaluated first, so procedures the
+/// expressions need should be defined there. Nothing is shared between
+/// workers. A `define` or `vector-set!` in one expression may be seen by
+/// later expressions on the same worker but never by the others, and which
+/// worker runs which expression is unspecified, so expressions should not
+/// rely on each other's effects.
+///
+/// Workers inherit the calling thread's recursion limit and pure mode, but
+/// not its fuel limit or hooks. Results are copied back as `Datum`s; an
+/// expression that produces a procedure or other non-data value fails with
+/// a type error.
+pub fn eval_parallel(prelude: &[Expr], exprs: &[Expr]) -> Vec<Result<Datum, EvalError>> {
+    let workers = thread::available_parallelism()
+        .map_or(1, |n| n.get())
+        .min(exprs.len());
+    let limit = recursion_limit();
+    let pure = is_pure_mode();
+    let next = AtomicUsize::new(0);
+    let results: Mutex<Vec<Option<Result<Datum, EvalError>>>> =
+        Mutex::new(exprs.iter().map(|_| None).collect());
+
+    thread::scope(|scope| {
+        for _ in 0..workers {
+            thread::Builder::new()
+                .stack_size(WORKER_STACK_SIZE)
+                .spawn_scoped(scope, || {
+                    set_recursion_limit(limit);
+                    set_pure_mode(pure);
+                    let env = default_env();
+                    let prelude_result = eval_sequence(prelude, env.clone())
+                        .map_err(|e| format!("{:?}", e));
+                    loop {
+                        let index = next.fetch_add(1, Ordering::Relaxed);
+                        let Some(expr) = exprs.get(index) else { break };
+                        let result = match &prelude_result {
+                            Ok(_) => eval(expr, env.clone()).and_then(|value| Datum::from_value(&value)),
+                            Err(e) => Err(EvalError::Other(format!("Prelude failed: {}", e))),
+                        };
+                        results.lock().unwrap()[index] = Some(result);
+                    }
+                })
+                .expect("failed to spawn worker thread");
+        }
+    });
+
+    results.into_inner().unwrap()
+        .into_iter()
+        .map(|result| result.expect("every expression is evaluated"))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::tokenize;
+    use crate::parser::parse_program;
+
+    fn parse_all(source: &str) -> Vec<Expr> {
+        parse_program(tokenize(source).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_results_keep_expression_order() {
+        let prelude = parse_all("(define (square x) (* x x))");
+        let exprs = parse_all(&(1..=20).map(|n| format!("(square {})", n)).collect::<Vec<_>>().join(" "));
+        let results = eval_parallel(&prelude, &exprs);
+        let squares: Vec<_> = results.into_iter().map(|r| r.unwrap()).collect();
+        assert_eq!(squares, (1..=20).map(|n| Datum::Number(n * n)).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_errors_are_reported_per_expression() {
+        let results = eval_parallel(&[], &parse_all("(+ 1 2) (car 5) undefined-name"));
+        assert_eq!(results[0].as_ref().unwrap(), &Datum::Number(3));
+        assert!(matches!(results[1], Err(EvalError::TypeError(_))));
+        assert!(matches!(results[2], Err(EvalError::UndefinedSymbol(_))));
+    }
+
+    #[test]
+    fn test_non_data_results_are_rejected() {
+        let results = eval_parallel(&[], &parse_all("(lambda (x) x) (list 1 (vector 2 \"three\"))"));
+        assert!(matches!(results[0], Err(EvalError::TypeError(_))));
+        let list = results[1].as_ref().unwrap().clone().into_value();
+        assert_eq!(list.to_string(), "(1 #(2 \"three\"))");
+    }
+
+    #[test]
+    fn test_prelude_failure_fails_every_expression() {
+        let results = eval_parallel(&parse_all("(car 1)"), &parse_all("1 2"));
+        assert!(results.iter().all(|r| matches!(r, Err(EvalError::Other(_)))));
+    }
+
+    #[test]
+    fn test_cyclic_values_are_rejected() {
+        let v = Value::Vector(Rc::new(RefCell::new(vec![Value::Number(0)])));
+        if let Value::Vector(items) = &v {
+            items.borrow_mut()[0] = v.clone();
+        }
+        assert!(matches!(Datum::from_value(&v), Err(EvalError::TypeError(_))));
+        if let Value::Vector(items) = &v {
+            items.borrow_mut().clear();
+        }
+    }
+}
@@ -1,11 +1,17 @@
-use crate::lexer::{Token, LexError};
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+
+use crate::lexer::{tokenize_with_spans, Lexeme, LexError, Span, Token};
 use crate::ast::Expr;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum ParseError {
     UnexpectedEOF,
     UnexpectedToken(Token),
     LexError(LexError),
+    InvalidSyntax(String),
 }
 
 /// Parses a vector of tokens into an abstract syntax tree (AST).
@@ -16,22 +22,64 @@ pub fn parse(tokens: Vec<Token>) -> Result<Expr, ParseError> {
     parse_expr(&mut iter)
 }
 
-fn parse_expr<I>(tokens: &mut std::iter::Peekable<I>) -> Result<Expr, ParseError>
+/// Parses a vector of tokens containing any number of top-level expressions,
+/// such as the contents of a source file.
+pub fn parse_program(tokens: Vec<Token>) -> Result<Vec<Expr>, ParseError> {
+    let mut iter = tokens.into_iter().peekable();
+    let mut exprs = Vec::new();
+    while iter.peek().is_some() {
+        exprs.push(parse_expr(&mut iter)?);
+    }
+    Ok(exprs)
+}
+
+/// Parses one expression from the front of a token stream, leaving any
+/// tokens after it unconsumed.
+pub fn parse_expr<I>(tokens: &mut core::iter::Peekable<I>) -> Result<Expr, ParseError>
 where
     I: Iterator<Item = Token>,
 {
     match tokens.next() {
-        Some(Token::Number(n)) => Ok(Expr::Number(n)),
-        Some(Token::Boolean(b)) => Ok(Expr::Boolean(b)),
-        Some(Token::String(s)) => Ok(Expr::String(s)),
-        Some(Token::Symbol(s)) => Ok(Expr::Symbol(s)),
-        Some(Token::LParen) => parse_list(tokens),
-        Some(Token::RParen) => Err(ParseError::UnexpectedToken(Token::RParen)),
+        Some(open @ (Token::LParen | Token::HashTableOpen | Token::VectorOpen | Token::BytevectorOpen)) => {
+            compound(&open, parse_items(tokens)?)
+        }
+        Some(Token::Quote) => list(vec![Expr::Symbol("quote".into()), parse_expr(tokens)?]),
+        Some(token) => atom(token).map_err(ParseError::UnexpectedToken),
         None => Err(ParseError::UnexpectedEOF),
     }
 }
 
-fn parse_list<I>(tokens: &mut std::iter::Peekable<I>) -> Result<Expr, ParseError>
+/// The expression a token stands for on its own, or the token back if it
+/// does not.
+fn atom(token: Token) -> Result<Expr, Token> {
+    match token {
+        Token::Number(n) => Ok(Expr::Number(n)),
+        Token::Rational(n, d) => Ok(Expr::Rational(n, d)),
+        Token::Float(x) => Ok(Expr::Float(x)),
+        Token::Boolean(b) => Ok(Expr::Boolean(b)),
+        Token::String(s) => Ok(Expr::String(s)),
+        Token::Symbol(s) if s != "." => Ok(Expr::Symbol(s)),
+        Token::Keyword(k) => Ok(Expr::Keyword(k)),
+        Token::Char(c) => Ok(Expr::Char(c)),
+        Token::Datum(expr) => Ok(expr),
+        other => Err(other),
+    }
+}
+
+/// Parses one expression as data rather than code: like `parse_expr`, but
+/// a dotted pair anywhere in it is an error, as in quoted data.
+pub fn parse_datum<I>(tokens: &mut core::iter::Peekable<I>) -> Result<Expr, ParseError>
+where
+    I: Iterator<Item = Token>,
+{
+    let datum = parse_expr(tokens)?;
+    check_datum(&datum)?;
+    Ok(datum)
+}
+
+/// Parses the elements of a list, vector, bytevector, or hash table, after
+/// its opening token, up to and including its `)`.
+fn parse_items<I>(tokens: &mut core::iter::Peekable<I>) -> Result<Vec<Expr>, ParseError>
 where
     I: Iterator<Item = Token>,
 {
@@ -40,7 +88,12 @@ where
     while let Some(token) = tokens.peek() {
         if *token == Token::RParen {
             tokens.next(); // consume RParen
-            return Ok(Expr::List(exprs));
+            return Ok(exprs);
+        }
+        if is_dot(token) {
+            tokens.next();
+            exprs.push(Expr::Symbol(".".into()));
+            continue;
         }
 
         let expr = parse_expr(tokens)?;
@@ -50,6 +103,192 @@ where
     Err(ParseError::UnexpectedEOF)
 }
 
+fn is_dot(token: &Token) -> bool {
+    matches!(token, Token::Symbol(s) if s == ".")
+}
+
+/// Makes the expression that `open` starts from the elements read up to
+/// its `)`.
+fn compound(open: &Token, items: Vec<Expr>) -> Result<Expr, ParseError> {
+    match open {
+        Token::HashTableOpen => hash_table(items),
+        Token::VectorOpen => vector(items),
+        Token::BytevectorOpen => bytevector(items),
+        _ => list(items),
+    }
+}
+
+/// Makes a list from its elements, checking where they use `.`.
+///
+/// There are no dotted pairs. A `.` may only come before the last element
+/// of a list, as in formals such as `(a b . rest)` and the entries of
+/// `#hash(...)`, and data that is quoted cannot contain one. The
+/// evaluator accepts such formals in `receive` and `let-values`, and
+/// rejects them in `lambda` and `define`, which have no rest parameters.
+fn list(items: Vec<Expr>) -> Result<Expr, ParseError> {
+    let dot = items.iter().position(|item| *item == Expr::Symbol(".".into()));
+    if matches!(dot, Some(position) if position == 0 || position + 2 != items.len()) {
+        return Err(ParseError::InvalidSyntax(format!("Misplaced . in {}", Expr::List(items))));
+    }
+    if let [Expr::Symbol(quote), datum] = &items[..]
+        && quote == "quote"
+    {
+        check_datum(datum)?;
+    }
+    Ok(Expr::List(items))
+}
+
+/// Fails if `datum`, a quoted or literal value, has a `.` in it: there are
+/// no dotted pairs.
+fn check_datum(datum: &Expr) -> Result<(), ParseError> {
+    match datum {
+        Expr::List(items) if items.contains(&Expr::Symbol(".".into())) => {
+            Err(ParseError::InvalidSyntax(format!("Dotted pairs are not supported: {}", datum)))
+        }
+        Expr::Vector(items) if items.contains(&Expr::Symbol(".".into())) => {
+            Err(ParseError::InvalidSyntax(format!("Misplaced . in {}", datum)))
+        }
+        Expr::List(items) | Expr::Vector(items) => items.iter().try_for_each(check_datum),
+        Expr::HashTable(entries) => entries.iter().try_for_each(|(key, value)| {
+            check_datum(key)?;
+            check_datum(value)
+        }),
+        _ => Ok(()),
+    }
+}
+
+/// Makes a vector literal from the elements written in `#(...)`, which are
+/// data.
+fn vector(items: Vec<Expr>) -> Result<Expr, ParseError> {
+    let vector = Expr::Vector(items);
+    check_datum(&vector)?;
+    Ok(vector)
+}
+
+/// Makes a bytevector literal from the elements written in `#u8(...)`,
+/// which must be integers from 0 to 255.
+fn bytevector(items: Vec<Expr>) -> Result<Expr, ParseError> {
+    let bytes = items.iter().map(|item| match item {
+        Expr::Number(n) => u8::try_from(*n).ok(),
+        _ => None,
+    }).collect::<Option<_>>();
+    bytes.map(Expr::Bytevector).ok_or_else(|| {
+        ParseError::InvalidSyntax(format!("Bytevectors hold integers from 0 to 255, not {}", Expr::List(items)))
+    })
+}
+
+/// Makes a hash table literal from the elements written in `#hash(...)`.
+fn hash_table(entries: Vec<Expr>) -> Result<Expr, ParseError> {
+    let entries = entries.into_iter().map(|entry| match entry {
+        Expr::List(items) if items.len() == 3 && items[1] == Expr::Symbol(".".into()) => {
+            let mut items = items.into_iter();
+            let (key, value) = (items.next().unwrap(), items.nth(1).unwrap());
+            check_datum(&key)?;
+            check_datum(&value)?;
+            Ok((key, value))
+        }
+        other => Err(ParseError::InvalidSyntax(format!("Expected (key . value) in hash table literal, got {}", other))),
+    }).collect::<Result<_, _>>()?;
+    Ok(Expr::HashTable(entries))
+}
+
+/// A syntax error found by `parse_with_recovery`, with the span of the
+/// source it concerns.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyntaxError {
+    pub error: ParseError,
+    pub span: Span,
+}
+
+/// Reads every top-level expression of `source`, like `tokenize` followed by
+/// `parse_program`, without stopping at the first error. Each piece of
+/// source that cannot be read or parsed becomes an `Expr::Error` in its
+/// place, and parsing carries on after it: a list left open at the end
+/// gets an error as its last element, and a `)` that closes nothing is an
+/// error of its own. Returns the expressions and every error found, for
+/// linters and editors, which must report all the problems in a file and
+/// keep working on code being typed.
+pub fn parse_with_recovery(source: &str) -> (Vec<Expr>, Vec<SyntaxError>) {
+    let lexemes = tokenize_with_spans(source).into_iter()
+        .filter(|(lexeme, _)| *lexeme != Lexeme::Comment)
+        .collect();
+    let mut parser = Recovery { lexemes, position: 0, end: source.len(), errors: Vec::new() };
+    let mut exprs = Vec::new();
+    while parser.position < parser.lexemes.len() {
+        exprs.push(parser.expr());
+    }
+    (exprs, parser.errors)
+}
+
+struct Recovery {
+    lexemes: Vec<(Lexeme, Span)>,
+    position: usize,
+    /// The length of the source, where an unexpected end is reported.
+    end: usize,
+    errors: Vec<SyntaxError>,
+}
+
+impl Recovery {
+    fn peek(&self) -> Option<&Lexeme> {
+        self.lexemes.get(self.position).map(|(lexeme, _)| lexeme)
+    }
+
+    /// Records an error and returns the node that stands in for the source.
+    fn error(&mut self, error: ParseError, span: Span) -> Expr {
+        let node = Expr::Error(format!("{:?}", error));
+        self.errors.push(SyntaxError { error, span });
+        node
+    }
+
+    fn expr(&mut self) -> Expr {
+        let Some((lexeme, span)) = self.lexemes.get(self.position).cloned() else {
+            return self.error(ParseError::UnexpectedEOF, Span { start: self.end, end: self.end });
+        };
+        self.position += 1;
+        match lexeme {
+            Lexeme::Token(open @ (Token::LParen | Token::HashTableOpen | Token::VectorOpen | Token::BytevectorOpen)) => {
+                let items = self.items(span);
+                let end = self.lexemes[self.position - 1].1.end;
+                compound(&open, items).unwrap_or_else(|e| self.error(e, Span { start: span.start, end }))
+            }
+            Lexeme::Token(Token::Quote) => {
+                let datum = match self.peek() {
+                    Some(Lexeme::Token(Token::RParen)) => self.error(ParseError::UnexpectedToken(Token::RParen), span),
+                    None => self.error(ParseError::UnexpectedEOF, span),
+                    Some(_) => self.expr(),
+                };
+                let end = self.lexemes[self.position - 1].1.end;
+                list(vec![Expr::Symbol("quote".into()), datum]).unwrap_or_else(|e| self.error(e, Span { start: span.start, end }))
+            }
+            Lexeme::Token(token) => atom(token).unwrap_or_else(|token| self.error(ParseError::UnexpectedToken(token), span)),
+            Lexeme::Error(e) => self.error(ParseError::LexError(e), span),
+            Lexeme::Comment => unreachable!("comments are filtered out"),
+        }
+    }
+
+    /// Reads the elements of a list up to its `)`, which `open` started.
+    fn items(&mut self, open: Span) -> Vec<Expr> {
+        let mut items = Vec::new();
+        loop {
+            match self.peek() {
+                Some(Lexeme::Token(Token::RParen)) => {
+                    self.position += 1;
+                    return items;
+                }
+                None => {
+                    items.push(self.error(ParseError::UnexpectedEOF, open));
+                    return items;
+                }
+                Some(Lexeme::Token(token)) if is_dot(token) => {
+                    self.position += 1;
+                    items.push(Expr::Symbol(".".into()));
+                }
+                Some(_) => items.push(self.expr()),
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests{
     use super::*;
@@ -165,4 +404,102 @@ mod tests{
         );
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn test_parse_quote_shorthand() {
+        let tokens = tokenize("'(1 x)").unwrap();
+        let expr = parse(tokens).unwrap();
+        assert_eq!(
+            expr,
+            Expr::List(vec![
+                Expr::Symbol("quote".into()),
+                Expr::List(vec![Expr::Number(1), Expr::Symbol("x".into())]),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_quote_without_datum() {
+        let tokens = tokenize("'").unwrap();
+        let err = parse(tokens).unwrap_err();
+        assert_eq!(err, ParseError::UnexpectedEOF);
+    }
+
+    #[test]
+    fn test_parse_program_multiple_forms() {
+        let tokens = tokenize("(define x 1) x 'y").unwrap();
+        let exprs = parse_program(tokens).unwrap();
+        assert_eq!(exprs.len(), 3);
+        assert_eq!(exprs[1], Expr::Symbol("x".into()));
+    }
+
+    #[test]
+    fn test_parse_program_empty() {
+        let exprs = parse_program(vec![]).unwrap();
+        assert!(exprs.is_empty());
+    }
+
+    #[test]
+    fn test_parse_program_unbalanced() {
+        let tokens = tokenize("(a) (b").unwrap();
+        let err = parse_program(tokens).unwrap_err();
+        assert_eq!(err, ParseError::UnexpectedEOF);
+    }
+
+    #[test]
+    fn test_parse_hash_table_literal() {
+        let expr = parse(tokenize("#hash((a . 1) (\"b\" . (2 3)))").unwrap()).unwrap();
+        let expected = Expr::HashTable(vec![
+            (Expr::Symbol("a".into()), Expr::Number(1)),
+            (Expr::String("b".into()), Expr::List(vec![Expr::Number(2), Expr::Number(3)])),
+        ]);
+        assert_eq!(expr, expected);
+        assert_eq!(expr.to_string(), "#hash((a . 1) (\"b\" . (2 3)))");
+    }
+
+    #[test]
+    fn test_parse_invalid_hash_table_entry() {
+        let result = parse(tokenize("#hash((a 1))").unwrap());
+        assert!(matches!(result, Err(ParseError::InvalidSyntax(_))));
+    }
+
+    #[test]
+    fn test_parse_dots() {
+        let expr = parse(tokenize("(receive (a . rest) (values 1 2) rest)").unwrap()).unwrap();
+        assert_eq!(expr.to_string(), "(receive (a . rest) (values 1 2) rest)");
+        for source in ["'(1 . 2)", "(quote (a (b . c)))", "#hash((a . (1 . 2)))", "(. a)", "(a . b c)", "(a .)", "'."] {
+            assert!(parse(tokenize(source).unwrap()).is_err(), "{}", source);
+        }
+        let (exprs, errors) = parse_with_recovery("(f '(1 . 2)) (g .)");
+        assert_eq!(exprs.len(), 2);
+        let spans: Vec<Span> = errors.iter().map(|e| e.span).collect();
+        assert_eq!(spans, vec![Span { start: 3, end: 11 }, Span { start: 13, end: 18 }]);
+    }
+
+    #[test]
+    fn test_parse_with_recovery_reports_every_error() {
+        let source = "(define x 1) ) (f #q 2) '\n(g (h 3)";
+        let (exprs, errors) = parse_with_recovery(source);
+        let printed: Vec<String> = exprs.iter().map(|e| e.to_string()).collect();
+        assert_eq!(printed, vec![
+            "(define x 1)",
+            "#<error UnexpectedToken(RParen)>",
+            "(f #<error LexError(InvalidToken(\"#Some('q')\"))> 2)",
+            "(quote (g (h 3) #<error UnexpectedEOF>))",
+        ]);
+        let spans: Vec<&str> = errors.iter().map(|e| &source[e.span.start..e.span.end]).collect();
+        assert_eq!(spans, vec![")", "#q", "("]);
+        assert_eq!(errors[2].error, ParseError::UnexpectedEOF);
+        assert_eq!(exprs[3].find_error(), Some("UnexpectedEOF"));
+        assert_eq!(exprs[0].find_error(), None);
+    }
+
+    #[test]
+    fn test_parse_with_recovery_matches_parse_program_on_valid_source() {
+        let source = "(define (f x) (* x 2)) ; comment\n'(a #hash((k . 1))) (f 4)";
+        let (exprs, errors) = parse_with_recovery(source);
+        assert!(errors.is_empty());
+        assert_eq!(exprs, parse_program(tokenize(source).unwrap()).unwrap());
+        assert_eq!(parse_with_recovery("'").1[0].error, ParseError::UnexpectedEOF);
+        assert_eq!(parse_with_recovery("#hash((a 1))").1[0].span, Span { start: 0, end: 12 });
+    }
+}
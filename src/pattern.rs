@@ -0,0 +1,222 @@
+use std::rc::Rc;
+
+use crate::ast::Expr;
+use crate::env::{Env, EvalError, Value};
+use crate::eval::{apply, check_distinct, eval, eval_sequence, quote_expr};
+
+/// Evaluates `(match expr (pattern body...) ...)`: the body of the first
+/// clause whose pattern matches the value of `expr` runs with the pattern's
+/// variables bound.
+///
+/// Patterns:
+/// - `_` matches anything; any other symbol matches anything and binds it.
+/// - Numbers, strings, booleans, keywords, and `'datum` match `equal?` values.
+/// - `(list p ...)` matches a list element by element. A final `p ...`
+///   matches all remaining elements, binding each variable of `p` to a list.
+/// - `(list-rest p ... rest)` and `(cons p rest)` match a list with at least
+///   as many elements as the leading patterns; `rest` matches the remainder.
+/// - `(vector p ...)` matches a vector like `list` matches a list.
+/// - `(? pred p ...)` matches when `(pred value)` is true and every `p` matches.
+pub(crate) fn eval_match(list: &[Expr], env: Rc<Env>) -> Result<Value, EvalError> {
+    if list.len() < 2 {
+        return Err(EvalError::ArityMismatch);
+    }
+
+    let value = eval(&list[1], env.clone())?;
+    for clause in &list[2..] {
+        let (pattern, body) = match clause {
+            Expr::List(items) if items.len() >= 2 => (&items[0], &items[1..]),
+            _ => return Err(EvalError::TypeError("Expected (pattern body...) clause in match".into())),
+        };
+        check_distinct(pattern_variables(pattern).iter())?;
+
+        let mut bindings = Vec::new();
+        if match_pattern(pattern, &value, &env, &mut bindings)? {
+            let clause_env = Env::extend(env);
+            for (name, value) in bindings {
+                clause_env.define(name, value);
+            }
+            return eval_sequence(body, clause_env);
+        }
+    }
+
+    Err(EvalError::Other(format!("match: no clause matches {}", value)))
+}
+
+fn match_pattern(pattern: &Expr, value: &Value, env: &Rc<Env>, bindings: &mut Vec<(String, Value)>) -> Result<bool, EvalError> {
+    let items = match pattern {
+        Expr::Symbol(s) if s == "_" => return Ok(true),
+        Expr::Symbol(s) => {
+            bindings.push((s.clone(), value.clone()));
+            return Ok(true);
+        }
+        Expr::List(items) if !items.is_empty() => items,
+        literal => return Ok(quote_expr(literal) == *value),
+    };
+
+    let form = match &items[0] {
+        Expr::Symbol(form) => form.as_str(),
+        _ => return Err(invalid_pattern(pattern)),
+    };
+    match (form, value) {
+        ("quote", _) if items.len() == 2 => Ok(quote_expr(&items[1]) == *value),
+        ("list", Value::List(values)) => match_sequence(&items[1..], values, env, bindings),
+        ("vector", Value::Vector(values)) => {
+            let values = values.borrow().clone();
+            match_sequence(&items[1..], &values, env, bindings)
+        }
+        ("list-rest" | "cons", Value::List(values)) if items.len() >= 2 => {
+            if form == "cons" && items.len() != 3 {
+                return Err(invalid_pattern(pattern));
+            }
+            let (leading, rest) = items[1..].split_at(items.len() - 2);
+            if values.len() < leading.len() {
+                return Ok(false);
+            }
+            let (values, remainder) = values.split_at(leading.len());
+            Ok(match_sequence(leading, values, env, bindings)?
+                && match_pattern(&rest[0], &Value::List(remainder.into()), env, bindings)?)
+        }
+        ("?", _) if items.len() >= 2 => {
+            let predicate = eval(&items[1], env.clone())?;
+            if apply(predicate, vec![value.clone()])? == Value::Boolean(false) {
+                return Ok(false);
+            }
+            for item in &items[2..] {
+                if !match_pattern(item, value, env, bindings)? {
+                    return Ok(false);
+                }
+            }
+            Ok(true)
+        }
+        ("list" | "vector" | "list-rest" | "cons", _) => Ok(false),
+        _ => Err(invalid_pattern(pattern)),
+    }
+}
+
+/// Matches elements against patterns one to one, except that a final
+/// `p ...` matches any number of remaining elements.
+fn match_sequence(patterns: &[Expr], values: &[Value], env: &Rc<Env>, bindings: &mut Vec<(String, Value)>) -> Result<bool, EvalError> {
+    let (fixed, repeated) = match patterns {
+        [fixed @ .., repeated, Expr::Symbol(dots)] if dots == "..." => (fixed, Some(repeated)),
+        _ => (patterns, None),
+    };
+    if values.len() < fixed.len() || (repeated.is_none() && values.len() != fixed.len()) {
+        return Ok(false);
+    }
+
+    for (pattern, value) in fixed.iter().zip(values) {
+        if !match_pattern(pattern, value, env, bindings)? {
+            return Ok(false);
+        }
+    }
+
+    if let Some(repeated) = repeated {
+        let names = pattern_variables(repeated);
+        let mut collected = vec![Vec::new(); names.len()];
+        for value in &values[fixed.len()..] {
+            let mut element_bindings = Vec::new();
+            if !match_pattern(repeated, value, env, &mut element_bindings)? {
+                return Ok(false);
+            }
+            for ((_, value), column) in element_bindings.into_iter().zip(&mut collected) {
+                column.push(value);
+            }
+        }
+        bindings.extend(names.into_iter().zip(collected.into_iter().map(|column| Value::List(column.into()))));
+    }
+    Ok(true)
+}
+
+/// Lists the variables a pattern binds, in the order `match_pattern` binds them.
+fn pattern_variables(pattern: &Expr) -> Vec<String> {
+    match pattern {
+        Expr::Symbol(s) if s == "_" || s == "..." => vec![],
+        Expr::Symbol(s) => vec![s.clone()],
+        Expr::List(items) => match items.first() {
+            Some(Expr::Symbol(form)) if form == "list" || form == "vector" || form == "list-rest" || form == "cons" => {
+                items[1..].iter().flat_map(pattern_variables).collect()
+            }
+            Some(Expr::Symbol(form)) if form == "?" => items.iter().skip(2).flat_map(pattern_variables).collect(),
+            _ => vec![],
+        },
+        _ => vec![],
+    }
+}
+
+fn invalid_pattern(pattern: &Expr) -> EvalError {
+    EvalError::TypeError(format!("Invalid pattern in match: {}", pattern))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::env::{default_env, EvalError, Value};
+    use crate::eval::eval;
+    use crate::lexer::tokenize;
+    use crate::parser::parse;
+
+    fn eval_expr(source: &str) -> Result<Value, EvalError> {
+        eval(&parse(tokenize(source).unwrap()).unwrap(), default_env())
+    }
+
+    #[test]
+    fn test_match_literals_and_wildcard() {
+        let classify = |x: &str| eval_expr(&format!("(match {} (0 'zero) (\"s\" 'string) ('sym 'symbol) (_ 'other))", x)).unwrap();
+        assert_eq!(classify("0"), Value::Symbol("zero".into()));
+        assert_eq!(classify("\"s\""), Value::Symbol("string".into()));
+        assert_eq!(classify("'sym"), Value::Symbol("symbol".into()));
+        assert_eq!(classify("#t"), Value::Symbol("other".into()));
+    }
+
+    #[test]
+    fn test_match_list_binds_variables() {
+        let result = eval_expr("(match (list 1 2 3) ((list a b) 'two) ((list a b c) (+ a b c)))").unwrap();
+        assert_eq!(result, Value::Number(6));
+    }
+
+    #[test]
+    fn test_match_rest_patterns() {
+        assert_eq!(eval_expr("(match (list 1 2 3) ((cons x xs) xs))").unwrap().to_string(), "(2 3)");
+        assert_eq!(eval_expr("(match (list 1 2 3) ((list-rest a b more) more))").unwrap().to_string(), "(3)");
+        assert_eq!(eval_expr("(match (list) ((cons x xs) 1) (_ 2))").unwrap(), Value::Number(2));
+    }
+
+    #[test]
+    fn test_match_ellipsis() {
+        let result = eval_expr("
+            (match '(let ((x 1) (y 2)) body)
+              ((list 'let (list (list names inits) ...) body) (list names inits)))
+        ").unwrap();
+        assert_eq!(result.to_string(), "((x y) (1 2))");
+        assert_eq!(eval_expr("(match (list 1) ((list a rest ...) rest))").unwrap().to_string(), "()");
+    }
+
+    #[test]
+    fn test_match_vector_and_predicate() {
+        let result = eval_expr("(match (vector 1 \"a\") ((vector (? (lambda (x) (= x 1)) one) s) (list one s)))").unwrap();
+        assert_eq!(result.to_string(), r#"(1 "a")"#);
+        let result = eval_expr("(match (vector 1 2) ((vector (? vector? v) _) v) ((vector a b) (+ a b)))").unwrap();
+        assert_eq!(result, Value::Number(3));
+    }
+
+    #[test]
+    fn test_match_expression_interpreter() {
+        let result = eval_expr("
+            (begin
+              (define (calc e)
+                (match e
+                  ((list '+ a b) (+ (calc a) (calc b)))
+                  ((list '* a b) (* (calc a) (calc b)))
+                  ((? exact? n) n)))
+              (calc '(+ 1 (* 2 3))))
+        ").unwrap();
+        assert_eq!(result, Value::Number(7));
+    }
+
+    #[test]
+    fn test_match_errors() {
+        assert!(matches!(eval_expr("(match 1 (2 'two))"), Err(EvalError::Other(_))));
+        assert!(matches!(eval_expr("(match 1 ((foo x) x))"), Err(EvalError::TypeError(_))));
+        assert!(matches!(eval_expr("(match (list 1 2) ((list x x) x))"), Err(EvalError::DuplicateBinding(_))));
+    }
+}
@@ -0,0 +1,257 @@
+use std::cell::{Cell, RefCell};
+use std::collections::VecDeque;
+use std::io::Write;
+
+use crate::env::EvalError;
+use crate::eval::{check_effect, check_wait};
+
+thread_local! {
+    /// Characters read from standard input but not yet consumed. Standard
+    /// input is read a line at a time, so `peek-char` can look ahead.
+    static STDIN_BUFFER: RefCell<VecDeque<char>> = const { RefCell::new(VecDeque::new()) };
+    /// Collects what is written to standard output during `capture_stdout`.
+    static STDOUT_CAPTURE: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Calls `f`, collecting what it writes to standard output through ports
+/// instead of writing it, and returns its result with the text.
+pub fn capture_stdout<T>(f: impl FnOnce() -> T) -> (T, String) {
+    let outer = STDOUT_CAPTURE.with(|c| c.replace(Some(String::new())));
+    let result = f();
+    let text = STDOUT_CAPTURE.with(|c| c.replace(outer)).unwrap_or_default();
+    (result, text)
+}
+
+/// A source or destination for text: `display`, `write`, `write-string`,
+/// `newline`, and `format` write to output ports; `read-char`, `peek-char`,
+/// `read-line`, and `char-ready?` read from input ports. Binary ports hold
+/// bytes instead, for `read-u8`, `read-bytevector`, `write-u8`, and
+/// `write-bytevector`.
+#[derive(Debug)]
+pub enum Port {
+    /// The process's standard output.
+    Stdout,
+    /// Accumulates output in memory, from `open-output-string`. Appending is
+    /// amortized constant time, so building a long string piece by piece
+    /// stays linear.
+    OutputString(RefCell<String>),
+    /// The process's standard input, shared by every port value for it.
+    Stdin,
+    /// Reads from a fixed string, from `open-input-string`. `position` is a
+    /// byte offset into `text`.
+    InputString { text: String, position: Cell<usize> },
+    /// Reads from a fixed bytevector, from `open-input-bytevector` or
+    /// `open-binary-input-file`. `position` is an index into `bytes`.
+    InputBytes { bytes: Vec<u8>, position: Cell<usize> },
+    /// Accumulates bytes in memory, from `open-output-bytevector`.
+    OutputBytes(RefCell<Vec<u8>>),
+}
+
+impl Port {
+    pub fn output_string() -> Self {
+        Port::OutputString(RefCell::new(String::new()))
+    }
+
+    pub fn input_string(text: String) -> Self {
+        Port::InputString { text, position: Cell::new(0) }
+    }
+
+    pub fn input_bytes(bytes: Vec<u8>) -> Self {
+        Port::InputBytes { bytes, position: Cell::new(0) }
+    }
+
+    pub fn is_binary(&self) -> bool {
+        matches!(self, Port::InputBytes { .. } | Port::OutputBytes(_))
+    }
+
+    pub fn write_str(&self, text: &str) -> Result<(), EvalError> {
+        match self {
+            Port::Stdout => {
+                check_effect("write to standard output")?;
+                let captured = STDOUT_CAPTURE.with(|c| c.borrow_mut().as_mut().map(|out| out.push_str(text)).is_some());
+                if captured {
+                    return Ok(());
+                }
+                let mut stdout = std::io::stdout();
+                stdout.write_all(text.as_bytes())
+                    .and_then(|_| stdout.flush())
+                    .map_err(|e| EvalError::Other(format!("Error writing to standard output: {}", e)))
+            }
+            Port::OutputString(buffer) => {
+                buffer.borrow_mut().push_str(text);
+                Ok(())
+            }
+            Port::OutputBytes(_) => Err(EvalError::TypeError("Expected textual output port".into())),
+            Port::Stdin | Port::InputString { .. } | Port::InputBytes { .. } => Err(EvalError::TypeError("Expected output port".into())),
+        }
+    }
+
+    /// Writes bytes to a binary output port.
+    pub fn write_bytes(&self, bytes: &[u8]) -> Result<(), EvalError> {
+        match self {
+            Port::OutputBytes(buffer) => {
+                buffer.borrow_mut().extend_from_slice(bytes);
+                Ok(())
+            }
+            _ => Err(EvalError::TypeError("Expected binary output port".into())),
+        }
+    }
+
+    /// Returns everything written so far to a bytevector port.
+    pub fn byte_contents(&self) -> Option<Vec<u8>> {
+        match self {
+            Port::OutputBytes(buffer) => Some(buffer.borrow().clone()),
+            _ => None,
+        }
+    }
+
+    /// Returns the next byte without consuming it, or `None` at the end of
+    /// input.
+    pub fn peek_u8(&self) -> Result<Option<u8>, EvalError> {
+        match self {
+            Port::InputBytes { bytes, position } => Ok(bytes.get(position.get()).copied()),
+            _ => Err(EvalError::TypeError("Expected binary input port".into())),
+        }
+    }
+
+    /// Consumes and returns up to `count` bytes, fewer at the end of input.
+    pub fn read_bytes(&self, count: usize) -> Result<Vec<u8>, EvalError> {
+        match self {
+            Port::InputBytes { bytes, position } => {
+                let start = position.get();
+                let end = start.saturating_add(count).min(bytes.len());
+                position.set(end);
+                Ok(bytes[start..end].to_vec())
+            }
+            _ => Err(EvalError::TypeError("Expected binary input port".into())),
+        }
+    }
+
+    /// Returns everything written so far to a string port.
+    pub fn contents(&self) -> Option<String> {
+        match self {
+            Port::OutputString(buffer) => Some(buffer.borrow().clone()),
+            _ => None,
+        }
+    }
+
+    /// Returns the next character without consuming it, or `None` at the
+    /// end of input. Blocks until standard input has a line to read.
+    pub fn peek_char(&self) -> Result<Option<char>, EvalError> {
+        match self {
+            Port::Stdin => {
+                fill_stdin_buffer()?;
+                Ok(STDIN_BUFFER.with(|b| b.borrow().front().copied()))
+            }
+            Port::InputString { text, position } => Ok(text[position.get()..].chars().next()),
+            Port::InputBytes { .. } => Err(EvalError::TypeError("Expected textual input port".into())),
+            _ => Err(EvalError::TypeError("Expected input port".into())),
+        }
+    }
+
+    /// Consumes and returns the next character, or `None` at the end of input.
+    pub fn read_char(&self) -> Result<Option<char>, EvalError> {
+        let c = self.peek_char()?;
+        match self {
+            Port::Stdin => {
+                STDIN_BUFFER.with(|b| b.borrow_mut().pop_front());
+            }
+            Port::InputString { position, .. } => {
+                position.set(position.get() + c.map_or(0, char::len_utf8));
+            }
+            _ => unreachable!("peek_char accepts only input ports"),
+        }
+        Ok(c)
+    }
+
+    /// Consumes the rest of the current line and returns it without the line
+    /// ending, or `None` if the input is already at its end.
+    pub fn read_line(&self) -> Result<Option<String>, EvalError> {
+        let mut line = String::new();
+        loop {
+            match self.read_char()? {
+                Some('\n') => break,
+                Some(c) => line.push(c),
+                None if line.is_empty() => return Ok(None),
+                None => break,
+            }
+        }
+        if line.ends_with('\r') {
+            line.pop();
+        }
+        Ok(Some(line))
+    }
+
+    /// Returns true if reading a character now would not block.
+    pub fn char_ready(&self) -> Result<bool, EvalError> {
+        match self {
+            Port::Stdin => {
+                check_effect("read from standard input")?;
+                if STDIN_BUFFER.with(|b| !b.borrow().is_empty()) {
+                    return Ok(true);
+                }
+                Ok(stdin_has_input())
+            }
+            Port::InputString { .. } => Ok(true),
+            _ => Err(EvalError::TypeError("Expected input port".into())),
+        }
+    }
+}
+
+/// Reads another line from standard input if nothing is buffered. At the
+/// end of input the buffer stays empty.
+fn fill_stdin_buffer() -> Result<(), EvalError> {
+    check_effect("read from standard input")?;
+    if STDIN_BUFFER.with(|b| !b.borrow().is_empty()) {
+        return Ok(());
+    }
+    wait_for_stdin()?;
+    let mut line = String::new();
+    std::io::stdin().read_line(&mut line)
+        .map_err(|e| EvalError::Other(format!("Error reading standard input: {}", e)))?;
+    STDIN_BUFFER.with(|b| b.borrow_mut().extend(line.chars()));
+    Ok(())
+}
+
+/// Polls standard input without blocking. The end of input also counts as
+/// ready, since reading it returns immediately.
+#[cfg(unix)]
+fn stdin_has_input() -> bool {
+    poll_stdin(0) > 0
+}
+
+/// Waits for standard input, up to `timeout_ms` milliseconds, returning
+/// what `poll` does: positive if it is ready, 0 if not, negative on error.
+#[cfg(unix)]
+fn poll_stdin(timeout_ms: i32) -> i32 {
+    let mut fd = libc::pollfd { fd: libc::STDIN_FILENO, events: libc::POLLIN, revents: 0 };
+    // SAFETY: `fd` is a valid pollfd, and the count of 1 matches it.
+    unsafe { libc::poll(&mut fd, 1, timeout_ms) }
+}
+
+/// Waits until standard input has something to read, so that an interrupt
+/// or time limit can stop a program waiting for input. Once part of a line
+/// has arrived, reading waits for the rest of it.
+#[cfg(unix)]
+fn wait_for_stdin() -> Result<(), EvalError> {
+    loop {
+        check_wait()?;
+        // Errors, such as a signal arriving, also end the wait, and reading
+        // reports any that persist.
+        if poll_stdin(crate::eval::WAIT_SLICE.as_millis() as i32) != 0 {
+            return Ok(());
+        }
+    }
+}
+
+/// Other platforms have no portable non-blocking check, so input is
+/// assumed to be ready and reading may wait for it.
+#[cfg(not(unix))]
+fn stdin_has_input() -> bool {
+    true
+}
+
+#[cfg(not(unix))]
+fn wait_for_stdin() -> Result<(), EvalError> {
+    check_wait()
+}
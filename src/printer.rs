@@ -0,0 +1,579 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+use crate::env::{children, Env, Value};
+use crate::eval::apply;
+use crate::lexer::{char_literal, escape_string, symbol_literal};
+use crate::port::Port;
+use crate::record::Record;
+use crate::number::{format_float, round_significant};
+
+/// How strings are written.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum PrintStyle {
+    /// Machine-readable, as by `write`: strings are quoted and escaped.
+    #[default]
+    Write,
+    /// Human-readable, as by `display`: strings are written as their contents.
+    Display,
+}
+
+/// Options applied when printing values. The limits work like Common Lisp's
+/// `*print-depth*` and `*print-length*`.
+///
+/// Lists nested deeper than `max_depth` print as `...`, and lists with more
+/// than `max_length` elements print their first `max_length` elements
+/// followed by `...`. `None` means unlimited.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PrintOptions {
+    pub max_depth: Option<usize>,
+    pub max_length: Option<usize>,
+    pub style: PrintStyle,
+    /// Floats are rounded to this many significant digits; `None` prints
+    /// the shortest form that reads back as the same float.
+    pub float_precision: Option<usize>,
+}
+
+impl PrintOptions {
+    /// Reads the limits from the `*print-depth*` and `*print-length*`
+    /// variables in the given environment, and the float precision from
+    /// `*print-precision*`. A non-negative integer sets the limit; any
+    /// other value (conventionally `#f`) leaves it unlimited.
+    pub fn from_env(env: &Env) -> Self {
+        let limit = |name: &str| match env.get(name) {
+            Some(Value::Number(n)) => usize::try_from(n).ok(),
+            _ => None,
+        };
+        PrintOptions {
+            max_depth: limit("*print-depth*"),
+            max_length: limit("*print-length*"),
+            style: PrintStyle::Write,
+            float_precision: limit("*print-precision*"),
+        }
+    }
+}
+
+enum Item<'a> {
+    Value(Cow<'a, Value>, usize),
+    Text(&'static str),
+    Label(String),
+}
+
+/// Prints a value using an explicit stack, so arbitrarily deep lists
+/// cannot overflow the Rust stack.
+///
+/// Vectors and hash tables that contain themselves are written with datum
+/// labels, as in `#0=#(1 #0#)`, so printing circular structures always
+/// terminates. Hash tables print as `#hash((key . value) ...)`, which the
+/// reader accepts back.
+pub fn print_value(value: &Value, options: &PrintOptions) -> String {
+    let cyclic = cyclic_containers(value);
+    let mut labels = HashMap::new();
+    let mut out = String::new();
+    let mut stack = vec![Item::Value(Cow::Borrowed(value), 0)];
+
+    while let Some(item) = stack.pop() {
+        let (node, depth) = match item {
+            Item::Text(text) => {
+                out.push_str(text);
+                continue;
+            }
+            Item::Label(text) => {
+                out.push_str(&text);
+                continue;
+            }
+            Item::Value(node, depth) => (node, depth),
+        };
+
+        let (open, close) = match node.as_ref() {
+            Value::List(_) => (Cow::Borrowed("("), ")"),
+            Value::Vector(_) => ("#(".into(), ")"),
+            Value::HashTable(_) => ("#hash(".into(), ")"),
+            Value::Queue(_) => ("#<queue".into(), ">"),
+            Value::Record(record) => match custom_print(record, &node) {
+                Some(text) => {
+                    out.push_str(&text);
+                    continue;
+                }
+                None => (format!("#<{}", record.rtd.display_name()).into(), ">"),
+            },
+            Value::Values(_) => {
+                push_elements(&mut stack, children(node), depth, "\n", None);
+                continue;
+            }
+            atom => {
+                print_atom(atom, options, &mut out);
+                continue;
+            }
+        };
+
+        if options.max_depth.is_some_and(|max| depth >= max) {
+            out.push_str("...");
+            continue;
+        }
+        if let Some(ptr) = container_ptr(&node).filter(|ptr| cyclic.contains(ptr)) {
+            if let Some(label) = labels.get(&ptr) {
+                out.push_str(&format!("#{}#", label));
+                continue;
+            }
+            let label = labels.len();
+            labels.insert(ptr, label);
+            out.push_str(&format!("#{}=", label));
+        }
+
+        out.push_str(&open);
+        stack.push(Item::Text(close));
+        match node.as_ref() {
+            Value::HashTable(_) => push_entries(&mut stack, children(node), depth + 1, options.max_length),
+            Value::Record(record) => {
+                let names = record.rtd.fields.clone();
+                for (name, value) in names.into_iter().zip(children(node)).rev() {
+                    stack.push(Item::Value(value, depth + 1));
+                    stack.push(Item::Label(format!(" {}: ", name)));
+                }
+            }
+            Value::Queue(_) => {
+                let items = children(node);
+                if !items.is_empty() {
+                    push_elements(&mut stack, items, depth + 1, " ", options.max_length);
+                    stack.push(Item::Text(" "));
+                }
+            }
+            _ => push_elements(&mut stack, children(node), depth + 1, " ", options.max_length),
+        }
+    }
+
+    out
+}
+
+/// Where two values first differ: an expression that extracts the
+/// differing part, and that part of each value, printed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Difference {
+    pub place: String,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl std::fmt::Display for Difference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "at {}, expected {} but got {}", self.place, self.expected, self.actual)
+    }
+}
+
+/// Compares two values and, if they are not `equal?`, finds the first
+/// differing part, descending into lists, vectors, and hash tables whose
+/// shapes match. The place is written as accessors applied to `source`,
+/// the expression that produced `actual`, as in `(cadr (caddr source))`.
+/// When lists differ in length, the place is the tail after their common
+/// elements. The differing parts are printed with `options`, so the
+/// `*print-depth*` and `*print-length*` limits keep large values short.
+pub fn find_difference(expected: &Value, actual: &Value, source: &str, options: &PrintOptions) -> Option<Difference> {
+    if expected == actual {
+        return None;
+    }
+    let mut place = source.to_string();
+    let mut visited = HashSet::new();
+    let (mut expected, mut actual) = (Cow::Borrowed(expected), Cow::Borrowed(actual));
+    loop {
+        if let (Some(x), Some(y)) = (container_ptr(&expected), container_ptr(&actual))
+            && !visited.insert((x, y))
+        {
+            break;
+        }
+        let next = match (expected.as_ref(), actual.as_ref()) {
+            (Value::List(xs), Value::List(ys)) => {
+                let common = xs.len().min(ys.len());
+                match (0..common).find(|&i| xs[i] != ys[i]) {
+                    Some(i) => {
+                        place = compose_cxr(&format!("a{}", "d".repeat(i)), place);
+                        (xs[i].clone(), ys[i].clone())
+                    }
+                    None => {
+                        place = compose_cxr(&"d".repeat(common), place);
+                        (Value::List(xs[common..].into()), Value::List(ys[common..].into()))
+                    }
+                }
+            }
+            (Value::Vector(xs), Value::Vector(ys)) if xs.borrow().len() == ys.borrow().len() => {
+                let (xs, ys) = (xs.borrow(), ys.borrow());
+                let Some(i) = (0..xs.len()).find(|&i| xs[i] != ys[i]) else { break };
+                place = format!("(vector-ref {} {})", place, i);
+                (xs[i].clone(), ys[i].clone())
+            }
+            (Value::HashTable(xs), Value::HashTable(ys)) => {
+                let (xs, ys) = (xs.borrow(), ys.borrow());
+                if xs.len() != ys.len() || xs.keys().any(|key| !ys.contains_key(key)) {
+                    break;
+                }
+                let Some((key, x)) = xs.iter().find(|(key, x)| ys[*key] != **x) else { break };
+                place = format!("(hash-table-ref {} {})", place, print_value(key, &PrintOptions::default()));
+                (x.clone(), ys[key].clone())
+            }
+            _ => break,
+        };
+        // A list tail with no common elements is the list itself.
+        if next.0 == *expected && next.1 == *actual {
+            break;
+        }
+        (expected, actual) = (Cow::Owned(next.0), Cow::Owned(next.1));
+    }
+    Some(Difference {
+        place,
+        expected: print_value(&expected, options),
+        actual: print_value(&actual, options),
+    })
+}
+
+/// Applies the `car`s and `cdr`s spelled by `letters` (as in `cadr`) to
+/// `place`, using names of at most four letters: `(caddr (cddddr x))`.
+fn compose_cxr(letters: &str, mut place: String) -> String {
+    let mut letters = letters.to_string();
+    while !letters.is_empty() {
+        let inner = letters.split_off(letters.len().saturating_sub(4));
+        place = format!("(c{}r {})", inner, place);
+    }
+    place
+}
+
+/// Prints a record with its type's custom printer, if it has one that
+/// returns a string.
+fn custom_print(record: &Record, value: &Value) -> Option<String> {
+    let printer = record.rtd.printer.borrow().clone()?;
+    match apply(printer, vec![value.clone()]) {
+        Ok(Value::String(ref text)) => Some(text.to_string()),
+        _ => None,
+    }
+}
+
+/// Identifies a mutable container, which is what can make a structure circular.
+fn container_ptr(value: &Value) -> Option<*const ()> {
+    match value {
+        Value::Vector(items) => Some(Rc::as_ptr(items).cast()),
+        Value::HashTable(table) => Some(Rc::as_ptr(table).cast()),
+        Value::Queue(items) => Some(Rc::as_ptr(items).cast()),
+        Value::Record(record) => Some(Rc::as_ptr(record).cast()),
+        _ => None,
+    }
+}
+
+/// Finds the vectors and hash tables that are reachable from themselves,
+/// which are the ones that need datum labels when printed.
+fn cyclic_containers(value: &Value) -> HashSet<*const ()> {
+    enum Visit<'a> {
+        Enter(Cow<'a, Value>),
+        Leave(*const ()),
+    }
+
+    let mut on_path = HashSet::new();
+    let mut finished = HashSet::new();
+    let mut cyclic = HashSet::new();
+    let mut stack = vec![Visit::Enter(Cow::Borrowed(value))];
+
+    while let Some(visit) = stack.pop() {
+        match visit {
+            Visit::Leave(ptr) => {
+                on_path.remove(&ptr);
+                finished.insert(ptr);
+            }
+            Visit::Enter(node) => {
+                if let Some(ptr) = container_ptr(&node) {
+                    if on_path.contains(&ptr) {
+                        cyclic.insert(ptr);
+                        continue;
+                    }
+                    if finished.contains(&ptr) {
+                        continue;
+                    }
+                    on_path.insert(ptr);
+                    stack.push(Visit::Leave(ptr));
+                }
+                stack.extend(children(node).into_iter().map(Visit::Enter));
+            }
+        }
+    }
+
+    cyclic
+}
+
+/// Schedules elements for printing (in order, since the stack is LIFO),
+/// truncating after `max_length` elements.
+fn push_elements<'a>(
+    stack: &mut Vec<Item<'a>>,
+    mut items: Vec<Cow<'a, Value>>,
+    depth: usize,
+    separator: &'static str,
+    max_length: Option<usize>,
+) {
+    let shown = max_length.map_or(items.len(), |max| max.min(items.len()));
+    if shown < items.len() {
+        stack.push(Item::Text("..."));
+        if shown > 0 {
+            stack.push(Item::Text(separator));
+        }
+    }
+    items.truncate(shown);
+    for (i, item) in items.into_iter().enumerate().rev() {
+        stack.push(Item::Value(item, depth));
+        if i > 0 {
+            stack.push(Item::Text(separator));
+        }
+    }
+}
+
+/// Schedules the entries of a hash table, given as interleaved keys and
+/// values, to print as `(key . value)`, truncating like `push_elements`.
+fn push_entries<'a>(stack: &mut Vec<Item<'a>>, items: Vec<Cow<'a, Value>>, depth: usize, max_length: Option<usize>) {
+    let mut entries = Vec::with_capacity(items.len() / 2);
+    let mut items = items.into_iter();
+    while let (Some(key), Some(value)) = (items.next(), items.next()) {
+        entries.push((key, value));
+    }
+
+    let shown = max_length.map_or(entries.len(), |max| max.min(entries.len()));
+    if shown < entries.len() {
+        stack.push(Item::Text("..."));
+        if shown > 0 {
+            stack.push(Item::Text(" "));
+        }
+    }
+    entries.truncate(shown);
+    for (i, (key, value)) in entries.into_iter().enumerate().rev() {
+        stack.push(Item::Text(")"));
+        stack.push(Item::Value(value, depth + 1));
+        stack.push(Item::Text(" . "));
+        stack.push(Item::Value(key, depth + 1));
+        stack.push(Item::Text("("));
+        if i > 0 {
+            stack.push(Item::Text(" "));
+        }
+    }
+}
+
+fn print_atom(value: &Value, options: &PrintOptions, out: &mut String) {
+    let style = options.style;
+    let text = match value {
+        Value::Float(x) if let Some(digits) = options.float_precision => format_float(round_significant(*x, digits)),
+        Value::Number(n) => n.to_string(),
+        Value::Rational(n, d) => format!("{}/{}", n, d),
+        Value::Float(x) => format_float(*x),
+        Value::Boolean(true) => "#t".to_string(),
+        Value::Boolean(false) => "#f".to_string(),
+        Value::String(s) if style == PrintStyle::Display => s.to_string(),
+        Value::String(s) => escape_string(s),
+        Value::Symbol(s) if style == PrintStyle::Display => s.to_string(),
+        Value::Symbol(s) => symbol_literal(s),
+        Value::Keyword(k) => format!("#:{}", k),
+        Value::Char(c) if style == PrintStyle::Display => c.to_string(),
+        Value::Char(c) => char_literal(*c),
+        Value::Function(_) => "<builtin-function>".to_string(),
+        Value::Lambda(_) => "<lambda>".to_string(),
+        Value::Memoized(_) => "<memoized>".to_string(),
+        Value::Combinator(c) => format!("<{}>", c.name()),
+        Value::Promise(_) => "#<promise>".to_string(),
+        Value::Generator(_) => "#<generator>".to_string(),
+        Value::Fiber(_) => "#<fiber>".to_string(),
+        Value::Channel(_) => "#<channel>".to_string(),
+        Value::Mutex(_) => "#<mutex>".to_string(),
+        Value::WeakBox(_) => "#<weak-box>".to_string(),
+        Value::Pid(pid) => format!("#<pid {}>", pid),
+        Value::Bytevector(bytes) => {
+            let bytes: Vec<String> = bytes.borrow().iter().map(u8::to_string).collect();
+            format!("#u8({})", bytes.join(" "))
+        }
+        Value::Condition(condition) => format!("#<condition {}: {}>", condition.kind, condition.message),
+        Value::Port(port) => match port.as_ref() {
+            Port::Stdout => "#<output-port stdout>".to_string(),
+            Port::OutputString(_) => "#<output-port string>".to_string(),
+            Port::Stdin => "#<input-port stdin>".to_string(),
+            Port::InputString { .. } => "#<input-port string>".to_string(),
+            Port::InputBytes { .. } => "#<input-port bytevector>".to_string(),
+            Port::OutputBytes(_) => "#<output-port bytevector>".to_string(),
+        },
+        Value::RecordType(rtd) => format!("#<record-type {}>", rtd.display_name()),
+        Value::RecordProcedure(_) => "<record-procedure>".to_string(),
+        Value::Eof => "#<eof>".to_string(),
+        Value::Unspecified => "#<unspecified>".to_string(),
+        Value::List(_) | Value::Vector(_) | Value::HashTable(_) | Value::Queue(_) | Value::Record(_) | Value::Values(_) => unreachable!("compound values are printed by print_value"),
+    };
+    out.push_str(&text);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    fn numbers(range: std::ops::Range<i64>) -> Value {
+        Value::List(range.map(Value::Number).collect())
+    }
+
+    fn nested(depth: usize) -> Value {
+        let mut value = Value::List(Rc::new([]));
+        for _ in 0..depth {
+            value = Value::List(Rc::new([value]));
+        }
+        value
+    }
+
+    #[test]
+    fn test_find_difference_descends_into_matching_shapes() {
+        let options = PrintOptions::default();
+        assert_eq!(find_difference(&numbers(0..3), &numbers(0..3), "x", &options), None);
+        let vector = |items: Vec<Value>| Value::Vector(Rc::new(RefCell::new(items)));
+        let expected = vector(vec![Value::Number(0), numbers(0..7)]);
+        let actual = vector(vec![Value::Number(0), Value::List((0..7).map(|n| Value::Number(n.min(5))).collect())]);
+        let difference = find_difference(&expected, &actual, "x", &options).unwrap();
+        assert_eq!(difference.to_string(), "at (caddr (cddddr (vector-ref x 1))), expected 6 but got 5");
+        let difference = find_difference(&Value::Number(1), &numbers(0..1), "(f)", &options).unwrap();
+        assert_eq!(difference.to_string(), "at (f), expected 1 but got (0)");
+    }
+
+    #[test]
+    fn test_print_nested_list() {
+        let value = Value::List(Rc::new([
+            Value::Number(1),
+            Value::List(Rc::new([Value::String("a".into()), Value::Boolean(true)])),
+            Value::List(Rc::new([])),
+        ]));
+        assert_eq!(print_value(&value, &PrintOptions::default()), "(1 (\"a\" #t) ())");
+    }
+
+    #[test]
+    fn test_print_length_limit() {
+        let options = PrintOptions { max_length: Some(3), ..Default::default() };
+        assert_eq!(print_value(&numbers(0..10), &options), "(0 1 2 ...)");
+        assert_eq!(print_value(&numbers(0..3), &options), "(0 1 2)");
+    }
+
+    #[test]
+    fn test_print_length_zero() {
+        let options = PrintOptions { max_length: Some(0), ..Default::default() };
+        assert_eq!(print_value(&numbers(0..2), &options), "(...)");
+    }
+
+    #[test]
+    fn test_print_depth_limit() {
+        let options = PrintOptions { max_depth: Some(2), ..Default::default() };
+        let value = Value::List(Rc::new([Value::Number(1), Value::List(Rc::new([Value::List(Rc::new([]))]))]));
+        assert_eq!(print_value(&value, &options), "(1 (...))");
+    }
+
+    #[test]
+    fn test_print_multiple_values() {
+        let value = Value::Values(Rc::new([Value::Number(1), numbers(2..4)]));
+        assert_eq!(print_value(&value, &PrintOptions::default()), "1\n(2 3)");
+    }
+
+    #[test]
+    fn test_print_very_deep_list() {
+        let value = nested(100_000);
+        let printed = print_value(&value, &PrintOptions::default());
+        assert_eq!(printed.len(), 200_002);
+    }
+
+    #[test]
+    fn test_equality_of_very_deep_lists() {
+        let (a, b) = (nested(100_000), nested(100_000));
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn test_write_escapes_strings() {
+        let value = Value::String("say \"hi\"\n\\".into());
+        assert_eq!(print_value(&value, &PrintOptions::default()), r#""say \"hi\"\n\\""#);
+        let value = Value::String("\r\u{7}😀".into());
+        assert_eq!(print_value(&value, &PrintOptions::default()), r#""\r\x7;😀""#);
+    }
+
+    #[test]
+    fn test_display_writes_string_contents() {
+        let value = Value::List(Rc::new([Value::String("a b".into()), Value::Symbol("c".into())]));
+        let options = PrintOptions { style: PrintStyle::Display, ..Default::default() };
+        assert_eq!(print_value(&value, &options), "(a b c)");
+    }
+
+    fn vector(items: Vec<Value>) -> Value {
+        Value::Vector(Rc::new(RefCell::new(items)))
+    }
+
+    #[test]
+    fn test_print_vector() {
+        let value = vector(vec![Value::Number(1), numbers(2..4)]);
+        assert_eq!(print_value(&value, &PrintOptions::default()), "#(1 (2 3))");
+    }
+
+    #[test]
+    fn test_print_self_referencing_vector() {
+        let value = vector(vec![Value::Number(0), Value::Number(1)]);
+        if let Value::Vector(items) = &value {
+            items.borrow_mut()[1] = value.clone();
+        }
+        assert_eq!(print_value(&value, &PrintOptions::default()), "#0=#(0 #0#)");
+    }
+
+    #[test]
+    fn test_print_mutually_referencing_vectors() {
+        let inner = vector(vec![Value::Number(1)]);
+        let outer = vector(vec![inner.clone(), Value::List(Rc::new([inner.clone()]))]);
+        if let Value::Vector(items) = &inner {
+            items.borrow_mut().push(outer.clone());
+        }
+        assert_eq!(print_value(&outer, &PrintOptions::default()), "#0=#(#(1 #0#) (#(1 #0#)))");
+    }
+
+    #[test]
+    fn test_print_shared_vector_without_cycle_is_not_labelled() {
+        let shared = vector(vec![Value::Number(1)]);
+        let value = Value::List(Rc::new([shared.clone(), shared]));
+        assert_eq!(print_value(&value, &PrintOptions::default()), "(#(1) #(1))");
+    }
+
+    #[test]
+    fn test_print_cycle_with_length_limit() {
+        let value = vector(vec![Value::Number(0)]);
+        if let Value::Vector(items) = &value {
+            items.borrow_mut().extend([value.clone(), Value::Number(2)]);
+        }
+        let options = PrintOptions { max_length: Some(2), ..Default::default() };
+        assert_eq!(print_value(&value, &options), "#0=#(0 #0# ...)");
+    }
+
+    #[test]
+    fn test_float_precision() {
+        let value = Value::List(Rc::new([Value::Float(1.0 / 3.0), Value::Float(123456.0), Value::Number(7)]));
+        assert_eq!(print_value(&value, &PrintOptions::default()), "(0.3333333333333333 123456.0 7)");
+        let options = PrintOptions { float_precision: Some(3), ..Default::default() };
+        assert_eq!(print_value(&value, &options), "(0.333 123000.0 7)");
+    }
+
+    #[test]
+    fn test_options_from_env() {
+        let env = Env::new();
+        env.define("*print-length*".into(), Value::Number(5));
+        env.define("*print-depth*".into(), Value::Boolean(false));
+        let options = PrintOptions::from_env(&env);
+        assert_eq!(options, PrintOptions { max_depth: None, max_length: Some(5), style: PrintStyle::Write, float_precision: None });
+    }
+
+    fn table(entries: Vec<(Value, Value)>) -> Value {
+        Value::HashTable(Rc::new(RefCell::new(entries.into_iter().collect())))
+    }
+
+    #[test]
+    fn test_print_hash_table() {
+        let value = table(vec![(Value::Symbol("a".into()), Value::List(Rc::new([Value::String("x".into())])))]);
+        assert_eq!(print_value(&value, &PrintOptions::default()), r#"#hash((a . ("x")))"#);
+        assert_eq!(print_value(&table(vec![]), &PrintOptions::default()), "#hash()");
+    }
+
+    #[test]
+    fn test_print_self_referencing_hash_table() {
+        let value = table(vec![]);
+        if let Value::HashTable(entries) = &value {
+            entries.borrow_mut().insert(Value::Number(1), value.clone());
+        }
+        assert_eq!(print_value(&value, &PrintOptions::default()), "#0=#hash((1 . #0#))");
+    }
+}
@@ -0,0 +1,197 @@
+//! The `scheme_rs` Python module, for using the interpreter from Python and
+//! Jupyter notebooks:
+//!
+//! ```python
+//! from scheme_rs import EvalContext
+//! ctx = EvalContext()
+//! ctx.define("xs", [1, 2, 3])
+//! ctx.eval("(map (lambda (x) (* x x)) xs)")  # [1, 4, 9]
+//! ```
+//!
+//! Values convert both ways: integers, rationals (`fractions.Fraction`),
+//! floats, booleans, strings, lists, vectors (tuples), hash tables (dicts),
+//! and bytevectors (bytes). Symbols become `Symbol` objects. Anything else,
+//! such as a procedure, becomes an opaque `Value` that can be passed back,
+//! and called if it is a procedure.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use indexmap::IndexMap;
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use pyo3::types::{PyBool, PyBytes, PyDict, PyFloat, PyInt, PyList, PyString, PyTuple};
+
+use crate::env::{default_env, Env, EvalError, Value};
+use crate::eval::{apply, eval_sequence};
+use crate::lexer::tokenize;
+use crate::parser::parse_program;
+
+create_exception!(scheme_rs, SchemeError, PyException, "An error reading or evaluating Scheme code.");
+
+/// An interpreter session with its own global environment.
+#[pyclass(name = "EvalContext", unsendable)]
+pub struct Context {
+    env: Rc<Env>,
+}
+
+#[pymethods]
+impl Context {
+    #[new]
+    fn new() -> Self {
+        Context { env: default_env() }
+    }
+
+    /// Evaluates the expressions in `source` and returns the value of the
+    /// last one, converted to Python.
+    fn eval(&self, py: Python<'_>, source: &str) -> PyResult<Py<PyAny>> {
+        let tokens = tokenize(source).map_err(|e| SchemeError::new_err(format!("Lex error: {:?}", e)))?;
+        let program = parse_program(tokens).map_err(|e| SchemeError::new_err(format!("Parse error: {:?}", e)))?;
+        let value = eval_sequence(&program, self.env.clone()).map_err(eval_error)?;
+        to_python(py, &value).map(Bound::unbind)
+    }
+
+    /// Defines a global variable with a value converted from Python.
+    fn define(&self, name: &str, value: &Bound<'_, PyAny>) -> PyResult<()> {
+        self.env.define(name.into(), from_python(value)?);
+        Ok(())
+    }
+}
+
+/// A Scheme symbol.
+#[pyclass(name = "Symbol", frozen, eq, hash)]
+#[derive(PartialEq, Eq, Hash)]
+pub struct Symbol {
+    #[pyo3(get)]
+    name: String,
+}
+
+#[pymethods]
+impl Symbol {
+    #[new]
+    fn new(name: String) -> Self {
+        Symbol { name }
+    }
+
+    fn __repr__(&self) -> String {
+        format!("Symbol({:?})", self.name)
+    }
+}
+
+/// A Scheme value with no Python counterpart, such as a procedure or record.
+#[pyclass(name = "Value", unsendable)]
+pub struct Opaque {
+    value: Value,
+}
+
+#[pymethods]
+impl Opaque {
+    fn __repr__(&self) -> String {
+        self.value.write_string()
+    }
+
+    /// Calls the value, if it is a procedure, with arguments converted from
+    /// Python.
+    #[pyo3(signature = (*args))]
+    fn __call__(&self, py: Python<'_>, args: &Bound<'_, PyTuple>) -> PyResult<Py<PyAny>> {
+        let args = args.iter().map(|arg| from_python(&arg)).collect::<PyResult<Vec<_>>>()?;
+        let result = apply(self.value.clone(), args).map_err(eval_error)?;
+        to_python(py, &result).map(Bound::unbind)
+    }
+}
+
+fn eval_error(error: EvalError) -> PyErr {
+    SchemeError::new_err(format!("Eval error: {:?}", error))
+}
+
+fn to_python<'py>(py: Python<'py>, value: &Value) -> PyResult<Bound<'py, PyAny>> {
+    let items = |items: &[Value]| items.iter().map(|item| to_python(py, item)).collect::<PyResult<Vec<_>>>();
+    Ok(match value {
+        Value::Number(n) => n.into_pyobject(py)?.into_any(),
+        Value::Rational(n, d) => py.import("fractions")?.getattr("Fraction")?.call1((n, d))?,
+        Value::Float(x) => x.into_pyobject(py)?.into_any(),
+        Value::Boolean(b) => b.into_pyobject(py)?.to_owned().into_any(),
+        Value::String(s) => s.into_pyobject(py)?.into_any(),
+        Value::Char(c) => c.into_pyobject(py)?.into_any(),
+        Value::Symbol(name) => Bound::new(py, Symbol { name: name.to_string() })?.into_any(),
+        Value::List(list) => PyList::new(py, items(list)?)?.into_any(),
+        Value::Vector(vector) => PyTuple::new(py, items(&vector.borrow())?)?.into_any(),
+        Value::Values(values) => PyTuple::new(py, items(values)?)?.into_any(),
+        Value::Bytevector(bytes) => PyBytes::new(py, &bytes.borrow()).into_any(),
+        Value::HashTable(table) => {
+            let dict = PyDict::new(py);
+            for (key, value) in table.borrow().iter() {
+                dict.set_item(to_python(py, key)?, to_python(py, value)?)?;
+            }
+            dict.into_any()
+        }
+        Value::Unspecified => py.None().into_bound(py),
+        other => Bound::new(py, Opaque { value: other.clone() })?.into_any(),
+    })
+}
+
+fn from_python(object: &Bound<'_, PyAny>) -> PyResult<Value> {
+    let items = |items: Bound<'_, PyAny>| items.try_iter()?.map(|item| from_python(&item?)).collect::<PyResult<Vec<_>>>();
+    // `bool` is a subclass of `int`, so it is checked first.
+    if let Ok(b) = object.cast::<PyBool>() {
+        Ok(Value::Boolean(b.is_true()))
+    } else if object.is_instance_of::<PyInt>() {
+        object.extract::<i64>().map(Value::Number)
+    } else if let Ok(x) = object.cast::<PyFloat>() {
+        Ok(Value::Float(x.value()))
+    } else if let Ok(s) = object.cast::<PyString>() {
+        Ok(Value::String(s.to_str()?.into()))
+    } else if let Ok(symbol) = object.cast::<Symbol>() {
+        Ok(Value::Symbol(symbol.get().name.as_str().into()))
+    } else if let Ok(opaque) = object.cast::<Opaque>() {
+        Ok(opaque.borrow().value.clone())
+    } else if object.is_instance_of::<PyList>() {
+        Ok(Value::List(items(object.clone())?.into()))
+    } else if object.is_instance_of::<PyTuple>() {
+        Ok(Value::Vector(Rc::new(RefCell::new(items(object.clone())?))))
+    } else if let Ok(bytes) = object.cast::<PyBytes>() {
+        Ok(Value::Bytevector(Rc::new(RefCell::new(bytes.as_bytes().to_vec()))))
+    } else if let Ok(dict) = object.cast::<PyDict>() {
+        let table = dict.iter()
+            .map(|(key, value)| Ok((from_python(&key)?, from_python(&value)?)))
+            .collect::<PyResult<IndexMap<_, _>>>()?;
+        Ok(Value::HashTable(Rc::new(RefCell::new(table))))
+    } else if object.is_none() {
+        Ok(Value::Unspecified)
+    } else if object.is_instance(&object.py().import("fractions")?.getattr("Fraction")?)? {
+        let (n, d): (i64, i64) = (object.getattr("numerator")?.extract()?, object.getattr("denominator")?.extract()?);
+        Ok(crate::number::Number::rational(n, d).map_err(eval_error)?.into())
+    } else {
+        Err(SchemeError::new_err(format!("Cannot convert {} to a Scheme value", object.get_type().name()?)))
+    }
+}
+
+#[pymodule]
+fn scheme_rs(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<Context>()?;
+    module.add_class::<Symbol>()?;
+    module.add_class::<Opaque>()?;
+    module.add("SchemeError", module.py().get_type::<SchemeError>())?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_values_round_trip_through_python() {
+        Python::initialize();
+        Python::attach(|py| {
+            let context = Context::new();
+            let value = PyList::new(py, [1, 2]).unwrap();
+            context.define("xs", value.as_any()).unwrap();
+            let result = context.eval(py, "(list (map (lambda (x) (* x 10)) xs) (/ 1 2) 'a (vector #t \"s\"))").unwrap();
+            assert_eq!(result.bind(py).repr().unwrap().to_string(), "[[10, 20], Fraction(1, 2), Symbol(\"a\"), (True, 's')]");
+            assert_eq!(from_python(result.bind(py)).unwrap().to_string(), "((10 20) 1/2 a #(#t \"s\"))");
+            let error = context.eval(py, "(car 1)").unwrap_err();
+            assert!(error.is_instance_of::<SchemeError>(py));
+        });
+    }
+}
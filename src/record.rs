@@ -0,0 +1,229 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+use crate::ast::Expr;
+use crate::env::{Env, EvalError, Value};
+
+/// A record type created by `define-record-type`.
+pub struct RecordType {
+    pub name: String,
+    pub fields: Vec<String>,
+    /// Procedure called with a record to produce its printed form, set by
+    /// `set-record-type-printer!`.
+    pub printer: RefCell<Option<Value>>,
+}
+
+impl RecordType {
+    /// The type name without surrounding angle brackets, so that `<point>`
+    /// records print as `#<point ...>`.
+    pub fn display_name(&self) -> &str {
+        self.name.strip_prefix('<').and_then(|n| n.strip_suffix('>')).unwrap_or(&self.name)
+    }
+}
+
+impl fmt::Debug for RecordType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RecordType")
+            .field("name", &self.name)
+            .field("fields", &self.fields)
+            .finish_non_exhaustive()
+    }
+}
+
+/// An instance of a record type.
+#[derive(Debug)]
+pub struct Record {
+    pub rtd: Rc<RecordType>,
+    pub fields: RefCell<Vec<Value>>,
+}
+
+/// The procedures `define-record-type` defines for a record type.
+#[derive(Debug)]
+pub enum RecordProcedure {
+    /// Builds a record from arguments for the given field indices; the
+    /// other fields start as `#f`.
+    Constructor(Rc<RecordType>, Vec<usize>),
+    Predicate(Rc<RecordType>),
+    Accessor(Rc<RecordType>, usize),
+    Modifier(Rc<RecordType>, usize),
+}
+
+impl RecordProcedure {
+    pub fn call(&self, args: Vec<Value>) -> Result<Value, EvalError> {
+        match self {
+            RecordProcedure::Constructor(rtd, indices) => {
+                if args.len() != indices.len() {
+                    return Err(EvalError::ArityMismatch);
+                }
+                let mut fields = vec![Value::Boolean(false); rtd.fields.len()];
+                for (&i, value) in indices.iter().zip(args) {
+                    fields[i] = value;
+                }
+                Ok(Value::Record(Rc::new(Record { rtd: rtd.clone(), fields: RefCell::new(fields) })))
+            }
+            RecordProcedure::Predicate(rtd) => match &args[..] {
+                [Value::Record(record)] => Ok(Value::Boolean(Rc::ptr_eq(&record.rtd, rtd))),
+                [_] => Ok(Value::Boolean(false)),
+                _ => Err(EvalError::ArityMismatch),
+            },
+            RecordProcedure::Accessor(rtd, i) => match &args[..] {
+                [Value::Record(record)] if Rc::ptr_eq(&record.rtd, rtd) => Ok(record.fields.borrow()[*i].clone()),
+                [_] => Err(expected_record(rtd)),
+                _ => Err(EvalError::ArityMismatch),
+            },
+            RecordProcedure::Modifier(rtd, i) => match &args[..] {
+                [Value::Record(record), value] if Rc::ptr_eq(&record.rtd, rtd) => {
+                    record.fields.borrow_mut()[*i] = value.clone();
+                    Ok(Value::Unspecified)
+                }
+                [_, _] => Err(expected_record(rtd)),
+                _ => Err(EvalError::ArityMismatch),
+            },
+        }
+    }
+}
+
+fn expected_record(rtd: &RecordType) -> EvalError {
+    EvalError::TypeError(format!("Expected {} record", rtd.display_name()))
+}
+
+/// Evaluates
+/// `(define-record-type <name> (constructor field...) predicate (field accessor [modifier]) ...)`,
+/// defining the type name, constructor, predicate, accessors, and modifiers
+/// in the current environment.
+pub(crate) fn eval_define_record_type(list: &[Expr], env: Rc<Env>) -> Result<Value, EvalError> {
+    if list.len() < 4 {
+        return Err(EvalError::ArityMismatch);
+    }
+
+    let name = symbol(&list[1], "record type name")?;
+    let constructor = match &list[2] {
+        Expr::List(items) if !items.is_empty() => items,
+        _ => return Err(EvalError::TypeError("Expected (constructor field...) in define-record-type".into())),
+    };
+    let predicate = symbol(&list[3], "predicate name")?;
+
+    let mut fields = Vec::new();
+    let mut procedures = Vec::new();
+    for spec in &list[4..] {
+        let parts = match spec {
+            Expr::List(parts) if (2..=3).contains(&parts.len()) => parts,
+            _ => return Err(EvalError::TypeError("Expected (field accessor [modifier]) in define-record-type".into())),
+        };
+        let index = fields.len();
+        fields.push(symbol(&parts[0], "field name")?);
+        procedures.push((symbol(&parts[1], "accessor name")?, index, false));
+        if let Some(modifier) = parts.get(2) {
+            procedures.push((symbol(modifier, "modifier name")?, index, true));
+        }
+    }
+    crate::eval::check_distinct(fields.iter())?;
+
+    let constructor_fields = constructor[1..].iter().map(|field| {
+        let field = symbol(field, "field name")?;
+        fields.iter().position(|f| *f == field)
+            .ok_or_else(|| EvalError::TypeError(format!("Constructor field {} is not a field of {}", field, name)))
+    }).collect::<Result<Vec<_>, _>>()?;
+
+    let rtd = Rc::new(RecordType { name: name.clone(), fields, printer: RefCell::new(None) });
+    let procedure = |p: RecordProcedure| Value::RecordProcedure(Rc::new(p));
+
+    env.define(name, Value::RecordType(rtd.clone()));
+    env.define(symbol(&constructor[0], "constructor name")?, procedure(RecordProcedure::Constructor(rtd.clone(), constructor_fields)));
+    env.define(predicate, procedure(RecordProcedure::Predicate(rtd.clone())));
+    for (proc_name, index, is_modifier) in procedures {
+        let p = if is_modifier {
+            RecordProcedure::Modifier(rtd.clone(), index)
+        } else {
+            RecordProcedure::Accessor(rtd.clone(), index)
+        };
+        env.define(proc_name, procedure(p));
+    }
+    Ok(Value::Unspecified)
+}
+
+fn symbol(expr: &Expr, what: &str) -> Result<String, EvalError> {
+    match expr {
+        Expr::Symbol(s) => Ok(s.clone()),
+        _ => Err(EvalError::TypeError(format!("Expected {} in define-record-type", what))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::env::{default_env, EvalError, Value};
+    use crate::eval::eval;
+    use crate::lexer::tokenize;
+    use crate::parser::parse;
+
+    fn eval_expr(source: &str) -> Result<Value, EvalError> {
+        eval(&parse(tokenize(source).unwrap()).unwrap(), default_env())
+    }
+
+    const POINT: &str = "(define-record-type <point> (make-point x y) point? (x point-x set-point-x!) (y point-y))";
+
+    #[test]
+    fn test_record_accessors_and_modifiers() {
+        let result = eval_expr(&format!("
+            (begin
+              {}
+              (define p (make-point 1 2))
+              (set-point-x! p 10)
+              (list (point-x p) (point-y p) (point? p) (point? 5)))
+        ", POINT)).unwrap();
+        assert_eq!(result.to_string(), "(10 2 #t #f)");
+    }
+
+    #[test]
+    fn test_record_default_printing() {
+        let result = eval_expr(&format!("(begin {} (list (make-point 1 \"a\")))", POINT)).unwrap();
+        assert_eq!(result.to_string(), "(#<point x: 1 y: \"a\">)");
+        assert_eq!(result.display_string(), "(#<point x: 1 y: a>)");
+    }
+
+    #[test]
+    fn test_record_custom_printer() {
+        let result = eval_expr(&format!("
+            (begin
+              {}
+              (set-record-type-printer! <point>
+                (lambda (p) (format \"<~a, ~a>\" (point-x p) (point-y p))))
+              (->string (list (make-point 1 2))))
+        ", POINT)).unwrap();
+        assert_eq!(result, Value::String("(<1, 2>)".into()));
+    }
+
+    #[test]
+    fn test_record_constructor_subset_of_fields() {
+        let result = eval_expr("
+            (begin
+              (define-record-type node (make-node value) node? (value node-value) (next node-next set-node-next!))
+              (node-next (make-node 1)))
+        ").unwrap();
+        assert_eq!(result, Value::Boolean(false));
+    }
+
+    #[test]
+    fn test_record_accessor_rejects_other_types() {
+        let result = eval_expr(&format!("
+            (begin
+              {}
+              (define-record-type <size> (make-size w) size? (w size-w))
+              (point-x (make-size 1)))
+        ", POINT));
+        assert!(matches!(result, Err(EvalError::TypeError(_))));
+    }
+
+    #[test]
+    fn test_self_referencing_record_prints_with_label() {
+        let result = eval_expr("
+            (begin
+              (define-record-type cell (make-cell next) cell? (next cell-next set-cell-next!))
+              (define c (make-cell #f))
+              (set-cell-next! c c)
+              (->string c))
+        ").unwrap();
+        assert_eq!(result, Value::String("#0=#<cell next: #0#>".into()));
+    }
+}
@@ -0,0 +1,207 @@
+//! Errors as structured data, for the CLI's `--format json` and for
+//! editors and other tools that would otherwise parse the messages meant
+//! for people.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use crate::ast::Expr;
+use crate::condition::error_summary;
+use crate::coverage::{assign_lines, copy_lines};
+use crate::env::{Env, EvalError, Lambda, Value};
+use crate::eval::{eval, set_hooks, EvalHooks};
+use crate::json::Json;
+use crate::lexer::{tokenize_with_lines, Token};
+use crate::parser::{parse_program, parse_with_recovery};
+
+/// How many frames of the stack an `ErrorReport` keeps, innermost first.
+pub const MAX_FRAMES: usize = 32;
+
+/// How much of an expression's source a stack frame shows.
+const FRAME_TEXT: usize = 80;
+
+/// An expression being evaluated when an error happened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StackFrame {
+    /// The expression's source, shortened with `...` if long.
+    pub expr: String,
+    /// The line (counting from 1) it starts on, if known.
+    pub line: Option<usize>,
+}
+
+/// An error with what is known about where it happened.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorReport {
+    /// The kind a `guard` would see (see `condition::error_summary`), or
+    /// `syntax` for source that cannot be read.
+    pub kind: String,
+    pub message: String,
+    /// The line (counting from 1) of the innermost expression with a known
+    /// line.
+    pub line: Option<usize>,
+    /// The compound expressions that were being evaluated, innermost first,
+    /// up to `MAX_FRAMES`. Empty for syntax errors.
+    pub stack: Vec<StackFrame>,
+}
+
+impl ErrorReport {
+    /// Describes an error with no location.
+    pub fn from_error(error: &EvalError) -> Self {
+        let (kind, message) = error_summary(error);
+        ErrorReport { kind: kind.into(), message, line: None, stack: Vec::new() }
+    }
+
+    /// The report as a JSON object with its `kind`, `message`, `line` (or
+    /// `null`), and `stack`, an array of objects with `expr` and `line`.
+    pub fn to_json(&self) -> Json {
+        let line = |line: Option<usize>| line.map_or(Json::Null, |line| Json::Number(line as f64));
+        Json::Object(vec![
+            ("kind".into(), self.kind.clone().into()),
+            ("message".into(), self.message.clone().into()),
+            ("line".into(), line(self.line)),
+            ("stack".into(), Json::Array(self.stack.iter().map(|frame| Json::Object(vec![
+                ("expr".into(), frame.expr.clone().into()),
+                ("line".into(), line(frame.line)),
+            ])).collect())),
+        ])
+    }
+}
+
+/// A program with the tokens it was read from and their lines, as
+/// `eval_with_report` needs them.
+pub type ReadProgram = (Vec<Expr>, Vec<(Token, usize)>);
+
+/// Reads a program, reporting every syntax error with its line if it
+/// cannot be read.
+pub fn read_with_report(source: &str) -> Result<ReadProgram, Vec<ErrorReport>> {
+    let tokens = tokenize_with_lines(source).map_err(|_| syntax_errors(source))?;
+    let program = parse_program(tokens.iter().map(|(token, _)| token.clone()).collect()).map_err(|_| syntax_errors(source))?;
+    Ok((program, tokens))
+}
+
+fn syntax_errors(source: &str) -> Vec<ErrorReport> {
+    let (_, errors) = parse_with_recovery(source);
+    errors.iter().map(|e| ErrorReport {
+        kind: "syntax".into(),
+        message: format!("{:?}", e.error),
+        line: Some(source[..e.span.start].matches('\n').count() + 1),
+        stack: Vec::new(),
+    }).collect()
+}
+
+/// Evaluates a program read by `read_with_report` form by form, passing
+/// each value to `each_value`, and stops at the first error, which is
+/// reported with the stack of expressions it happened in. Evaluation hooks
+/// installed before are replaced while it runs.
+pub fn eval_with_report(
+    program: &[Expr],
+    tokens: &[(Token, usize)],
+    env: Rc<Env>,
+    mut each_value: impl FnMut(&Value),
+) -> Result<Value, ErrorReport> {
+    let recorder = Rc::new(StackRecorder::default());
+    let mut remaining = tokens.iter();
+    for expr in program {
+        assign_lines(expr, &mut remaining, &mut recorder.lines.borrow_mut(), true);
+    }
+    set_hooks(Some(recorder.clone()));
+    let mut result = Ok(Value::Unspecified);
+    for expr in program {
+        result = eval(expr, env.clone());
+        match &result {
+            Ok(value) => each_value(value),
+            Err(_) => break,
+        }
+    }
+    set_hooks(None);
+    result.map_err(|error| {
+        let stack = recorder.unwinding.take();
+        ErrorReport { line: stack.iter().find_map(|frame| frame.line), stack, ..ErrorReport::from_error(&error) }
+    })
+}
+
+/// Collects the expressions an error propagates out of. Every expression
+/// enclosing the one that failed finishes with the error in turn, innermost
+/// first; any that finishes with a value means the error was handled.
+#[derive(Default)]
+struct StackRecorder {
+    lines: RefCell<HashMap<*const Expr, usize>>,
+    unwinding: RefCell<Vec<StackFrame>>,
+    /// Closures created while recording, kept alive so that the addresses
+    /// of their bodies are not reused.
+    closures: RefCell<Vec<Rc<Lambda>>>,
+}
+
+impl EvalHooks for StackRecorder {
+    fn on_eval_exit(&self, expr: &Expr, result: &Result<Value, EvalError>) {
+        let mut unwinding = self.unwinding.borrow_mut();
+        match result {
+            Ok(_) => unwinding.clear(),
+            Err(_) if matches!(expr, Expr::List(_)) && unwinding.len() < MAX_FRAMES => {
+                let mut text = expr.to_string();
+                if text.len() > FRAME_TEXT {
+                    let end = (0..=FRAME_TEXT - 3).rev().find(|&i| text.is_char_boundary(i)).unwrap_or(0);
+                    text.truncate(end);
+                    text.push_str("...");
+                }
+                let line = self.lines.borrow().get(&(expr as *const Expr)).copied();
+                unwinding.push(StackFrame { expr: text, line });
+            }
+            Err(_) => {}
+        }
+    }
+
+    fn on_lambda(&self, source: &[Expr], lambda: &Rc<Lambda>) {
+        // A body of several expressions is wrapped in `begin`; see `make_lambda`.
+        let copies = match (source, &lambda.body) {
+            ([single], body) => vec![(single, body)],
+            (_, Expr::List(items)) => source.iter().zip(&items[1..]).collect(),
+            _ => return,
+        };
+        let mut lines = self.lines.borrow_mut();
+        for (original, copy) in copies {
+            copy_lines(original, copy, &mut lines);
+        }
+        self.closures.borrow_mut().push(lambda.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::default_env;
+
+    fn run(source: &str) -> ErrorReport {
+        let (program, tokens) = read_with_report(source).unwrap();
+        eval_with_report(&program, &tokens, default_env(), |_| {}).unwrap_err()
+    }
+
+    #[test]
+    fn test_error_report_has_kind_line_and_stack() {
+        let report = run("(define (f x)\n  (+ 1\n     (car x)))\n(display 1)\n(f 2)");
+        assert_eq!((report.kind.as_str(), report.message.as_str()), ("type", "Expected non-empty list"));
+        assert_eq!(report.line, Some(3));
+        let frames: Vec<_> = report.stack.iter().map(|f| (f.expr.as_str(), f.line)).collect();
+        assert_eq!(frames, vec![("(car x)", Some(3)), ("(+ 1 (car x))", Some(2)), ("(f 2)", Some(5))]);
+        assert_eq!(
+            report.to_json().to_string(),
+            r#"{"kind":"type","message":"Expected non-empty list","line":3,"stack":[{"expr":"(car x)","line":3},{"expr":"(+ 1 (car x))","line":2},{"expr":"(f 2)","line":5}]}"#
+        );
+    }
+
+    #[test]
+    fn test_handled_errors_leave_no_stack() {
+        let report = run("(guard (e (#t 0)) (car 1))\n(undefined-thing)");
+        assert_eq!(report.kind, "undefined");
+        assert_eq!(report.message, "Undefined symbol undefined-thing");
+        assert_eq!(report.stack, vec![StackFrame { expr: "(undefined-thing)".into(), line: Some(2) }]);
+    }
+
+    #[test]
+    fn test_syntax_errors_are_reported_with_lines() {
+        let reports = read_with_report("(display 1)\n(car 1))\n(f").unwrap_err();
+        let summary: Vec<_> = reports.iter().map(|r| (r.kind.as_str(), r.line)).collect();
+        assert_eq!(summary, vec![("syntax", Some(2)), ("syntax", Some(3))]);
+    }
+}
@@ -0,0 +1,147 @@
+//! Where programs get their files from. `load`, `include`, `include-ci`,
+//! `open-input-file`, and `open-binary-input-file` read through the
+//! `FileResolver` installed on the current thread, so that embedders can
+//! serve sources from somewhere other than the disk (resources built into
+//! the binary, an archive, or JavaScript on the web) or confine untrusted
+//! code to one directory. Without one installed, files come from the
+//! operating system as usual.
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+
+use crate::env::EvalError;
+
+/// A source of files, looked up by the path a program names.
+pub trait FileResolver {
+    /// Returns the contents of the file at `path`, or why it cannot be read.
+    fn read(&self, path: &str) -> Result<Vec<u8>, String>;
+}
+
+thread_local! {
+    static RESOLVER: RefCell<Option<Rc<dyn FileResolver>>> = const { RefCell::new(None) };
+}
+
+/// Installs a resolver for the files programs read on the current thread,
+/// replacing any installed before. `None` goes back to reading from the
+/// operating system.
+pub fn set_file_resolver(resolver: Option<Rc<dyn FileResolver>>) {
+    RESOLVER.with(|r| *r.borrow_mut() = resolver);
+}
+
+/// Returns the resolver installed on the current thread, if any.
+pub fn file_resolver() -> Option<Rc<dyn FileResolver>> {
+    RESOLVER.with(|r| r.borrow().clone())
+}
+
+/// Reads a file for a program, through the installed resolver if there is one.
+pub(crate) fn read_file(path: &str) -> Result<Vec<u8>, EvalError> {
+    let contents = match file_resolver() {
+        Some(resolver) => resolver.read(path),
+        None => std::fs::read(path).map_err(|e| e.to_string()),
+    };
+    contents.map_err(|e| EvalError::File(format!("Cannot read {}: {}", path, e)))
+}
+
+/// Like `read_file`, for text.
+pub(crate) fn read_text_file(path: &str) -> Result<String, EvalError> {
+    String::from_utf8(read_file(path)?).map_err(|_| EvalError::File(format!("Cannot read {}: not UTF-8", path)))
+}
+
+/// Files held in memory, such as sources built into the binary with
+/// `include_str!`, by path.
+#[derive(Debug, Clone, Default)]
+pub struct MemoryFiles {
+    files: HashMap<String, Vec<u8>>,
+}
+
+impl MemoryFiles {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a file, replacing any at the same path.
+    pub fn insert(&mut self, path: &str, contents: impl Into<Vec<u8>>) {
+        self.files.insert(path.to_string(), contents.into());
+    }
+}
+
+impl FileResolver for MemoryFiles {
+    fn read(&self, path: &str) -> Result<Vec<u8>, String> {
+        self.files.get(path).cloned().ok_or_else(|| "no such file".to_string())
+    }
+}
+
+/// Files under one directory of the disk, for untrusted code. Paths are
+/// relative to the directory; absolute paths, `..`, and links that lead
+/// outside it are refused.
+#[derive(Debug, Clone)]
+pub struct SandboxedFiles {
+    root: PathBuf,
+}
+
+impl SandboxedFiles {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        SandboxedFiles { root: root.into() }
+    }
+}
+
+impl FileResolver for SandboxedFiles {
+    fn read(&self, path: &str) -> Result<Vec<u8>, String> {
+        let relative = Path::new(path);
+        if !relative.components().all(|c| matches!(c, Component::Normal(_) | Component::CurDir)) {
+            return Err("outside the sandbox".into());
+        }
+        let root = self.root.canonicalize().map_err(|e| e.to_string())?;
+        let full = root.join(relative).canonicalize().map_err(|e| e.to_string())?;
+        if !full.starts_with(&root) {
+            return Err("outside the sandbox".into());
+        }
+        std::fs::read(full).map_err(|e| e.to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::default_env;
+    use crate::testing::eval_source;
+
+    #[test]
+    fn test_memory_files() {
+        let mut files = MemoryFiles::new();
+        files.insert("lib/square.scm", "(define (square x) (* x x))");
+        set_file_resolver(Some(Rc::new(files)));
+        let result = eval_source("(load \"lib/square.scm\") (square 7)", &default_env());
+        let missing = eval_source("(include \"other.scm\")", &default_env());
+        set_file_resolver(None);
+        assert_eq!(result.unwrap().to_string(), "49");
+        assert!(matches!(missing, Err(EvalError::File(message)) if message == "Cannot read other.scm: no such file"));
+    }
+
+    #[test]
+    #[cfg(feature = "io")]
+    fn test_memory_files_open_as_ports() {
+        let mut files = MemoryFiles::new();
+        files.insert("data.txt", "first line\nsecond line\n");
+        set_file_resolver(Some(Rc::new(files)));
+        let result = eval_source("(read-line (open-input-file \"data.txt\"))", &default_env());
+        set_file_resolver(None);
+        assert_eq!(result.unwrap().to_string(), "\"first line\"");
+    }
+
+    #[test]
+    fn test_sandboxed_files() {
+        let root = std::env::temp_dir().join(format!("scheme_rs_sandbox_{}", std::process::id()));
+        std::fs::create_dir_all(root.join("inner")).unwrap();
+        std::fs::write(root.join("inner/ok.scm"), "(define sandboxed 1)").unwrap();
+        let sandbox = SandboxedFiles::new(root.join("inner"));
+        assert_eq!(sandbox.read("ok.scm").unwrap(), b"(define sandboxed 1)");
+        assert_eq!(sandbox.read("./ok.scm").unwrap(), b"(define sandboxed 1)");
+        for path in ["../inner/ok.scm", "/etc/passwd", "missing.scm"] {
+            assert!(sandbox.read(path).is_err(), "{}", path);
+        }
+        std::fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,250 @@
+//! A machine-oriented mode for editors and notebook kernels, started with
+//! `scheme_rs --server`. Each line of input is a JSON request, and each line
+//! of output a JSON response carrying the request's `id`:
+//!
+//! - `{"id": 1, "op": "evaluate", "code": "(display 1) (+ 1 2)"}` evaluates
+//!   the code in the session's environment and answers
+//!   `{"id": 1, "status": "ok", "value": "3", "output": "1"}`.
+//! - `{"id": 2, "op": "complete", "prefix": "str"}` answers with
+//!   `"completions"`, the variables and special forms starting with `str`.
+//! - `{"id": 3, "op": "inspect", "name": "map"}` answers with the variable's
+//!   `"type"`, its printed `"value"`, and for procedures a `"description"`.
+//! - `{"id": 4, "op": "interrupt"}` stops the evaluation in progress, which
+//!   then fails.
+//!
+//! A request that fails is answered with `"status": "error"` and an
+//! `"error"` message. Requests are handled in order, except `interrupt`,
+//! which is handled as soon as it is read.
+
+use std::io::{self, BufRead, Write};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+
+use crate::builtins::describe_procedure;
+use crate::env::{default_env, Env};
+use crate::eval::{eval_sequence, set_interrupt_flag, SpecialForm};
+use crate::json::{self, Json};
+use crate::lexer::tokenize;
+use crate::parser::parse_program;
+use crate::port::capture_stdout;
+use crate::printer::{print_value, PrintOptions};
+
+/// Answers the requests read from `input` on `output` until the input ends,
+/// in a session with its own global environment. Evaluation happens on the
+/// calling thread; a second thread reads requests so that an `interrupt`
+/// can arrive while code runs.
+pub fn serve(input: impl BufRead + Send, output: impl Write + Send) -> io::Result<()> {
+    let output = Mutex::new(output);
+    let interrupt = Arc::new(AtomicBool::new(false));
+    let (sender, receiver) = mpsc::channel();
+    thread::scope(|scope| {
+        let reader = scope.spawn(|| -> io::Result<()> {
+            let sender = sender;
+            for line in input.lines() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                match json::parse(&line) {
+                    Ok(request) if request.get("op").and_then(Json::as_str) == Some("interrupt") => {
+                        interrupt.store(true, Ordering::Relaxed);
+                        respond(&output, success(&request, vec![]))?;
+                    }
+                    request => {
+                        if sender.send(request).is_err() {
+                            break;
+                        }
+                    }
+                }
+            }
+            Ok(())
+        });
+        let env = default_env();
+        set_interrupt_flag(Some(interrupt.clone()));
+        let mut result = Ok(());
+        for request in receiver {
+            // An interrupt that arrived while nothing was running is stale.
+            interrupt.store(false, Ordering::Relaxed);
+            let response = match request {
+                Ok(request) => handle(&request, &env),
+                Err(e) => failure(&Json::Null, format!("Bad request: {}", e), vec![]),
+            };
+            result = respond(&output, response);
+            if result.is_err() {
+                break;
+            }
+        }
+        set_interrupt_flag(None);
+        result.and(reader.join().expect("the request reader does not panic"))
+    })
+}
+
+fn respond(output: &Mutex<impl Write>, response: Json) -> io::Result<()> {
+    let mut output = output.lock().unwrap_or_else(|e| e.into_inner());
+    writeln!(output, "{}", response)?;
+    output.flush()
+}
+
+fn handle(request: &Json, env: &Rc<Env>) -> Json {
+    match request.get("op").and_then(Json::as_str) {
+        Some("evaluate") => evaluate(request, env),
+        Some("complete") => complete(request, env),
+        Some("inspect") => inspect(request, env),
+        Some(op) => failure(request, format!("Unknown op {:?}", op), vec![]),
+        None => failure(request, "Request has no op".into(), vec![]),
+    }
+}
+
+fn reply(request: &Json, status: &str, fields: Vec<(&str, Json)>) -> Json {
+    let id = request.get("id").cloned().unwrap_or(Json::Null);
+    let mut entries = vec![("id".to_string(), id), ("status".to_string(), status.into())];
+    entries.extend(fields.into_iter().map(|(key, value)| (key.to_string(), value)));
+    Json::Object(entries)
+}
+
+fn success(request: &Json, fields: Vec<(&str, Json)>) -> Json {
+    reply(request, "ok", fields)
+}
+
+fn failure(request: &Json, message: String, mut fields: Vec<(&str, Json)>) -> Json {
+    fields.insert(0, ("error", message.into()));
+    reply(request, "error", fields)
+}
+
+fn string_field<'a>(request: &'a Json, key: &str) -> Result<&'a str, Json> {
+    request.get(key).and_then(Json::as_str)
+        .ok_or_else(|| failure(request, format!("Request needs a string {:?}", key), vec![]))
+}
+
+fn evaluate(request: &Json, env: &Rc<Env>) -> Json {
+    let code = match string_field(request, "code") {
+        Ok(code) => code,
+        Err(response) => return response,
+    };
+    let program = match tokenize(code) {
+        Ok(tokens) => match parse_program(tokens) {
+            Ok(program) => program,
+            Err(e) => return failure(request, format!("Parse error: {:?}", e), vec![]),
+        },
+        Err(e) => return failure(request, format!("Lex error: {:?}", e), vec![]),
+    };
+    let (result, output) = capture_stdout(|| eval_sequence(&program, env.clone()));
+    match result {
+        Ok(value) => success(request, vec![
+            ("value", print_value(&value, &PrintOptions::from_env(env)).into()),
+            ("output", output.into()),
+        ]),
+        Err(e) => failure(request, format!("Eval error: {:?}", e), vec![("output", output.into())]),
+    }
+}
+
+fn complete(request: &Json, env: &Rc<Env>) -> Json {
+    let prefix = match string_field(request, "prefix") {
+        Ok(prefix) => prefix,
+        Err(response) => return response,
+    };
+    let mut names = env.visible_names();
+    names.extend(SpecialForm::ALL.map(|form| form.name().to_string()));
+    names.extend(env.special_form_names());
+    names.retain(|name| name.starts_with(prefix));
+    names.sort();
+    names.dedup();
+    success(request, vec![("completions", Json::Array(names.into_iter().map(Json::String).collect()))])
+}
+
+fn inspect(request: &Json, env: &Rc<Env>) -> Json {
+    let name = match string_field(request, "name") {
+        Ok(name) => name,
+        Err(response) => return response,
+    };
+    let Some(value) = env.get(name) else {
+        return failure(request, format!("Undefined symbol {}", name), vec![]);
+    };
+    let mut fields = vec![
+        ("type", value.type_name().into()),
+        ("value", print_value(&value, &PrintOptions::from_env(env)).into()),
+    ];
+    if let Some(description) = describe_procedure(&value) {
+        fields.push(("description", description.into()));
+    }
+    success(request, fields)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufReader, Read};
+    use std::time::Duration;
+
+    fn run(requests: &str) -> Vec<Json> {
+        let mut output = Vec::new();
+        serve(requests.as_bytes(), &mut output).unwrap();
+        String::from_utf8(output).unwrap().lines().map(|line| json::parse(line).unwrap()).collect()
+    }
+
+    #[test]
+    fn test_requests_share_a_session() {
+        let responses = run(concat!(
+            r#"{"id": 1, "op": "evaluate", "code": "(define (square x) (* x x)) (display \"hi\") (square 4)"}"#, "\n",
+            r#"{"id": "two", "op": "complete", "prefix": "squ"}"#, "\n",
+            "\n",
+            r#"{"id": 3, "op": "inspect", "name": "square"}"#, "\n",
+            r#"{"id": 4, "op": "evaluate", "code": "(car 1)"}"#, "\n",
+            r#"{"id": 5, "op": "frobnicate"}"#, "\n",
+            "not json\n",
+        ));
+        assert_eq!(responses[0].to_string(), r#"{"id":1,"status":"ok","value":"16","output":"hi"}"#);
+        assert_eq!(responses[1].to_string(), r#"{"id":"two","status":"ok","completions":["square"]}"#);
+        assert_eq!(responses[2].get("type"), Some(&Json::from("procedure")));
+        assert!(responses[2].get("description").is_some());
+        assert_eq!(responses[3].get("status"), Some(&Json::from("error")));
+        assert!(responses[3].get("error").and_then(Json::as_str).unwrap().starts_with("Eval error"));
+        assert_eq!(responses[4].get("error"), Some(&Json::from("Unknown op \"frobnicate\"")));
+        assert_eq!(responses[5].get("id"), Some(&Json::Null));
+        assert_eq!(responses.len(), 6);
+    }
+
+    #[test]
+    fn test_complete_includes_special_forms_and_builtins() {
+        let responses = run("{\"op\": \"complete\", \"prefix\": \"let\"}\n");
+        let Some(Json::Array(names)) = responses[0].get("completions") else { panic!("no completions") };
+        for name in ["let", "let*", "let-values", "letrec"] {
+            assert!(names.contains(&Json::from(name)), "{}", name);
+        }
+    }
+
+    /// Requests that arrive one at a time, after a delay, so that the
+    /// interrupt comes while the loop is running.
+    struct Slow(Vec<&'static str>);
+
+    impl Read for Slow {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            if self.0.is_empty() {
+                return Ok(0);
+            }
+            thread::sleep(Duration::from_millis(100));
+            let line = self.0.remove(0);
+            buf[..line.len()].copy_from_slice(line.as_bytes());
+            Ok(line.len())
+        }
+    }
+
+    #[test]
+    fn test_interrupt_stops_a_running_evaluation() {
+        let input = Slow(vec![
+            // A billion calls, without deep recursion.
+            "{\"id\": 1, \"op\": \"evaluate\", \"code\": \"(define v (make-vector 1000 0)) \
+             (vector-for-each (lambda (x) (vector-for-each (lambda (y) (vector-for-each (lambda (z) z) v)) v)) v)\"}\n",
+            "{\"id\": 2, \"op\": \"interrupt\"}\n",
+            "{\"id\": 3, \"op\": \"evaluate\", \"code\": \"(+ 1 2)\"}\n",
+        ]);
+        let mut output = Vec::new();
+        serve(BufReader::new(input), &mut output).unwrap();
+        let responses: Vec<Json> = String::from_utf8(output).unwrap().lines().map(|l| json::parse(l).unwrap()).collect();
+        assert_eq!(responses[0].to_string(), r#"{"id":2,"status":"ok"}"#);
+        assert_eq!(responses[1].get("error"), Some(&Json::from("Eval error: Interrupted")));
+        assert_eq!(responses[2].get("value"), Some(&Json::from("3")));
+    }
+}
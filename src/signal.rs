@@ -0,0 +1,109 @@
+use std::cell::RefCell;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::env::{EvalError, Value};
+use crate::eval::{apply, check_effect};
+
+/// Signal names accepted by `on-signal`, with their numbers.
+#[cfg(unix)]
+const SIGNALS: &[(&str, libc::c_int)] = &[
+    ("interrupt", libc::SIGINT),
+    ("terminate", libc::SIGTERM),
+    ("hangup", libc::SIGHUP),
+];
+
+#[cfg(not(unix))]
+const SIGNALS: &[(&str, i32)] = &[];
+
+/// Set by the native signal handler for each signal in `SIGNALS` that has
+/// arrived but not been handled yet.
+static PENDING: [AtomicBool; 3] = [const { AtomicBool::new(false) }; 3];
+
+/// Set when any signal is pending, so the evaluator's check is one load.
+static ANY_PENDING: AtomicBool = AtomicBool::new(false);
+
+thread_local! {
+    /// The Scheme handler for each signal in `SIGNALS`, on the thread that
+    /// installed it.
+    static HANDLERS: RefCell<[Option<Value>; 3]> = const { RefCell::new([None, None, None]) };
+}
+
+#[cfg(unix)]
+extern "C" fn record_signal(signal: libc::c_int) {
+    // Only atomic stores here: nothing else is safe in a signal handler.
+    if let Some(index) = SIGNALS.iter().position(|&(_, number)| number == signal) {
+        PENDING[index].store(true, Ordering::SeqCst);
+        ANY_PENDING.store(true, Ordering::SeqCst);
+    }
+}
+
+/// `(on-signal name handler)` calls `handler` with the signal name when the
+/// process receives the signal `interrupt` (SIGINT), `terminate` (SIGTERM),
+/// or `hangup` (SIGHUP), instead of terminating. The handler runs at the
+/// next point where evaluation checks for signals, between expressions, on
+/// the thread that installed it. A handler of `#f` restores the default.
+pub fn builtin_on_signal(args: Vec<Value>) -> Result<Value, EvalError> {
+    let (name, handler) = match <[Value; 2]>::try_from(args) {
+        Ok([Value::Symbol(ref name), handler]) => (name.clone(), handler),
+        Ok(_) => return Err(EvalError::TypeError("on-signal expects a signal name and a procedure or #f".into())),
+        Err(_) => return Err(EvalError::ArityMismatch),
+    };
+    check_effect("on-signal")?;
+    let index = SIGNALS.iter().position(|(signal, _)| **signal == *name)
+        .ok_or_else(|| EvalError::TypeError(format!("on-signal: unknown signal {}", name)))?;
+    let handler = (handler != Value::Boolean(false)).then_some(handler);
+    install(index, handler.is_some());
+    HANDLERS.with(|h| h.borrow_mut()[index] = handler);
+    Ok(Value::Unspecified)
+}
+
+#[cfg(unix)]
+fn install(index: usize, trap: bool) {
+    let action = if trap { record_signal as *const () as libc::sighandler_t } else { libc::SIG_DFL };
+    // SAFETY: `record_signal` only performs atomic stores.
+    unsafe {
+        libc::signal(SIGNALS[index].1, action);
+    }
+}
+
+#[cfg(not(unix))]
+fn install(_index: usize, _trap: bool) {}
+
+/// Runs the handlers of the signals that have arrived, if this thread
+/// installed them. The evaluator calls this before each expression.
+pub fn run_pending_handlers() -> Result<(), EvalError> {
+    if !ANY_PENDING.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+    let handlers: Vec<(usize, Value)> = HANDLERS.with(|h| {
+        h.borrow().iter().enumerate()
+            .filter_map(|(i, handler)| Some((i, handler.clone()?)))
+            .filter(|(i, _)| PENDING[*i].swap(false, Ordering::SeqCst))
+            .collect()
+    });
+    ANY_PENDING.store(PENDING.iter().any(|p| p.load(Ordering::SeqCst)), Ordering::SeqCst);
+    for (index, handler) in handlers {
+        apply(handler, vec![Value::Symbol(SIGNALS[index].0.into())])?;
+    }
+    Ok(())
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::env::default_env;
+    use crate::testing::eval_source;
+
+    #[test]
+    fn test_signal_handlers_run_between_expressions() {
+        let env = default_env();
+        eval_source("(define caught (make-vector 1 #f)) (on-signal 'hangup (lambda (name) (vector-set! caught 0 name)))", &env).unwrap();
+        // SAFETY: the handler installed above only records the signal.
+        unsafe {
+            libc::raise(libc::SIGHUP);
+        }
+        assert_eq!(eval_source("(+ 1 2) (vector-ref caught 0)", &env).unwrap(), Value::Symbol("hangup".into()));
+        eval_source("(on-signal 'hangup #f)", &env).unwrap();
+        assert!(matches!(eval_source("(on-signal 'usr1 #f)", &env), Err(EvalError::TypeError(_))));
+    }
+}
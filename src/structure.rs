@@ -0,0 +1,156 @@
+//! Navigation by s-expression, for structural editing in editors and the
+//! REPL. Offsets are byte offsets into the source, and a cursor offset is
+//! the position between two characters. Unbalanced source is handled the
+//! way an editor needs: an unclosed list runs to the end of the text, and a
+//! parenthesis that closes nothing is skipped.
+
+use crate::lexer::{tokenize_with_spans, Lexeme, Span, Token};
+
+/// What surrounds a cursor position, from `navigate`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Navigation {
+    /// The innermost list the cursor is inside, from its opening
+    /// parenthesis (or the quote before it) to its closing one.
+    pub enclosing: Option<Span>,
+    /// The start of the parenthesis matching the one just after the cursor,
+    /// or failing that the one just before it.
+    pub matching_paren: Option<usize>,
+    /// The first expression beside the cursor, in the same list or at top
+    /// level, that starts at or after it.
+    pub next_sibling: Option<Span>,
+    /// The last expression beside the cursor that ends at or before it.
+    pub previous_sibling: Option<Span>,
+}
+
+/// An expression read for navigation: an atom, or a list with its
+/// parentheses and elements.
+struct Datum {
+    span: Span,
+    open: Option<Span>,
+    close: Option<Span>,
+    children: Vec<Datum>,
+}
+
+/// Describes the structure around `offset` in `source`.
+pub fn navigate(source: &str, offset: usize) -> Navigation {
+    let data = read_data(source);
+    let mut siblings = &data[..];
+    let mut enclosing = None;
+    while let Some(list) = siblings.iter().find(|d| {
+        d.open.is_some_and(|open| open.end <= offset) && d.close.is_none_or(|close| offset <= close.start)
+    }) {
+        enclosing = Some(list.span);
+        siblings = &list.children;
+    }
+    let paren_at = |offset| find_matching(&data, offset);
+    Navigation {
+        enclosing,
+        matching_paren: paren_at(offset).or_else(|| paren_at(offset.checked_sub(1)?)),
+        next_sibling: siblings.iter().find(|d| d.span.start >= offset).map(|d| d.span),
+        previous_sibling: siblings.iter().rev().find(|d| d.span.end <= offset).map(|d| d.span),
+    }
+}
+
+/// The start of the parenthesis matching the one that starts at `offset`.
+fn find_matching(data: &[Datum], offset: usize) -> Option<usize> {
+    data.iter().find_map(|d| match (d.open, d.close) {
+        (Some(open), Some(close)) if open.start <= offset && offset < open.end => Some(close.start),
+        (Some(open), Some(close)) if close.start == offset => Some(open.start),
+        _ if d.span.start <= offset && offset < d.span.end => find_matching(&d.children, offset),
+        _ => None,
+    })
+}
+
+/// Reads the top-level expressions of `source`, never failing: text that
+/// cannot be read counts as an atom.
+fn read_data(source: &str) -> Vec<Datum> {
+    let mut top = Vec::new();
+    let mut open_lists: Vec<Datum> = Vec::new();
+    // Where the quotes waiting for the next expression start.
+    let mut quote: Option<usize> = None;
+    for (lexeme, span) in tokenize_with_spans(source) {
+        match lexeme {
+            Lexeme::Comment => {}
+            Lexeme::Token(Token::Quote) => {
+                quote.get_or_insert(span.start);
+            }
+            Lexeme::Token(Token::LParen | Token::HashTableOpen | Token::VectorOpen | Token::BytevectorOpen) => {
+                let start = quote.take().unwrap_or(span.start);
+                let list = Span { start, end: source.len() };
+                open_lists.push(Datum { span: list, open: Some(span), close: None, children: Vec::new() });
+            }
+            Lexeme::Token(Token::RParen) => {
+                quote = None;
+                if let Some(mut list) = open_lists.pop() {
+                    list.close = Some(span);
+                    list.span.end = span.end;
+                    attach(&mut open_lists, &mut top, list);
+                }
+            }
+            Lexeme::Token(_) | Lexeme::Error(_) => {
+                let start = quote.take().unwrap_or(span.start);
+                let atom = Datum { span: Span { start, end: span.end }, open: None, close: None, children: Vec::new() };
+                attach(&mut open_lists, &mut top, atom);
+            }
+        }
+    }
+    while let Some(list) = open_lists.pop() {
+        attach(&mut open_lists, &mut top, list);
+    }
+    top
+}
+
+fn attach(open_lists: &mut [Datum], top: &mut Vec<Datum>, datum: Datum) {
+    match open_lists.last_mut() {
+        Some(list) => list.children.push(datum),
+        None => top.push(datum),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn text(source: &str, span: Option<Span>) -> Option<&str> {
+        span.map(|span| &source[span.start..span.end])
+    }
+
+    #[test]
+    fn test_navigate_inside_a_list() {
+        let source = "(define (f x) '(a b) ; note\n  (g x))";
+        let cursor = source.find("'(a").unwrap();
+        let nav = navigate(source, cursor);
+        assert_eq!(text(source, nav.enclosing), Some(source));
+        assert_eq!(text(source, nav.previous_sibling), Some("(f x)"));
+        assert_eq!(text(source, nav.next_sibling), Some("'(a b)"));
+        assert_eq!(nav.matching_paren, None);
+
+        let nav = navigate(source, source.find("b)").unwrap());
+        assert_eq!(text(source, nav.enclosing), Some("'(a b)"));
+        assert_eq!(text(source, nav.previous_sibling), Some("a"));
+        assert_eq!(text(source, nav.next_sibling), Some("b"));
+    }
+
+    #[test]
+    fn test_matching_paren_before_or_after_the_cursor() {
+        let source = "(a (b c)) d";
+        assert_eq!(navigate(source, 3).matching_paren, Some(7));
+        assert_eq!(navigate(source, 8).matching_paren, Some(0));
+        assert_eq!(navigate(source, 9).matching_paren, Some(0));
+        assert_eq!(navigate(source, 10).matching_paren, None);
+        let nav = navigate(source, 9);
+        assert_eq!(nav.enclosing, None);
+        assert_eq!(text(source, nav.previous_sibling), Some("(a (b c))"));
+        assert_eq!(text(source, nav.next_sibling), Some("d"));
+    }
+
+    #[test]
+    fn test_unbalanced_source() {
+        let source = ") (a (b";
+        let nav = navigate(source, source.len());
+        assert_eq!(text(source, nav.enclosing), Some("(b"));
+        assert_eq!(text(source, nav.previous_sibling), Some("b"));
+        assert_eq!(navigate(source, 0).matching_paren, None);
+        assert_eq!(navigate(source, 2).matching_paren, None);
+    }
+}
@@ -0,0 +1,82 @@
+use std::cell::Cell;
+use std::io::IsTerminal;
+
+use crate::eval::is_pure_mode;
+
+/// Style names and their ANSI SGR codes.
+const STYLES: &[(&str, &str)] = &[
+    ("bold", "1"),
+    ("dim", "2"),
+    ("italic", "3"),
+    ("underline", "4"),
+    ("black", "30"),
+    ("red", "31"),
+    ("green", "32"),
+    ("yellow", "33"),
+    ("blue", "34"),
+    ("magenta", "35"),
+    ("cyan", "36"),
+    ("white", "37"),
+    ("gray", "90"),
+];
+
+/// Resets every style.
+pub const RESET: &str = "\x1b[0m";
+
+thread_local! {
+    static COLOR_OVERRIDE: Cell<Option<bool>> = const { Cell::new(None) };
+}
+
+/// Forces styled output on or off for the current thread; `None` goes back
+/// to detecting it.
+pub fn set_colors_enabled(enabled: Option<bool>) {
+    COLOR_OVERRIDE.with(|c| c.set(enabled));
+}
+
+/// Returns whether styled output is produced. Unless set explicitly, styles
+/// are used only when standard output is a terminal and the `NO_COLOR`
+/// environment variable is unset. In pure mode they are off, so that results
+/// do not depend on where output goes.
+pub fn colors_enabled() -> bool {
+    COLOR_OVERRIDE.with(|c| c.get()).unwrap_or_else(|| {
+        !is_pure_mode() && std::env::var_os("NO_COLOR").is_none() && std::io::stdout().is_terminal()
+    })
+}
+
+/// Returns the escape sequence that turns on the named styles, such as
+/// `["bold", "red"]`, or the first name that is not a style.
+pub fn escape_for<'a>(names: &[&'a str]) -> Result<String, &'a str> {
+    let codes = names.iter()
+        .map(|name| STYLES.iter().find(|(style, _)| style == name).map(|(_, code)| *code).ok_or(*name))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(format!("\x1b[{}m", codes.join(";")))
+}
+
+/// Wraps `text` in the escapes for the named styles, or returns it unchanged
+/// if styled output is off.
+pub fn paint<'a>(text: &str, names: &[&'a str]) -> Result<String, &'a str> {
+    let escape = escape_for(names)?;
+    if !colors_enabled() {
+        return Ok(text.to_string());
+    }
+    Ok(format!("{}{}{}", escape, text, RESET))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_paint_wraps_text_when_enabled() {
+        set_colors_enabled(Some(true));
+        assert_eq!(paint("hi", &["bold", "red"]).unwrap(), "\x1b[1;31mhi\x1b[0m");
+        set_colors_enabled(Some(false));
+        assert_eq!(paint("hi", &["bold", "red"]).unwrap(), "hi");
+        set_colors_enabled(None);
+    }
+
+    #[test]
+    fn test_unknown_styles_are_reported() {
+        assert_eq!(paint("hi", &["red", "plaid"]), Err("plaid"));
+    }
+}
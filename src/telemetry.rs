@@ -0,0 +1,103 @@
+//! Instrumentation for the `tracing` ecosystem, built with the `tracing`
+//! feature, so that embedders can see where their Scheme workloads spend
+//! time with the subscribers they already use.
+//!
+//! Evaluation opens a `trace`-level `eval` span around each expression,
+//! with the expression as its `expr` field, and a `debug`-level `apply` span
+//! around each procedure call, with the procedure as `procedure`. Both cost
+//! next to nothing unless a subscriber enables them.
+//!
+//! Counts of the work done, such as environments allocated and global
+//! lookup cache hits, are kept per thread; `emit_counters` reports them as
+//! an event whose fields follow the `monotonic_counter.` convention that
+//! metrics layers such as `tracing-opentelemetry` understand.
+
+use std::cell::Cell;
+
+/// How much work the interpreter has done on the current thread since the
+/// counters were last reset.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Counters {
+    /// Expressions evaluated.
+    pub evaluations: u64,
+    /// Procedures applied to arguments.
+    pub applications: u64,
+    /// Environments allocated, one for each procedure call and `let`.
+    pub environments: u64,
+    /// Closures created by `lambda`.
+    pub closures: u64,
+    /// Global variable lookups answered by the lookup cache.
+    pub cache_hits: u64,
+    /// Global variable lookups that searched the global environment.
+    pub cache_misses: u64,
+}
+
+thread_local! {
+    static COUNTERS: Cell<Counters> = const { Cell::new(Counters {
+        evaluations: 0,
+        applications: 0,
+        environments: 0,
+        closures: 0,
+        cache_hits: 0,
+        cache_misses: 0,
+    }) };
+}
+
+/// Returns the counters of the current thread.
+pub fn counters() -> Counters {
+    COUNTERS.with(Cell::get)
+}
+
+/// Sets the counters of the current thread back to zero.
+pub fn reset_counters() {
+    COUNTERS.with(|c| c.set(Counters::default()));
+}
+
+pub(crate) fn count(update: impl FnOnce(&mut Counters)) {
+    COUNTERS.with(|c| {
+        let mut counters = c.get();
+        update(&mut counters);
+        c.set(counters);
+    });
+}
+
+/// Reports the counters of the current thread as an `info`-level event
+/// with target `scheme_rs::counters`, then resets them, so that each event
+/// carries the increments since the one before.
+pub fn emit_counters() {
+    let c = counters();
+    tracing::event!(
+        target: "scheme_rs::counters",
+        tracing::Level::INFO,
+        monotonic_counter.evaluations = c.evaluations,
+        monotonic_counter.applications = c.applications,
+        monotonic_counter.environments = c.environments,
+        monotonic_counter.closures = c.closures,
+        monotonic_counter.cache_hits = c.cache_hits,
+        monotonic_counter.cache_misses = c.cache_misses,
+        "interpreter counters",
+    );
+    reset_counters();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::env::default_env;
+    use crate::eval::eval_sequence;
+    use crate::lexer::tokenize;
+    use crate::parser::parse_program;
+
+    #[test]
+    fn test_counters() {
+        let program = parse_program(tokenize("(define (f x) (+ x 1)) (f (f 1))").unwrap()).unwrap();
+        reset_counters();
+        eval_sequence(&program, default_env()).unwrap();
+        let c = counters();
+        assert_eq!((c.applications, c.environments, c.closures), (4, 2, 1));
+        assert!(c.evaluations > 10);
+        assert!(c.cache_hits + c.cache_misses >= 4);
+        emit_counters();
+        assert_eq!(counters(), Counters::default());
+    }
+}
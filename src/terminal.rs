@@ -0,0 +1,152 @@
+#[cfg(unix)]
+use std::cell::RefCell;
+
+use crate::env::{EvalError, Value};
+use crate::eval::check_effect;
+use crate::port::Port;
+
+#[cfg(unix)]
+thread_local! {
+    /// Terminal settings from before raw mode was turned on, to restore.
+    static SAVED_TERMIOS: RefCell<Option<libc::termios>> = const { RefCell::new(None) };
+}
+
+/// `(terminal-raw-mode! on?)` turns raw mode on or off. In raw mode input is
+/// available a key at a time, without echo or line editing, so `read-char`
+/// returns each key as it is pressed.
+pub fn builtin_terminal_raw_mode(args: Vec<Value>) -> Result<Value, EvalError> {
+    let on = match &args[..] {
+        [value] => *value != Value::Boolean(false),
+        _ => return Err(EvalError::ArityMismatch),
+    };
+    check_effect("terminal-raw-mode!")?;
+    if on {
+        enter_raw_mode()?;
+    } else {
+        restore_terminal();
+    }
+    Ok(Value::Unspecified)
+}
+
+/// `(terminal-size)` returns the size of the terminal as a list
+/// `(columns rows)`.
+pub fn builtin_terminal_size(args: Vec<Value>) -> Result<Value, EvalError> {
+    if !args.is_empty() {
+        return Err(EvalError::ArityMismatch);
+    }
+    check_effect("terminal-size")?;
+    let (columns, rows) = terminal_size()?;
+    Ok(Value::List([Value::Number(columns as i64), Value::Number(rows as i64)].into()))
+}
+
+/// `(terminal-clear)` clears the screen and moves the cursor to the top left.
+pub fn builtin_terminal_clear(args: Vec<Value>) -> Result<Value, EvalError> {
+    if !args.is_empty() {
+        return Err(EvalError::ArityMismatch);
+    }
+    write_escape("\x1b[2J\x1b[H")
+}
+
+/// `(terminal-move-cursor! row column)` moves the cursor. Both count from 0
+/// at the top left corner.
+pub fn builtin_terminal_move_cursor(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [Value::Number(row), Value::Number(column)] if *row >= 0 && *column >= 0 => {
+            write_escape(&format!("\x1b[{};{}H", row + 1, column + 1))
+        }
+        [_, _] => Err(EvalError::TypeError("Expected non-negative row and column".into())),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+/// `(terminal-cursor-visible! visible?)` shows or hides the cursor.
+pub fn builtin_terminal_cursor_visible(args: Vec<Value>) -> Result<Value, EvalError> {
+    match &args[..] {
+        [Value::Boolean(false)] => write_escape("\x1b[?25l"),
+        [_] => write_escape("\x1b[?25h"),
+        _ => Err(EvalError::ArityMismatch),
+    }
+}
+
+fn write_escape(sequence: &str) -> Result<Value, EvalError> {
+    Port::Stdout.write_str(sequence)?;
+    Ok(Value::Unspecified)
+}
+
+/// Leaves raw mode if a program turned it on, restoring the settings it
+/// replaced. The REPL calls this after each evaluation, so a program that
+/// fails in raw mode does not leave the terminal unusable.
+pub fn restore_terminal() {
+    #[cfg(unix)]
+    if let Some(saved) = SAVED_TERMIOS.with(|s| s.borrow_mut().take()) {
+        // SAFETY: `saved` was filled in by `tcgetattr` on the same descriptor.
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &saved);
+        }
+    }
+}
+
+#[cfg(unix)]
+fn enter_raw_mode() -> Result<(), EvalError> {
+    if SAVED_TERMIOS.with(|s| s.borrow().is_some()) {
+        return Ok(());
+    }
+    // SAFETY: `termios` is plain data that `tcgetattr` fills in before use.
+    unsafe {
+        let mut termios = std::mem::zeroed::<libc::termios>();
+        if libc::tcgetattr(libc::STDIN_FILENO, &mut termios) != 0 {
+            return Err(EvalError::Other("terminal-raw-mode!: standard input is not a terminal".into()));
+        }
+        let saved = termios;
+        libc::cfmakeraw(&mut termios);
+        if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &termios) != 0 {
+            return Err(EvalError::Other("terminal-raw-mode!: cannot change terminal settings".into()));
+        }
+        SAVED_TERMIOS.with(|s| *s.borrow_mut() = Some(saved));
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn enter_raw_mode() -> Result<(), EvalError> {
+    Err(EvalError::Other("terminal-raw-mode!: not supported on this platform".into()))
+}
+
+#[cfg(unix)]
+fn terminal_size() -> Result<(u16, u16), EvalError> {
+    // SAFETY: `winsize` is plain data that `ioctl` fills in on success.
+    unsafe {
+        let mut size = std::mem::zeroed::<libc::winsize>();
+        if libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) != 0 || size.ws_col == 0 {
+            return Err(EvalError::Other("terminal-size: standard output is not a terminal".into()));
+        }
+        Ok((size.ws_col, size.ws_row))
+    }
+}
+
+#[cfg(not(unix))]
+fn terminal_size() -> Result<(u16, u16), EvalError> {
+    Err(EvalError::Other("terminal-size: not supported on this platform".into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eval::set_pure_mode;
+
+    #[test]
+    fn test_cursor_arguments_are_checked() {
+        let negative = builtin_terminal_move_cursor(vec![Value::Number(-1), Value::Number(0)]);
+        assert!(matches!(negative, Err(EvalError::TypeError(_))));
+        assert!(matches!(builtin_terminal_move_cursor(vec![Value::Number(1)]), Err(EvalError::ArityMismatch)));
+    }
+
+    #[test]
+    fn test_terminal_control_is_an_effect() {
+        set_pure_mode(true);
+        assert!(matches!(builtin_terminal_clear(vec![]), Err(EvalError::EffectInPureMode(_))));
+        assert!(matches!(builtin_terminal_size(vec![]), Err(EvalError::EffectInPureMode(_))));
+        assert!(matches!(builtin_terminal_raw_mode(vec![Value::Boolean(true)]), Err(EvalError::EffectInPureMode(_))));
+        set_pure_mode(false);
+    }
+}
@@ -0,0 +1,137 @@
+//! Helpers for golden-output tests of Scheme code, for crates that embed
+//! the interpreter and want concise integration tests of their extensions:
+//!
+//! ```
+//! use scheme_rs::testing::{assert_fails, assert_prints};
+//!
+//! assert_prints("(define (f x) (* x 2)) (map f '(1 2))", "(2 4)");
+//! assert_prints("(list 'a\n      \"b  c\")", "(a   \"b  c\")");
+//! assert_fails("(car 1)", "type");
+//! ```
+//!
+//! Printed results are compared after `normalize`, so expected output can
+//! be laid out freely, and mismatches fail through `assert_eq!` with the
+//! expected and actual text on lines of their own, where test runners and
+//! IDEs show a diff.
+
+use std::rc::Rc;
+
+use crate::condition::error_summary;
+use crate::env::{default_env, Env, EvalError, Value};
+use crate::eval::eval_sequence;
+use crate::lexer::tokenize;
+use crate::parser::parse_program;
+use crate::printer::{print_value, PrintOptions};
+
+/// Evaluates `source` in `env` and returns its last value, failing with a
+/// message for source that cannot be read.
+pub fn eval_source(source: &str, env: &Rc<Env>) -> Result<Value, EvalError> {
+    let tokens = tokenize(source).map_err(|e| EvalError::Read(format!("{:?}", e)))?;
+    let program = parse_program(tokens).map_err(|e| EvalError::Read(format!("{:?}", e)))?;
+    eval_sequence(&program, env.clone())
+}
+
+/// Evaluates `source` in `env` and prints its last value as the REPL
+/// would, or describes the error as `kind: message`.
+pub fn eval_to_string(source: &str, env: &Rc<Env>) -> Result<String, String> {
+    match eval_source(source, env) {
+        Ok(value) => Ok(print_value(&value, &PrintOptions::from_env(env))),
+        Err(error) => {
+            let (kind, message) = error_summary(&error);
+            Err(format!("{}: {}", kind, message))
+        }
+    }
+}
+
+/// Collapses each run of whitespace outside string literals to one space
+/// and removes whitespace at either end, after `(`, and before `)`, so that
+/// printed data compare equal however they are laid out.
+pub fn normalize(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.trim().chars();
+    let mut space = false;
+    while let Some(c) = chars.next() {
+        if c.is_whitespace() {
+            space = true;
+            continue;
+        }
+        if space && c != ')' && !out.ends_with('(') {
+            out.push(' ');
+        }
+        space = false;
+        out.push(c);
+        if c == '"' && !out[..out.len() - 1].ends_with("#\\") {
+            while let Some(c) = chars.next() {
+                out.push(c);
+                match c {
+                    '\\' => out.extend(chars.next()),
+                    '"' => break,
+                    _ => {}
+                }
+            }
+        }
+    }
+    out
+}
+
+/// Asserts that `source`, evaluated in a fresh default environment, prints
+/// as `expected` (see `normalize`).
+#[track_caller]
+pub fn assert_prints(source: &str, expected: &str) {
+    assert_prints_in(&default_env(), source, expected);
+}
+
+/// Like `assert_prints`, in a given environment, such as one with an
+/// extension's procedures defined.
+#[track_caller]
+pub fn assert_prints_in(env: &Rc<Env>, source: &str, expected: &str) {
+    match eval_to_string(source, env) {
+        Ok(actual) => assert_eq!(normalize(&actual), normalize(expected), "printed result of {}", source),
+        Err(error) => panic!("evaluating {} failed with {}\nexpected: {}", source, error, expected),
+    }
+}
+
+/// Asserts that `source`, evaluated in a fresh default environment, fails
+/// with an error of `kind`, as `condition-kind` gives it (such as `type` or
+/// `undefined`).
+#[track_caller]
+pub fn assert_fails(source: &str, kind: &str) {
+    assert_fails_in(&default_env(), source, kind);
+}
+
+/// Like `assert_fails`, in a given environment.
+#[track_caller]
+pub fn assert_fails_in(env: &Rc<Env>, source: &str, kind: &str) {
+    match eval_source(source, env) {
+        Ok(value) => panic!("evaluating {} returned {} but should fail with {}", source, value, kind),
+        Err(error) => assert_eq!(error_summary(&error).0, kind, "error kind of {}", source),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize() {
+        assert_eq!(normalize("  ( 1\n   2 ( 3 )\t) "), "(1 2 (3))");
+        assert_eq!(normalize("(\"a  (b\"   \"c\\\"  d\")"), "(\"a  (b\" \"c\\\"  d\")");
+        assert_eq!(normalize("(#\\\"   x)"), "(#\\\" x)");
+    }
+
+    #[test]
+    fn test_assertions() {
+        let env = default_env();
+        eval_source("(define (twice x) (list x x))", &env).unwrap();
+        assert_prints_in(&env, "(twice \"a b\")", "(\"a b\"\n \"a b\")");
+        assert_eq!(eval_to_string("(undefined-thing)", &env), Err("undefined: Undefined symbol undefined-thing".into()));
+        assert_fails_in(&env, "(twice)", "arity");
+        assert_fails("(car", "read");
+    }
+
+    #[test]
+    #[should_panic(expected = "printed result of (+ 1 2)")]
+    fn test_mismatch_fails() {
+        assert_prints("(+ 1 2)", "4");
+    }
+}
@@ -0,0 +1,174 @@
+use crate::env::{EvalError, Value};
+use crate::eval::check_effect;
+use crate::number::Number;
+
+#[cfg(not(browser))]
+use std::cell::{Cell, RefCell};
+#[cfg(not(browser))]
+use std::time::{Duration, Instant};
+
+#[cfg(not(browser))]
+use crate::eval::{apply, check_wait, WAIT_SLICE};
+
+#[cfg(browser)]
+use wasm_bindgen::prelude::*;
+
+#[cfg(not(browser))]
+thread_local! {
+    /// Thunks scheduled by `after`, with when they are due, in the order
+    /// they were scheduled.
+    static TIMERS: RefCell<Vec<(Instant, Value)>> = const { RefCell::new(Vec::new()) };
+    /// Set while due timers are running, so that their own evaluation does
+    /// not start them again.
+    static RUNNING: Cell<bool> = const { Cell::new(false) };
+}
+
+#[cfg(browser)]
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_name = setTimeout)]
+    fn set_timeout(callback: &JsValue, ms: f64) -> JsValue;
+
+    #[wasm_bindgen(js_namespace = console, js_name = error)]
+    fn console_error(message: &str);
+}
+
+fn seconds_arg(value: &Value, name: &str) -> Result<f64, EvalError> {
+    match Number::try_from(value).map(Number::to_f64) {
+        Ok(x) if x >= 0.0 && x.is_finite() => Ok(x),
+        _ => Err(EvalError::TypeError(format!("{} expects a non-negative number", name))),
+    }
+}
+
+/// `(sleep seconds)` pauses for the given number of seconds, which need not
+/// be whole, running any `after` timers that fall due meanwhile. An
+/// interrupt or time limit stops it early. In the
+/// browser, where blocking would freeze the page, it is an error; schedule
+/// the rest of the work with `after` instead.
+pub fn builtin_sleep(args: Vec<Value>) -> Result<Value, EvalError> {
+    let [seconds] = <[Value; 1]>::try_from(args).map_err(|_| EvalError::ArityMismatch)?;
+    let seconds = seconds_arg(&seconds, "sleep")?;
+    check_effect("sleep")?;
+    #[cfg(not(browser))]
+    {
+        let end = Instant::now() + Duration::from_secs_f64(seconds);
+        loop {
+            run_due_timers()?;
+            let now = Instant::now();
+            if now >= end {
+                return Ok(Value::Unspecified);
+            }
+            check_wait()?;
+            let next = TIMERS.with(|t| t.borrow().iter().map(|(due, _)| *due).min());
+            let wake = next.map_or(end, |next| next.min(end)).saturating_duration_since(now);
+            std::thread::sleep(wake.min(WAIT_SLICE));
+        }
+    }
+    #[cfg(browser)]
+    {
+        let _ = seconds;
+        Err(EvalError::Other("sleep: would freeze the page; use after instead".into()))
+    }
+}
+
+/// `(after ms thunk)` calls `thunk` once `ms` milliseconds have passed.
+/// Natively it runs at the next point where evaluation checks for timers,
+/// between expressions or during `sleep`; in the browser it runs from the
+/// page's event loop, and errors go to the console.
+pub fn builtin_after(args: Vec<Value>) -> Result<Value, EvalError> {
+    let [ms, thunk] = <[Value; 2]>::try_from(args).map_err(|_| EvalError::ArityMismatch)?;
+    let ms = seconds_arg(&ms, "after")?;
+    if !matches!(thunk, Value::Function(_) | Value::Lambda(_) | Value::Memoized(_) | Value::Combinator(_) | Value::RecordProcedure(_)) {
+        return Err(EvalError::TypeError("after expects a procedure".into()));
+    }
+    check_effect("after")?;
+    #[cfg(not(browser))]
+    {
+        let due = Instant::now() + Duration::from_secs_f64(ms / 1000.0);
+        TIMERS.with(|t| t.borrow_mut().push((due, thunk)));
+    }
+    #[cfg(browser)]
+    {
+        let callback = Closure::once_into_js(move || {
+            if let Err(e) = crate::eval::apply(thunk, vec![]) {
+                console_error(&format!("Error in after: {:?}", e));
+            }
+        });
+        set_timeout(&callback, ms);
+    }
+    Ok(Value::Unspecified)
+}
+
+/// Runs the `after` thunks that are due, earliest first. The evaluator
+/// calls this before each expression; with no timers pending it does not
+/// look at the clock.
+#[cfg(not(browser))]
+pub fn run_due_timers() -> Result<(), EvalError> {
+    if RUNNING.with(|r| r.get()) || TIMERS.with(|t| t.borrow().is_empty()) {
+        return Ok(());
+    }
+    let now = Instant::now();
+    let mut due: Vec<(Instant, Value)> = TIMERS.with(|t| {
+        let (due, pending) = t.take().into_iter().partition(|(at, _)| *at <= now);
+        *t.borrow_mut() = pending;
+        due
+    });
+    due.sort_by_key(|(at, _)| *at);
+    RUNNING.with(|r| r.set(true));
+    let result = due.into_iter().try_for_each(|(_, thunk)| apply(thunk, vec![]).map(|_| ()));
+    RUNNING.with(|r| r.set(false));
+    result
+}
+
+#[cfg(all(test, not(browser)))]
+mod tests {
+    use super::*;
+    use crate::env::default_env;
+    use crate::testing::eval_source;
+
+    #[test]
+    fn test_timers_run_between_expressions_and_during_sleep() {
+        let source = "
+            (define fired (make-vector 1 '()))
+            (define (note x) (lambda () (vector-set! fired 0 (cons x (vector-ref fired 0)))))
+            (after 20 (note 'late))
+            (after 0 (note 'soon))
+            (after 10000 (note 'never))
+            (define before (vector-ref fired 0))
+            (sleep (/ 1 20))
+            (list before (vector-ref fired 0))";
+        let start = Instant::now();
+        assert_eq!(eval_source(source, &default_env()).unwrap().to_string(), "((soon) (late soon))");
+        assert!(start.elapsed() >= Duration::from_millis(50));
+        TIMERS.with(|t| t.borrow_mut().clear());
+    }
+
+    #[test]
+    fn test_sleep_stops_when_interrupted() {
+        use std::sync::atomic::{AtomicBool, Ordering};
+        use std::sync::Arc;
+
+        let flag = Arc::new(AtomicBool::new(false));
+        crate::eval::set_interrupt_flag(Some(flag.clone()));
+        let timer = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            flag.store(true, Ordering::Relaxed);
+        });
+        let start = Instant::now();
+        assert!(matches!(eval_source("(sleep 10)", &default_env()), Err(EvalError::Interrupted)));
+        assert!(start.elapsed() < Duration::from_secs(2));
+        crate::eval::set_interrupt_flag(None);
+        timer.join().unwrap();
+
+        let start = Instant::now();
+        assert_eq!(eval_source("(with-limits (seconds (/ 1 20) default 'late) (sleep 10))", &default_env()).unwrap(), Value::Symbol("late".into()));
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[test]
+    fn test_timer_argument_errors() {
+        assert!(matches!(eval_source("(sleep (- 0 1))", &default_env()), Err(EvalError::TypeError(_))));
+        assert!(matches!(eval_source("(after 10 5)", &default_env()), Err(EvalError::TypeError(_))));
+        assert!(matches!(eval_source("(after 10)", &default_env()), Err(EvalError::WrongArgumentCount(message)) if message == "after: expected 2 arguments, got 1"));
+    }
+}
@@ -0,0 +1,367 @@
+//! Weak boxes and finalizers. `(make-weak-box obj)` refers to `obj` without
+//! keeping it alive, so a cache can hold values that the rest of the program
+//! may drop; `(weak-box-value box)` returns `obj` while something else still
+//! refers to it. `(set-finalizer! obj thunk)` calls `thunk` once `obj` is
+//! gone, to release what it stood for, such as a foreign resource.
+//!
+//! Values are reference counted, so an object goes as soon as the last
+//! reference to it does, though the global lookup cache may hold on to one
+//! until it is checked. Finalizers are run between expressions, checked
+//! every `SLICE` of them, and by `(collect-garbage)`. There is no cycle
+//! collector: objects in a reference cycle are never freed, and neither is
+//! one that its own finalizer refers to.
+//!
+//! `(make-weak-hash-table)` returns a hash table whose entries go once
+//! nothing but the keys of such tables refers to their keys, so that no
+//! lookup could find them again; `memoize` forgets results the same way. That applies to
+//! keys compared by identity, such as records and procedures: a list or
+//! vector key could be rebuilt equal to the original, so its entry stays.
+//! An entry whose value refers to its key keeps it.
+//!
+//! Only objects are held weakly. Numbers, characters, booleans, strings,
+//! symbols, and other values that are copied rather than shared, as well as
+//! built-in procedures and the empty list, are held as usual; a weak box
+//! holding one never empties.
+
+use std::cell::{Cell, RefCell};
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::rc::{Rc, Weak};
+
+use indexmap::IndexMap;
+
+use crate::channel::Channel;
+use crate::combinator::Combinator;
+use crate::condition::Condition;
+use crate::env::{drop_stale_lookups, EvalError, Lambda, Memoized, Promise, Value};
+use crate::eval::apply;
+use crate::fiber::Fiber;
+use crate::generator::Generator;
+use crate::mutex::Mutex;
+use crate::port::Port;
+use crate::record::{Record, RecordProcedure, RecordType};
+
+/// The contents of a hash table.
+type Table = RefCell<IndexMap<Value, Value>>;
+
+/// How many expressions are evaluated between checks for finalizers to run.
+const SLICE: u32 = 1000;
+
+thread_local! {
+    /// Objects with finalizers, and the thunks to call once they are gone.
+    static FINALIZERS: RefCell<Vec<(WeakValue, Value)>> = const { RefCell::new(Vec::new()) };
+    /// Expressions left until the next check.
+    static CLOCK: Cell<u32> = const { Cell::new(SLICE) };
+    /// The hash tables from `make-weak-hash-table`.
+    static WEAK_TABLES: RefCell<Vec<Weak<Table>>> = const { RefCell::new(Vec::new()) };
+    /// The procedures from `memoize`, whose caches are weak in the same way.
+    static MEMOIZED: RefCell<Vec<Weak<Memoized>>> = const { RefCell::new(Vec::new()) };
+}
+
+macro_rules! weak_value {
+    ($($variant:ident($target:ty)),* $(,)?) => {
+        /// A value held without keeping the object it refers to alive.
+        enum WeakValue {
+            Strong(Value),
+            $($variant(Weak<$target>),)*
+        }
+
+        impl WeakValue {
+            fn new(value: &Value) -> WeakValue {
+                match value {
+                    Value::List(items) if items.is_empty() => WeakValue::Strong(value.clone()),
+                    $(Value::$variant(object) => WeakValue::$variant(Rc::downgrade(object)),)*
+                    other => WeakValue::Strong(other.clone()),
+                }
+            }
+
+            /// Returns the value, unless it was an object that is gone.
+            fn get(&self) -> Option<Value> {
+                match self {
+                    WeakValue::Strong(value) => Some(value.clone()),
+                    $(WeakValue::$variant(object) => object.upgrade().map(Value::$variant),)*
+                }
+            }
+
+            fn is_object(&self) -> bool {
+                !matches!(self, WeakValue::Strong(_))
+            }
+
+            fn is_gone(&self) -> bool {
+                match self {
+                    WeakValue::Strong(_) => false,
+                    $(WeakValue::$variant(object) => object.strong_count() == 0,)*
+                }
+            }
+        }
+    };
+}
+
+weak_value! {
+    Lambda(Lambda),
+    Memoized(Memoized),
+    Combinator(Combinator),
+    List([Value]),
+    Vector(RefCell<Vec<Value>>),
+    Bytevector(RefCell<Vec<u8>>),
+    HashTable(Table),
+    Queue(RefCell<VecDeque<Value>>),
+    Record(Record),
+    RecordType(RecordType),
+    RecordProcedure(RecordProcedure),
+    Port(Port),
+    Promise(Promise),
+    Generator(Generator),
+    Fiber(Fiber),
+    Channel(Channel),
+    Mutex(Mutex),
+    WeakBox(WeakBox),
+    Condition(Condition),
+}
+
+/// A weak box from `make-weak-box`.
+pub struct WeakBox {
+    target: WeakValue,
+}
+
+impl fmt::Debug for WeakBox {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WeakBox").field("empty", &self.target.is_gone()).finish()
+    }
+}
+
+/// `(make-weak-box obj)` returns a weak box holding `obj`.
+pub fn builtin_make_weak_box(args: Vec<Value>) -> Result<Value, EvalError> {
+    Ok(Value::WeakBox(Rc::new(WeakBox { target: WeakValue::new(&args[0]) })))
+}
+
+/// Returns true if the argument is a weak box.
+pub fn builtin_is_weak_box(args: Vec<Value>) -> Result<Value, EvalError> {
+    Ok(Value::Boolean(matches!(args[0], Value::WeakBox(_))))
+}
+
+/// `(weak-box-value box [default])` returns the box's value, or `default`
+/// (#f if not given) once it is gone.
+pub fn builtin_weak_box_value(args: Vec<Value>) -> Result<Value, EvalError> {
+    let Value::WeakBox(weak_box) = &args[0] else {
+        return Err(EvalError::TypeError(format!("weak-box-value: expected a weak box, got {}", args[0].type_name())));
+    };
+    drop_stale_lookups();
+    Ok(weak_box.target.get().unwrap_or_else(|| args.get(1).cloned().unwrap_or(Value::Boolean(false))))
+}
+
+/// `(set-finalizer! obj thunk)` arranges for `thunk` to be called once `obj`
+/// is gone. An object can have several finalizers.
+pub fn builtin_set_finalizer(args: Vec<Value>) -> Result<Value, EvalError> {
+    let [object, thunk] = <[Value; 2]>::try_from(args).map_err(|_| EvalError::ArityMismatch)?;
+    let weak = WeakValue::new(&object);
+    if !weak.is_object() {
+        return Err(EvalError::TypeError(format!("set-finalizer!: a {} is never freed, so its finalizer would never run", object.type_name())));
+    }
+    if !matches!(thunk, Value::Function(_) | Value::Lambda(_) | Value::Memoized(_) | Value::Combinator(_) | Value::RecordProcedure(_)) {
+        return Err(EvalError::TypeError(format!("set-finalizer!: expected a procedure, got {}", thunk.type_name())));
+    }
+    FINALIZERS.with(|f| f.borrow_mut().push((weak, thunk)));
+    Ok(Value::Unspecified)
+}
+
+/// `(make-weak-hash-table)` returns a new hash table that does not keep its
+/// keys alive. Copies made with `hash-table-copy` are ordinary tables.
+pub fn builtin_make_weak_hash_table(_args: Vec<Value>) -> Result<Value, EvalError> {
+    let table = Rc::new(RefCell::new(IndexMap::new()));
+    WEAK_TABLES.with(|t| t.borrow_mut().push(Rc::downgrade(&table)));
+    Ok(Value::HashTable(table))
+}
+
+/// `(hash-table-weak? table)` returns whether the hash table was made by
+/// `make-weak-hash-table`.
+pub fn builtin_is_weak_hash_table(args: Vec<Value>) -> Result<Value, EvalError> {
+    let Value::HashTable(table) = &args[0] else {
+        return Err(EvalError::TypeError(format!("hash-table-weak?: expected a hash table, got {}", args[0].type_name())));
+    };
+    Ok(Value::Boolean(WEAK_TABLES.with(|t| t.borrow().iter().any(|weak| std::ptr::eq(weak.as_ptr(), Rc::as_ptr(table))))))
+}
+
+/// Lets `memoize` forget the results for arguments that are gone.
+pub(crate) fn track_memoized(memoized: &Rc<Memoized>) {
+    MEMOIZED.with(|m| m.borrow_mut().push(Rc::downgrade(memoized)));
+}
+
+/// The address and reference count of an object compared by identity, for
+/// telling whether only weak tables refer to it.
+fn identity(value: &Value) -> Option<(*const (), usize)> {
+    fn of<T: ?Sized>(object: &Rc<T>) -> Option<(*const (), usize)> {
+        Some((Rc::as_ptr(object).cast(), Rc::strong_count(object)))
+    }
+    match value {
+        Value::Lambda(object) => of(object),
+        Value::Memoized(object) => of(object),
+        Value::Combinator(object) => of(object),
+        Value::Queue(object) => of(object),
+        Value::Record(object) => of(object),
+        Value::RecordType(object) => of(object),
+        Value::RecordProcedure(object) => of(object),
+        Value::Port(object) => of(object),
+        Value::Promise(object) => of(object),
+        Value::Generator(object) => of(object),
+        Value::Fiber(object) => of(object),
+        Value::Channel(object) => of(object),
+        Value::Mutex(object) => of(object),
+        Value::WeakBox(object) => of(object),
+        Value::Condition(object) => of(object),
+        _ => None,
+    }
+}
+
+/// Removes the entries of weak hash tables and memoized procedures whose
+/// keys nothing but these keys refers to, so that no lookup can find them
+/// again. Removing entries can leave other keys unreachable, so it repeats
+/// until there are none.
+fn sweep_weak_tables() {
+    let tables: Vec<_> = WEAK_TABLES.with(|t| {
+        let mut tables = t.borrow_mut();
+        tables.retain(|table| table.strong_count() > 0);
+        tables.iter().filter_map(Weak::upgrade).collect()
+    });
+    let memoized: Vec<_> = MEMOIZED.with(|m| {
+        let mut memoized = m.borrow_mut();
+        memoized.retain(|memoized| memoized.strong_count() > 0);
+        memoized.iter().filter_map(Weak::upgrade).collect()
+    });
+    loop {
+        // How many of the references to each object are keys.
+        let mut weak_references: HashMap<*const (), usize> = HashMap::new();
+        let mut count = |key: &Value| if let Some((object, _)) = identity(key) {
+            *weak_references.entry(object).or_default() += 1;
+        };
+        tables.iter().for_each(|table| table.borrow().keys().for_each(&mut count));
+        memoized.iter().for_each(|memoized| memoized.for_each_args(|args| args.iter().for_each(&mut count)));
+        let unreachable = |key: &Value| identity(key).is_some_and(|(object, references)| weak_references[&object] == references);
+        let mut removed = false;
+        for table in &tables {
+            let mut table = table.borrow_mut();
+            let len = table.len();
+            table.retain(|key, _| !unreachable(key));
+            removed |= table.len() < len;
+        }
+        for memoized in &memoized {
+            removed |= memoized.retain(|args| !args.iter().any(unreachable));
+        }
+        if !removed {
+            break;
+        }
+    }
+}
+
+/// `(collect-garbage)` removes the entries of weak hash tables whose keys are
+/// gone and runs the finalizers of the objects that are.
+pub fn builtin_collect_garbage(_args: Vec<Value>) -> Result<Value, EvalError> {
+    run_finalizers()?;
+    Ok(Value::Unspecified)
+}
+
+/// Called by `eval` before each expression; does the work of
+/// `collect-garbage` every `SLICE` expressions.
+pub(crate) fn check_finalizers() -> Result<(), EvalError> {
+    let due = CLOCK.with(|c| match c.get() {
+        0 => true,
+        n => {
+            c.set(n - 1);
+            false
+        }
+    });
+    if !due {
+        return Ok(());
+    }
+    CLOCK.with(|c| c.set(SLICE));
+    run_finalizers()
+}
+
+/// Sweeps the weak hash tables, then calls, in the order they were set, the
+/// finalizers of the objects that are gone, and forgets them.
+fn run_finalizers() -> Result<(), EvalError> {
+    drop_stale_lookups();
+    sweep_weak_tables();
+    let due: Vec<Value> = FINALIZERS.with(|f| {
+        let mut finalizers = f.borrow_mut();
+        let (gone, alive) = finalizers.drain(..).partition::<Vec<_>, _>(|(object, _)| object.is_gone());
+        *finalizers = alive;
+        gone.into_iter().map(|(_, thunk)| thunk).collect()
+    });
+    for thunk in due {
+        apply(thunk, Vec::new())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{assert_fails, assert_prints};
+
+    #[test]
+    fn test_weak_box_empties_when_the_object_goes() {
+        assert_prints("
+            (define v (vector 1 2))
+            (define b (make-weak-box v))
+            (define before (vector? (weak-box-value b)))
+            (define v #f)
+            (list before (weak-box-value b) (weak-box-value b 'gone)
+                  (weak-box? b) (weak-box-value (make-weak-box (list 1 2)))
+                  (weak-box-value (make-weak-box 42)) (weak-box-value (make-weak-box '())))",
+            "(#t #f gone #t #f 42 ())");
+    }
+
+    #[test]
+    fn test_finalizers_run_once_the_object_goes() {
+        assert_prints("
+            (define log (make-queue))
+            (define table (make-hash-table))
+            (set-finalizer! table (lambda () (enqueue! log 'table)))
+            (set-finalizer! table (lambda () (enqueue! log 'again)))
+            (collect-garbage)
+            (define before (queue->list log))
+            (define table #f)
+            (collect-garbage)
+            (collect-garbage)
+            (list before (queue->list log))",
+            "(() (table again))");
+        // Without `collect-garbage`, they run between expressions.
+        assert_prints("
+            (define freed (make-vector 1 #f))
+            (set-finalizer! (vector) (lambda () (vector-set! freed 0 #t)))
+            (vector-map (lambda (i) (+ i 1)) (make-vector 1000 0))
+            (vector-ref freed 0)",
+            "#t");
+    }
+
+    #[test]
+    fn test_weak_hash_tables_and_memoize_forget_unreachable_keys() {
+        assert_prints("
+            (define cache (make-weak-hash-table))
+            (define key (make-queue))
+            (define chained (make-queue))
+            (hash-table-set! cache key chained)
+            (hash-table-set! cache chained 'chained)
+            (hash-table-set! cache (list 1 2) 'rebuildable)
+            (hash-table-set! cache 'name 'kept)
+            (define square (memoize (lambda (q) (queue? q))))
+            (square key)
+            (square (list 1 2))
+            (collect-garbage)
+            (define before (list (hash-table-count cache) (memo-cache-size square)))
+            (define key #f)
+            (define chained #f)
+            (collect-garbage)
+            (list before (hash-table-count cache) (memo-cache-size square)
+                  (hash-table-ref cache (list 1 2))
+                  (hash-table-weak? cache) (hash-table-weak? (make-hash-table)))",
+            "((4 2) 2 1 rebuildable #t #f)");
+    }
+
+    #[test]
+    fn test_finalizers_need_objects_and_procedures() {
+        assert_fails("(set-finalizer! 42 (lambda () 'never))", "type");
+        assert_fails("(set-finalizer! (vector) 'not-a-procedure)", "type");
+        assert_fails("(weak-box-value (vector))", "type");
+    }
+}